@@ -0,0 +1,331 @@
+//! libvirt's public C API constants, from `libvirt-api.xml`.
+//!
+//! The `.x` files pass flags as a bare `unsigned int flags`; the values
+//! are defined as C enums in libvirt's headers. libvirt's build describes
+//! those headers in `libvirt-api.xml` (installed under
+//! `/usr/share/libvirt/api/`). [`Api::parse`] reads its enums and the
+//! `flags` argument of each function, and [`apply_flags`] types the
+//! `flags` fields of the matching procedures' arguments.
+
+use crate::ast::*;
+use heck::{ToShoutySnakeCase, ToUpperCamelCase};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Enums and functions of libvirt's public API.
+#[derive(Debug, Clone, Default)]
+pub struct Api {
+    /// Enums, in the order of their first member in the file, then those
+    /// merged for functions whose flags come from several.
+    pub enums: Vec<ApiEnum>,
+    /// The enum documenting each function's `flags` argument, by function
+    /// name, e.g. `virDomainUndefineFlags` -> `virDomainUndefineFlagsValues`.
+    pub function_flags: HashMap<String, String>,
+}
+
+/// A C enum, e.g. `virDomainUndefineFlagsValues`.
+#[derive(Debug, Clone)]
+pub struct ApiEnum {
+    pub name: String,
+    /// Whether the members are bits to OR together: the enum documents a
+    /// function's `flags` argument, or its name says so.
+    pub flags: bool,
+    /// Members, sorted by value.
+    pub members: Vec<ApiConstant>,
+}
+
+/// A member of an [`ApiEnum`].
+#[derive(Debug, Clone)]
+pub struct ApiConstant {
+    pub name: String,
+    pub value: i64,
+    pub doc: Option<String>,
+}
+
+impl Api {
+    /// Parse the contents of a `libvirt-api.xml` file.
+    ///
+    /// Only `<enum>` and `<function>` symbols are read. Enum members whose
+    /// value is not a number are skipped.
+    pub fn parse(xml: &str) -> Result<Self, String> {
+        let document = roxmltree::Document::parse(xml).map_err(|e| e.to_string())?;
+        let root = document.root_element();
+        if !root.has_tag_name("api") {
+            return Err(format!("expected <api>, found <{}>", root.tag_name().name()));
+        }
+
+        let mut api = Api::default();
+        let mut index = HashMap::new();
+        let symbols = root.descendants().filter(|n| n.is_element());
+        for node in symbols {
+            match node.tag_name().name() {
+                "enum" => {
+                    let (Some(name), Some(ty), Some(value)) =
+                        (node.attribute("name"), node.attribute("type"), node.attribute("value"))
+                    else {
+                        continue;
+                    };
+                    let Ok(value) = value.parse() else { continue };
+                    let i = *index.entry(ty.to_string()).or_insert_with(|| {
+                        api.enums.push(ApiEnum {
+                            name: ty.to_string(),
+                            flags: ty.ends_with("Flags") || ty.ends_with("FlagsValues"),
+                            members: Vec::new(),
+                        });
+                        api.enums.len() - 1
+                    });
+                    api.enums[i].members.push(ApiConstant {
+                        name: name.to_string(),
+                        value,
+                        doc: node.attribute("info").map(str::to_string),
+                    });
+                }
+                "function" => {
+                    let Some(name) = node.attribute("name") else { continue };
+                    let info = node
+                        .children()
+                        .find(|arg| arg.has_tag_name("arg") && arg.attribute("name") == Some("flags"))
+                        .and_then(|arg| arg.attribute("info"));
+                    if let Some(info) = info {
+                        api.function_flags.insert(name.to_string(), info.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Until now `function_flags` holds the argument's description, which
+        // names the enum(s) its bits come from. Where it names several, the
+        // function gets an enum of their members together, named after it.
+        let mut function_flags: Vec<_> = std::mem::take(&mut api.function_flags).into_iter().collect();
+        function_flags.sort();
+        for (function, info) in function_flags {
+            let mut named: Vec<&str> = Vec::new();
+            for word in info.split(|c: char| !c.is_ascii_alphanumeric() && c != '_') {
+                if index.contains_key(word) && !named.contains(&word) {
+                    named.push(word);
+                }
+            }
+            let enum_name = match named[..] {
+                [] => continue,
+                [name] => name.to_string(),
+                _ => {
+                    let name = match function.strip_suffix("Flags") {
+                        Some(_) => format!("{}Values", function),
+                        None => format!("{}FlagsValues", function),
+                    };
+                    if index.contains_key(&name) {
+                        return Err(format!(
+                            "the flags of {} come from {}, but their merged enum {} already exists",
+                            function,
+                            named.join(", "),
+                            name
+                        ));
+                    }
+                    let mut members = Vec::new();
+                    for e in &named {
+                        let e = &mut api.enums[index[*e]];
+                        e.flags = true;
+                        members.extend(e.members.iter().cloned());
+                    }
+                    index.insert(name.clone(), api.enums.len());
+                    api.enums.push(ApiEnum {
+                        name: name.clone(),
+                        flags: true,
+                        members,
+                    });
+                    name
+                }
+            };
+            api.function_flags.insert(function, enum_name);
+        }
+        for e in &mut api.enums {
+            e.flags |= api.function_flags.values().any(|name| *name == e.name);
+            e.members.sort_by_key(|m| m.value);
+        }
+        Ok(api)
+    }
+
+    /// Read and parse a `libvirt-api.xml` file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let xml = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Self::parse(&xml).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// The flags enum of C function `function`, if it takes flags.
+    pub fn flags_of(&self, function: &str) -> Option<&ApiEnum> {
+        let name = self.function_flags.get(function)?;
+        self.enums.iter().find(|e| e.name == *name && e.flags)
+    }
+}
+
+impl ApiEnum {
+    /// Rust name of the generated type: `virDomainUndefineFlagsValues`
+    /// becomes `DomainUndefineFlags`, and `virDomainRebootFlagValues`
+    /// `DomainRebootFlags`.
+    pub fn rust_name(&self) -> String {
+        let name = self.name.strip_prefix("vir").unwrap_or(&self.name);
+        let name = match name.strip_suffix("FlagValues") {
+            Some(base) => format!("{}Flags", base),
+            None => name.strip_suffix("Values").unwrap_or(name).to_string(),
+        };
+        name.to_upper_camel_case()
+    }
+
+    /// Members with a value, which [`generate`](crate::generate) turns into
+    /// the constants of a flags type: a zero member such as
+    /// `VIR_DOMAIN_AFFECT_CURRENT` is the absence of flags, i.e. `empty()`.
+    pub fn bits(&self) -> impl Iterator<Item = (&ApiConstant, String)> {
+        self.members.iter().zip(self.member_names()).filter(|(m, _)| m.value != 0)
+    }
+
+    /// Names of the members without the prefix they share, e.g. `NVRAM`
+    /// for `VIR_DOMAIN_UNDEFINE_NVRAM`.
+    ///
+    /// A lone member drops the words it shares with the enum's name
+    /// instead. Names never start with a digit or become empty.
+    pub fn member_names(&self) -> Vec<String> {
+        let words: Vec<Vec<&str>> = self.members.iter().map(|m| m.name.split('_').collect()).collect();
+        let reference: Vec<String> = if words.len() > 1 {
+            words[0].iter().map(|w| w.to_string()).collect()
+        } else {
+            let snake = self.name.to_shouty_snake_case();
+            snake.split('_').map(str::to_string).collect()
+        };
+        let mut shared = reference.len();
+        for member in &words {
+            let common = member.iter().zip(&reference).take_while(|(a, b)| *a == b).count();
+            shared = shared.min(common);
+        }
+        // Keep at least one word, and don't start on a digit
+        while shared > 0
+            && words.iter().any(|w| {
+                w.len() <= shared || w[shared].starts_with(|c: char| c.is_ascii_digit())
+            })
+        {
+            shared -= 1;
+        }
+        words.iter().map(|w| w[shared..].join("_")).collect()
+    }
+}
+
+/// C function a procedure stands for: `REMOTE_PROC_DOMAIN_UNDEFINE_FLAGS`
+/// is `virDomainUndefineFlags`. Compared case-insensitively, since
+/// acronyms (`virNodeGetCPUStats`) lose their case in the `.x` names.
+fn function_key(name: &str) -> String {
+    name.chars().filter(|c| *c != '_').flat_map(char::to_lowercase).collect()
+}
+
+/// Type the `flags` argument of each of `protocol`'s procedures whose C
+/// function is documented in `api` as [`Type::Flags`].
+///
+/// Procedures are matched to functions by name, so this applies to the
+/// remote protocol, whose procedures are named after the public API.
+pub fn apply_flags(protocol: &mut Protocol, api: &Api) {
+    let functions: HashMap<String, &ApiEnum> = api
+        .function_flags
+        .keys()
+        .filter_map(|f| Some((function_key(f), api.flags_of(f)?)))
+        .collect();
+
+    let mut args = HashMap::new();
+    for proc in &protocol.procedures {
+        let call = proc.name.strip_prefix(&protocol.config.proc_prefix).unwrap_or(&proc.name);
+        let key = function_key(&format!("vir_{}", call));
+        if let (Some(args_name), Some(flags)) = (&proc.args, functions.get(&key)) {
+            args.insert(args_name.clone(), *flags);
+        }
+    }
+
+    for type_def in &mut protocol.types {
+        let TypeDef::Struct(s) = type_def else { continue };
+        let Some(flags) = args.get(&s.name) else { continue };
+        for field in &mut s.fields {
+            if field.name == "flags" && matches!(field.ty, Type::UInt) {
+                field.ty = Type::Flags(flags.rust_name());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_protocol;
+
+    const API: &str = r#"<?xml version="1.0" encoding="ISO-8859-1"?>
+<api name='libvirt'>
+  <symbols>
+    <enum name='VIR_DOMAIN_UNDEFINE_NVRAM' file='libvirt-domain' value='4' type='virDomainUndefineFlagsValues'/>
+    <enum name='VIR_DOMAIN_UNDEFINE_MANAGED_SAVE' file='libvirt-domain' value='1' type='virDomainUndefineFlagsValues' info='Also remove any managed save'/>
+    <enum name='VIR_DOMAIN_AFFECT_CURRENT' file='libvirt-domain' value='0' type='virDomainModificationImpact'/>
+    <enum name='VIR_DOMAIN_AFFECT_LIVE' file='libvirt-domain' value='1' type='virDomainModificationImpact'/>
+    <enum name='VIR_DOMAIN_RUNNING' file='libvirt-domain' value='1' type='virDomainState'/>
+    <enum name='VIR_DOMAIN_DEFINE_VALIDATE' file='libvirt-domain' value='1' type='virDomainDefineFlags'/>
+    <enum name='VIR_DOMAIN_MEM_MAXIMUM' file='libvirt-domain' value='4' type='virDomainMemoryModFlags'/>
+    <function name='virDomainUndefineFlags' file='libvirt-domain' module='libvirt-domain'>
+      <info><![CDATA[Undefine a domain.]]></info>
+      <return type='int' info='0 in case of success, -1 in case of error'/>
+      <arg name='domain' type='virDomainPtr' info='pointer to a defined domain'/>
+      <arg name='flags' type='unsigned int' info='bitwise-OR of supported virDomainUndefineFlagsValues'/>
+    </function>
+    <function name='virDomainSetAutostart' file='libvirt-domain' module='libvirt-domain'>
+      <arg name='domain' type='virDomainPtr' info='a domain object'/>
+      <arg name='autostart' type='int' info='whether the domain should be automatically started 0 or 1'/>
+    </function>
+    <function name='virDomainSetMemoryFlags' file='libvirt-domain' module='libvirt-domain'>
+      <arg name='flags' type='unsigned int' info='bitwise-OR of virDomainModificationImpact and virDomainMemoryModFlags'/>
+    </function>
+  </symbols>
+</api>"#;
+
+    #[test]
+    fn test_parse_api() {
+        let api = Api::parse(API).unwrap();
+        let names: Vec<_> = api.enums.iter().map(|e| (e.name.as_str(), e.flags)).collect();
+        assert_eq!(
+            names,
+            [
+                ("virDomainUndefineFlagsValues", true),
+                ("virDomainModificationImpact", true),
+                ("virDomainState", false),
+                ("virDomainDefineFlags", true),
+                ("virDomainMemoryModFlags", true),
+                ("virDomainSetMemoryFlagsValues", true),
+            ]
+        );
+        let undefine = api.flags_of("virDomainUndefineFlags").unwrap();
+        assert_eq!(undefine.rust_name(), "DomainUndefineFlags");
+        assert_eq!(undefine.member_names(), ["MANAGED_SAVE", "NVRAM"]);
+        assert_eq!(undefine.members[0].doc.as_deref(), Some("Also remove any managed save"));
+        let set_memory = api.flags_of("virDomainSetMemoryFlags").unwrap();
+        assert_eq!(set_memory.rust_name(), "DomainSetMemoryFlags");
+        let bits: Vec<_> = set_memory.bits().map(|(m, name)| (name, m.value)).collect();
+        assert_eq!(bits, [("AFFECT_LIVE".to_string(), 1), ("MEM_MAXIMUM".to_string(), 4)]);
+        assert!(api.flags_of("virDomainSetAutostart").is_none());
+        assert_eq!(api.enums[3].member_names(), ["VALIDATE"]);
+    }
+
+    #[test]
+    fn test_apply_flags() {
+        let mut protocol = parse_protocol(
+            r#"
+            struct remote_domain_undefine_flags_args {
+                remote_nonnull_domain dom;
+                unsigned int flags;
+            };
+            enum remote_procedure {
+                REMOTE_PROC_DOMAIN_UNDEFINE_FLAGS = 231
+            };
+            "#,
+        )
+        .unwrap();
+        let api = Api::parse(API).unwrap();
+        apply_flags(&mut protocol, &api);
+
+        let TypeDef::Struct(s) = &protocol.types[0] else { panic!("expected struct") };
+        assert!(matches!(&s.fields[1].ty, Type::Flags(name) if name == "DomainUndefineFlags"));
+    }
+}
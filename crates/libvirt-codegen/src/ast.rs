@@ -1,5 +1,9 @@
 //! AST definitions for XDR protocol.
 
+use crate::api::Api;
+use crate::config::ProtocolConfig;
+use crate::diagnostic::Diagnostic;
+
 /// Represents a complete XDR protocol definition.
 #[derive(Debug, Clone)]
 pub struct Protocol {
@@ -7,24 +11,32 @@ pub struct Protocol {
     pub constants: Vec<Constant>,
     pub types: Vec<TypeDef>,
     pub procedures: Vec<Procedure>,
+    /// `program` blocks, as found in rpcgen-style files
+    pub programs: Vec<ProgramDef>,
     /// Program ID (e.g., REMOTE_PROGRAM = 0x20008086)
     pub program_id: Option<u32>,
     /// Protocol version
     pub protocol_version: Option<u32>,
-    /// Procedure enum name prefix (e.g., "REMOTE_PROC" or "QEMU_PROC")
-    pub proc_prefix: Option<String>,
+    /// Names of the program constant, procedure enum and prefixes
+    pub config: ProtocolConfig,
+    /// Definitions that were parsed but will not be generated
+    pub warnings: Vec<Diagnostic>,
 }
 
 impl Protocol {
     pub fn new(name: impl Into<String>) -> Self {
+        let name = name.into();
+        let config = ProtocolConfig::for_name(&name);
         Self {
-            name: name.into(),
+            name,
             constants: Vec::new(),
             types: Vec::new(),
             procedures: Vec::new(),
+            programs: Vec::new(),
             program_id: None,
             protocol_version: None,
-            proc_prefix: None,
+            config,
+            warnings: Vec::new(),
         }
     }
 }
@@ -38,6 +50,8 @@ pub struct ProtocolBundle {
     pub qemu: Option<Protocol>,
     /// LXC-specific protocol
     pub lxc: Option<Protocol>,
+    /// libvirt's public API constants, to type the remote protocol's flags
+    pub api: Option<Api>,
 }
 
 impl ProtocolBundle {
@@ -51,6 +65,8 @@ impl ProtocolBundle {
 pub struct Constant {
     pub name: String,
     pub value: ConstValue,
+    /// Comment attached to the declaration in the `.x` file, if any.
+    pub doc: Option<String>,
 }
 
 /// Constant value.
@@ -60,6 +76,17 @@ pub enum ConstValue {
     Ident(String),
 }
 
+impl ConstValue {
+    /// The value as a length, if it is a literal (or has been resolved to
+    /// one) that fits.
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            ConstValue::Int(n) => u32::try_from(*n).ok(),
+            ConstValue::Ident(_) => None,
+        }
+    }
+}
+
 /// Type definition.
 #[derive(Debug, Clone)]
 pub enum TypeDef {
@@ -69,11 +96,25 @@ pub enum TypeDef {
     Typedef(TypedefDef),
 }
 
+impl TypeDef {
+    /// Name of the defined type.
+    pub fn name(&self) -> &str {
+        match self {
+            TypeDef::Struct(s) => &s.name,
+            TypeDef::Enum(e) => &e.name,
+            TypeDef::Union(u) => &u.name,
+            TypeDef::Typedef(t) => &t.name,
+        }
+    }
+}
+
 /// Struct definition.
 #[derive(Debug, Clone)]
 pub struct StructDef {
     pub name: String,
     pub fields: Vec<Field>,
+    /// Comment attached to the declaration in the `.x` file, if any.
+    pub doc: Option<String>,
 }
 
 /// Struct field.
@@ -81,6 +122,8 @@ pub struct StructDef {
 pub struct Field {
     pub name: String,
     pub ty: Type,
+    /// Comment attached to the declaration in the `.x` file, if any.
+    pub doc: Option<String>,
 }
 
 /// Enum definition.
@@ -88,6 +131,8 @@ pub struct Field {
 pub struct EnumDef {
     pub name: String,
     pub variants: Vec<EnumVariant>,
+    /// Comment attached to the declaration in the `.x` file, if any.
+    pub doc: Option<String>,
 }
 
 /// Enum variant.
@@ -95,6 +140,8 @@ pub struct EnumDef {
 pub struct EnumVariant {
     pub name: String,
     pub value: Option<ConstValue>,
+    /// Comment attached to the declaration in the `.x` file, if any.
+    pub doc: Option<String>,
 }
 
 /// Union definition (discriminated union).
@@ -104,6 +151,8 @@ pub struct UnionDef {
     pub discriminant: Field,
     pub cases: Vec<UnionCase>,
     pub default: Option<Box<Type>>,
+    /// Comment attached to the declaration in the `.x` file, if any.
+    pub doc: Option<String>,
 }
 
 /// Union case.
@@ -118,6 +167,8 @@ pub struct UnionCase {
 pub struct TypedefDef {
     pub name: String,
     pub target: Type,
+    /// Comment attached to the declaration in the `.x` file, if any.
+    pub doc: Option<String>,
 }
 
 /// Type representation.
@@ -137,10 +188,12 @@ pub enum Type {
     Float,
     /// double
     Double,
+    /// quadruple
+    Quadruple,
     /// bool
     Bool,
     /// string<N> or string<>
-    String { max_len: Option<u32> },
+    String { max_len: Option<ConstValue> },
     /// opaque<N> or opaque[N]
     Opaque { len: LengthSpec },
     /// T<N> or T[N] (array)
@@ -150,27 +203,77 @@ pub enum Type {
     },
     /// T * (optional)
     Optional(Box<Type>),
+    /// T * whose target contains the declaring type again, as in a linked
+    /// list; boxed to give the Rust type a finite size
+    RecursiveOptional(Box<Type>),
     /// Named type reference
     Named(String),
+    /// `unsigned int flags` typed by one of libvirt's flags enums, named
+    /// by the Rust type generated for it (see [`crate::api`])
+    Flags(String),
 }
 
 /// Length specification for arrays and opaque data.
 #[derive(Debug, Clone)]
 pub enum LengthSpec {
     /// Fixed length [N]
-    Fixed(u32),
+    Fixed(ConstValue),
     /// Variable length <N> or <>
-    Variable { max: Option<u32> },
+    Variable { max: Option<ConstValue> },
 }
 
-/// RPC procedure definition.
+/// `program NAME { ... } = NUMBER;`, the ONC RPC way of listing
+/// procedures. libvirt's own protocols use a procedure enum instead.
+#[derive(Debug, Clone)]
+pub struct ProgramDef {
+    pub name: String,
+    pub number: ConstValue,
+    pub versions: Vec<VersionDef>,
+}
+
+/// `version NAME { ... } = NUMBER;` inside a program.
 #[derive(Debug, Clone)]
+pub struct VersionDef {
+    pub name: String,
+    pub number: ConstValue,
+    pub procedures: Vec<ProcedureDef>,
+}
+
+/// `RET NAME(ARGS) = NUMBER;` inside a program version.
+#[derive(Debug, Clone)]
+pub struct ProcedureDef {
+    pub name: String,
+    pub number: ConstValue,
+    /// Argument types; empty for `void`.
+    pub args: Vec<Type>,
+    pub ret: Type,
+}
+
+/// RPC procedure definition.
+///
+/// Besides the name and number from the procedure enum, this carries the
+/// annotations from the `/** ... */` comment preceding the enum variant.
+#[derive(Debug, Clone, Default)]
 pub struct Procedure {
     pub name: String,
     pub number: u32,
     pub args: Option<String>,
     pub ret: Option<String>,
+    /// `@priority: low|high`
     pub priority: Priority,
+    /// `@generate: none|client|server|both`
+    pub generate: Generate,
+    /// `@acl: <object>:<permission>[:<flag>]` rules, in declaration order.
+    /// Empty for `@acl: none`.
+    pub acl: Vec<String>,
+    /// `@aclfilter: <object>:<permission>` filters applied to returned lists.
+    pub acl_filter: Vec<String>,
+    /// `@readstream` / `@writestream`
+    pub stream: Option<StreamSpec>,
+    /// `@sparseflag: <flag>`: the flag enabling sparse streams.
+    pub sparse_flag: Option<String>,
+    /// Non-annotation lines of the procedure's comment, if any.
+    pub doc: Option<String>,
 }
 
 /// Procedure priority.
@@ -180,3 +283,30 @@ pub enum Priority {
     Low,
     High,
 }
+
+/// Which side libvirt generates dispatch stubs for (`@generate`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Generate {
+    #[default]
+    Both,
+    Client,
+    Server,
+    None,
+}
+
+/// Data stream opened by a procedure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamSpec {
+    pub direction: StreamDirection,
+    /// Offset of the stream parameter in the C API's parameter list.
+    pub param: u32,
+}
+
+/// Direction of a data stream, from the client's point of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamDirection {
+    /// `@readstream`: data flows from the daemon to the client.
+    Read,
+    /// `@writestream`: data flows from the client to the daemon.
+    Write,
+}
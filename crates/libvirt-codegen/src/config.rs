@@ -0,0 +1,81 @@
+//! Naming conventions of a protocol definition.
+//!
+//! libvirt's `.x` files follow the conventions `gendispatch.pl` relies on:
+//! a `<NAME>_PROGRAM` constant, a `<name>_procedure` enum whose variants
+//! are `<NAME>_PROC_<CALL>`, and `<name>_<call>_args`/`_ret` structs.
+//! [`ProtocolConfig`] spells these out so protocols using other names can
+//! be parsed and generated too.
+
+use crate::ast::Constant;
+
+/// Where the parser and generator find a protocol's program number and
+/// procedures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolConfig {
+    /// Constant holding the program number, e.g. `REMOTE_PROGRAM`.
+    pub program_const: String,
+    /// Constant holding the protocol version, e.g. `REMOTE_PROTOCOL_VERSION`.
+    pub version_const: String,
+    /// Enum listing the procedures, e.g. `remote_procedure`.
+    pub procedure_enum: String,
+    /// Prefix of the procedure enum variants, e.g. `REMOTE_PROC_`.
+    pub proc_prefix: String,
+    /// Prefix of the argument and return structs, e.g. `remote_` for
+    /// `remote_<call>_args`. Stripped from type names in the primary
+    /// protocol's generated code.
+    pub type_prefix: String,
+}
+
+impl ProtocolConfig {
+    /// Configuration following libvirt's conventions for protocol `name`.
+    pub fn for_name(name: &str) -> Self {
+        let upper = name.to_uppercase();
+        let lower = name.to_lowercase();
+        Self {
+            program_const: format!("{}_PROGRAM", upper),
+            version_const: format!("{}_PROTOCOL_VERSION", upper),
+            procedure_enum: format!("{}_procedure", lower),
+            proc_prefix: format!("{}_PROC_", upper),
+            type_prefix: format!("{}_", lower),
+        }
+    }
+
+    /// Protocol name implied by the constants: `qemu` for a file defining
+    /// `QEMU_PROGRAM`, and so on. `None` if there is no `*_PROGRAM`
+    /// constant.
+    pub fn detect_name(constants: &[Constant]) -> Option<String> {
+        constants
+            .iter()
+            .find_map(|c| c.name.strip_suffix("_PROGRAM"))
+            .filter(|prefix| !prefix.is_empty())
+            .map(str::to_lowercase)
+    }
+}
+
+impl Default for ProtocolConfig {
+    fn default() -> Self {
+        Self::for_name("remote")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::ConstValue;
+
+    #[test]
+    fn test_for_name() {
+        let config = ProtocolConfig::for_name("qemu");
+        assert_eq!(config.program_const, "QEMU_PROGRAM");
+        assert_eq!(config.procedure_enum, "qemu_procedure");
+        assert_eq!(config.proc_prefix, "QEMU_PROC_");
+        assert_eq!(config.type_prefix, "qemu_");
+
+        let constants = [Constant {
+            name: "LXC_PROGRAM".to_string(),
+            value: ConstValue::Int(0x00068000),
+            doc: None,
+        }];
+        assert_eq!(ProtocolConfig::detect_name(&constants).as_deref(), Some("lxc"));
+    }
+}
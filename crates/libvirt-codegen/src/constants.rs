@@ -0,0 +1,195 @@
+//! Resolution of constants defined in terms of other constants.
+//!
+//! `.x` files may give a constant, an enum variant or an array bound as the
+//! name of another constant, e.g. `REMOTE_SECURITY_MODEL_MAX =
+//! VIR_SECURITY_MODEL_BUFLEN` or `remote_nonnull_domain domains<REMOTE_DOMAIN_LIST_MAX>`.
+//! [`resolve_constants`] replaces such references by their values, looking
+//! names up in the protocol itself, then in [`LIBVIRT_CONSTANTS`] or, for
+//! the QEMU and LXC protocols, in the remote protocol.
+
+use crate::ast::*;
+use std::collections::HashMap;
+
+/// Constants from `libvirt.h` that libvirt's `.x` files refer to.
+pub const LIBVIRT_CONSTANTS: &[(&str, i64)] = &[
+    ("VIR_UUID_BUFLEN", 16),
+    ("VIR_UUID_STRING_BUFLEN", 37),
+    ("VIR_SECURITY_LABEL_BUFLEN", 4097),
+    ("VIR_SECURITY_MODEL_BUFLEN", 257),
+    ("VIR_SECURITY_DOI_BUFLEN", 257),
+    ("VIR_TYPED_PARAM_FIELD_LENGTH", 80),
+    ("VIR_DOMAIN_SCHED_FIELD_LENGTH", 80),
+    ("VIR_DOMAIN_BLKIO_FIELD_LENGTH", 80),
+    ("VIR_DOMAIN_MEMORY_FIELD_LENGTH", 80),
+    ("VIR_DOMAIN_BLOCK_STATS_FIELD_LENGTH", 80),
+    ("VIR_NODE_CPU_STATS_FIELD_LENGTH", 80),
+    ("VIR_NODE_MEMORY_STATS_FIELD_LENGTH", 80),
+    // virTypedParameterType
+    ("VIR_TYPED_PARAM_INT", 1),
+    ("VIR_TYPED_PARAM_UINT", 2),
+    ("VIR_TYPED_PARAM_LLONG", 3),
+    ("VIR_TYPED_PARAM_ULLONG", 4),
+    ("VIR_TYPED_PARAM_DOUBLE", 5),
+    ("VIR_TYPED_PARAM_BOOLEAN", 6),
+    ("VIR_TYPED_PARAM_STRING", 7),
+];
+
+/// [`LIBVIRT_CONSTANTS`] as a lookup table for [`resolve_constants`].
+pub fn libvirt_constants() -> HashMap<String, i64> {
+    LIBVIRT_CONSTANTS
+        .iter()
+        .map(|(name, value)| (name.to_string(), *value))
+        .collect()
+}
+
+/// Values of the constants and enum variants `protocol` defines with a
+/// literal (or already resolved) value.
+pub fn defined_constants(protocol: &Protocol) -> HashMap<String, i64> {
+    let constants = protocol.constants.iter().map(|c| (&c.name, &c.value));
+    let variants = protocol.types.iter().flat_map(|t| match t {
+        TypeDef::Enum(e) => e.variants.iter().filter_map(|v| Some((&v.name, v.value.as_ref()?))).collect(),
+        _ => Vec::new(),
+    });
+    constants
+        .chain(variants)
+        .filter_map(|(name, value)| match value {
+            ConstValue::Int(n) => Some((name.clone(), *n)),
+            ConstValue::Ident(_) => None,
+        })
+        .collect()
+}
+
+/// Replace references to other constants throughout `protocol` by their
+/// values.
+///
+/// Constants and enum variants may refer to each other in any order and
+/// through any number of steps. Names the protocol does not define are
+/// looked up in `external`. References that cannot be resolved are left
+/// in place. Union case labels keep their names, since the generated
+/// variants are named after them.
+pub fn resolve_constants(protocol: &mut Protocol, external: &HashMap<String, i64>) {
+    let mut values = external.clone();
+    values.extend(defined_constants(protocol));
+
+    let resolve = |value: &mut ConstValue, values: &HashMap<String, i64>| match value {
+        ConstValue::Ident(name) => match values.get(name.as_str()) {
+            Some(n) => {
+                *value = ConstValue::Int(*n);
+                true
+            }
+            None => false,
+        },
+        ConstValue::Int(_) => false,
+    };
+    // Each round resolves at least one more reference, until none is left
+    // that can be.
+    loop {
+        let mut progress = false;
+        for constant in &mut protocol.constants {
+            progress |= resolve(&mut constant.value, &values);
+        }
+        for type_def in &mut protocol.types {
+            let TypeDef::Enum(e) = type_def else { continue };
+            for variant in &mut e.variants {
+                progress |= variant.value.as_mut().is_some_and(|v| resolve(v, &values));
+            }
+        }
+        if !progress {
+            break;
+        }
+        values.extend(defined_constants(protocol));
+    }
+
+    for type_def in &mut protocol.types {
+        match type_def {
+            TypeDef::Struct(s) => {
+                for field in &mut s.fields {
+                    resolve_lengths(&mut field.ty, &values);
+                }
+            }
+            TypeDef::Union(u) => {
+                resolve_lengths(&mut u.discriminant.ty, &values);
+                for field in u.cases.iter_mut().filter_map(|case| case.field.as_mut()) {
+                    resolve_lengths(&mut field.ty, &values);
+                }
+                if let Some(ty) = &mut u.default {
+                    resolve_lengths(ty, &values);
+                }
+            }
+            TypeDef::Typedef(t) => resolve_lengths(&mut t.target, &values),
+            TypeDef::Enum(_) => {}
+        }
+    }
+}
+
+/// Resolve the lengths of `ty` and the types nested in it.
+fn resolve_lengths(ty: &mut Type, values: &HashMap<String, i64>) {
+    let resolve = |value: &mut ConstValue| {
+        if let ConstValue::Ident(name) = value {
+            if let Some(n) = values.get(name.as_str()) {
+                *value = ConstValue::Int(*n);
+            }
+        }
+    };
+    match ty {
+        Type::String { max_len: Some(max) } | Type::Opaque { len: LengthSpec::Variable { max: Some(max) } } => {
+            resolve(max)
+        }
+        Type::Opaque { len: LengthSpec::Fixed(len) } => resolve(len),
+        Type::Array { elem, len } => {
+            match len {
+                LengthSpec::Fixed(len) | LengthSpec::Variable { max: Some(len) } => resolve(len),
+                LengthSpec::Variable { max: None } => {}
+            }
+            resolve_lengths(elem, values);
+        }
+        Type::Optional(inner) => resolve_lengths(inner, values),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_protocol;
+
+    #[test]
+    fn test_resolve_constants() {
+        // Parsing resolves references within the file and to libvirt.h
+        let protocol = parse_protocol(
+            r#"
+            const B = A;
+            const A = VIR_UUID_BUFLEN;
+            const C = MISSING;
+            enum e {
+                E_FIRST = B,
+                E_SECOND = 2
+            };
+            struct s {
+                int items<E_SECOND>;
+                opaque uuid[A];
+            };
+            "#,
+        )
+        .unwrap();
+
+        let values: Vec<_> = protocol.constants.iter().map(|c| c.value.as_u32()).collect();
+        assert_eq!(values, [Some(16), Some(16), None]);
+
+        let TypeDef::Enum(e) = &protocol.types[0] else { panic!("expected enum") };
+        assert_eq!(e.variants[0].value.as_ref().and_then(ConstValue::as_u32), Some(16));
+
+        let TypeDef::Struct(s) = &protocol.types[1] else { panic!("expected struct") };
+        assert!(matches!(
+            &s.fields[0].ty,
+            Type::Array { len: LengthSpec::Variable { max: Some(ConstValue::Int(2)) }, .. }
+        ));
+        assert!(matches!(&s.fields[1].ty, Type::Opaque { len: LengthSpec::Fixed(ConstValue::Int(16)) }));
+
+        // References to another protocol's constants
+        let mut other = parse_protocol("const D = E_SECOND;").unwrap();
+        assert!(other.constants[0].value.as_u32().is_none());
+        resolve_constants(&mut other, &defined_constants(&protocol));
+        assert_eq!(other.constants[0].value.as_u32(), Some(2));
+    }
+}
@@ -0,0 +1,165 @@
+//! Errors and warnings about a `.x` file, pointing at the offending source.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The file could not be parsed.
+    Error,
+    /// The file was parsed, but part of it will not be generated.
+    Warning,
+}
+
+/// A position in a `.x` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, in characters.
+    pub column: usize,
+    /// The full text of the line, for display.
+    pub source_line: String,
+}
+
+impl Location {
+    /// Location of byte `offset` in `source`.
+    pub(crate) fn at(source: &str, offset: usize) -> Self {
+        let offset = offset.min(source.len());
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[offset..].find('\n').map_or(source.len(), |i| offset + i);
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: source[line_start..offset].chars().count() + 1,
+            source_line: source[line_start..line_end].trim_end_matches('\r').to_string(),
+        }
+    }
+}
+
+/// A parse error or warning, rendered like a compiler diagnostic:
+///
+/// ```text
+/// error: expected `;`, found `}`
+///   --> remote_protocol.x:12:15
+///    |
+/// 12 |     int serial
+///    |               ^
+///    = note: while parsing a field in a struct
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// What the parser was doing, e.g. `while parsing a field in a struct`.
+    pub note: Option<String>,
+    /// File the diagnostic is about, if parsed from a file.
+    pub path: Option<PathBuf>,
+    /// Where in the file, if known.
+    pub location: Option<Location>,
+}
+
+impl Diagnostic {
+    pub(crate) fn error(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            note: None,
+            path: None,
+            location: None,
+        }
+    }
+
+    pub(crate) fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message)
+        }
+    }
+
+    /// Point the diagnostic at byte `offset` of `source`.
+    pub(crate) fn at(mut self, source: &str, offset: usize) -> Self {
+        self.location = Some(Location::at(source, offset));
+        self
+    }
+
+    pub(crate) fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.note = Some(note.into());
+        self
+    }
+
+    pub(crate) fn with_path(mut self, path: &Path) -> Self {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+
+    fn fmt_note(&self, f: &mut fmt::Formatter<'_>, gutter: usize) -> fmt::Result {
+        match &self.note {
+            Some(note) => write!(f, "\n{:gutter$} = note: {}", "", note, gutter = gutter),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}", severity, self.message)?;
+
+        let path = self.path.as_deref().map(Path::display);
+        let Some(location) = &self.location else {
+            if let Some(path) = path {
+                write!(f, "\n --> {}", path)?;
+            }
+            return self.fmt_note(f, 1);
+        };
+
+        let gutter = location.line.to_string().len();
+        match path {
+            Some(path) => write!(f, "\n{:gutter$}--> {}:", "", path, gutter = gutter)?,
+            None => write!(f, "\n{:gutter$}--> <input>:", "", gutter = gutter)?,
+        }
+        write!(f, "{}:{}", location.line, location.column)?;
+        write!(f, "\n{:gutter$} |", "", gutter = gutter)?;
+        write!(f, "\n{} | {}", location.line, location.source_line)?;
+        // Tabs keep their width so the caret lines up.
+        let indent: String = location
+            .source_line
+            .chars()
+            .take(location.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(f, "\n{:gutter$} | {}^", "", indent, gutter = gutter)?;
+        self.fmt_note(f, gutter)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let source = "struct a {\n    int serial\n};\n";
+        let diagnostic = Diagnostic::error("expected `;`, found `}`")
+            .at(source, source.find("\n}").unwrap())
+            .with_note("while parsing a field in a struct")
+            .with_path(Path::new("test.x"));
+
+        assert_eq!(
+            diagnostic.to_string(),
+            "error: expected `;`, found `}`\n \
+             --> test.x:2:15\n  \
+             |\n\
+             2 |     int serial\n  \
+             |               ^\n  \
+             = note: while parsing a field in a struct"
+        );
+    }
+}
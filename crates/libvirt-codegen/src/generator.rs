@@ -1,12 +1,20 @@
 //! Rust code generator from XDR AST.
 
+use crate::api::{apply_flags, Api, ApiEnum};
 use crate::ast::*;
+use crate::config::ProtocolConfig;
+use crate::constants::{defined_constants, libvirt_constants, resolve_constants, LIBVIRT_CONSTANTS};
 use heck::{ToSnakeCase, ToUpperCamelCase};
 use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
+use std::collections::{HashMap, HashSet};
 
 /// Generate Rust code from a protocol definition.
+///
+/// The protocol's type prefix is stripped from type names, so `remote_domain`
+/// becomes `Domain`.
 pub fn generate(protocol: &Protocol) -> String {
+    let protocol = &strip_type_prefix(protocol, &protocol.config.type_prefix);
     let mut tokens = TokenStream::new();
 
     // Generate prelude
@@ -19,11 +27,17 @@ pub fn generate(protocol: &Protocol) -> String {
 
     // Generate types
     for type_def in &protocol.types {
-        tokens.extend(generate_type(type_def));
+        tokens.extend(generate_type(type_def, &protocol.config.type_prefix));
     }
 
     // Generate RPC client methods
-    tokens.extend(generate_client_methods(&protocol.procedures, "remote"));
+    tokens.extend(generate_client_methods(protocol, &type_index(&[protocol])));
+    tokens.extend(generate_procedure_enum_info(protocol));
+    tokens.extend(generate_service(protocol));
+
+    tokens.extend(generate_program_tables(&[protocol]));
+    tokens.extend(generate_fuzz_decoders(&[protocol]));
+    tokens.extend(generate_payload_decoders(&[protocol]));
 
     // Format the output
     let file = syn::parse2(tokens).expect("generated invalid Rust code");
@@ -32,6 +46,7 @@ pub fn generate(protocol: &Protocol) -> String {
 
 /// Generate Rust code from multiple protocol definitions (remote + qemu + lxc).
 pub fn generate_bundle(bundle: &ProtocolBundle) -> String {
+    let bundle = &prepare_bundle(bundle);
     let mut tokens = TokenStream::new();
 
     // Generate prelude
@@ -46,31 +61,225 @@ pub fn generate_bundle(bundle: &ProtocolBundle) -> String {
 
         // Generate types
         for type_def in &remote.types {
-            tokens.extend(generate_type(type_def));
+            tokens.extend(generate_type(type_def, &remote.config.type_prefix));
+        }
+
+        // Generate flags types and enum constants of the public API
+        if let Some(api) = &bundle.api {
+            tokens.extend(generate_api(api));
         }
 
         // Generate LibvirtRpc trait and GeneratedClient
-        tokens.extend(generate_client_methods(&remote.procedures, "remote"));
+        tokens.extend(generate_client_methods(remote, &type_index(&[remote])));
+        tokens.extend(generate_procedure_enum_info(remote));
+        tokens.extend(generate_service(remote));
     }
 
     // Generate QEMU protocol (only types and methods, reuses remote types)
     if let Some(qemu) = &bundle.qemu {
-        tokens.extend(generate_secondary_protocol(qemu, "qemu"));
+        tokens.extend(generate_secondary_protocol(qemu, bundle.remote.as_ref()));
     }
 
     // Generate LXC protocol (only types and methods, reuses remote types)
     if let Some(lxc) = &bundle.lxc {
-        tokens.extend(generate_secondary_protocol(lxc, "lxc"));
+        tokens.extend(generate_secondary_protocol(lxc, bundle.remote.as_ref()));
     }
 
+    let protocols: Vec<_> = [&bundle.remote, &bundle.qemu, &bundle.lxc]
+        .into_iter()
+        .flatten()
+        .collect();
+    tokens.extend(generate_program_tables(&protocols));
+    tokens.extend(generate_fuzz_decoders(&protocols));
+    tokens.extend(generate_payload_decoders(&protocols));
+
     // Format the output
     let file = syn::parse2(tokens).expect("generated invalid Rust code");
     prettyplease::unparse(&file)
 }
 
+/// Copy of `protocol` with `prefix` removed from the names of the types it
+/// declares and references.
+pub(crate) fn strip_type_prefix(protocol: &Protocol, prefix: &str) -> Protocol {
+    let strip = |name: &mut String| {
+        if let Some(rest) = name.strip_prefix(prefix).filter(|rest| !rest.is_empty()) {
+            *name = rest.to_string();
+        }
+    };
+
+    let mut protocol = protocol.clone();
+    for type_def in &mut protocol.types {
+        match type_def {
+            TypeDef::Struct(s) => {
+                strip(&mut s.name);
+                for field in &mut s.fields {
+                    strip_named_types(&mut field.ty, &strip);
+                }
+            }
+            TypeDef::Enum(e) => strip(&mut e.name),
+            TypeDef::Union(u) => {
+                strip(&mut u.name);
+                strip_named_types(&mut u.discriminant.ty, &strip);
+                for field in u.cases.iter_mut().filter_map(|c| c.field.as_mut()) {
+                    strip_named_types(&mut field.ty, &strip);
+                }
+                if let Some(default) = &mut u.default {
+                    strip_named_types(default, &strip);
+                }
+            }
+            TypeDef::Typedef(t) => {
+                strip(&mut t.name);
+                strip_named_types(&mut t.target, &strip);
+            }
+        }
+    }
+    for procedure in &mut protocol.procedures {
+        procedure.args.iter_mut().for_each(strip);
+        procedure.ret.iter_mut().for_each(strip);
+    }
+    strip(&mut protocol.config.procedure_enum);
+    protocol
+}
+
+fn strip_named_types(ty: &mut Type, strip: &impl Fn(&mut String)) {
+    match ty {
+        Type::Named(name) => strip(name),
+        Type::Array { elem, .. } => strip_named_types(elem, strip),
+        Type::Optional(inner) | Type::RecursiveOptional(inner) => strip_named_types(inner, strip),
+        _ => {}
+    }
+}
+
+/// Strip the remote protocol's type prefix throughout a bundle, type its
+/// flags arguments from the bundle's [`Api`], and resolve the secondary
+/// protocols' references to its constants. The secondary protocols refer
+/// to its types and keep their own prefixes.
+pub(crate) fn prepare_bundle(bundle: &ProtocolBundle) -> ProtocolBundle {
+    let Some(remote) = &bundle.remote else {
+        return bundle.clone();
+    };
+    let prefix = remote.config.type_prefix.as_str();
+    let mut constants = libvirt_constants();
+    constants.extend(defined_constants(remote));
+    let secondary = |protocol: &Protocol| {
+        let mut protocol = strip_type_prefix(protocol, prefix);
+        resolve_constants(&mut protocol, &constants);
+        protocol
+    };
+    let mut remote = strip_type_prefix(remote, prefix);
+    if let Some(api) = &bundle.api {
+        apply_flags(&mut remote, api);
+    }
+    ProtocolBundle {
+        remote: Some(remote),
+        qemu: bundle.qemu.as_ref().map(secondary),
+        lxc: bundle.lxc.as_ref().map(secondary),
+        api: bundle.api.clone(),
+    }
+}
+
+/// Generate a `bitflags` type for each flags enum of `api`, and constants
+/// for the members of the other enums.
+///
+/// The flags types encode as their bits, and keep bits they do not name,
+/// so flags added by newer libvirt versions can still be passed.
+fn generate_api(api: &Api) -> TokenStream {
+    let mut tokens = TokenStream::new();
+    for e in &api.enums {
+        if e.flags {
+            tokens.extend(generate_flags(e));
+            continue;
+        }
+        for member in &e.members {
+            // The prelude already has those the `.x` files use, from
+            // LIBVIRT_CONSTANTS
+            if LIBVIRT_CONSTANTS.iter().any(|(name, _)| *name == member.name) {
+                continue;
+            }
+            let name = format_ident!("{}", member.name);
+            let value = member.value as i32;
+            let docs = member.doc.iter().map(|d| format!(" {}", d));
+            tokens.extend(quote! {
+                #(#[doc = #docs])*
+                pub const #name: i32 = #value;
+            });
+        }
+    }
+    tokens
+}
+
+/// Generate the `bitflags` type of a flags enum, with its XDR and serde
+/// implementations.
+fn generate_flags(e: &ApiEnum) -> TokenStream {
+    let ident = format_ident!("{}", e.rust_name());
+    let doc = format!(" Flags from libvirt's `{}`.", e.name);
+    let consts = e.bits().map(|(member, name)| {
+        let name = format_ident!("{}", name);
+        let value = member.value as u32;
+        let docs: Vec<_> = match &member.doc {
+            Some(d) => vec![format!(" `{}`: {}", member.name, d)],
+            None => vec![format!(" `{}`", member.name)],
+        };
+        quote! {
+            #(#[doc = #docs])*
+            const #name = #value;
+        }
+    });
+
+    quote! {
+        bitflags::bitflags! {
+            #[doc = #doc]
+            ///
+            /// Bits without a name here can be passed with `from_bits_retain`.
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+            pub struct #ident: u32 {
+                #(#consts)*
+            }
+        }
+
+        impl XdrEncode for #ident {
+            fn encode(&self, ser: &mut libvirt_xdr::XdrSerializer) -> libvirt_xdr::Result<()> {
+                self.bits().encode(ser)
+            }
+        }
+
+        impl XdrDecode for #ident {
+            fn decode(de: &mut libvirt_xdr::XdrDeserializer<'_>) -> libvirt_xdr::Result<Self> {
+                u32::decode(de).map(Self::from_bits_retain)
+            }
+        }
+
+        impl From<u32> for #ident {
+            fn from(bits: u32) -> Self {
+                Self::from_bits_retain(bits)
+            }
+        }
+
+        impl From<#ident> for u32 {
+            fn from(flags: #ident) -> Self {
+                flags.bits()
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl Serialize for #ident {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                self.bits().serialize(serializer)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> Deserialize<'de> for #ident {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                u32::deserialize(deserializer).map(Self::from_bits_retain)
+            }
+        }
+    }
+}
+
 /// Generate code for a secondary protocol (QEMU or LXC).
 /// These protocols reuse types from the remote protocol.
-fn generate_secondary_protocol(protocol: &Protocol, prefix: &str) -> TokenStream {
+fn generate_secondary_protocol(protocol: &Protocol, remote: Option<&Protocol>) -> TokenStream {
     let mut tokens = TokenStream::new();
 
     // Generate protocol-specific constants
@@ -83,20 +292,309 @@ fn generate_secondary_protocol(protocol: &Protocol, prefix: &str) -> TokenStream
         // Skip the procedure enum - we handle it separately
         if let TypeDef::Enum(e) = type_def {
             if e.name.ends_with("_procedure") {
-                tokens.extend(generate_type(type_def));
+                tokens.extend(generate_type(type_def, &protocol.config.type_prefix));
                 continue;
             }
         }
-        tokens.extend(generate_type(type_def));
+        tokens.extend(generate_type(type_def, &protocol.config.type_prefix));
     }
 
     // Generate RPC trait and client for this protocol
-    tokens.extend(generate_secondary_client_methods(&protocol.procedures, prefix, protocol.program_id));
+    let mut protocols: Vec<_> = remote.into_iter().collect();
+    protocols.push(protocol);
+    let types = type_index(&protocols);
+    tokens.extend(generate_secondary_client_methods(protocol, &types));
+    tokens.extend(generate_procedure_enum_info(protocol));
+    tokens.extend(generate_service(protocol));
 
     tokens
 }
 
+/// Generate the `*_PROCEDURES` table of each protocol, the `PROGRAMS`
+/// table over them, and the types and lookups they use.
+///
+/// Unlike the procedure enum's accessors, these describe procedures by
+/// number, so tools handling raw packets (tracing, dumps, mock servers)
+/// can name and classify calls of any program.
+fn generate_program_tables(protocols: &[&Protocol]) -> TokenStream {
+    let mut tables = TokenStream::new();
+    let mut programs = Vec::new();
+    for protocol in protocols {
+        let mut procedures: Vec<_> = protocol.procedures.iter().collect();
+        procedures.sort_by_key(|p| p.number);
+        let entries = procedures.iter().map(|p| {
+            let name = &p.name;
+            let method = method_name(p, &protocol.config);
+            let number = p.number;
+            let type_name = |ty: &Option<String>| match ty {
+                Some(ty) => {
+                    let ty = to_rust_type_name(ty);
+                    quote! { Some(#ty) }
+                }
+                None => quote! { None },
+            };
+            let args = type_name(&p.args);
+            let ret = type_name(&p.ret);
+            let high_priority = p.priority == Priority::High;
+            let stream = match p.stream.as_ref().map(|s| s.direction) {
+                Some(StreamDirection::Read) => quote! { Some(StreamDirection::Read) },
+                Some(StreamDirection::Write) => quote! { Some(StreamDirection::Write) },
+                None => quote! { None },
+            };
+            let acl = &p.acl;
+            quote! {
+                ProcedureInfo {
+                    name: #name,
+                    method: #method,
+                    number: #number,
+                    args: #args,
+                    ret: #ret,
+                    high_priority: #high_priority,
+                    stream: #stream,
+                    acl: &[#(#acl),*],
+                }
+            }
+        });
+
+        let table = format_ident!("{}_PROCEDURES", protocol.name.to_uppercase());
+        let len = procedures.len();
+        let doc = format!(" Procedures of the {} program, sorted by number.", protocol.name);
+        tables.extend(quote! {
+            #[doc = #doc]
+            pub static #table: [ProcedureInfo; #len] = [#(#entries),*];
+        });
+
+        // Without a program number there is nothing to look the table up by
+        if let Some(number) = protocol.program_id {
+            let name = &protocol.name;
+            let version = protocol.protocol_version.unwrap_or(1);
+            programs.push(quote! {
+                ProgramInfo {
+                    name: #name,
+                    number: #number,
+                    version: #version,
+                    procedures: &#table,
+                }
+            });
+        }
+    }
+
+    quote! {
+        /// Direction of the data stream a procedure opens.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum StreamDirection {
+            /// `@readstream`: the daemon sends data to the client.
+            Read,
+            /// `@writestream`: the client sends data to the daemon.
+            Write,
+        }
+
+        /// Static description of an RPC procedure, from its `.x` definition.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct ProcedureInfo {
+            /// Name in the `.x` file, e.g. `REMOTE_PROC_CONNECT_OPEN`.
+            pub name: &'static str,
+            /// Name of the generated client method, e.g. `connect_open`.
+            pub method: &'static str,
+            pub number: u32,
+            /// Name of the argument type, if the procedure takes arguments.
+            pub args: Option<&'static str>,
+            /// Name of the return type, if the procedure returns a value.
+            pub ret: Option<&'static str>,
+            /// Whether the daemon handles the procedure on its high-priority
+            /// worker pool (`@priority: high`).
+            pub high_priority: bool,
+            /// Direction of the data stream the procedure opens, if any.
+            pub stream: Option<StreamDirection>,
+            /// Access control rules the daemon checks (`@acl`), as
+            /// `object:permission[:flag]`.
+            pub acl: &'static [&'static str],
+        }
+
+        /// An RPC program and the procedures of its current version.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct ProgramInfo {
+            /// Protocol name, e.g. `remote`.
+            pub name: &'static str,
+            pub number: u32,
+            pub version: u32,
+            /// Procedures, sorted by number.
+            pub procedures: &'static [ProcedureInfo],
+        }
+
+        impl ProgramInfo {
+            /// The procedure with the given number.
+            pub fn procedure(&self, number: u32) -> Option<&'static ProcedureInfo> {
+                let procedures = self.procedures;
+                let index = procedures.binary_search_by_key(&number, |p| p.number).ok()?;
+                Some(&procedures[index])
+            }
+
+            /// The procedure with the given `.x` name (`REMOTE_PROC_CONNECT_OPEN`)
+            /// or method name (`connect_open`).
+            pub fn procedure_by_name(&self, name: &str) -> Option<&'static ProcedureInfo> {
+                let procedures = self.procedures;
+                procedures.iter().find(|p| p.name == name || p.method == name)
+            }
+        }
+
+        #tables
+
+        /// Every program generated into this module.
+        pub static PROGRAMS: &[ProgramInfo] = &[#(#programs),*];
+
+        /// The program with the given number.
+        pub fn program_info(number: u32) -> Option<&'static ProgramInfo> {
+            PROGRAMS.iter().find(|p| p.number == number)
+        }
+
+        /// Procedure `number` of program `program`, e.g. to name the call a
+        /// packet carries.
+        pub fn procedure_info(program: u32, number: u32) -> Option<&'static ProcedureInfo> {
+            program_info(program)?.procedure(number)
+        }
+    }
+}
+
+/// Generate the table of reply and event decoders used by the fuzz targets.
+///
+/// Gated behind the including crate's `fuzzing` feature so regular builds
+/// do not pay for it.
+fn generate_fuzz_decoders(protocols: &[&Protocol]) -> TokenStream {
+    let entries: Vec<_> = protocols
+        .iter()
+        .flat_map(|p| &p.types)
+        .filter_map(|t| match t {
+            TypeDef::Struct(s) if s.name.ends_with("_ret") || s.name.ends_with("_msg") => {
+                let name = to_rust_type_name(&s.name);
+                let ty = format_ident!("{}", name);
+                Some(quote! { (#name, roundtrip::<#ty>) })
+            }
+            _ => None,
+        })
+        .collect();
+
+    quote! {
+        /// Decoders for every `*_ret` and `*_msg` type, driven by the fuzz
+        /// targets in `fuzz/`.
+        #[cfg(feature = "fuzzing")]
+        pub mod fuzzing {
+            use super::*;
+
+            /// Decode `data` as `T` and, if that succeeds, check that the
+            /// value re-encodes to bytes that decode to the same encoding.
+            pub fn roundtrip<T: XdrEncode + XdrDecode>(data: &[u8]) {
+                let Ok(value) = libvirt_xdr::from_bytes::<T>(data) else {
+                    return;
+                };
+                let encoded = libvirt_xdr::to_bytes(&value).expect("decoded value failed to encode");
+                let again = libvirt_xdr::from_bytes::<T>(&encoded).expect("re-encoded value failed to decode");
+                assert_eq!(libvirt_xdr::to_bytes(&again).unwrap(), encoded);
+            }
+
+            /// `(type name, roundtrip::<T>)` for each reply and event type.
+            pub const DECODERS: &[(&str, fn(&[u8]))] = &[#(#entries),*];
+        }
+    }
+}
+
+/// Generate `payload::decode_payload`, which decodes the payload of a
+/// packet into the argument or return type of its procedure.
+///
+/// Gated behind the including crate's `serde` feature: the decoded value is
+/// handed to a visitor as `Serialize + Debug`, so dump tools can print it
+/// in any format without naming the several hundred types.
+fn generate_payload_decoders(protocols: &[&Protocol]) -> TokenStream {
+    let mut arms = Vec::new();
+    for protocol in protocols {
+        let Some(program) = protocol.program_id else {
+            continue;
+        };
+        let structs: HashSet<_> = protocol
+            .types
+            .iter()
+            .filter_map(|t| match t {
+                TypeDef::Struct(s) => Some(s.name.as_str()),
+                _ => None,
+            })
+            .collect();
+        for procedure in &protocol.procedures {
+            let number = procedure.number;
+            // Events have no `*_args`: the daemon sends their `*_msg`
+            let msg = format!("{}_msg", method_name(procedure, &protocol.config));
+            let args = match &procedure.args {
+                None if structs.contains(msg.as_str()) => Some(msg),
+                args => args.clone(),
+            };
+            for (ty, reply) in [(&args, false), (&procedure.ret, true)] {
+                if let Some(ty) = ty {
+                    let name = to_rust_type_name(ty);
+                    let ty = format_ident!("{}", name);
+                    arms.push(quote! {
+                        (#program, #number, #reply) => decode::<#ty, V>(#name, data, visitor),
+                    });
+                }
+            }
+        }
+    }
+
+    quote! {
+        /// Decoding of packet payloads into the types of their procedures,
+        /// for tools showing the content of raw packets.
+        #[cfg(feature = "serde")]
+        pub mod payload {
+            use super::*;
+
+            /// Receives a decoded payload.
+            pub trait PayloadVisitor {
+                type Output;
+
+                /// Called with the decoded value and the name of its type.
+                fn visit<T: Serialize + std::fmt::Debug>(self, type_name: &'static str, value: &T) -> Self::Output;
+            }
+
+            fn decode<T, V>(type_name: &'static str, data: &[u8], visitor: V) -> Option<libvirt_xdr::Result<V::Output>>
+            where
+                T: XdrDecode + Serialize + std::fmt::Debug,
+                V: PayloadVisitor,
+            {
+                Some(libvirt_xdr::from_bytes::<T>(data).map(|value| visitor.visit(type_name, &value)))
+            }
+
+            /// Decode the payload of a call to procedure `procedure` of
+            /// `program`, or of its successful reply if `reply` is set, and
+            /// pass it to `visitor`.
+            ///
+            /// Events are sent as calls of their `*_msg` type. Returns `None`
+            /// for unknown procedures and payloads without a type (calls
+            /// without arguments, replies without a return value).
+            pub fn decode_payload<V: PayloadVisitor>(
+                program: u32,
+                procedure: u32,
+                reply: bool,
+                data: &[u8],
+                visitor: V,
+            ) -> Option<libvirt_xdr::Result<V::Output>> {
+                match (program, procedure, reply) {
+                    #(#arms)*
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
 fn generate_prelude() -> TokenStream {
+    // The libvirt.h constants the `.x` files refer to, e.g. the
+    // virTypedParameterType discriminants of remote_typed_param_value
+    let libvirt_constants = LIBVIRT_CONSTANTS.iter().map(|(name, value)| {
+        let name = format_ident!("{}", name);
+        quote! {
+            /// From `libvirt.h`.
+            pub const #name: i64 = #value;
+        }
+    });
+
     // Note: This code is included into a submodule via include!(),
     // so we cannot use inner attributes (like #![allow(...)]).
     // The parent module should add the necessary attributes.
@@ -104,11 +602,11 @@ fn generate_prelude() -> TokenStream {
         // Generated code from libvirt protocol definition.
         // Do not edit manually.
 
+        use libvirt_xdr::{XdrDecode, XdrEncode};
+        #[cfg(feature = "serde")]
         use serde::{Serialize, Deserialize};
 
-        // Well-known libvirt constants from libvirt.h
-        pub const VIR_UUID_BUFLEN: usize = 16;
-        pub const VIR_UUID_STRING_BUFLEN: usize = 37;
+        #(#libvirt_constants)*
 
         // Re-export fixed opaque type for UUID
         pub use libvirt_xdr::opaque::FixedOpaque16;
@@ -116,6 +614,7 @@ fn generate_prelude() -> TokenStream {
 }
 fn generate_constant(constant: &Constant) -> TokenStream {
     let name = format_ident!("{}", constant.name);
+    let doc = doc_attrs(constant.doc.as_deref());
 
     // Only generate constants with literal integer values.
     // Skip constants that reference external symbols (like VIR_* from libvirt.h)
@@ -123,6 +622,7 @@ fn generate_constant(constant: &Constant) -> TokenStream {
     match &constant.value {
         ConstValue::Int(n) => {
             quote! {
+                #doc
                 pub const #name: i64 = #n;
             }
         }
@@ -133,11 +633,12 @@ fn generate_constant(constant: &Constant) -> TokenStream {
     }
 }
 
-fn generate_type(type_def: &TypeDef) -> TokenStream {
+/// Generate a type of a protocol whose type prefix is `type_prefix`.
+fn generate_type(type_def: &TypeDef, type_prefix: &str) -> TokenStream {
     match type_def {
         TypeDef::Struct(s) => generate_struct(s),
-        TypeDef::Enum(e) => generate_enum(e),
-        TypeDef::Union(u) => generate_union(u),
+        TypeDef::Enum(e) => generate_enum(e, type_prefix),
+        TypeDef::Union(u) => generate_union(u, type_prefix),
         TypeDef::Typedef(t) => generate_typedef(t),
     }
 }
@@ -151,45 +652,53 @@ fn generate_struct(s: &StructDef) -> TokenStream {
         .map(|f| {
             let field_name = format_ident!("{}", to_rust_field_name(&f.name));
             let field_type = type_to_tokens(&f.ty);
+            let bound = max_len_attr(&f.ty);
+            let doc = doc_attrs(f.doc.as_deref());
             quote! {
+                #doc
+                #bound
                 pub #field_name: #field_type
             }
         })
         .collect();
 
+    let doc = doc_attrs(s.doc.as_deref());
+
     quote! {
-        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        #doc
+        #[derive(Debug, Clone, PartialEq, XdrEncode, XdrDecode)]
+        #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
         pub struct #name {
             #(#fields),*
         }
     }
 }
 
-fn generate_enum(e: &EnumDef) -> TokenStream {
+fn generate_enum(e: &EnumDef, type_prefix: &str) -> TokenStream {
     let name = format_ident!("{}", to_rust_type_name(&e.name));
 
     let variants: Vec<_> = e
         .variants
         .iter()
         .filter_map(|v| {
-            let variant_name = format_ident!("{}", to_rust_variant_name(&v.name, &e.name));
-
+            let variant_name = enum_variant_ident(v, &e.name, type_prefix)?;
+            let doc = doc_attrs(v.doc.as_deref());
             match &v.value {
                 Some(ConstValue::Int(n)) => {
                     let n = *n as i32;
-                    Some(quote! { #variant_name = #n })
+                    Some(quote! { #doc #variant_name = #n })
                 }
-                Some(ConstValue::Ident(_)) => {
-                    // Skip variants that reference other constants
-                    None
-                }
-                None => Some(quote! { #variant_name }),
+                _ => Some(quote! { #doc #variant_name }),
             }
         })
         .collect();
 
+    let doc = doc_attrs(e.doc.as_deref());
+
     quote! {
-        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        #doc
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, XdrEncode, XdrDecode)]
+        #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
         #[repr(i32)]
         pub enum #name {
             #(#variants),*
@@ -197,46 +706,206 @@ fn generate_enum(e: &EnumDef) -> TokenStream {
     }
 }
 
-fn generate_union(u: &UnionDef) -> TokenStream {
+fn generate_union(u: &UnionDef, type_prefix: &str) -> TokenStream {
     let name = format_ident!("{}", to_rust_type_name(&u.name));
 
-    let variants: Vec<_> = u
-        .cases
-        .iter()
-        .filter_map(|case| {
-            let variant_name = match &case.values.first()? {
-                ConstValue::Int(n) => format_ident!("V{}", *n as u64),
-                ConstValue::Ident(s) => format_ident!("{}", to_rust_variant_name(s, &u.name)),
-            };
-
-            match &case.field {
-                Some(f) => {
-                    let field_type = type_to_tokens(&f.ty);
-                    Some(quote! { #variant_name(#field_type) })
-                }
-                None => Some(quote! { #variant_name }),
+    let variants: Vec<_> = union_variants(u, type_prefix)
+        .into_iter()
+        .map(|v| {
+            let variant_name = &v.ident;
+            let doc = doc_attrs(v.doc);
+            let field_type = v.ty.map(type_to_tokens);
+            match (&v.discriminant, field_type) {
+                (Some(discriminant), Some(field_type)) => quote! {
+                    #doc
+                    #[xdr(discriminant = #discriminant)]
+                    #variant_name(#field_type)
+                },
+                (Some(discriminant), None) => quote! {
+                    #[xdr(discriminant = #discriminant)]
+                    #variant_name
+                },
+                (None, Some(field_type)) => quote! {
+                    #doc
+                    #[xdr(default)]
+                    #variant_name(i32, #field_type)
+                },
+                (None, None) => quote! {
+                    #doc
+                    #[xdr(default)]
+                    #variant_name(i32)
+                },
             }
         })
         .collect();
 
+    let doc = doc_attrs(u.doc.as_deref());
+
     quote! {
-        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        #doc
+        #[derive(Debug, Clone, PartialEq, XdrEncode, XdrDecode)]
+        #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
         pub enum #name {
             #(#variants),*
         }
     }
 }
 
+/// Rust variant name of an enum variant, or `None` if the variant is
+/// skipped because its value references another constant.
+pub(crate) fn enum_variant_ident(v: &EnumVariant, enum_name: &str, type_prefix: &str) -> Option<proc_macro2::Ident> {
+    match &v.value {
+        Some(ConstValue::Ident(_)) => None,
+        _ => Some(format_ident!("{}", to_rust_variant_name(&v.name, enum_name, type_prefix))),
+    }
+}
+
+/// A variant of a generated union.
+pub(crate) struct UnionVariant<'a> {
+    pub ident: proc_macro2::Ident,
+    /// Discriminant expression; `None` for the `default:` arm, whose
+    /// first field holds the discriminant instead.
+    pub discriminant: Option<TokenStream>,
+    /// Type of the arm, `None` for `void`.
+    pub ty: Option<&'a Type>,
+    pub doc: Option<&'a str>,
+}
+
+/// Variants of a union: one per case label, so that labels sharing an arm
+/// still round-trip, then `Default` for the `default:` arm if there is one.
+pub(crate) fn union_variants<'a>(u: &'a UnionDef, type_prefix: &str) -> Vec<UnionVariant<'a>> {
+    let mut variants: Vec<_> = u
+        .cases
+        .iter()
+        .flat_map(|case| {
+            case.values.iter().map(move |label| {
+                let (ident, discriminant) = union_case_label(label, u, type_prefix);
+                UnionVariant {
+                    ident,
+                    discriminant: Some(discriminant),
+                    ty: case.field.as_ref().map(|f| &f.ty),
+                    doc: case.field.as_ref().and_then(|f| f.doc.as_deref()),
+                }
+            })
+        })
+        .collect();
+
+    if let Some(default) = &u.default {
+        variants.push(UnionVariant {
+            ident: format_ident!("Default"),
+            discriminant: None,
+            ty: Some(default.as_ref()).filter(|ty| !matches!(ty, Type::Void)),
+            doc: Some("Any other discriminant, which it holds."),
+        });
+    }
+    variants
+}
+
+/// Rust variant name and discriminant expression of a union case label.
+///
+/// Labels of unions switching on an enum name its variants; other named
+/// labels are constants.
+fn union_case_label(label: &ConstValue, u: &UnionDef, type_prefix: &str) -> (proc_macro2::Ident, TokenStream) {
+    match (label, &u.discriminant.ty) {
+        (ConstValue::Int(n), _) => {
+            let n = *n as i32;
+            (format_ident!("V{}", n as u32), quote! { #n })
+        }
+        (ConstValue::Ident(s), Type::Bool) if s == "TRUE" || s == "FALSE" => {
+            let value = i32::from(s == "TRUE");
+            (format_ident!("{}", s.to_upper_camel_case()), quote! { #value })
+        }
+        (ConstValue::Ident(s), Type::Named(enum_name)) if !matches!(enum_name.as_str(), "u8" | "u16" | "i8" | "i16") => {
+            let enum_ident = format_ident!("{}", to_rust_type_name(enum_name));
+            let variant = format_ident!("{}", to_rust_variant_name(s, enum_name, type_prefix));
+            (variant.clone(), quote! { #enum_ident::#variant })
+        }
+        (ConstValue::Ident(s), _) => {
+            let ident = format_ident!("{}", s);
+            (
+                format_ident!("{}", to_rust_variant_name(s, &u.name, type_prefix)),
+                quote! { #ident },
+            )
+        }
+    }
+}
+
 fn generate_typedef(t: &TypedefDef) -> TokenStream {
     let name = format_ident!("{}", to_rust_type_name(&t.name));
     let target = type_to_tokens(&t.target);
+    let doc = doc_attrs(t.doc.as_deref());
 
     quote! {
+        #doc
         pub type #name = #target;
     }
 }
 
-fn type_to_tokens(ty: &Type) -> TokenStream {
+/// `#[doc]` attributes for a comment taken from the `.x` file.
+fn doc_attrs(doc: Option<&str>) -> TokenStream {
+    let lines = doc.map(doc_lines).unwrap_or_default();
+    quote! { #(#[doc = #lines])* }
+}
+
+/// Turn comment text into rustdoc lines.
+///
+/// The comments are plain text, so `<`, `[` and `]` are escaped to keep
+/// rustdoc from reading them as HTML tags or links, and gendispatch
+/// markers like `insert@1` are set as code.
+fn doc_lines(doc: &str) -> Vec<String> {
+    doc.lines()
+        .map(|line| {
+            let words: Vec<_> = line
+                .split(' ')
+                .map(|word| {
+                    if word.contains('@') && !word.starts_with('@') {
+                        format!("`{}`", word)
+                    } else {
+                        word.replace('<', "\\<")
+                            .replace('[', "\\[")
+                            .replace(']', "\\]")
+                    }
+                })
+                .collect();
+            format!(" {}", words.join(" "))
+        })
+        .collect()
+}
+
+/// `#[xdr(max_len = N)]` for variable-length data with a known upper bound.
+fn max_len_attr(ty: &Type) -> TokenStream {
+    let max = match ty {
+        Type::String { max_len: Some(n) } => n,
+        Type::Opaque {
+            len: LengthSpec::Variable { max: Some(n) },
+        } => n,
+        Type::Array {
+            len: LengthSpec::Variable { max: Some(n) },
+            ..
+        } => n,
+        _ => return TokenStream::new(),
+    };
+    // An unresolved bound is reported by the parser and not enforced.
+    let Some(max) = max.as_u32() else {
+        return TokenStream::new();
+    };
+    let max = max as usize;
+    quote! { #[xdr(max_len = #max)] }
+}
+
+/// A fixed length as a `usize`. Parsing fails on lengths that do not
+/// resolve to one, so only a hand-built [`Protocol`] can trip this.
+pub(crate) fn fixed_len(n: &ConstValue) -> usize {
+    match n {
+        ConstValue::Int(v) => match u32::try_from(*v) {
+            Ok(n) => n as usize,
+            Err(_) => panic!("fixed length {} is not a valid length", v),
+        },
+        ConstValue::Ident(name) => panic!("fixed length `{}` is not resolved to a number", name),
+    }
+}
+
+pub(crate) fn type_to_tokens(ty: &Type) -> TokenStream {
     match ty {
         Type::Void => quote! { () },
         Type::Int => quote! { i32 },
@@ -245,11 +914,12 @@ fn type_to_tokens(ty: &Type) -> TokenStream {
         Type::UHyper => quote! { u64 },
         Type::Float => quote! { f32 },
         Type::Double => quote! { f64 },
+        Type::Quadruple => quote! { libvirt_xdr::opaque::Quadruple },
         Type::Bool => quote! { bool },
         Type::String { .. } => quote! { String },
         Type::Opaque { len } => match len {
             LengthSpec::Fixed(n) => {
-                let n = *n as usize;
+                let n = fixed_len(n);
                 // Use FixedOpaque16 for 16-byte opaque (UUID) to handle XDR correctly
                 if n == 16 {
                     quote! { FixedOpaque16 }
@@ -263,7 +933,7 @@ fn type_to_tokens(ty: &Type) -> TokenStream {
             let elem_type = type_to_tokens(elem);
             match len {
                 LengthSpec::Fixed(n) => {
-                    let n = *n as usize;
+                    let n = fixed_len(n);
                     quote! { [#elem_type; #n] }
                 }
                 LengthSpec::Variable { .. } => quote! { Vec<#elem_type> },
@@ -273,15 +943,23 @@ fn type_to_tokens(ty: &Type) -> TokenStream {
             let inner_type = type_to_tokens(inner);
             quote! { Option<#inner_type> }
         }
+        Type::RecursiveOptional(inner) => {
+            let inner_type = type_to_tokens(inner);
+            quote! { Option<Box<#inner_type>> }
+        }
         Type::Named(name) => {
             let ident = format_ident!("{}", to_rust_type_name(name));
             quote! { #ident }
         }
+        Type::Flags(name) => {
+            let ident = format_ident!("{}", name);
+            quote! { #ident }
+        }
     }
 }
 
 /// Convert XDR type name to Rust type name (PascalCase).
-fn to_rust_type_name(name: &str) -> String {
+pub(crate) fn to_rust_type_name(name: &str) -> String {
     // Preserve Rust primitive types as-is
     match name {
         "u8" | "u16" | "u32" | "u64" | "u128" | "usize" |
@@ -292,12 +970,6 @@ fn to_rust_type_name(name: &str) -> String {
         _ => {}
     }
 
-    // Remove common prefixes
-    let name = name
-        .strip_prefix("remote_")
-        .or_else(|| name.strip_prefix("virNet"))
-        .unwrap_or(name);
-
     let converted = name.to_upper_camel_case();
 
     // Avoid collision with Rust standard types
@@ -313,7 +985,7 @@ fn to_rust_type_name(name: &str) -> String {
 
 /// Convert XDR field name to Rust field name (snake_case).
 /// Handles Rust keywords by appending underscore.
-fn to_rust_field_name(name: &str) -> String {
+pub(crate) fn to_rust_field_name(name: &str) -> String {
     let name = name.to_snake_case();
 
     // Handle Rust keywords
@@ -370,11 +1042,21 @@ fn to_rust_field_name(name: &str) -> String {
 }
 
 /// Convert XDR enum variant name to Rust variant name.
-fn to_rust_variant_name(name: &str, enum_name: &str) -> String {
-    // Try to strip the enum name prefix
+///
+/// `type_prefix` is the protocol's, stripped from variant names as from
+/// type names: with `remote_`, `REMOTE_PROC_OPEN` becomes `ProcOpen`.
+fn to_rust_variant_name(name: &str, enum_name: &str, type_prefix: &str) -> String {
+    // Try to strip the enum name prefix. The enum name has already lost
+    // its type prefix, so `REMOTE_STATE_OFF` in `state` becomes `Off`.
+    let enum_prefix = format!("{}_", enum_name.to_uppercase());
+    let type_prefix = type_prefix.to_uppercase();
     let name = name
-        .strip_prefix(&format!("{}_", enum_name.to_uppercase()))
-        .or_else(|| name.strip_prefix("REMOTE_"))
+        .strip_prefix(&enum_prefix)
+        .or_else(|| {
+            name.strip_prefix(type_prefix.as_str())
+                .filter(|rest| !type_prefix.is_empty() && !rest.is_empty())
+                .map(|rest| rest.strip_prefix(&enum_prefix).unwrap_or(rest))
+        })
         .or_else(|| name.strip_prefix("VIR_"))
         .unwrap_or(name);
 
@@ -382,11 +1064,27 @@ fn to_rust_variant_name(name: &str, enum_name: &str) -> String {
 }
 
 /// Generate RPC client methods from procedure definitions.
-fn generate_client_methods(procedures: &[Procedure], _protocol_name: &str) -> TokenStream {
-    let methods: Vec<_> = procedures
+fn generate_client_methods(protocol: &Protocol, types: &TypeIndex<'_>) -> TokenStream {
+    let methods: Vec<_> = protocol
+        .procedures
         .iter()
-        .map(|proc| generate_client_method(proc, "REMOTE_PROC_", "remote_"))
+        .map(|proc| generate_client_method(proc, protocol))
         .collect();
+    let client = format_ident!("GeneratedClient");
+    let flat = generate_flat_client(protocol, &client, types);
+
+    // Protocols other than libvirt's remote protocol may not define an
+    // error struct; their errors are passed on undecoded.
+    let has_error_type = protocol
+        .types
+        .iter()
+        .any(|t| matches!(t, TypeDef::Struct(s) if to_rust_type_name(&s.name) == "Error"));
+    let server_error = if has_error_type {
+        quote! { Error }
+    } else {
+        quote! { Vec<u8> }
+    };
+    let rpc_call_doc = format!(" Calls are to the program `{}`.", protocol.config.program_const);
 
     quote! {
         /// Trait for making RPC calls to libvirt daemon.
@@ -394,11 +1092,72 @@ fn generate_client_methods(procedures: &[Procedure], _protocol_name: &str) -> To
         #[allow(async_fn_in_trait)]
         pub trait LibvirtRpc {
             /// Make an RPC call with the given procedure number and payload.
-            /// Uses the default REMOTE_PROGRAM.
+            #[doc = #rpc_call_doc]
             async fn rpc_call(&self, procedure: u32, payload: Vec<u8>) -> Result<Vec<u8>, RpcError>;
 
             /// Make an RPC call with a specific program ID.
             async fn rpc_call_program(&self, program: u32, procedure: u32, payload: Vec<u8>) -> Result<Vec<u8>, RpcError>;
+
+            /// Make an RPC call that opens a data stream (`@readstream` or
+            /// `@writestream` procedures), returning the reply payload and
+            /// a handle for the stream.
+            async fn rpc_call_stream(&self, program: u32, procedure: u32, payload: Vec<u8>) -> Result<(Vec<u8>, StreamHandle), RpcError>;
+
+            /// Send a chunk of data on a stream.
+            async fn stream_send(&self, stream: &StreamHandle, data: Vec<u8>) -> Result<(), RpcError>;
+
+            /// Receive the next chunk of data from a stream, or `None` once
+            /// the daemon has finished sending.
+            async fn stream_recv(&self, stream: &StreamHandle) -> Result<Option<Vec<u8>>, RpcError>;
+
+            /// Finish a stream successfully.
+            async fn stream_finish(&self, stream: &StreamHandle) -> Result<(), RpcError>;
+
+            /// Abort a stream.
+            async fn stream_abort(&self, stream: &StreamHandle) -> Result<(), RpcError>;
+        }
+
+        /// Data stream opened by an RPC call.
+        ///
+        /// Stream packets reuse the program, procedure and serial of the
+        /// call that opened the stream.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct StreamHandle {
+            pub program: u32,
+            pub procedure: u32,
+            pub serial: i32,
+        }
+
+        /// Lets a client borrow a transport, e.g. to decode one call with
+        /// different [`libvirt_xdr::DecodeOptions`].
+        impl<T: LibvirtRpc + ?Sized> LibvirtRpc for &T {
+            async fn rpc_call(&self, procedure: u32, payload: Vec<u8>) -> Result<Vec<u8>, RpcError> {
+                (**self).rpc_call(procedure, payload).await
+            }
+
+            async fn rpc_call_program(&self, program: u32, procedure: u32, payload: Vec<u8>) -> Result<Vec<u8>, RpcError> {
+                (**self).rpc_call_program(program, procedure, payload).await
+            }
+
+            async fn rpc_call_stream(&self, program: u32, procedure: u32, payload: Vec<u8>) -> Result<(Vec<u8>, StreamHandle), RpcError> {
+                (**self).rpc_call_stream(program, procedure, payload).await
+            }
+
+            async fn stream_send(&self, stream: &StreamHandle, data: Vec<u8>) -> Result<(), RpcError> {
+                (**self).stream_send(stream, data).await
+            }
+
+            async fn stream_recv(&self, stream: &StreamHandle) -> Result<Option<Vec<u8>>, RpcError> {
+                (**self).stream_recv(stream).await
+            }
+
+            async fn stream_finish(&self, stream: &StreamHandle) -> Result<(), RpcError> {
+                (**self).stream_finish(stream).await
+            }
+
+            async fn stream_abort(&self, stream: &StreamHandle) -> Result<(), RpcError> {
+                (**self).stream_abort(stream).await
+            }
         }
 
         /// Error type for RPC operations.
@@ -411,7 +1170,7 @@ fn generate_client_methods(procedures: &[Procedure], _protocol_name: &str) -> To
             /// Transport/connection error
             Transport(String),
             /// Server returned an error
-            Server(Error),
+            Server(#server_error),
         }
 
         impl std::fmt::Display for RpcError {
@@ -427,15 +1186,64 @@ fn generate_client_methods(procedures: &[Procedure], _protocol_name: &str) -> To
 
         impl std::error::Error for RpcError {}
 
+        /// Error handling a call on the server side, returned by the
+        /// `*Service` trait methods and the `dispatch_*` functions.
+        #[derive(Debug)]
+        pub enum ServiceError {
+            /// The service does not implement the procedure, or the program
+            /// has no procedure with this number.
+            Unsupported { procedure: u32 },
+            /// The call's arguments could not be decoded.
+            Decode(String),
+            /// The reply could not be encoded.
+            Encode(String),
+            /// Reply with this error.
+            Server(Box<#server_error>),
+        }
+
+        impl std::fmt::Display for ServiceError {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    ServiceError::Unsupported { procedure } => write!(f, "unsupported procedure {}", procedure),
+                    ServiceError::Decode(e) => write!(f, "XDR decode error: {}", e),
+                    ServiceError::Encode(e) => write!(f, "XDR encode error: {}", e),
+                    ServiceError::Server(e) => write!(f, "Server error: {:?}", e),
+                }
+            }
+        }
+
+        impl std::error::Error for ServiceError {}
+
+        impl From<#server_error> for ServiceError {
+            fn from(error: #server_error) -> Self {
+                ServiceError::Server(Box::new(error))
+            }
+        }
+
         /// Generated RPC client methods for libvirt protocol.
         pub struct GeneratedClient<T: LibvirtRpc> {
             inner: T,
+            decode_options: libvirt_xdr::DecodeOptions,
         }
 
         impl<T: LibvirtRpc> GeneratedClient<T> {
             /// Create a new GeneratedClient wrapping an RPC transport.
             pub fn new(inner: T) -> Self {
-                Self { inner }
+                Self {
+                    inner,
+                    decode_options: libvirt_xdr::DecodeOptions::default(),
+                }
+            }
+
+            /// Decode responses with the given options.
+            pub fn with_decode_options(mut self, options: libvirt_xdr::DecodeOptions) -> Self {
+                self.decode_options = options;
+                self
+            }
+
+            /// Options used to decode responses.
+            pub fn decode_options(&self) -> &libvirt_xdr::DecodeOptions {
+                &self.decode_options
             }
 
             /// Get a reference to the inner transport.
@@ -450,36 +1258,48 @@ fn generate_client_methods(procedures: &[Procedure], _protocol_name: &str) -> To
 
             #(#methods)*
         }
+
+        #flat
     }
 }
 
 /// Generate RPC client methods for secondary protocols (QEMU, LXC).
-fn generate_secondary_client_methods(procedures: &[Procedure], protocol_name: &str, program_id: Option<u32>) -> TokenStream {
-    let (proc_prefix, type_prefix) = match protocol_name {
-        "qemu" => ("QEMU_PROC_", "qemu_"),
-        "lxc" => ("LXC_PROC_", "lxc_"),
-        _ => ("REMOTE_PROC_", "remote_"),
-    };
-
-    let methods: Vec<_> = procedures
+fn generate_secondary_client_methods(protocol: &Protocol, types: &TypeIndex<'_>) -> TokenStream {
+    let methods: Vec<_> = protocol
+        .procedures
         .iter()
-        .map(|proc| generate_secondary_client_method(proc, proc_prefix, type_prefix, protocol_name, program_id))
+        .map(|proc| generate_secondary_client_method(proc, protocol))
         .collect();
 
-    let _trait_name = format_ident!("{}Rpc", protocol_name.to_upper_camel_case());
-    let client_name = format_ident!("{}Client", protocol_name.to_upper_camel_case());
-    let _program_const = format_ident!("{}_PROGRAM", protocol_name.to_uppercase());
+    let client_name = format_ident!("{}Client", protocol.name.to_upper_camel_case());
+    let flat = generate_flat_client(protocol, &client_name, types);
+    let client_doc = format!(" Generated RPC client methods for the {} protocol.", protocol.name);
 
     quote! {
-        /// Generated RPC client methods for #protocol_name protocol.
+        #[doc = #client_doc]
         pub struct #client_name<T: LibvirtRpc> {
             inner: T,
+            decode_options: libvirt_xdr::DecodeOptions,
         }
 
         impl<T: LibvirtRpc> #client_name<T> {
             /// Create a new client wrapping an RPC transport.
             pub fn new(inner: T) -> Self {
-                Self { inner }
+                Self {
+                    inner,
+                    decode_options: libvirt_xdr::DecodeOptions::default(),
+                }
+            }
+
+            /// Decode responses with the given options.
+            pub fn with_decode_options(mut self, options: libvirt_xdr::DecodeOptions) -> Self {
+                self.decode_options = options;
+                self
+            }
+
+            /// Options used to decode responses.
+            pub fn decode_options(&self) -> &libvirt_xdr::DecodeOptions {
+                &self.decode_options
             }
 
             /// Get a reference to the inner transport.
@@ -494,152 +1314,578 @@ fn generate_secondary_client_methods(procedures: &[Procedure], protocol_name: &s
 
             #(#methods)*
         }
+
+        #flat
     }
 }
 
-/// Generate a single RPC method for a procedure.
-fn generate_client_method(proc: &Procedure, proc_prefix: &str, _type_prefix: &str) -> TokenStream {
-    // Convert REMOTE_PROC_CONNECT_LIST_DOMAINS to connect_list_domains
-    let method_name = proc
-        .name
-        .strip_prefix(proc_prefix)
-        .unwrap_or(&proc.name)
-        .to_lowercase();
-    let method_ident = format_ident!("{}", method_name);
+/// Generate the server side of a protocol: the `{Name}Service` trait, with
+/// one method per procedure, and `dispatch_{name}`, which decodes a call's
+/// arguments, passes them to the trait and encodes the reply.
+///
+/// Every trait method defaults to [`ServiceError::Unsupported`], so a
+/// service only implements the procedures it handles.
+fn generate_service(protocol: &Protocol) -> TokenStream {
+    if protocol.procedures.is_empty() {
+        return TokenStream::new();
+    }
+    let trait_ident = format_ident!("{}Service", protocol.name.to_upper_camel_case());
+    let dispatch_ident = format_ident!("dispatch_{}", protocol.name.to_snake_case());
+
+    let mut methods = Vec::new();
+    let mut arms = Vec::new();
+    for proc in &protocol.procedures {
+        let method = format_ident!("{}", method_name(proc, &protocol.config));
+        let number = proc.number;
+        let mut docs = procedure_docs(proc);
+        docs[0] = format!(" Handles `{}` (procedure {}).", proc.name, proc.number);
+
+        let ret = match &proc.ret {
+            Some(ret) => {
+                let ty = format_ident!("{}", to_rust_type_name(ret));
+                quote! { #ty }
+            }
+            None => quote! { () },
+        };
+        let reply = |call: TokenStream| match &proc.ret {
+            Some(_) => quote! {
+                let ret = #call.await?;
+                libvirt_xdr::to_bytes(&ret).map_err(|e| ServiceError::Encode(e.to_string()))
+            },
+            None => quote! {
+                #call.await?;
+                Ok(Vec::new())
+            },
+        };
 
-    // Convert to Procedure enum variant name: ProcConnectListDomains
-    let proc_variant = format_ident!(
-        "Proc{}",
-        proc.name
-            .strip_prefix(proc_prefix)
-            .unwrap_or(&proc.name)
-            .to_upper_camel_case()
-    );
+        match &proc.args {
+            Some(args) => {
+                let args_type = format_ident!("{}", to_rust_type_name(args));
+                methods.push(quote! {
+                    #(#[doc = #docs])*
+                    async fn #method(&self, args: #args_type) -> Result<#ret, ServiceError> {
+                        let _ = args;
+                        Err(ServiceError::Unsupported { procedure: #number })
+                    }
+                });
+                let reply = reply(quote! { service.#method(args) });
+                arms.push(quote! {
+                    #number => {
+                        let args = libvirt_xdr::from_bytes(payload)
+                            .map_err(|e| ServiceError::Decode(e.to_string()))?;
+                        #reply
+                    }
+                });
+            }
+            None => {
+                methods.push(quote! {
+                    #(#[doc = #docs])*
+                    async fn #method(&self) -> Result<#ret, ServiceError> {
+                        Err(ServiceError::Unsupported { procedure: #number })
+                    }
+                });
+                let reply = reply(quote! { service.#method() });
+                arms.push(quote! {
+                    #number => { #reply }
+                });
+            }
+        }
+    }
 
-    match (&proc.args, &proc.ret) {
-        (Some(args_name), Some(ret_name)) => {
-            // Has both args and return
-            let args_type = format_ident!("{}", to_rust_type_name(args_name));
-            let ret_type = format_ident!("{}", to_rust_type_name(ret_name));
+    let trait_docs = [
+        format!(" Server side of the {} protocol: one method per procedure, each", protocol.name),
+        " returning [`ServiceError::Unsupported`] unless implemented.".to_string(),
+        String::new(),
+        format!(" Calls are routed to it by [`{}`]. Procedures opening a data", dispatch_ident),
+        " stream only handle the call; the stream's data is up to the server.".to_string(),
+    ];
+    let dispatch_docs = [
+        format!(" Decode a call to procedure `procedure` of the {} program from", protocol.name),
+        " `payload`, pass it to `service` and encode the reply.".to_string(),
+        String::new(),
+        " Unknown procedures are [`ServiceError::Unsupported`].".to_string(),
+    ];
 
-            quote! {
-                /// RPC method for procedure #method_name.
-                pub async fn #method_ident(&self, args: #args_type) -> Result<#ret_type, RpcError> {
-                    let payload = libvirt_xdr::to_bytes(&args)
-                        .map_err(|e| RpcError::Encode(e.to_string()))?;
-                    let response = self.inner.rpc_call(Procedure::#proc_variant as u32, payload).await?;
-                    libvirt_xdr::from_bytes(&response)
-                        .map_err(|e| RpcError::Decode(e.to_string()))
-                }
+    quote! {
+        #(#[doc = #trait_docs])*
+        #[allow(async_fn_in_trait)]
+        pub trait #trait_ident {
+            #(#methods)*
+        }
+
+        #(#[doc = #dispatch_docs])*
+        pub async fn #dispatch_ident<S: #trait_ident>(
+            service: &S,
+            procedure: u32,
+            payload: &[u8],
+        ) -> Result<Vec<u8>, ServiceError> {
+            match procedure {
+                #(#arms)*
+                _ => Err(ServiceError::Unsupported { procedure }),
             }
         }
-        (Some(args_name), None) => {
-            // Has args but no return
-            let args_type = format_ident!("{}", to_rust_type_name(args_name));
+    }
+}
 
-            quote! {
-                /// RPC method for procedure #method_name.
-                pub async fn #method_ident(&self, args: #args_type) -> Result<(), RpcError> {
-                    let payload = libvirt_xdr::to_bytes(&args)
-                        .map_err(|e| RpcError::Encode(e.to_string()))?;
-                    let _ = self.inner.rpc_call(Procedure::#proc_variant as u32, payload).await?;
-                    Ok(())
-                }
+/// Generated types by name, to look up argument and return structs.
+type TypeIndex<'a> = HashMap<&'a str, &'a TypeDef>;
+
+/// Index of the types of `protocols`; later protocols take precedence.
+fn type_index<'a>(protocols: &[&'a Protocol]) -> TypeIndex<'a> {
+    protocols
+        .iter()
+        .flat_map(|p| &p.types)
+        .map(|t| (t.name(), t))
+        .collect()
+}
+
+/// Generate the `*Api` view of client `client`: its methods, taking the
+/// fields of their arguments struct as parameters, and returning the field
+/// of single-field return structs. `int`s libvirt uses as booleans are
+/// `bool`s.
+fn generate_flat_client(
+    protocol: &Protocol,
+    client: &proc_macro2::Ident,
+    types: &TypeIndex<'_>,
+) -> TokenStream {
+    let client_name = client.to_string();
+    let api = format_ident!("{}Api", client_name.strip_suffix("Client").unwrap_or(&client_name));
+    let methods: Vec<_> = protocol
+        .procedures
+        .iter()
+        .map(|proc| generate_flat_method(proc, &method_name(proc, &protocol.config), client, types))
+        .collect();
+    let doc = format!(
+        " [`{}`] methods taking their arguments as parameters instead of an `*Args`\n \
+         struct, and returning the value of single-field `*Ret` structs.",
+        client_name
+    );
+    let accessor_doc = format!(" Methods taking their arguments as parameters; see [`{}`].", api);
+
+    quote! {
+        #[doc = #doc]
+        pub struct #api<'a, T: LibvirtRpc> {
+            client: &'a #client<T>,
+        }
+
+        impl<T: LibvirtRpc> Clone for #api<'_, T> {
+            fn clone(&self) -> Self {
+                *self
             }
         }
-        (None, Some(ret_name)) => {
-            // No args but has return
-            let ret_type = format_ident!("{}", to_rust_type_name(ret_name));
 
-            quote! {
-                /// RPC method for procedure #method_name.
-                pub async fn #method_ident(&self) -> Result<#ret_type, RpcError> {
-                    let response = self.inner.rpc_call(Procedure::#proc_variant as u32, Vec::new()).await?;
-                    libvirt_xdr::from_bytes(&response)
-                        .map_err(|e| RpcError::Decode(e.to_string()))
-                }
+        impl<T: LibvirtRpc> Copy for #api<'_, T> {}
+
+        impl<T: LibvirtRpc> #client<T> {
+            #[doc = #accessor_doc]
+            pub fn api(&self) -> #api<'_, T> {
+                #api { client: self }
             }
         }
-        (None, None) => {
-            // No args and no return
-            quote! {
-                /// RPC method for procedure #method_name.
-                pub async fn #method_ident(&self) -> Result<(), RpcError> {
-                    let _ = self.inner.rpc_call(Procedure::#proc_variant as u32, Vec::new()).await?;
-                    Ok(())
-                }
+
+        impl<'a, T: LibvirtRpc> #api<'a, T> {
+            /// The client the calls go through.
+            pub fn client(&self) -> &'a #client<T> {
+                self.client
             }
+
+            #(#methods)*
         }
     }
 }
 
-/// Generate a single RPC method for a secondary protocol (QEMU/LXC).
-fn generate_secondary_client_method(
+/// Generate the flattened form of a client method.
+///
+/// Strings, arrays and structs are taken by reference and cloned into the
+/// arguments struct; everything `Copy` is taken by value.
+fn generate_flat_method(
     proc: &Procedure,
-    proc_prefix: &str,
-    _type_prefix: &str,
-    protocol_name: &str,
-    _program_id: Option<u32>,
+    method_name: &str,
+    client: &proc_macro2::Ident,
+    types: &TypeIndex<'_>,
 ) -> TokenStream {
-    // Convert QEMU_PROC_DOMAIN_MONITOR_COMMAND to domain_monitor_command
-    let method_name = proc
-        .name
-        .strip_prefix(proc_prefix)
-        .unwrap_or(&proc.name)
-        .to_lowercase();
-    let method_ident = format_ident!("{}", method_name);
+    let method = format_ident!("{}", method_name);
+    let mut docs = vec![format!(
+        " Calls `{}` (procedure {}); see [`{}::{}`].",
+        proc.name, proc.number, client, method_name
+    )];
+
+    let (params, call_args) = match &proc.args {
+        Some(args) => {
+            let args_type = format_ident!("{}", to_rust_type_name(args));
+            match types.get(args.as_str()) {
+                Some(TypeDef::Struct(s)) => {
+                    let mut params = Vec::new();
+                    let mut inits = Vec::new();
+                    for field in &s.fields {
+                        let name = format_ident!("{}", to_rust_field_name(&field.name));
+                        let (param, init) = if is_boolean_arg(field) {
+                            (quote! { bool }, quote! { #name: i32::from(#name) })
+                        } else {
+                            flat_param(&name, &field.ty, types)
+                        };
+                        params.push(quote! { #name: #param });
+                        inits.push(init);
+                    }
+                    (params, quote! { #args_type { #(#inits),* } })
+                }
+                _ => (vec![quote! { args: #args_type }], quote! { args }),
+            }
+        }
+        None => (Vec::new(), quote! {}),
+    };
 
-    // Use procedure number directly since we don't have a Procedure enum for secondary protocols
-    let proc_number = proc.number;
-    let program_const = format_ident!("{}_PROGRAM", protocol_name.to_uppercase());
+    // A single-field return struct is replaced by its field, and an empty
+    // one by `()`
+    let fields = proc.ret.as_deref().and_then(|ret| match types.get(ret) {
+        Some(TypeDef::Struct(s)) if s.fields.len() <= 1 => Some(s.fields.first()),
+        _ => None,
+    });
+    let (ret, unwrap) = match (&proc.ret, fields) {
+        (Some(ret), Some(Some(field))) => {
+            let field_name = format_ident!("{}", to_rust_field_name(&field.name));
+            docs.push(format!(" Returns the `{}` of [`{}`].", field_name, to_rust_type_name(ret)));
+            let (ty, value) = if returns_boolean(method_name, field) {
+                (quote! { bool }, quote! { ret.#field_name != 0 })
+            } else {
+                (type_to_tokens(&field.ty), quote! { ret.#field_name })
+            };
+            if proc.stream.is_some() {
+                (quote! { (#ty, StreamHandle) }, quote! { .map(|(ret, stream)| (#value, stream)) })
+            } else {
+                (quote! { #ty }, quote! { .map(|ret| #value) })
+            }
+        }
+        (Some(_), Some(None)) if proc.stream.is_some() => {
+            (quote! { StreamHandle }, quote! { .map(|(_, stream)| stream) })
+        }
+        (Some(_), Some(None)) => (quote! { () }, quote! { .map(|_| ()) }),
+        (Some(ret), None) => {
+            let ty = format_ident!("{}", to_rust_type_name(ret));
+            if proc.stream.is_some() {
+                (quote! { (#ty, StreamHandle) }, quote! {})
+            } else {
+                (quote! { #ty }, quote! {})
+            }
+        }
+        (None, _) if proc.stream.is_some() => (quote! { StreamHandle }, quote! {}),
+        (None, _) => (quote! { () }, quote! {}),
+    };
 
-    match (&proc.args, &proc.ret) {
-        (Some(args_name), Some(ret_name)) => {
-            let args_type = format_ident!("{}", to_rust_type_name(args_name));
-            let ret_type = format_ident!("{}", to_rust_type_name(ret_name));
+    quote! {
+        #(#[doc = #docs])*
+        pub async fn #method(&self #(, #params)*) -> Result<#ret, RpcError> {
+            self.client.#method(#call_args).await #unwrap
+        }
+    }
+}
 
-            quote! {
-                /// RPC method for procedure #method_name.
-                pub async fn #method_ident(&self, args: #args_type) -> Result<#ret_type, RpcError> {
-                    let payload = libvirt_xdr::to_bytes(&args)
-                        .map_err(|e| RpcError::Encode(e.to_string()))?;
-                    let response = self.inner.rpc_call_program(#program_const as u32, #proc_number, payload).await?;
-                    libvirt_xdr::from_bytes(&response)
-                        .map_err(|e| RpcError::Decode(e.to_string()))
-                }
+/// `int` arguments libvirt uses as booleans, e.g. the `autostart` of
+/// `remote_domain_set_autostart_args`.
+const BOOLEAN_ARGS: &[&str] = &["autostart", "cancelled"];
+
+/// Whether `field` is an `int` argument libvirt uses as a boolean, which
+/// the flat methods take as `bool`.
+fn is_boolean_arg(field: &Field) -> bool {
+    matches!(field.ty, Type::Int) && BOOLEAN_ARGS.contains(&field.name.as_str())
+}
+
+/// Whether the procedure `method_name` returns a boolean as its `int`
+/// `field`, which the flat method then returns as `bool`.
+///
+/// These are the predicates, like `domain_is_active`,
+/// `domain_has_current_snapshot` and `connect_supports_feature`, and the
+/// getters of boolean arguments, like `domain_get_autostart`. The field
+/// name says nothing: `domain_snapshot_is_current` returns `current` and
+/// `domain_has_current_snapshot` returns `result`.
+fn returns_boolean(method_name: &str, field: &Field) -> bool {
+    let predicate = ["_is_", "_has_", "_supports_"].iter().any(|verb| method_name.contains(verb));
+    let getter = BOOLEAN_ARGS.iter().any(|arg| method_name.contains(&format!("_get_{}", arg)));
+    matches!(field.ty, Type::Int) && (predicate || getter)
+}
+
+/// Parameter type for a field of type `ty`, and the field initializer
+/// building the arguments struct from the parameter `name`.
+fn flat_param(name: &proc_macro2::Ident, ty: &Type, types: &TypeIndex<'_>) -> (TokenStream, TokenStream) {
+    let owned = type_to_tokens(ty);
+    match resolve_typedefs(ty, types) {
+        Type::String { .. } => (quote! { &str }, quote! { #name: #name.to_string() }),
+        Type::Optional(inner) => match resolve_typedefs(inner, types) {
+            Type::String { .. } => (quote! { Option<&str> }, quote! { #name: #name.map(str::to_string) }),
+            _ if is_copy(inner, types) => (owned, quote! { #name }),
+            _ => {
+                let inner = type_to_tokens(inner);
+                (quote! { Option<&#inner> }, quote! { #name: #name.cloned() })
             }
+        },
+        Type::Array { elem, len: LengthSpec::Variable { .. } } => {
+            let elem = type_to_tokens(elem);
+            (quote! { &[#elem] }, quote! { #name: #name.to_vec() })
+        }
+        Type::Opaque { len: LengthSpec::Variable { .. } } => {
+            (quote! { &[u8] }, quote! { #name: #name.to_vec() })
+        }
+        _ if is_copy(ty, types) => (owned, quote! { #name }),
+        _ => (quote! { &#owned }, quote! { #name: #name.clone() }),
+    }
+}
+
+/// `ty`, looking through typedefs.
+fn resolve_typedefs<'a>(ty: &'a Type, types: &TypeIndex<'a>) -> &'a Type {
+    match ty {
+        Type::Named(name) => match types.get(name.as_str()) {
+            Some(TypeDef::Typedef(t)) => resolve_typedefs(&t.target, types),
+            _ => ty,
+        },
+        _ => ty,
+    }
+}
+
+/// Whether the Rust type generated for `ty` is `Copy`.
+fn is_copy(ty: &Type, types: &TypeIndex<'_>) -> bool {
+    match ty {
+        Type::Void
+        | Type::Int
+        | Type::UInt
+        | Type::Hyper
+        | Type::UHyper
+        | Type::Float
+        | Type::Double
+        | Type::Quadruple
+        | Type::Bool
+        | Type::Flags(_)
+        | Type::Opaque { len: LengthSpec::Fixed(_) } => true,
+        Type::Array { elem, len: LengthSpec::Fixed(_) } | Type::Optional(elem) => is_copy(elem, types),
+        Type::Named(name) => match types.get(name.as_str()) {
+            Some(TypeDef::Enum(_)) => true,
+            Some(TypeDef::Typedef(t)) => is_copy(&t.target, types),
+            Some(_) => false,
+            // Primitives named by their Rust type, e.g. `char` as `i8`
+            None => name != "String",
+        },
+        _ => false,
+    }
+}
+
+/// How a generated method addresses its procedure.
+enum CallTarget {
+    /// A variant of the primary protocol's procedure enum, sent to the
+    /// transport's default program.
+    Primary {
+        procedure_enum: proc_macro2::Ident,
+        variant: proc_macro2::Ident,
+        program: proc_macro2::Ident,
+    },
+    /// A procedure number of another program, such as `QEMU_PROGRAM` or
+    /// one declared in a `program` block.
+    Program(proc_macro2::Ident, u32),
+}
+
+/// Method name of a procedure: `REMOTE_PROC_CONNECT_LIST_DOMAINS` becomes
+/// `connect_list_domains`.
+fn method_name(proc: &Procedure, config: &ProtocolConfig) -> String {
+    proc.name
+        .strip_prefix(&config.proc_prefix)
+        .unwrap_or(&proc.name)
+        .to_lowercase()
+}
+
+/// Generate a single RPC method for a procedure.
+fn generate_client_method(proc: &Procedure, protocol: &Protocol) -> TokenStream {
+    let config = &protocol.config;
+    // Procedures of a `program` block have no enum to name them by
+    let from_program = !protocol.programs.is_empty()
+        && !protocol
+            .types
+            .iter()
+            .any(|t| matches!(t, TypeDef::Enum(e) if e.name == config.procedure_enum));
+    let target = if !from_program {
+        CallTarget::Primary {
+            procedure_enum: format_ident!("{}", to_rust_type_name(&config.procedure_enum)),
+            variant: format_ident!("{}", to_rust_variant_name(&proc.name, &config.procedure_enum, &config.type_prefix)),
+            program: format_ident!("{}", config.program_const),
         }
-        (Some(args_name), None) => {
+    } else {
+        CallTarget::Program(format_ident!("{}", config.program_const), proc.number)
+    };
+    generate_method(proc, &method_name(proc, config), target)
+}
+
+/// Generate a single RPC method for a secondary protocol (QEMU/LXC).
+fn generate_secondary_client_method(proc: &Procedure, protocol: &Protocol) -> TokenStream {
+    // Secondary procedures are addressed by program and number
+    let program = format_ident!("{}", protocol.config.program_const);
+    generate_method(
+        proc,
+        &method_name(proc, &protocol.config),
+        CallTarget::Program(program, proc.number),
+    )
+}
+
+/// Build the method body shared by all clients.
+///
+/// Procedures annotated with `@readstream`/`@writestream` go through
+/// `rpc_call_stream` and additionally return the [`StreamHandle`] for
+/// the data stream the call opened.
+fn generate_method(proc: &Procedure, method_name: &str, target: CallTarget) -> TokenStream {
+    let method_ident = format_ident!("{}", method_name);
+    let docs = procedure_docs(proc);
+
+    let (param, payload) = match &proc.args {
+        Some(args_name) => {
             let args_type = format_ident!("{}", to_rust_type_name(args_name));
+            (
+                quote! { , args: #args_type },
+                quote! {
+                    libvirt_xdr::to_bytes(&args)
+                        .map_err(|e| RpcError::Encode(e.to_string()))?
+                },
+            )
+        }
+        None => (quote! {}, quote! { Vec::new() }),
+    };
 
-            quote! {
-                /// RPC method for procedure #method_name.
-                pub async fn #method_ident(&self, args: #args_type) -> Result<(), RpcError> {
-                    let payload = libvirt_xdr::to_bytes(&args)
-                        .map_err(|e| RpcError::Encode(e.to_string()))?;
-                    let _ = self.inner.rpc_call_program(#program_const as u32, #proc_number, payload).await?;
-                    Ok(())
-                }
-            }
+    let call = match (&target, proc.stream.is_some()) {
+        (CallTarget::Primary { procedure_enum, variant, .. }, false) => {
+            quote! { self.inner.rpc_call(#procedure_enum::#variant as u32, payload) }
+        }
+        (CallTarget::Primary { procedure_enum, variant, program }, true) => {
+            quote! { self.inner.rpc_call_stream(#program as u32, #procedure_enum::#variant as u32, payload) }
+        }
+        (CallTarget::Program(program, number), false) => {
+            quote! { self.inner.rpc_call_program(#program as u32, #number, payload) }
+        }
+        (CallTarget::Program(program, number), true) => {
+            quote! { self.inner.rpc_call_stream(#program as u32, #number, payload) }
+        }
+    };
+
+    let decode_stream_ret = |ret_type: &proc_macro2::Ident| {
+        quote! {
+            libvirt_xdr::from_bytes_with::<#ret_type>(&response, &self.decode_options)
+                .map_err(|e| RpcError::Decode(e.to_string()))?
+        }
+    };
+
+    let (ret, body) = match (&proc.ret, proc.stream.is_some()) {
+        (Some(ret_name), false) => {
+            let ret_type = format_ident!("{}", to_rust_type_name(ret_name));
+            (quote! { #ret_type }, quote! {
+                let response = #call.await?;
+                libvirt_xdr::from_bytes_with(&response, &self.decode_options)
+                    .map_err(|e| RpcError::Decode(e.to_string()))
+            })
         }
-        (None, Some(ret_name)) => {
+        (None, false) => (quote! { () }, quote! {
+            let _ = #call.await?;
+            Ok(())
+        }),
+        (Some(ret_name), true) => {
             let ret_type = format_ident!("{}", to_rust_type_name(ret_name));
+            let decode = decode_stream_ret(&ret_type);
+            (quote! { (#ret_type, StreamHandle) }, quote! {
+                let (response, stream) = #call.await?;
+                Ok((#decode, stream))
+            })
+        }
+        (None, true) => (quote! { StreamHandle }, quote! {
+            let (_, stream) = #call.await?;
+            Ok(stream)
+        }),
+    };
 
-            quote! {
-                /// RPC method for procedure #method_name.
-                pub async fn #method_ident(&self) -> Result<#ret_type, RpcError> {
-                    let response = self.inner.rpc_call_program(#program_const as u32, #proc_number, Vec::new()).await?;
-                    libvirt_xdr::from_bytes(&response)
-                        .map_err(|e| RpcError::Decode(e.to_string()))
-                }
-            }
+    quote! {
+        #(#[doc = #docs])*
+        pub async fn #method_ident(&self #param) -> Result<#ret, RpcError> {
+            let payload = #payload;
+            #body
         }
-        (None, None) => {
-            quote! {
-                /// RPC method for procedure #method_name.
-                pub async fn #method_ident(&self) -> Result<(), RpcError> {
-                    let _ = self.inner.rpc_call_program(#program_const as u32, #proc_number, Vec::new()).await?;
-                    Ok(())
+    }
+}
+
+/// Doc lines for a generated method: the procedure's comment, followed by
+/// its number, argument and return types, and annotations.
+fn procedure_docs(proc: &Procedure) -> Vec<String> {
+    let mut docs = vec![format!(" Calls `{}` (procedure {}).", proc.name, proc.number)];
+    if let Some(doc) = &proc.doc {
+        docs.push(String::new());
+        docs.extend(doc_lines(doc));
+    }
+    docs.push(String::new());
+
+    let type_link = |name: &str| format!("[`{}`]", to_rust_type_name(name));
+    if let Some(args) = &proc.args {
+        docs.push(format!(" - Arguments: {}", type_link(args)));
+    }
+    if let Some(ret) = &proc.ret {
+        docs.push(format!(" - Returns: {}", type_link(ret)));
+    }
+    if proc.acl.is_empty() {
+        docs.push(" - Required ACL: none".to_string());
+    } else {
+        let acl: Vec<_> = proc.acl.iter().map(|a| format!("`{}`", a)).collect();
+        docs.push(format!(" - Required ACL: {}", acl.join(", ")));
+    }
+    if !proc.acl_filter.is_empty() {
+        let filter: Vec<_> = proc.acl_filter.iter().map(|a| format!("`{}`", a)).collect();
+        docs.push(format!(" - Results filtered by ACL: {}", filter.join(", ")));
+    }
+    if proc.priority == Priority::High {
+        docs.push(
+            " - High priority: libvirtd may run the call on its priority worker pool; \
+             this crate treats it like any other"
+                .to_string(),
+        );
+    }
+    if let Some(stream) = &proc.stream {
+        let direction = match stream.direction {
+            StreamDirection::Read => "the daemon sends data to the client",
+            StreamDirection::Write => "the client sends data to the daemon",
+        };
+        docs.push(format!(" - Opens a data stream where {}", direction));
+    }
+    docs
+}
+
+/// Generate procedure metadata accessors for the protocol's procedure enum,
+/// if it has one.
+fn generate_procedure_enum_info(protocol: &Protocol) -> TokenStream {
+    protocol
+        .types
+        .iter()
+        .find_map(|t| match t {
+            TypeDef::Enum(e) if e.name == protocol.config.procedure_enum => Some(&e.name),
+            _ => None,
+        })
+        .map(|name| generate_procedure_info(&protocol.procedures, name, &protocol.config.type_prefix))
+        .unwrap_or_default()
+}
+
+/// Generate the `acl` accessor of a procedure enum. Priorities are in the
+/// procedure tables, for code handling raw packets.
+fn generate_procedure_info(procedures: &[Procedure], enum_name: &str, type_prefix: &str) -> TokenStream {
+    let enum_ident = format_ident!("{}", to_rust_type_name(enum_name));
+    let variant = |p: &Procedure| format_ident!("{}", to_rust_variant_name(&p.name, enum_name, type_prefix));
+
+    let acl_arms: Vec<_> = procedures
+        .iter()
+        .filter(|p| !p.acl.is_empty())
+        .map(|p| {
+            let v = variant(p);
+            let acl = &p.acl;
+            quote! { Self::#v => &[#(#acl),*], }
+        })
+        .collect();
+
+    quote! {
+        impl #enum_ident {
+            /// Access control rules the daemon checks before running this
+            /// procedure (`@acl`), as `object:permission[:flag]`.
+            pub fn acl(self) -> &'static [&'static str] {
+                match self {
+                    #(#acl_arms)*
+                    #[allow(unreachable_patterns)]
+                    _ => &[],
                 }
             }
         }
@@ -649,12 +1895,33 @@ fn generate_secondary_client_method(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parser::{parse_protocol, parse_protocol_with};
 
     #[test]
     fn test_to_rust_type_name() {
-        assert_eq!(to_rust_type_name("remote_domain"), "Domain");
-        assert_eq!(to_rust_type_name("remote_nonnull_domain"), "NonnullDomain");
+        assert_eq!(to_rust_type_name("domain"), "Domain");
+        assert_eq!(to_rust_type_name("nonnull_domain"), "NonnullDomain");
         assert_eq!(to_rust_type_name("foo_bar"), "FooBar");
+        assert_eq!(to_rust_type_name("string"), "RemoteString");
+    }
+
+    #[test]
+    fn test_strip_type_prefix() {
+        let mut protocol = Protocol::new("remote");
+        protocol.types.push(TypeDef::Typedef(TypedefDef {
+            name: "remote_domain_list".to_string(),
+            target: Type::Array {
+                elem: Box::new(Type::Named("remote_nonnull_domain".to_string())),
+                len: LengthSpec::Variable { max: None },
+            },
+            doc: None,
+        }));
+
+        let stripped = strip_type_prefix(&protocol, "remote_");
+        let TypeDef::Typedef(t) = &stripped.types[0] else { unreachable!() };
+        assert_eq!(t.name, "domain_list");
+        assert_eq!(type_to_tokens(&t.target).to_string(), "Vec < NonnullDomain >");
+        assert_eq!(stripped.config.procedure_enum, "procedure");
     }
 
     #[test]
@@ -666,17 +1933,20 @@ mod tests {
     #[test]
     fn test_generate_struct() {
         let s = StructDef {
-            name: "remote_domain".to_string(),
+            name: "domain".to_string(),
             fields: vec![
                 Field {
                     name: "name".to_string(),
                     ty: Type::String { max_len: None },
+                    doc: None,
                 },
                 Field {
                     name: "id".to_string(),
                     ty: Type::Int,
+                    doc: None,
                 },
             ],
+            doc: None,
         };
 
         let code = generate_struct(&s).to_string();
@@ -688,22 +1958,224 @@ mod tests {
     #[test]
     fn test_generate_enum() {
         let e = EnumDef {
-            name: "remote_domain_state".to_string(),
+            name: "domain_state".to_string(),
             variants: vec![
                 EnumVariant {
                     name: "VIR_DOMAIN_NOSTATE".to_string(),
                     value: Some(ConstValue::Int(0)),
+                    doc: None,
                 },
                 EnumVariant {
                     name: "VIR_DOMAIN_RUNNING".to_string(),
                     value: Some(ConstValue::Int(1)),
+                    doc: None,
                 },
             ],
+            doc: None,
         };
 
-        let code = generate_enum(&e).to_string();
+        let code = generate_enum(&e, "remote_").to_string();
         assert!(code.contains("enum DomainState"));
         assert!(code.contains("DomainNostate"));
         assert!(code.contains("DomainRunning"));
     }
+
+    #[test]
+    fn test_generate_union() {
+        let u = UnionDef {
+            name: "typed_param_value".to_string(),
+            discriminant: Field {
+                name: "type".to_string(),
+                ty: Type::Int,
+                doc: None,
+            },
+            cases: vec![
+                UnionCase {
+                    values: vec![ConstValue::Ident("VIR_TYPED_PARAM_INT".to_string())],
+                    field: Some(Field {
+                        name: "i".to_string(),
+                        ty: Type::Int,
+                        doc: None,
+                    }),
+                },
+                UnionCase {
+                    values: vec![ConstValue::Int(9)],
+                    field: None,
+                },
+            ],
+            default: None,
+            doc: None,
+        };
+
+        let code = generate_union(&u, "remote_").to_string();
+        assert!(code.contains("enum TypedParamValue"));
+        assert!(code.contains("xdr (discriminant = VIR_TYPED_PARAM_INT)"));
+        assert!(code.contains("TypedParamInt (i32)"));
+        assert!(code.contains("xdr (discriminant = 9i32)"));
+    }
+
+    #[test]
+    fn test_generate_configured_prefix() {
+        let input = r#"
+            const ACME_PROGRAM = 0x41434d45;
+            const ACME_PROTOCOL_VERSION = 1;
+            enum acme_state {
+                ACME_STATE_UP = 1,
+                ACME_STATE_DOWN = 2
+            };
+            struct acme_ping_args {
+                acme_state state;
+            };
+            enum acme_procedure {
+                ACME_PROC_PING = 1
+            };
+        "#;
+        let protocol = parse_protocol_with(input, "acme", ProtocolConfig::for_name("acme")).unwrap();
+        let code = generate(&protocol);
+        assert!(code.contains("pub enum State {"), "{}", code);
+        assert!(code.contains("    Up = 1i32,"), "{}", code);
+        assert!(code.contains("pub enum Procedure {"), "{}", code);
+        assert!(code.contains("    ProcPing = 1i32,"), "{}", code);
+        assert!(!code.contains("AcmeProc") && !code.contains("AcmeState"), "{}", code);
+        assert!(code.contains("/// Calls are to the program `ACME_PROGRAM`."), "{}", code);
+    }
+
+    #[test]
+    fn test_generate_method_annotations() {
+        let proc = Procedure {
+            name: "REMOTE_PROC_DOMAIN_SCREENSHOT".to_string(),
+            number: 176,
+            args: Some("domain_screenshot_args".to_string()),
+            ret: Some("domain_screenshot_ret".to_string()),
+            priority: Priority::High,
+            acl: vec!["domain:screenshot".to_string()],
+            stream: Some(StreamSpec {
+                direction: StreamDirection::Read,
+                param: 1,
+            }),
+            ..Procedure::default()
+        };
+
+        let protocol = strip_type_prefix(&Protocol::new("remote"), "remote_");
+        let code = generate_client_method(&proc, &protocol).to_string();
+        assert!(code.contains("Procedure :: ProcDomainScreenshot"));
+        assert!(code.contains("Required ACL: `domain:screenshot`"));
+        assert!(code.contains("High priority: libvirtd may run the call on its priority worker pool"));
+        assert!(code.contains("rpc_call_stream"));
+        assert!(code.contains("Result < (DomainScreenshotRet , StreamHandle) , RpcError >"));
+
+        let info = generate_procedure_info(&[proc], "remote_procedure", "remote_").to_string();
+        assert!(info.contains("Self :: ProcDomainScreenshot => & [\"domain:screenshot\"]"));
+    }
+
+    #[test]
+    fn test_generate_flat_methods() {
+        let protocol = parse_protocol(
+            r#"
+            const REMOTE_PROGRAM = 0x20008086;
+            typedef string remote_nonnull_string<64>;
+            typedef remote_nonnull_string *remote_string;
+            struct remote_nonnull_domain { remote_nonnull_string name; int id; };
+            struct remote_domain_set_autostart_args { remote_nonnull_domain dom; int autostart; };
+            struct remote_connect_get_hostname_ret { remote_nonnull_string hostname; };
+            struct remote_connect_open_args { remote_string name; unsigned int flags; };
+            struct remote_domain_get_info_args { remote_nonnull_domain dom; };
+            struct remote_domain_get_info_ret { unsigned char state; unsigned hyper memory; };
+            struct remote_domain_get_autostart_args { remote_nonnull_domain dom; };
+            struct remote_domain_get_autostart_ret { int autostart; };
+            struct remote_domain_reset_ret { };
+            struct remote_domain_has_current_snapshot_args { remote_nonnull_domain dom; unsigned int flags; };
+            struct remote_domain_has_current_snapshot_ret { int result; };
+            enum remote_procedure {
+                REMOTE_PROC_CONNECT_OPEN = 1,
+                REMOTE_PROC_DOMAIN_GET_INFO = 16,
+                REMOTE_PROC_CONNECT_GET_HOSTNAME = 59,
+                REMOTE_PROC_DOMAIN_GET_AUTOSTART = 68,
+                REMOTE_PROC_DOMAIN_SET_AUTOSTART = 69,
+                REMOTE_PROC_DOMAIN_HAS_CURRENT_SNAPSHOT = 192,
+                REMOTE_PROC_DOMAIN_RESET = 245
+            };
+            "#,
+        )
+        .unwrap();
+        let code = generate(&protocol);
+
+        assert!(code.contains("pub fn api(&self) -> GeneratedApi<'_, T>"));
+        // Structs by reference, `Copy` types by value
+        assert!(code.contains(
+            "pub async fn domain_set_autostart(\n        &self,\n        dom: &NonnullDomain,\n        autostart: bool,\n    ) -> Result<(), RpcError>"
+        ));
+        assert!(code.contains("dom: dom.clone(),\n                autostart: i32::from(autostart),\n"));
+        assert!(code.contains(".map(|ret| ret.autostart != 0)"));
+        // Predicates return `bool` whatever their field is called
+        assert!(code.contains(".map(|ret| ret.result != 0)"));
+        assert!(code.contains("pub async fn domain_reset(&self) -> Result<(), RpcError>"));
+        // Strings through typedefs
+        assert!(code.contains("name: Option<&str>,"));
+        assert!(code.contains("name: name.map(str::to_string),"));
+        // Single-field return structs are unwrapped, others are not
+        assert!(code.contains("pub async fn connect_get_hostname(&self) -> Result<NonnullString, RpcError>"));
+        assert!(code.contains(".map(|ret| ret.hostname)"));
+        assert!(code.contains(") -> Result<DomainGetInfoRet, RpcError>"));
+    }
+
+    #[test]
+    fn test_generate_payload_decoders() {
+        let protocol = parse_protocol(
+            r#"
+            const REMOTE_PROGRAM = 0x20008086;
+            struct remote_domain_event_lifecycle_msg { int event; int detail; };
+            struct remote_connect_get_hostname_ret { string hostname<64>; };
+            enum remote_procedure {
+                REMOTE_PROC_CONNECT_GET_HOSTNAME = 59,
+                REMOTE_PROC_DOMAIN_EVENT_LIFECYCLE = 107
+            };
+            "#,
+        )
+        .unwrap();
+        // Ignore how the arms are wrapped
+        let code: String = generate(&protocol).split_whitespace().collect();
+
+        assert!(code.contains("(536903814u32,59u32,true)=>{decode::<ConnectGetHostnameRet,V,>("));
+        // Events are sent as calls of their `*_msg`
+        assert!(code.contains("(536903814u32,107u32,false)=>{decode::<DomainEventLifecycleMsg,V,>("));
+    }
+
+    #[test]
+    fn test_generate_program_tables() {
+        let mut protocol = Protocol::new("remote");
+        protocol.program_id = Some(0x20008086);
+        protocol.procedures = vec![
+            Procedure {
+                name: "REMOTE_PROC_DOMAIN_SCREENSHOT".to_string(),
+                number: 176,
+                ret: Some("DomainScreenshotRet".to_string()),
+                stream: Some(StreamSpec {
+                    direction: StreamDirection::Read,
+                    param: 1,
+                }),
+                ..Procedure::default()
+            },
+            Procedure {
+                name: "REMOTE_PROC_CONNECT_OPEN".to_string(),
+                number: 1,
+                args: Some("ConnectOpenArgs".to_string()),
+                priority: Priority::High,
+                ..Procedure::default()
+            },
+        ];
+
+        let code = generate_program_tables(&[&protocol]).to_string();
+        assert!(code.contains("pub static REMOTE_PROCEDURES : [ProcedureInfo ; 2usize]"));
+        // Sorted by number, for binary search
+        let open = code.find("\"REMOTE_PROC_CONNECT_OPEN\"").unwrap();
+        let screenshot = code.find("\"REMOTE_PROC_DOMAIN_SCREENSHOT\"").unwrap();
+        assert!(open < screenshot);
+        assert!(code.contains(
+            "method : \"connect_open\" , number : 1u32 , args : Some (\"ConnectOpenArgs\") , ret : None , \
+             high_priority : true , stream : None"
+        ));
+        assert!(code.contains("stream : Some (StreamDirection :: Read)"));
+        assert!(code.contains("number : 536903814u32 , version : 1u32 , procedures : & REMOTE_PROCEDURES"));
+    }
 }
@@ -3,10 +3,19 @@
 //! This crate parses XDR protocol definition files and generates Rust code
 //! for types and RPC methods.
 
+pub mod api;
 pub mod ast;
+pub mod config;
+pub mod constants;
+pub mod diagnostic;
 pub mod generator;
 pub mod parser;
+pub mod roundtrip;
 
+pub use api::Api;
 pub use ast::{Protocol, ProtocolBundle};
+pub use config::ProtocolConfig;
+pub use diagnostic::{Diagnostic, Severity};
 pub use generator::{generate, generate_bundle};
-pub use parser::{parse_file, parse_protocol};
+pub use parser::{parse_file, parse_file_with, parse_protocol, parse_protocol_with};
+pub use roundtrip::generate_roundtrip_tests;
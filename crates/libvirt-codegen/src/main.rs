@@ -0,0 +1,351 @@
+//! Command-line front end: generate Rust code from any XDR `.x` file.
+//!
+//! ```text
+//! libvirt-codegen proto/my_protocol.x --type-prefix my_ -o src/my_protocol.rs
+//! libvirt-codegen --config codegen.conf
+//! ```
+
+use libvirt_codegen::{parse_file, parse_file_with, ProtocolConfig};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: libvirt-codegen [OPTIONS] [INPUT]
+
+Generate Rust types and an async RPC client from an XDR protocol
+definition (.x file) that follows libvirt's conventions.
+
+Options:
+  -o, --output <FILE>          Write the code to FILE instead of stdout
+  -c, --config <FILE>          Read options from FILE, one `key = \"value\"` per
+                               line; keys are the long option names with `_`
+                               for `-`, plus `input`. Paths are relative to FILE.
+      --name <NAME>            Protocol name [default: from the *_PROGRAM constant]
+      --program-const <NAME>   Program number constant [default: <NAME>_PROGRAM]
+      --version-const <NAME>   Protocol version constant [default: <NAME>_PROTOCOL_VERSION]
+      --procedure-enum <NAME>  Procedure enum [default: <name>_procedure]
+      --proc-prefix <PREFIX>   Prefix of procedure enum variants [default: <NAME>_PROC_]
+      --type-prefix <PREFIX>   Prefix stripped from type names [default: <name>_]
+      --module <NAME>          Wrap the code in `pub mod NAME { ... }`
+      --summary                Print what was parsed instead of generating code
+  -h, --help                   Print this help
+
+Command-line options override the config file.";
+
+/// Options from the command line and config file.
+#[derive(Debug, Default)]
+struct Options {
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    name: Option<String>,
+    program_const: Option<String>,
+    version_const: Option<String>,
+    procedure_enum: Option<String>,
+    proc_prefix: Option<String>,
+    type_prefix: Option<String>,
+    module: Option<String>,
+    summary: bool,
+    help: bool,
+}
+
+impl Options {
+    /// Set a string option by its config key. Returns false for unknown keys.
+    fn set(&mut self, key: &str, value: String) -> bool {
+        let slot = match key {
+            "input" => {
+                self.input = Some(value.into());
+                return true;
+            }
+            "output" => {
+                self.output = Some(value.into());
+                return true;
+            }
+            "name" => &mut self.name,
+            "program_const" => &mut self.program_const,
+            "version_const" => &mut self.version_const,
+            "procedure_enum" => &mut self.procedure_enum,
+            "proc_prefix" => &mut self.proc_prefix,
+            "type_prefix" => &mut self.type_prefix,
+            "module" => &mut self.module,
+            _ => return false,
+        };
+        *slot = Some(value);
+        true
+    }
+
+    /// Fill options not given on the command line from `other`.
+    fn or(self, other: Options) -> Options {
+        Options {
+            input: self.input.or(other.input),
+            output: self.output.or(other.output),
+            name: self.name.or(other.name),
+            program_const: self.program_const.or(other.program_const),
+            version_const: self.version_const.or(other.version_const),
+            procedure_enum: self.procedure_enum.or(other.procedure_enum),
+            proc_prefix: self.proc_prefix.or(other.proc_prefix),
+            type_prefix: self.type_prefix.or(other.type_prefix),
+            module: self.module.or(other.module),
+            summary: self.summary || other.summary,
+            help: self.help,
+        }
+    }
+
+    /// Whether any naming option was given, so the protocol must not be
+    /// parsed with the detected defaults.
+    fn has_naming(&self) -> bool {
+        self.name.is_some()
+            || self.program_const.is_some()
+            || self.version_const.is_some()
+            || self.procedure_enum.is_some()
+            || self.proc_prefix.is_some()
+            || self.type_prefix.is_some()
+    }
+}
+
+/// Parsed command line: the options, plus the config file to merge in.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(Options, Option<PathBuf>), String> {
+    let mut options = Options::default();
+    let mut config = None;
+
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |flag: &str| {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", flag))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => options.help = true,
+            "--summary" => options.summary = true,
+            "-c" | "--config" => config = Some(PathBuf::from(value(&flag)?)),
+            "-o" => options.output = Some(value(&flag)?.into()),
+            long if long.starts_with("--") => {
+                let key = long[2..].replace('-', "_");
+                let value = value(&flag)?;
+                if !options.set(&key, value) {
+                    return Err(format!("unknown option {}\n\n{}", flag, USAGE));
+                }
+            }
+            _ if flag.starts_with('-') => {
+                return Err(format!("unknown option {}\n\n{}", flag, USAGE));
+            }
+            _ if options.input.is_none() => options.input = Some(arg.into()),
+            _ => return Err(format!("unexpected argument {}\n\n{}", arg, USAGE)),
+        }
+    }
+
+    Ok((options, config))
+}
+
+/// Read a config file of `key = "value"` lines. `#` starts a comment.
+fn read_config(path: &Path) -> Result<Options, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut options = Options::default();
+
+    for (i, line) in text.lines().enumerate() {
+        let err = |msg: &str| format!("{}:{}: {}", path.display(), i + 1, msg);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, rest) = line.split_once('=').ok_or_else(|| err("expected `key = \"value\"`"))?;
+        let key = key.trim();
+        let rest = rest.trim();
+        let value = rest
+            .strip_prefix('"')
+            .and_then(|r| r.split_once('"'))
+            .filter(|(_, tail)| tail.trim().is_empty() || tail.trim().starts_with('#'))
+            .map(|(value, _)| value.to_string())
+            .ok_or_else(|| err("value must be a double-quoted string"))?;
+
+        let value = match key {
+            "input" | "output" => base.join(value).to_string_lossy().into_owned(),
+            _ => value,
+        };
+        if !options.set(key, value) {
+            return Err(err(&format!("unknown key `{}`", key)));
+        }
+    }
+
+    Ok(options)
+}
+
+fn run() -> Result<(), String> {
+    let (options, config) = parse_args(std::env::args().skip(1))?;
+    if options.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let options = match config {
+        Some(path) => options.or(read_config(&path)?),
+        None => options,
+    };
+
+    let input = options.input.as_ref().ok_or_else(|| format!("no input file\n\n{}", USAGE))?;
+
+    let protocol = if options.has_naming() {
+        let name = match &options.name {
+            Some(name) => name.clone(),
+            None => input
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .map(|s| s.strip_suffix("_protocol").unwrap_or(s).to_string())
+                .ok_or_else(|| "--name is required for this input path".to_string())?,
+        };
+        let defaults = ProtocolConfig::for_name(&name);
+        let config = ProtocolConfig {
+            program_const: options.program_const.clone().unwrap_or(defaults.program_const),
+            version_const: options.version_const.clone().unwrap_or(defaults.version_const),
+            procedure_enum: options.procedure_enum.clone().unwrap_or(defaults.procedure_enum),
+            proc_prefix: options.proc_prefix.clone().unwrap_or(defaults.proc_prefix),
+            type_prefix: options.type_prefix.clone().unwrap_or(defaults.type_prefix),
+        };
+        parse_file_with(input, &name, config)
+    } else {
+        parse_file(input)
+    }
+    .map_err(|e| e.to_string())?;
+
+    for warning in &protocol.warnings {
+        eprintln!("{}\n", warning);
+    }
+
+    let output = if options.summary {
+        summary(&protocol)
+    } else {
+        let code = libvirt_codegen::generate(&protocol);
+        match &options.module {
+            Some(module) => wrap_in_module(&code, module)?,
+            None => code,
+        }
+    };
+
+    match &options.output {
+        Some(path) => std::fs::write(path, output)
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e)),
+        None => {
+            print!("{}", output);
+            Ok(())
+        }
+    }
+}
+
+/// Human-readable overview of a parsed protocol.
+fn summary(protocol: &libvirt_codegen::Protocol) -> String {
+    let mut out = format!(
+        "protocol: {}\nprogram: {:?}\nversion: {:?}\nconstants: {}\ntypes: {}\nprocedures: {}\n",
+        protocol.name,
+        protocol.program_id,
+        protocol.protocol_version,
+        protocol.constants.len(),
+        protocol.types.len(),
+        protocol.procedures.len(),
+    );
+    for p in &protocol.procedures {
+        out.push_str(&format!(
+            "  {} = {} (args: {}, ret: {})\n",
+            p.name,
+            p.number,
+            p.args.as_deref().unwrap_or("-"),
+            p.ret.as_deref().unwrap_or("-"),
+        ));
+    }
+    out
+}
+
+fn wrap_in_module(code: &str, module: &str) -> Result<String, String> {
+    let file = syn::parse_file(&format!("pub mod {} {{ {} }}", module, code))
+        .map_err(|e| format!("invalid module name {}: {}", module, e))?;
+    Ok(prettyplease::unparse(&file))
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<(Options, Option<PathBuf>), String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let (options, config) =
+            parse(&["acme.x", "--type-prefix", "acme_", "--proc-prefix=ACME_CALL_", "-o", "acme.rs"]).unwrap();
+        assert_eq!(options.input, Some(PathBuf::from("acme.x")));
+        assert_eq!(options.output, Some(PathBuf::from("acme.rs")));
+        assert_eq!(options.type_prefix.as_deref(), Some("acme_"));
+        assert_eq!(options.proc_prefix.as_deref(), Some("ACME_CALL_"));
+        assert!(options.has_naming() && !options.summary && !options.help);
+        assert!(config.is_none());
+
+        let (options, config) = parse(&["-c", "acme.conf", "--summary"]).unwrap();
+        assert_eq!(config, Some(PathBuf::from("acme.conf")));
+        assert!(options.summary && !options.has_naming() && options.input.is_none());
+
+        assert!(parse(&["--help"]).unwrap().0.help);
+        assert!(parse(&["-h", "--no-such-flag"]).is_err());
+        assert_eq!(parse(&["--module"]).unwrap_err(), "--module needs a value");
+        assert!(parse(&["--verbose=1"]).unwrap_err().starts_with("unknown option --verbose"));
+        assert!(parse(&["-x"]).unwrap_err().starts_with("unknown option -x"));
+        assert!(parse(&["a.x", "b.x"]).unwrap_err().starts_with("unexpected argument b.x"));
+    }
+
+    #[test]
+    fn test_read_config() {
+        let dir = std::env::temp_dir().join(format!("libvirt-codegen-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("acme.conf");
+        let read = |text: &str| {
+            std::fs::write(&path, text).unwrap();
+            read_config(&path)
+        };
+
+        let options = read(
+            "# Acme's protocol\n\
+             input = \"proto/acme.x\"\n\
+             \n\
+             output = \"src/acme.rs\"  # generated\n\
+             type_prefix = \"acme_\"\n\
+             module = \"acme\"\n",
+        )
+        .unwrap();
+        assert_eq!(options.input, Some(dir.join("proto/acme.x")));
+        assert_eq!(options.output, Some(dir.join("src/acme.rs")));
+        assert_eq!(options.type_prefix.as_deref(), Some("acme_"));
+        assert_eq!(options.module.as_deref(), Some("acme"));
+
+        // The command line wins over the file
+        let (cli, _) = parse(&["--type-prefix", "other_", "--name", "acme"]).unwrap();
+        let merged = cli.or(options);
+        assert_eq!(merged.type_prefix.as_deref(), Some("other_"));
+        assert_eq!(merged.name.as_deref(), Some("acme"));
+        assert_eq!(merged.module.as_deref(), Some("acme"));
+
+        let error = read("input = proto/acme.x\n").unwrap_err();
+        assert!(error.ends_with(":1: value must be a double-quoted string"), "{}", error);
+        let error = read("\nsummary = \"yes\"\n").unwrap_err();
+        assert!(error.ends_with(":2: unknown key `summary`"), "{}", error);
+        let error = read("type_prefix\n").unwrap_err();
+        assert!(error.ends_with(":1: expected `key = \"value\"`"), "{}", error);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,52 +1,495 @@
 //! Parser for XDR protocol definition files (.x files).
 
 use crate::ast::*;
+use crate::config::ProtocolConfig;
+use crate::constants::{defined_constants, libvirt_constants, resolve_constants};
+use crate::diagnostic::{Diagnostic, Severity};
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_while, take_while1},
-    character::complete::{char, digit1, multispace0, multispace1},
-    combinator::{all_consuming, map, map_res, opt, recognize, value},
-    multi::{many0, separated_list0},
+    character::complete::{char, digit1, multispace0, multispace1, satisfy},
+    combinator::{cut, eof, map, map_res, not, opt, peek, recognize, value},
+    error::{context, ErrorKind, VerboseError, VerboseErrorKind},
+    multi::{many0, many1, separated_list0, separated_list1},
     sequence::{delimited, pair, preceded, terminated},
     IResult,
 };
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
+type PResult<'a, O> = IResult<&'a str, O, VerboseError<&'a str>>;
+
 /// Parse a protocol definition file.
-pub fn parse_file(path: impl AsRef<Path>) -> Result<Protocol, String> {
-    let content =
-        std::fs::read_to_string(path.as_ref()).map_err(|e| format!("failed to read file: {}", e))?;
-    parse_protocol(&content)
+///
+/// Errors and warnings name the file.
+pub fn parse_file(path: impl AsRef<Path>) -> Result<Protocol, Diagnostic> {
+    let path = path.as_ref();
+    in_file(parse_protocol(&read_file(path)?), path)
+}
+
+/// Parse a protocol definition file using the given naming configuration.
+pub fn parse_file_with(
+    path: impl AsRef<Path>,
+    name: &str,
+    config: ProtocolConfig,
+) -> Result<Protocol, Diagnostic> {
+    let path = path.as_ref();
+    in_file(parse_protocol_with(&read_file(path)?, name, config), path)
+}
+
+fn read_file(path: &Path) -> Result<String, Diagnostic> {
+    std::fs::read_to_string(path)
+        .map_err(|e| Diagnostic::error(format!("failed to read file: {}", e)).with_path(path))
+}
+
+/// Attach `path` to the error or the warnings of a parse.
+fn in_file(result: Result<Protocol, Diagnostic>, path: &Path) -> Result<Protocol, Diagnostic> {
+    let mut protocol = result.map_err(|e| e.with_path(path))?;
+    for warning in &mut protocol.warnings {
+        warning.path = Some(path.to_path_buf());
+    }
+    Ok(protocol)
 }
 
 /// Parse protocol definition from string.
-pub fn parse_protocol(input: &str) -> Result<Protocol, String> {
-    // Preprocess: remove comments
-    let input = remove_comments(input);
+///
+/// The protocol name is taken from its `<NAME>_PROGRAM` constant (`remote`
+/// if there is none), and libvirt's naming conventions are assumed.
+pub fn parse_protocol(input: &str) -> Result<Protocol, Diagnostic> {
+    parse(input, None)
+}
+
+/// Parse protocol definition from string, finding the program constant
+/// and procedures as described by `config`.
+pub fn parse_protocol_with(input: &str, name: &str, config: ProtocolConfig) -> Result<Protocol, Diagnostic> {
+    parse(input, Some((name, config)))
+}
+
+fn parse(input: &str, target: Option<(&str, ProtocolConfig)>) -> Result<Protocol, Diagnostic> {
+    // Docs and procedure annotations live in comments, so grab them first
+    let comments = collect_comments(input);
+
+    // Preprocess: blank out comments, keeping offsets valid in `input`
+    let stripped = remove_comments(input);
+
+    let items = definitions(input, &stripped)?;
+
+    // Where each definition starts, for pointing warnings at it
+    let mut offsets = HashMap::new();
+    let mut protocol = Protocol::new("remote");
+    for (offset, item) in items {
+        match item {
+            Definition::Const(c) => {
+                offsets.insert(c.name.clone(), offset);
+                protocol.constants.push(c);
+            }
+            Definition::Type(t) => {
+                offsets.insert(t.name().to_string(), offset);
+                protocol.types.push(t);
+            }
+            Definition::Program(p) => {
+                // As with rpcgen, program, version and procedure names are
+                // constants too
+                for c in program_constants(&p) {
+                    if !protocol.constants.iter().any(|k| k.name == c.name) {
+                        offsets.insert(c.name.clone(), offset);
+                        protocol.constants.push(c);
+                    }
+                }
+                protocol.programs.push(p);
+            }
+        }
+    }
+
+    match target {
+        Some((name, config)) => {
+            protocol.name = name.to_string();
+            protocol.config = config;
+        }
+        None => {
+            if let Some(name) = ProtocolConfig::detect_name(&protocol.constants) {
+                protocol.config = ProtocolConfig::for_name(&name);
+                protocol.name = name;
+            }
+        }
+    }
+
+    resolve_constants(&mut protocol, &libvirt_constants());
+    protocol.warnings = unresolved_constants(input, &stripped, &protocol, &offsets);
+    if let Some(i) = protocol.warnings.iter().position(|d| d.severity == Severity::Error) {
+        return Err(protocol.warnings.swap_remove(i));
+    }
+
+    // Extract program ID and protocol version from constants
+    extract_protocol_metadata(&mut protocol);
+
+    // Extract procedures from procedure enum
+    extract_procedures(&mut protocol);
+    for (name, message) in extract_program(&mut protocol) {
+        let offset = offsets.get(&name).copied().unwrap_or(0);
+        let at = find_word(&stripped, offset, &name).unwrap_or(offset);
+        protocol.warnings.push(Diagnostic::warning(message).at(input, at));
+    }
+
+    mark_recursive_optionals(&mut protocol);
+
+    attach_comments(&mut protocol, &comments);
+    Ok(protocol)
+}
+
+/// What a comment documents.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum CommentKey {
+    /// A constant, type or enum variant; these share one namespace.
+    Item(String),
+    /// A struct field or union arm: (type name, member name).
+    Member(String, String),
+}
+
+/// Token of the comment scanner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    Punct(char),
+    /// Comment body, whether the comment started its own line, and
+    /// whether it is a `/* */` block rather than a `//` line.
+    Comment(&'a str, bool, bool),
+    /// `%` passthrough or `#` preprocessor line.
+    Directive,
+    Newline,
+}
+
+/// Split a `.x` file into the tokens needed to attach comments.
+fn comment_tokens(input: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut line_has_token = false;
+    let mut rest = input;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '\n' {
+            tokens.push(Token::Newline);
+            line_has_token = false;
+            rest = &rest[1..];
+        } else if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if let Some(body) = rest.strip_prefix("/*") {
+            let end = body.find("*/").unwrap_or(body.len());
+            tokens.push(Token::Comment(&body[..end], !line_has_token, true));
+            // A comment spanning lines still ends on a non-blank line.
+            line_has_token = true;
+            rest = body.get(end + 2..).unwrap_or("");
+        } else if let Some(body) = rest.strip_prefix("//") {
+            let end = body.find('\n').unwrap_or(body.len());
+            tokens.push(Token::Comment(&body[..end], !line_has_token, false));
+            line_has_token = true;
+            rest = &body[end..];
+        } else if (c == '%' || c == '#') && !line_has_token {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            tokens.push(Token::Directive);
+            line_has_token = true;
+            rest = &rest[end..];
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Word(&rest[..end]));
+            line_has_token = true;
+            rest = &rest[end..];
+        } else {
+            tokens.push(Token::Punct(c));
+            line_has_token = true;
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    tokens
+}
+
+/// Collect the comments documenting each declaration.
+///
+/// A comment block on its own line(s) documents the declaration that
+/// follows it, unless a blank line separates them. Of several adjacent
+/// `/* */` blocks only the last one counts, since libvirt puts general
+/// notes right above the first annotated procedure. A comment after a
+/// declaration on the same line (e.g. `/* insert@1 */`) documents that
+/// declaration.
+fn collect_comments(input: &str) -> HashMap<CommentKey, String> {
+    let tokens = comment_tokens(input);
+    let mut comments: HashMap<CommentKey, String> = HashMap::new();
+
+    // Enclosing struct/union/enum bodies as (keyword, name).
+    let mut scopes: Vec<(&str, &str)> = Vec::new();
+    let mut opening: Option<(&str, &str)> = None;
+    let mut pending: Vec<&str> = Vec::new();
+    let mut current: Option<CommentKey> = None;
+    let mut last: Option<CommentKey> = None;
+    let mut at_statement = true;
+    let mut line_empty = true;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match *token {
+            Token::Newline => {
+                if line_empty {
+                    pending.clear();
+                }
+                line_empty = true;
+                last = None;
+            }
+            Token::Comment(text, own_line, block) => {
+                line_empty = false;
+                if own_line {
+                    if block {
+                        pending.clear();
+                    }
+                    pending.push(text);
+                } else if let Some(key) = &last {
+                    add_comment(&mut comments, key.clone(), text);
+                }
+            }
+            Token::Directive => {
+                line_empty = false;
+                pending.clear();
+                at_statement = true;
+            }
+            Token::Word(word) => {
+                line_empty = false;
+                if at_statement {
+                    current = declaration_key(&tokens[i..], scopes.last().copied());
+                    if let Some(key) = &current {
+                        for text in &pending {
+                            add_comment(&mut comments, key.clone(), text);
+                        }
+                    }
+                    pending.clear();
+                    at_statement = false;
+                }
+                if matches!(word, "struct" | "union" | "enum") {
+                    if let Some(Token::Word(name)) = tokens.get(i + 1) {
+                        opening = Some((word, name));
+                    }
+                }
+            }
+            Token::Punct(c) => {
+                line_empty = false;
+                pending.clear();
+                match c {
+                    '{' => {
+                        scopes.push(opening.take().unwrap_or(("", "")));
+                        last = current.take();
+                        at_statement = true;
+                    }
+                    '}' => {
+                        scopes.pop();
+                        current = None;
+                    }
+                    ';' => {
+                        last = current.take();
+                        opening = None;
+                        at_statement = true;
+                    }
+                    ',' if matches!(scopes.last(), Some(("enum", _))) => {
+                        last = current.take();
+                        at_statement = true;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    comments
+}
+
+/// Key of the declaration starting at `tokens[0]`, within `scope`.
+fn declaration_key(tokens: &[Token<'_>], scope: Option<(&str, &str)>) -> Option<CommentKey> {
+    let next_word = || match tokens.get(1) {
+        Some(Token::Word(name)) => Some(CommentKey::Item(name.to_string())),
+        _ => None,
+    };
+
+    match (scope, tokens.first()?) {
+        (None, Token::Word("const" | "struct" | "union" | "enum")) => next_word(),
+        (None, Token::Word("typedef")) => declarator(tokens).map(|name| CommentKey::Item(name.to_string())),
+        (Some(("enum", _)), Token::Word(name)) => Some(CommentKey::Item(name.to_string())),
+        (Some(("struct" | "union", owner)), Token::Word(_)) => {
+            declarator(tokens).map(|name| CommentKey::Member(owner.to_string(), name.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Name declared by a `TYPE NAME[...];` statement: the last word outside
+/// `<...>` and `[...]` before the semicolon.
+fn declarator<'a>(tokens: &[Token<'a>]) -> Option<&'a str> {
+    let mut depth = 0;
+    let mut name = None;
+    for token in tokens {
+        match *token {
+            Token::Punct('<' | '[') => depth += 1,
+            Token::Punct('>' | ']') => depth -= 1,
+            Token::Punct(';') => return name,
+            // A nested body; not something we attach comments to.
+            Token::Punct('{') => return None,
+            Token::Word(word) if depth == 0 => name = Some(word),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn add_comment(comments: &mut HashMap<CommentKey, String>, key: CommentKey, text: &str) {
+    let text = clean_comment(text);
+    if text.is_empty() {
+        return;
+    }
+    comments
+        .entry(key)
+        .and_modify(|doc| {
+            doc.push('\n');
+            doc.push_str(&text);
+        })
+        .or_insert(text);
+}
+
+/// Strip the leading `*` decoration and surrounding blank lines from a
+/// comment body.
+fn clean_comment(text: &str) -> String {
+    let lines: Vec<_> = text
+        .lines()
+        .map(|line| line.trim().trim_start_matches('*').trim())
+        .collect();
+    let start = lines.iter().position(|l| !l.is_empty()).unwrap_or(lines.len());
+    let end = lines.iter().rposition(|l| !l.is_empty()).map_or(start, |i| i + 1);
+    lines[start..end].join("\n")
+}
+
+/// Copy collected comments onto the AST, and parse procedure annotations.
+fn attach_comments(protocol: &mut Protocol, comments: &HashMap<CommentKey, String>) {
+    let item = |name: &str| comments.get(&CommentKey::Item(name.to_string())).cloned();
+    let member = |owner: &str, name: &str| {
+        comments
+            .get(&CommentKey::Member(owner.to_string(), name.to_string()))
+            .cloned()
+    };
+
+    for constant in &mut protocol.constants {
+        constant.doc = item(&constant.name);
+    }
+
+    for type_def in &mut protocol.types {
+        match type_def {
+            TypeDef::Struct(s) => {
+                s.doc = item(&s.name);
+                for field in &mut s.fields {
+                    field.doc = member(&s.name, &field.name);
+                }
+            }
+            TypeDef::Enum(e) => {
+                e.doc = item(&e.name);
+                for variant in &mut e.variants {
+                    variant.doc = item(&variant.name);
+                }
+            }
+            TypeDef::Union(u) => {
+                u.doc = item(&u.name);
+                for field in u.cases.iter_mut().filter_map(|c| c.field.as_mut()) {
+                    field.doc = member(&u.name, &field.name);
+                }
+            }
+            TypeDef::Typedef(t) => t.doc = item(&t.name),
+        }
+    }
 
-    let result = all_consuming(protocol_parser)(&input);
-    match result {
-        Ok((_, protocol)) => Ok(protocol),
-        Err(e) => Err(format!("parse error: {:?}", e)),
+    for procedure in &mut protocol.procedures {
+        if let Some(comment) = comments.get(&CommentKey::Item(procedure.name.clone())) {
+            apply_annotations(procedure, comment);
+        }
+    }
+}
+
+/// Fill in a procedure's metadata from `@key: value` lines of its comment.
+/// Remaining lines become the procedure's doc.
+fn apply_annotations(procedure: &mut Procedure, comment: &str) {
+    let mut doc = Vec::new();
+    for line in comment.lines() {
+        let Some(annotation) = line.strip_prefix('@') else {
+            doc.push(line);
+            continue;
+        };
+        let (key, value) = match annotation.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (annotation.trim(), ""),
+        };
+
+        match key {
+            "generate" => {
+                procedure.generate = match value {
+                    "none" => Generate::None,
+                    "client" => Generate::Client,
+                    "server" => Generate::Server,
+                    _ => Generate::Both,
+                }
+            }
+            "priority" => {
+                procedure.priority = match value {
+                    "high" => Priority::High,
+                    _ => Priority::Low,
+                }
+            }
+            "acl" if value != "none" => procedure.acl.push(value.to_string()),
+            "aclfilter" => procedure.acl_filter.push(value.to_string()),
+            "readstream" | "writestream" => {
+                procedure.stream = Some(StreamSpec {
+                    direction: if key == "readstream" {
+                        StreamDirection::Read
+                    } else {
+                        StreamDirection::Write
+                    },
+                    // A bare annotation is taken as offset 1.
+                    param: value.parse().unwrap_or(1),
+                })
+            }
+            "sparseflag" => procedure.sparse_flag = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    let doc = doc.join("\n");
+    let doc = doc.trim();
+    if !doc.is_empty() {
+        procedure.doc = Some(doc.to_string());
     }
 }
 
 /// Remove C-style comments, preprocessor directives, and XDR passthrough lines.
+///
+/// Removed text is replaced by spaces of the same byte length (newlines are
+/// kept), so offsets into the result are valid in `input` too.
 fn remove_comments(input: &str) -> String {
     let mut result = String::with_capacity(input.len());
     let mut chars = input.chars().peekable();
     let mut at_line_start = true;
 
+    let blank = |result: &mut String, c: char| {
+        if c == '\n' {
+            result.push('\n');
+        } else {
+            result.extend(std::iter::repeat(' ').take(c.len_utf8()));
+        }
+    };
+
     while let Some(c) = chars.next() {
         if c == '/' {
             match chars.peek() {
                 Some('*') => {
                     // Block comment
                     chars.next();
+                    result.push_str("  ");
                     while let Some(c) = chars.next() {
+                        blank(&mut result, c);
                         if c == '*' && chars.peek() == Some(&'/') {
                             chars.next();
-                            result.push(' '); // Replace with space to preserve line structure
+                            result.push(' ');
                             break;
                         }
                     }
@@ -55,10 +498,12 @@ fn remove_comments(input: &str) -> String {
                 Some('/') => {
                     // Line comment
                     chars.next();
+                    result.push_str("  ");
                     while let Some(&c) = chars.peek() {
                         if c == '\n' {
                             break;
                         }
+                        blank(&mut result, c);
                         chars.next();
                     }
                 }
@@ -69,12 +514,12 @@ fn remove_comments(input: &str) -> String {
             }
         } else if c == '#' || (c == '%' && at_line_start) {
             // Preprocessor directive or XDR passthrough - skip entire line
+            result.push(' ');
             while let Some(&c) = chars.peek() {
                 if c == '\n' {
-                    result.push('\n');
-                    at_line_start = true;
                     break;
                 }
+                blank(&mut result, c);
                 chars.next();
             }
         } else if c == '\n' {
@@ -92,15 +537,6 @@ fn remove_comments(input: &str) -> String {
     result
 }
 
-/// Resolve well-known libvirt constants to their values.
-fn resolve_well_known_constant(name: &str) -> Option<u32> {
-    match name {
-        "VIR_UUID_BUFLEN" => Some(16),
-        "VIR_UUID_STRING_BUFLEN" => Some(37),
-        _ => None,
-    }
-}
-
 // Helper parsers
 
 fn ws<'a, F, O, E>(inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
@@ -111,14 +547,25 @@ where
     delimited(multispace0, inner, multispace0)
 }
 
-fn identifier(input: &str) -> IResult<&str, &str> {
+/// A keyword not followed by more identifier characters, so `int` does
+/// not match the start of `interval`.
+fn word<'a>(word: &'static str) -> impl FnMut(&'a str) -> PResult<'a, &'a str> {
+    terminated(tag(word), not(satisfy(|c: char| c.is_ascii_alphanumeric() || c == '_')))
+}
+
+/// A keyword followed by whitespace.
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> PResult<'a, &'a str> {
+    terminated(tag(word), multispace1)
+}
+
+fn identifier(input: &str) -> PResult<'_, &str> {
     recognize(pair(
         take_while1(|c: char| c.is_ascii_alphabetic() || c == '_'),
         take_while(|c: char| c.is_ascii_alphanumeric() || c == '_'),
     ))(input)
 }
 
-fn integer(input: &str) -> IResult<&str, i64> {
+fn integer(input: &str) -> PResult<'_, i64> {
     alt((
         // Hex number
         map_res(
@@ -128,6 +575,11 @@ fn integer(input: &str) -> IResult<&str, i64> {
             ),
             |s: &str| i64::from_str_radix(s, 16),
         ),
+        // Octal
+        map_res(
+            preceded(char('0'), take_while1(|c: char| c.is_digit(8))),
+            |s: &str| i64::from_str_radix(s, 8),
+        ),
         // Decimal (possibly negative)
         map_res(recognize(pair(opt(char('-')), digit1)), |s: &str| {
             s.parse::<i64>()
@@ -135,62 +587,21 @@ fn integer(input: &str) -> IResult<&str, i64> {
     ))(input)
 }
 
-fn const_value(input: &str) -> IResult<&str, ConstValue> {
+fn const_value(input: &str) -> PResult<'_, ConstValue> {
     alt((
         map(integer, ConstValue::Int),
         map(identifier, |s| ConstValue::Ident(s.to_string())),
     ))(input)
 }
 
-// Protocol parser
-
-fn protocol_parser(input: &str) -> IResult<&str, Protocol> {
-    let (input, items) = many0(ws(definition))(input)?;
-
-    let mut protocol = Protocol::new("remote");
-
-    for item in items {
-        match item {
-            Definition::Const(c) => protocol.constants.push(c),
-            Definition::Type(t) => protocol.types.push(t),
-        }
-    }
-
-    // Extract program ID and protocol version from constants
-    extract_protocol_metadata(&mut protocol);
-
-    // Extract procedures from procedure enum
-    extract_procedures(&mut protocol);
-
-    Ok((input, protocol))
-}
-
 /// Extract program ID and protocol version from constants.
 fn extract_protocol_metadata(protocol: &mut Protocol) {
     for constant in &protocol.constants {
-        match constant.name.as_str() {
-            "REMOTE_PROGRAM" | "QEMU_PROGRAM" | "LXC_PROGRAM" => {
-                if let ConstValue::Int(v) = &constant.value {
-                    protocol.program_id = Some(*v as u32);
-                }
-                // Set protocol name based on program constant
-                if constant.name.starts_with("QEMU") {
-                    protocol.name = "qemu".to_string();
-                    protocol.proc_prefix = Some("QEMU_PROC".to_string());
-                } else if constant.name.starts_with("LXC") {
-                    protocol.name = "lxc".to_string();
-                    protocol.proc_prefix = Some("LXC_PROC".to_string());
-                } else {
-                    protocol.name = "remote".to_string();
-                    protocol.proc_prefix = Some("REMOTE_PROC".to_string());
-                }
-            }
-            "REMOTE_PROTOCOL_VERSION" | "QEMU_PROTOCOL_VERSION" | "LXC_PROTOCOL_VERSION" => {
-                if let ConstValue::Int(v) = &constant.value {
-                    protocol.protocol_version = Some(*v as u32);
-                }
-            }
-            _ => {}
+        let ConstValue::Int(v) = &constant.value else { continue };
+        if constant.name == protocol.config.program_const {
+            protocol.program_id = Some(*v as u32);
+        } else if constant.name == protocol.config.version_const {
+            protocol.protocol_version = Some(*v as u32);
         }
     }
 }
@@ -201,12 +612,10 @@ fn extract_protocol_metadata(protocol: &mut Protocol) {
 /// - args type: remote_domain_lookup_by_name_args (if exists)
 /// - ret type: remote_domain_lookup_by_name_ret (if exists)
 fn extract_procedures(protocol: &mut Protocol) {
-    // Determine the procedure enum name and prefix based on protocol type
-    let (enum_name, proc_prefix, type_prefix) = match protocol.name.as_str() {
-        "qemu" => ("qemu_procedure", "QEMU_PROC_", "qemu_"),
-        "lxc" => ("lxc_procedure", "LXC_PROC_", "lxc_"),
-        _ => ("remote_procedure", "REMOTE_PROC_", "remote_"),
-    };
+    let config = protocol.config.clone();
+    let enum_name = config.procedure_enum.as_str();
+    let proc_prefix = config.proc_prefix.as_str();
+    let type_prefix = config.type_prefix.as_str();
 
     // Find the procedure enum
     let procedure_enum = protocol
@@ -273,90 +682,507 @@ fn extract_procedures(protocol: &mut Protocol) {
             number,
             args,
             ret,
-            priority: Priority::default(),
+            ..Procedure::default()
         });
     }
 }
 
+/// The constants a `program` block defines: its own name and those of its
+/// versions and procedures.
+fn program_constants(program: &ProgramDef) -> Vec<Constant> {
+    let constant = |name: &str, value: &ConstValue| Constant {
+        name: name.to_string(),
+        value: value.clone(),
+        doc: None,
+    };
+    let mut constants = vec![constant(&program.name, &program.number)];
+    for version in &program.versions {
+        constants.push(constant(&version.name, &version.number));
+        for procedure in &version.procedures {
+            constants.push(constant(&procedure.name, &procedure.number));
+        }
+    }
+    constants
+}
+
+/// Take the program ID, version and procedures from a `program` block
+/// when the file does not define them libvirt's way. Procedures come from
+/// the last version of the program named by the configuration, or else
+/// of the first program.
+///
+/// Returns (procedure name, message) for procedures that are skipped.
+fn extract_program(protocol: &mut Protocol) -> Vec<(String, String)> {
+    let program = protocol
+        .programs
+        .iter()
+        .find(|p| p.name == protocol.config.program_const)
+        .or(protocol.programs.first());
+    let Some((program, version)) = program.and_then(|p| Some((p, p.versions.last()?))) else {
+        return Vec::new();
+    };
+    // The constants named after them hold the resolved numbers
+    let values = defined_constants(protocol);
+    let number = |name: &str| values.get(name).and_then(|n| u32::try_from(*n).ok());
+
+    let mut config = protocol.config.clone();
+    let mut program_id = protocol.program_id;
+    if program_id.is_none() {
+        program_id = number(&program.name);
+        config.program_const = program.name.clone();
+    }
+    let mut protocol_version = protocol.protocol_version;
+    if protocol_version.is_none() {
+        protocol_version = number(&version.name);
+        config.version_const = version.name.clone();
+    }
+
+    let mut procedures = Vec::new();
+    let mut skipped = Vec::new();
+    if protocol.procedures.is_empty() {
+        for procedure in &version.procedures {
+            let args = match procedure.args.as_slice() {
+                [] => Some(None),
+                [ty] => procedure_type_name(ty).map(Some),
+                _ => {
+                    let message = format!("procedure `{}` is skipped: it takes more than one argument", procedure.name);
+                    skipped.push((procedure.name.clone(), message));
+                    continue;
+                }
+            };
+            let ret = match &procedure.ret {
+                Type::Void => Some(None),
+                ty => procedure_type_name(ty).map(Some),
+            };
+            let (Some(args), Some(ret), Some(number)) = (args, ret, number(&procedure.name)) else {
+                let message = format!(
+                    "procedure `{}` is skipped: its number or argument or return type is not supported",
+                    procedure.name
+                );
+                skipped.push((procedure.name.clone(), message));
+                continue;
+            };
+            procedures.push(Procedure {
+                name: procedure.name.clone(),
+                number,
+                args,
+                ret,
+                ..Procedure::default()
+            });
+        }
+    }
+
+    protocol.config = config;
+    protocol.program_id = program_id;
+    protocol.protocol_version = protocol_version;
+    protocol.procedures.extend(procedures);
+    skipped
+}
+
+/// Name of a procedure argument or return type, as used by
+/// [`Procedure::args`] and [`Procedure::ret`].
+fn procedure_type_name(ty: &Type) -> Option<String> {
+    let name = match ty {
+        Type::Named(name) => name,
+        Type::Int => "i32",
+        Type::UInt => "u32",
+        Type::Hyper => "i64",
+        Type::UHyper => "u64",
+        Type::Float => "f32",
+        Type::Double => "f64",
+        Type::Bool => "bool",
+        Type::String { .. } => "String",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+/// Turn `T *` into [`Type::RecursiveOptional`] where `T` contains the
+/// declaring type again, so the generated type has a finite size. Only
+/// types held by value count: a variable-length array already keeps its
+/// elements on the heap.
+fn mark_recursive_optionals(protocol: &mut Protocol) {
+    let mut contains: HashMap<String, Vec<String>> = HashMap::new();
+    for type_def in &mut protocol.types {
+        let mut names = Vec::new();
+        for ty in member_types(type_def) {
+            by_value_names(ty, &mut names);
+        }
+        contains.insert(type_def.name().to_string(), names);
+    }
+
+    let reaches = |from: &str, to: &str| {
+        let mut stack = vec![from];
+        let mut seen = HashSet::new();
+        while let Some(name) = stack.pop() {
+            if name == to {
+                return true;
+            }
+            if seen.insert(name) {
+                stack.extend(contains.get(name).into_iter().flatten().map(String::as_str));
+            }
+        }
+        false
+    };
+
+    for type_def in &mut protocol.types {
+        let name = type_def.name().to_string();
+        for ty in member_types(type_def) {
+            box_optionals(ty, &|target| reaches(target, &name));
+        }
+    }
+}
+
+/// Types of the fields, arms or target of a definition.
+fn member_types(type_def: &mut TypeDef) -> Vec<&mut Type> {
+    match type_def {
+        TypeDef::Struct(s) => s.fields.iter_mut().map(|f| &mut f.ty).collect(),
+        TypeDef::Union(u) => {
+            let arms = u.cases.iter_mut().filter_map(|c| c.field.as_mut()).map(|f| &mut f.ty);
+            arms.chain(u.default.as_deref_mut()).collect()
+        }
+        TypeDef::Typedef(t) => vec![&mut t.target],
+        TypeDef::Enum(_) => Vec::new(),
+    }
+}
+
+/// Named types a value of `ty` contains directly.
+fn by_value_names(ty: &Type, names: &mut Vec<String>) {
+    match ty {
+        Type::Named(name) => names.push(name.clone()),
+        Type::Optional(inner)
+        | Type::RecursiveOptional(inner)
+        | Type::Array { elem: inner, len: LengthSpec::Fixed(_) } => by_value_names(inner, names),
+        _ => {}
+    }
+}
+
+fn box_optionals(ty: &mut Type, recursive: &impl Fn(&str) -> bool) {
+    match ty {
+        Type::Optional(inner) => match inner.as_ref() {
+            Type::Named(target) if recursive(target) => {
+                *ty = Type::RecursiveOptional(inner.clone());
+            }
+            _ => box_optionals(inner, recursive),
+        },
+        Type::Array { elem, len: LengthSpec::Fixed(_) } => box_optionals(elem, recursive),
+        _ => {}
+    }
+}
+
 enum Definition {
     Const(Constant),
     Type(TypeDef),
+    Program(ProgramDef),
 }
 
-fn definition(input: &str) -> IResult<&str, Definition> {
+/// Parse every definition in `input` (the comment-free `source`), along
+/// with the offset it starts at. Anonymous types come right before the
+/// definition declaring them, and share its offset.
+fn definitions(source: &str, input: &str) -> Result<Vec<(usize, Definition)>, Diagnostic> {
+    let offset = |rest: &str| input.len() - rest.len();
+    let mut items = Vec::new();
+    let mut rest = input.trim_start();
+
+    while !rest.is_empty() {
+        match definition(rest) {
+            Ok((next, defs)) => {
+                let at = offset(rest);
+                items.extend(defs.into_iter().map(|def| (at, def)));
+                rest = next.trim_start();
+            }
+            // No definition starts here
+            Err(nom::Err::Error(_)) => {
+                let message = format!(
+                    "expected `const`, `struct`, `enum`, `union` or `typedef`, found {}",
+                    found(rest)
+                );
+                return Err(Diagnostic::error(message).at(source, offset(rest)));
+            }
+            Err(nom::Err::Failure(e)) => {
+                let (offset, diagnostic) = describe_error(input, &e);
+                return Err(diagnostic.at(source, offset));
+            }
+            Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers never ask for more input"),
+        }
+    }
+
+    Ok(items)
+}
+
+/// Turn the error of a definition that failed past its keyword into a
+/// diagnostic, returning the offset in `input` where it failed.
+fn describe_error(input: &str, e: &VerboseError<&str>) -> (usize, Diagnostic) {
+    // The first entry is where parsing failed, the rest are its contexts.
+    let (at, kind) = &e.errors[0];
+    let mut offset = input.len() - at.len();
+    let message = match kind {
+        VerboseErrorKind::Char(c) => {
+            // A missing `;` or `}` belongs right after the previous token
+            offset = input[..offset].trim_end().len();
+            format!("expected `{}`, found {}", c, found(at))
+        }
+        VerboseErrorKind::Nom(ErrorKind::TakeWhile1) => {
+            format!("expected an identifier, found {}", found(at))
+        }
+        VerboseErrorKind::Nom(ErrorKind::MultiSpace) => {
+            format!("expected whitespace, found {}", found(at))
+        }
+        VerboseErrorKind::Nom(ErrorKind::MapRes) => format!("invalid number {}", found(at)),
+        _ => format!("unexpected {}", found(at)),
+    };
+
+    let contexts: Vec<_> = e
+        .errors
+        .iter()
+        .filter_map(|(_, kind)| match kind {
+            VerboseErrorKind::Context(context) => Some(*context),
+            _ => None,
+        })
+        .collect();
+
+    let mut diagnostic = Diagnostic::error(message);
+    if !contexts.is_empty() {
+        diagnostic = diagnostic.with_note(format!("while parsing {}", contexts.join(" in ")));
+    }
+    (offset, diagnostic)
+}
+
+/// The token at the start of `rest`, for error messages.
+fn found(rest: &str) -> String {
+    let word = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    match rest.chars().next() {
+        None => "end of file".to_string(),
+        Some(_) if word > 0 => format!("`{}`", &rest[..word]),
+        Some(c) => format!("`{}`", c),
+    }
+}
+
+/// Diagnostics for references to constants that could not be resolved:
+/// warnings for constants and enum variants that will not be generated and
+/// maximum lengths that will not be enforced, and errors for fixed lengths,
+/// without which the type cannot be generated.
+///
+/// `offsets` gives where each definition starts in `source`.
+fn unresolved_constants(
+    source: &str,
+    stripped: &str,
+    protocol: &Protocol,
+    offsets: &HashMap<String, usize>,
+) -> Vec<Diagnostic> {
+    let describe = |value: &str| {
+        if offsets.contains_key(value) {
+            format!("refers to `{}`, which could not be resolved", value)
+        } else {
+            format!("refers to `{}`, which is not defined in this file or libvirt.h", value)
+        }
+    };
+
+    // (definition name, name to point at, diagnostic)
+    let mut unresolved: Vec<(&str, &str, Diagnostic)> = Vec::new();
+    for c in &protocol.constants {
+        if let ConstValue::Ident(value) = &c.value {
+            let message = format!("constant `{}` is skipped: its value {}", c.name, describe(value));
+            unresolved.push((&c.name, &c.name, Diagnostic::warning(message)));
+        }
+    }
+
+    for type_def in &protocol.types {
+        let name = type_def.name();
+        let mut fields: Vec<(&str, &Type)> = Vec::new();
+        match type_def {
+            TypeDef::Enum(e) => {
+                for v in &e.variants {
+                    if let Some(ConstValue::Ident(value)) = &v.value {
+                        let message = format!(
+                            "variant `{}` of enum `{}` is skipped: its value {}",
+                            v.name,
+                            e.name,
+                            describe(value)
+                        );
+                        unresolved.push((name, &v.name, Diagnostic::warning(message)));
+                    }
+                }
+            }
+            TypeDef::Struct(st) => {
+                fields.extend(st.fields.iter().map(|f| (f.name.as_str(), &f.ty)));
+            }
+            TypeDef::Union(u) => {
+                let arms = u.cases.iter().filter_map(|case| case.field.as_ref());
+                fields.extend(arms.map(|f| (f.name.as_str(), &f.ty)));
+            }
+            TypeDef::Typedef(t) => fields.push((&t.name, &t.target)),
+        }
+
+        for (field, ty) in fields {
+            let (len, fixed) = match ty {
+                Type::Opaque { len: LengthSpec::Fixed(len) } | Type::Array { len: LengthSpec::Fixed(len), .. } => {
+                    (len, true)
+                }
+                Type::String { max_len: Some(max) }
+                | Type::Opaque { len: LengthSpec::Variable { max: Some(max) } }
+                | Type::Array { len: LengthSpec::Variable { max: Some(max) }, .. } => (max, false),
+                _ => continue,
+            };
+            let diagnostic = match len {
+                ConstValue::Ident(value) if fixed => {
+                    Diagnostic::error(format!("the length of `{}` {}", field, describe(value)))
+                }
+                ConstValue::Ident(value) => Diagnostic::warning(format!(
+                    "the maximum length of `{}` {} and is not enforced",
+                    field,
+                    describe(value)
+                )),
+                ConstValue::Int(n) if fixed && len.as_u32().is_none() => {
+                    Diagnostic::error(format!("the length of `{}` is {}, which is not a valid length", field, n))
+                }
+                ConstValue::Int(_) => continue,
+            };
+            unresolved.push((name, field, diagnostic));
+        }
+    }
+
+    unresolved
+        .into_iter()
+        .map(|(definition, name, diagnostic)| {
+            let offset = offsets.get(definition).copied().unwrap_or(0);
+            let at = find_word(stripped, offset, name).unwrap_or(offset);
+            diagnostic.at(source, at)
+        })
+        .collect()
+}
+
+/// Offset of the first occurrence of `word` as a whole identifier at or
+/// after `from`.
+fn find_word(text: &str, from: usize, word: &str) -> Option<usize> {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut start = from;
+    while let Some(i) = text[start..].find(word) {
+        let at = start + i;
+        let end = at + word.len();
+        let before = text[..at].chars().next_back();
+        let after = text[end..].chars().next();
+        if !before.is_some_and(is_ident) && !after.is_some_and(is_ident) {
+            return Some(at);
+        }
+        start = end;
+    }
+    None
+}
+
+/// A definition, preceded by the anonymous types declared inside it.
+fn definition(input: &str) -> PResult<'_, Vec<Definition>> {
     alt((
-        map(const_def, Definition::Const),
-        map(type_def, Definition::Type),
+        map(const_def, |c| vec![Definition::Const(c)]),
+        map(program_def, |p| vec![Definition::Program(p)]),
+        type_def,
     ))(input)
 }
 
 // Constant definition: const NAME = VALUE;
-fn const_def(input: &str) -> IResult<&str, Constant> {
-    let (input, _) = tag("const")(input)?;
-    let (input, _) = multispace1(input)?;
-    let (input, name) = identifier(input)?;
-    let (input, _) = ws(char('='))(input)?;
-    let (input, value) = const_value(input)?;
-    let (input, _) = ws(char(';'))(input)?;
-
-    Ok((
-        input,
-        Constant {
-            name: name.to_string(),
-            value,
-        },
-    ))
+fn const_def(input: &str) -> PResult<'_, Constant> {
+    let (input, _) = keyword("const")(input)?;
+    context(
+        "a constant",
+        cut(|input| {
+            let (input, name) = identifier(input)?;
+            let (input, _) = ws(char('='))(input)?;
+            let (input, value) = const_value(input)?;
+            let (input, _) = ws(char(';'))(input)?;
+
+            Ok((
+                input,
+                Constant {
+                    name: name.to_string(),
+                    value,
+                    doc: None,
+                },
+            ))
+        }),
+    )(input)
 }
 
+/// Types declared inline in a definition, hoisted to the top level.
+type Nested = Vec<TypeDef>;
+
 // Type definitions
-fn type_def(input: &str) -> IResult<&str, TypeDef> {
-    alt((
-        map(struct_def, TypeDef::Struct),
-        map(enum_def, TypeDef::Enum),
-        map(union_def, TypeDef::Union),
-        map(typedef_def, TypeDef::Typedef),
-    ))(input)
+fn type_def(input: &str) -> PResult<'_, Vec<Definition>> {
+    let (input, (def, nested)) = alt((struct_def, enum_def, union_def, typedef_def))(input)?;
+    let defs = nested.into_iter().chain(std::iter::once(def));
+    Ok((input, defs.map(Definition::Type).collect()))
 }
 
 // Struct definition: struct NAME { fields };
-fn struct_def(input: &str) -> IResult<&str, StructDef> {
-    let (input, _) = tag("struct")(input)?;
-    let (input, _) = multispace1(input)?;
-    let (input, name) = identifier(input)?;
+fn struct_def(input: &str) -> PResult<'_, (TypeDef, Nested)> {
+    let (input, _) = keyword("struct")(input)?;
+    context(
+        "a struct",
+        cut(|input| {
+            let (input, name) = identifier(input)?;
+            let (input, def) = struct_body(input, name)?;
+            let (input, _) = ws(char(';'))(input)?;
+            Ok((input, def))
+        }),
+    )(input)
+}
+
+// Struct body: { fields }
+fn struct_body<'a>(input: &'a str, name: &str) -> PResult<'a, (TypeDef, Nested)> {
     let (input, _) = ws(char('{'))(input)?;
-    let (input, fields) = many0(ws(field_def))(input)?;
+    let (input, fields) = many0(ws(preceded(
+        not(alt((tag("}"), eof))),
+        cut(context("a field", |input| field_def(input, name))),
+    )))(input)?;
     let (input, _) = ws(char('}'))(input)?;
-    let (input, _) = ws(char(';'))(input)?;
 
-    Ok((
-        input,
-        StructDef {
-            name: name.to_string(),
-            fields,
-        },
-    ))
+    let mut nested = Vec::new();
+    let fields = fields
+        .into_iter()
+        .map(|(field, inner)| {
+            nested.extend(inner);
+            field
+        })
+        .collect();
+    let def = TypeDef::Struct(StructDef {
+        name: name.to_string(),
+        fields,
+        doc: None,
+    });
+    Ok((input, (def, nested)))
+}
+
+// Field definition: DECLARATION;
+fn field_def<'a>(input: &'a str, scope: &str) -> PResult<'a, (Field, Nested)> {
+    let (input, field) = declaration(input, Some(scope))?;
+    let (input, _) = ws(char(';'))(input)?;
+    Ok((input, field))
 }
 
-// Field definition: TYPE NAME;
-fn field_def(input: &str) -> IResult<&str, Field> {
-    let (input, ty) = type_spec(input)?;
-    let (input, _) = multispace1(input)?;
+// Declaration: TYPE NAME, TYPE NAME[N], TYPE NAME<N> or TYPE *NAME.
+// Anonymous types in TYPE are named after NAME, prefixed by `scope`.
+fn declaration<'a>(input: &'a str, scope: Option<&str>) -> PResult<'a, (Field, Nested)> {
+    let (input, (ty, nested)) = type_spec(input, scope)?;
+    let (input, _) = multispace0(input)?;
+    let (input, pointer) = opt(terminated(char('*'), multispace0))(input)?;
     let (input, name) = identifier(input)?;
-    // Handle array suffix
     let (input, ty) = array_suffix(input, ty)?;
-    let (input, _) = ws(char(';'))(input)?;
 
-    Ok((
-        input,
-        Field {
-            name: name.to_string(),
-            ty,
-        },
-    ))
+    let ty = if pointer.is_some() {
+        Type::Optional(Box::new(ty))
+    } else {
+        ty
+    };
+    let field = Field {
+        name: name.to_string(),
+        ty,
+        doc: None,
+    };
+    Ok((input, (field, nested)))
 }
 
 // Handle array suffix [N] or <N>
-fn array_suffix(input: &str, base_ty: Type) -> IResult<&str, Type> {
+fn array_suffix(input: &str, base_ty: Type) -> PResult<'_, Type> {
     let input_trimmed = input.trim_start();
 
     if input_trimmed.starts_with('[') {
@@ -366,24 +1192,19 @@ fn array_suffix(input: &str, base_ty: Type) -> IResult<&str, Type> {
         let (input, len) = ws(const_value)(input)?;
         let (input, _) = char(']')(input)?;
 
-        let size = match &len {
-            ConstValue::Int(n) => *n as u32,
-            ConstValue::Ident(name) => resolve_well_known_constant(name).unwrap_or(0),
-        };
-
         // For opaque[N], return fixed-length opaque instead of array
         match &base_ty {
             Type::Opaque { .. } => Ok((
                 input,
                 Type::Opaque {
-                    len: LengthSpec::Fixed(size),
+                    len: LengthSpec::Fixed(len),
                 },
             )),
             _ => Ok((
                 input,
                 Type::Array {
                     elem: Box::new(base_ty),
-                    len: LengthSpec::Fixed(size),
+                    len: LengthSpec::Fixed(len),
                 },
             )),
         }
@@ -396,14 +1217,9 @@ fn array_suffix(input: &str, base_ty: Type) -> IResult<&str, Type> {
                 // For string and opaque, <N> just sets max length, type stays the same
                 let (input, _) = multispace0(input)?;
                 let (input, _) = char('<')(input)?;
-                let (input, len) = ws(opt(const_value))(input)?;
+                let (input, max) = ws(opt(const_value))(input)?;
                 let (input, _) = char('>')(input)?;
 
-                let max = len.and_then(|v| match v {
-                    ConstValue::Int(n) => Some(n as u32),
-                    ConstValue::Ident(_) => None,
-                });
-
                 // Return the same type, possibly with updated max length
                 match base_ty {
                     Type::String { .. } => Ok((input, Type::String { max_len: max })),
@@ -420,14 +1236,9 @@ fn array_suffix(input: &str, base_ty: Type) -> IResult<&str, Type> {
                 // For other types, <N> means variable-length array
                 let (input, _) = multispace0(input)?;
                 let (input, _) = char('<')(input)?;
-                let (input, len) = ws(opt(const_value))(input)?;
+                let (input, max) = ws(opt(const_value))(input)?;
                 let (input, _) = char('>')(input)?;
 
-                let max = len.and_then(|v| match v {
-                    ConstValue::Int(n) => Some(n as u32),
-                    ConstValue::Ident(_) => None,
-                });
-
                 Ok((
                     input,
                     Type::Array {
@@ -444,81 +1255,126 @@ fn array_suffix(input: &str, base_ty: Type) -> IResult<&str, Type> {
 }
 
 // Type specification
-fn type_spec(input: &str) -> IResult<&str, Type> {
+fn type_spec<'a>(input: &'a str, scope: Option<&str>) -> PResult<'a, (Type, Nested)> {
+    let named = |name: &str| (Type::Named(name.to_string()), Vec::new());
     alt((
-        value(Type::Void, tag("void")),
-        // unsigned types
-        value(
-            Type::UHyper,
-            pair(tag("unsigned"), preceded(multispace1, tag("hyper"))),
-        ),
-        value(
-            Type::UInt,
-            pair(tag("unsigned"), preceded(multispace1, tag("int"))),
-        ),
-        // unsigned char -> u8
+        |input| inline_type(input, scope),
+        map(primitive_type, |ty| (ty, Vec::new())),
+        // struct NAME, union NAME, enum NAME
         map(
-            pair(tag("unsigned"), preceded(multispace1, tag("char"))),
-            |_| Type::Named("u8".to_string()),
+            preceded(
+                pair(alt((word("struct"), word("union"), word("enum"))), multispace1),
+                identifier,
+            ),
+            named,
         ),
-        // unsigned short -> u16
+        map(string_type, |ty| (ty, Vec::new())),
+        map(opaque_type, |ty| (ty, Vec::new())),
+        map(identifier, named),
+    ))(input)
+}
+
+// Built-in types
+fn primitive_type(input: &str) -> PResult<'_, Type> {
+    let named = |name: &str| Type::Named(name.to_string());
+    alt((
+        value(Type::Void, word("void")),
+        // unsigned types; a bare `unsigned` is an unsigned int
         map(
-            pair(tag("unsigned"), preceded(multispace1, tag("short"))),
-            |_| Type::Named("u16".to_string()),
+            preceded(
+                word("unsigned"),
+                opt(preceded(
+                    multispace1,
+                    alt((
+                        value(Type::UHyper, word("hyper")),
+                        value(Type::UInt, word("int")),
+                        value(Type::UInt, word("long")),
+                        // unsigned char -> u8, unsigned short -> u16
+                        value(named("u8"), word("char")),
+                        value(named("u16"), word("short")),
+                    )),
+                )),
+            ),
+            |ty| ty.unwrap_or(Type::UInt),
         ),
-        // char -> i8
-        value(Type::Named("i8".to_string()), tag("char")),
-        // short -> i16
-        value(Type::Named("i16".to_string()), tag("short")),
-        value(Type::Hyper, tag("hyper")),
-        value(Type::Int, tag("int")),
-        value(Type::Float, tag("float")),
-        value(Type::Double, tag("double")),
-        value(Type::Bool, tag("bool")),
-        string_type,
-        opaque_type,
-        optional_type,
-        map(identifier, |s| Type::Named(s.to_string())),
+        // char -> i8, short -> i16
+        value(named("i8"), word("char")),
+        value(named("i16"), word("short")),
+        value(Type::Hyper, word("hyper")),
+        value(Type::Int, word("int")),
+        value(Type::Int, word("long")),
+        value(Type::Float, word("float")),
+        value(Type::Double, word("double")),
+        value(Type::Quadruple, word("quadruple")),
+        value(Type::Bool, word("bool")),
     ))(input)
 }
 
-// Optional type: TYPE *
-fn optional_type(input: &str) -> IResult<&str, Type> {
-    let (input, ty) = alt((
-        value(
-            Type::UHyper,
-            pair(tag("unsigned"), preceded(multispace1, tag("hyper"))),
-        ),
-        value(
-            Type::UInt,
-            pair(tag("unsigned"), preceded(multispace1, tag("int"))),
-        ),
-        value(Type::Hyper, tag("hyper")),
-        value(Type::Int, tag("int")),
-        value(Type::Float, tag("float")),
-        value(Type::Double, tag("double")),
-        value(Type::Bool, tag("bool")),
-        map(identifier, |s| Type::Named(s.to_string())),
+// Anonymous type: struct { ... }, union switch (...) { ... } or enum { ... }.
+// It becomes a definition of its own, named after the declaration it is
+// part of.
+fn inline_type<'a>(input: &'a str, scope: Option<&str>) -> PResult<'a, (Type, Nested)> {
+    let (body, keyword) = alt((
+        terminated(word("struct"), peek(ws(char('{')))),
+        terminated(word("enum"), peek(ws(char('{')))),
+        terminated(word("union"), peek(ws(word("switch")))),
     ))(input)?;
 
-    let (input, _) = ws(char('*'))(input)?;
+    let declared = declared_name(input).unwrap_or("anonymous");
+    let name = match scope {
+        Some(scope) => format!("{}_{}", scope, declared),
+        None => declared.to_string(),
+    };
+    let (input, (def, mut nested)) = match keyword {
+        "struct" => context("a struct", cut(|input| struct_body(input, &name)))(body)?,
+        "enum" => context(
+            "an enum",
+            cut(map(|input| enum_body(input, &name), |e| (TypeDef::Enum(e), Vec::new()))),
+        )(body)?,
+        _ => context(
+            "a union",
+            cut(map(|input| union_body(input, &name), |(u, nested)| (TypeDef::Union(u), nested))),
+        )(body)?,
+    };
+    nested.push(def);
+    Ok((input, (Type::Named(name), nested)))
+}
 
-    Ok((input, Type::Optional(Box::new(ty))))
+/// Name declared after the anonymous type starting at `input`, e.g. `b`
+/// in `struct { int a; } b`.
+fn declared_name(input: &str) -> Option<&str> {
+    let open = input.find('{')?;
+    let mut depth = 0;
+    for (i, c) in input[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    let rest = input[open + i + 1..].trim_start();
+                    let rest = rest.strip_prefix('*').unwrap_or(rest).trim_start();
+                    return identifier(rest).ok().map(|(_, name)| name);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
 }
 
 // String type: string<N> or string<>
-fn string_type(input: &str) -> IResult<&str, Type> {
-    let (input, _) = tag("string")(input)?;
-    let (input, max_len) = opt(delimited(char('<'), ws(opt(integer)), char('>')))(input)?;
+fn string_type(input: &str) -> PResult<'_, Type> {
+    let (input, _) = word("string")(input)?;
+    let (input, max_len) = opt(delimited(char('<'), ws(opt(const_value)), char('>')))(input)?;
 
-    let max_len = max_len.flatten().map(|n| n as u32);
+    let max_len = max_len.flatten();
 
     Ok((input, Type::String { max_len }))
 }
 
 // Opaque type: opaque NAME[N] or opaque NAME<N>
-fn opaque_type(input: &str) -> IResult<&str, Type> {
-    let (input, _) = tag("opaque")(input)?;
+fn opaque_type(input: &str) -> PResult<'_, Type> {
+    let (input, _) = word("opaque")(input)?;
 
     Ok((
         input,
@@ -529,130 +1385,242 @@ fn opaque_type(input: &str) -> IResult<&str, Type> {
 }
 
 // Enum definition: enum NAME { variants };
-fn enum_def(input: &str) -> IResult<&str, EnumDef> {
-    let (input, _) = tag("enum")(input)?;
-    let (input, _) = multispace1(input)?;
-    let (input, name) = identifier(input)?;
+fn enum_def(input: &str) -> PResult<'_, (TypeDef, Nested)> {
+    let (input, _) = keyword("enum")(input)?;
+    context(
+        "an enum",
+        cut(|input| {
+            let (input, name) = identifier(input)?;
+            let (input, def) = enum_body(input, name)?;
+            let (input, _) = ws(char(';'))(input)?;
+            Ok((input, (TypeDef::Enum(def), Vec::new())))
+        }),
+    )(input)
+}
+
+// Enum body: { variants }
+fn enum_body<'a>(input: &'a str, name: &str) -> PResult<'a, EnumDef> {
     let (input, _) = ws(char('{'))(input)?;
     let (input, variants) = separated_list0(ws(char(',')), ws(enum_variant))(input)?;
     let (input, _) = opt(ws(char(',')))(input)?; // trailing comma
     let (input, _) = ws(char('}'))(input)?;
-    let (input, _) = ws(char(';'))(input)?;
 
     Ok((
         input,
         EnumDef {
             name: name.to_string(),
             variants,
+            doc: None,
         },
     ))
 }
 
 // Enum variant: NAME = VALUE or NAME
-fn enum_variant(input: &str) -> IResult<&str, EnumVariant> {
+fn enum_variant(input: &str) -> PResult<'_, EnumVariant> {
     let (input, name) = identifier(input)?;
-    let (input, value) = opt(preceded(ws(char('=')), const_value))(input)?;
+    let (input, value) = opt(preceded(ws(char('=')), cut(const_value)))(input)?;
 
     Ok((
         input,
         EnumVariant {
             name: name.to_string(),
             value,
+            doc: None,
         },
     ))
 }
 
-// Union definition
-fn union_def(input: &str) -> IResult<&str, UnionDef> {
-    let (input, _) = tag("union")(input)?;
-    let (input, _) = multispace1(input)?;
-    let (input, name) = identifier(input)?;
-    let (input, _) = ws(tag("switch"))(input)?;
+// Union definition: union NAME switch (DISCRIMINANT) { cases };
+fn union_def(input: &str) -> PResult<'_, (TypeDef, Nested)> {
+    let (input, _) = keyword("union")(input)?;
+    context(
+        "a union",
+        cut(|input| {
+            let (input, name) = identifier(input)?;
+            let (input, (def, nested)) = union_body(input, name)?;
+            let (input, _) = ws(char(';'))(input)?;
+            Ok((input, (TypeDef::Union(def), nested)))
+        }),
+    )(input)
+}
+
+// Union body: switch (DISCRIMINANT) { cases }
+fn union_body<'a>(input: &'a str, name: &str) -> PResult<'a, (UnionDef, Nested)> {
+    let (input, _) = ws(word("switch"))(input)?;
     let (input, _) = ws(char('('))(input)?;
-    let (input, disc_ty) = type_spec(input)?;
-    let (input, _) = multispace1(input)?;
-    let (input, disc_name) = identifier(input)?;
+    let (input, (discriminant, mut nested)) = declaration(input, Some(name))?;
     let (input, _) = ws(char(')'))(input)?;
     let (input, _) = ws(char('{'))(input)?;
-    let (input, cases) = many0(ws(union_case))(input)?;
-    let (input, default) = opt(union_default)(input)?;
+    let (input, cases) = many0(ws(|input| union_case(input, name)))(input)?;
+    let (input, default) = opt(|input| union_default(input, name))(input)?;
     let (input, _) = ws(char('}'))(input)?;
-    let (input, _) = ws(char(';'))(input)?;
 
-    Ok((
-        input,
-        UnionDef {
-            name: name.to_string(),
-            discriminant: Field {
-                name: disc_name.to_string(),
-                ty: disc_ty,
-            },
-            cases,
-            default,
-        },
-    ))
+    let cases = cases
+        .into_iter()
+        .map(|(case, inner)| {
+            nested.extend(inner);
+            case
+        })
+        .collect();
+    let default = default.map(|(ty, inner)| {
+        nested.extend(inner);
+        ty
+    });
+    let def = UnionDef {
+        name: name.to_string(),
+        discriminant,
+        cases,
+        default,
+        doc: None,
+    };
+    Ok((input, (def, nested)))
 }
 
-// Union case: case VALUE: FIELD;
-fn union_case(input: &str) -> IResult<&str, UnionCase> {
-    let (input, _) = tag("case")(input)?;
-    let (input, _) = multispace1(input)?;
-    let (input, value) = const_value(input)?;
-    let (input, _) = ws(char(':'))(input)?;
-
-    // Field or void
-    let (input, field) = alt((
-        map(field_def, Some),
-        map(terminated(tag("void"), ws(char(';'))), |_| None),
-    ))(input)?;
-
-    Ok((
-        input,
-        UnionCase {
-            values: vec![value],
-            field,
-        },
-    ))
+// Union case: case VALUE: [case VALUE: ...] ARM;
+fn union_case<'a>(input: &'a str, scope: &str) -> PResult<'a, (UnionCase, Nested)> {
+    let (input, _) = keyword("case")(input)?;
+    context(
+        "a union case",
+        cut(|input| {
+            let label = |input| terminated(const_value, ws(char(':')))(input);
+            let (input, first) = label(input)?;
+            // Labels sharing the arm
+            let (input, mut values) = many0(preceded(keyword("case"), cut(label)))(input)?;
+            values.insert(0, first);
+            let (input, (field, nested)) = union_arm(input, scope)?;
+
+            Ok((input, (UnionCase { values, field }, nested)))
+        }),
+    )(input)
 }
 
-// Union default: default: FIELD;
-fn union_default(input: &str) -> IResult<&str, Box<Type>> {
-    let (input, _) = ws(tag("default"))(input)?;
-    let (input, _) = ws(char(':'))(input)?;
-    let (input, field) = field_def(input)?;
-
-    Ok((input, Box::new(field.ty)))
+// Union default: default: ARM;
+fn union_default<'a>(input: &'a str, scope: &str) -> PResult<'a, (Box<Type>, Nested)> {
+    let (input, _) = ws(word("default"))(input)?;
+    context(
+        "a union default",
+        cut(|input| {
+            let (input, _) = ws(char(':'))(input)?;
+            let (input, (field, nested)) = union_arm(input, scope)?;
+            let ty = field.map_or(Type::Void, |f| f.ty);
+
+            Ok((input, (Box::new(ty), nested)))
+        }),
+    )(input)
 }
 
-// Typedef: typedef TYPE NAME; or typedef TYPE *NAME;
-fn typedef_def(input: &str) -> IResult<&str, TypedefDef> {
-    let (input, _) = tag("typedef")(input)?;
-    let (input, _) = multispace1(input)?;
-    let (input, target) = type_spec(input)?;
-    let (input, _) = multispace0(input)?;
+// Union arm: void; or a field
+fn union_arm<'a>(input: &'a str, scope: &str) -> PResult<'a, (Option<Field>, Nested)> {
+    alt((
+        map(terminated(word("void"), ws(char(';'))), |_| (None, Vec::new())),
+        map(|input| field_def(input, scope), |(field, nested)| (Some(field), nested)),
+    ))(input)
+}
 
-    // Check for pointer typedef: typedef TYPE *NAME;
-    let (input, is_pointer) = opt(char('*'))(input)?;
-    let (input, _) = multispace0(input)?;
-    let (input, name) = identifier(input)?;
+// Typedef: typedef DECLARATION; an anonymous struct, union or enum
+// declared this way takes the typedef's name.
+fn typedef_def(input: &str) -> PResult<'_, (TypeDef, Nested)> {
+    let (input, _) = keyword("typedef")(input)?;
+    context(
+        "a typedef",
+        cut(|input| {
+            let (input, (field, mut nested)) = declaration(input, None)?;
+            let (input, _) = ws(char(';'))(input)?;
+
+            if matches!(&field.ty, Type::Named(target) if *target == field.name) {
+                if let Some(def) = nested.pop() {
+                    return Ok((input, (def, nested)));
+                }
+            }
+            let def = TypeDef::Typedef(TypedefDef {
+                name: field.name,
+                target: field.ty,
+                doc: None,
+            });
+            Ok((input, (def, nested)))
+        }),
+    )(input)
+}
 
-    // Handle array suffix
-    let (input, target) = array_suffix(input, target)?;
-    let (input, _) = ws(char(';'))(input)?;
+// Program definition: program NAME { versions } = NUMBER;
+fn program_def(input: &str) -> PResult<'_, ProgramDef> {
+    let (input, _) = keyword("program")(input)?;
+    context(
+        "a program",
+        cut(|input| {
+            let (input, name) = identifier(input)?;
+            let (input, _) = ws(char('{'))(input)?;
+            let (input, versions) = many1(ws(version_def))(input)?;
+            let (input, _) = ws(char('}'))(input)?;
+            let (input, number) = preceded(ws(char('=')), const_value)(input)?;
+            let (input, _) = ws(char(';'))(input)?;
+
+            Ok((
+                input,
+                ProgramDef {
+                    name: name.to_string(),
+                    number,
+                    versions,
+                },
+            ))
+        }),
+    )(input)
+}
 
-    let target = if is_pointer.is_some() {
-        Type::Optional(Box::new(target))
-    } else {
-        target
-    };
+// Version definition: version NAME { procedures } = NUMBER;
+fn version_def(input: &str) -> PResult<'_, VersionDef> {
+    let (input, _) = keyword("version")(input)?;
+    context(
+        "a version",
+        cut(|input| {
+            let (input, name) = identifier(input)?;
+            let (input, _) = ws(char('{'))(input)?;
+            let (input, procedures) = many1(ws(procedure_def))(input)?;
+            let (input, _) = ws(char('}'))(input)?;
+            let (input, number) = preceded(ws(char('=')), const_value)(input)?;
+            let (input, _) = ws(char(';'))(input)?;
+
+            Ok((
+                input,
+                VersionDef {
+                    name: name.to_string(),
+                    number,
+                    procedures,
+                },
+            ))
+        }),
+    )(input)
+}
 
-    Ok((
-        input,
-        TypedefDef {
-            name: name.to_string(),
-            target,
-        },
-    ))
+// Procedure definition: RET NAME(ARG, ...) = NUMBER;
+fn procedure_def(input: &str) -> PResult<'_, ProcedureDef> {
+    let proc_type = |input| map(|input| type_spec(input, None), |(ty, _)| ty)(input);
+    let (input, ret) = proc_type(input)?;
+    context(
+        "a procedure",
+        cut(move |input| {
+            let (input, _) = multispace1(input)?;
+            let (input, name) = identifier(input)?;
+            let (input, _) = ws(char('('))(input)?;
+            let (input, mut args) = separated_list1(ws(char(',')), proc_type)(input)?;
+            let (input, _) = ws(char(')'))(input)?;
+            let (input, number) = preceded(ws(char('=')), const_value)(input)?;
+            let (input, _) = ws(char(';'))(input)?;
+
+            if matches!(args.as_slice(), [Type::Void]) {
+                args.clear();
+            }
+            Ok((
+                input,
+                ProcedureDef {
+                    name: name.to_string(),
+                    number,
+                    args,
+                    ret: ret.clone(),
+                },
+            ))
+        }),
+    )(input)
 }
 
 #[cfg(test)]
@@ -671,6 +1639,9 @@ mod tests {
         assert!(!result.contains("block comment"));
         assert!(!result.contains("line comment"));
         assert!(!result.contains("preprocessor"));
+        // Offsets into the result must stay valid in the input
+        assert_eq!(result.len(), input.len());
+        assert_eq!(result.lines().count(), input.lines().count());
     }
 
     #[test]
@@ -732,4 +1703,189 @@ mod tests {
             panic!("expected typedef");
         }
     }
+
+    #[test]
+    fn test_parse_procedure_annotations() {
+        let input = r#"
+            const REMOTE_PROGRAM = 0x20008086;
+            struct remote_storage_vol_download_args {
+                int offset;
+            };
+            enum remote_procedure {
+                /**
+                 * @generate: none
+                 * @priority: high
+                 * @acl: none
+                 */
+                REMOTE_PROC_CONNECT_CLOSE = 2,
+
+                /**
+                 * @generate: both
+                 * @readstream: 1
+                 * @sparseflag: VIR_STORAGE_VOL_DOWNLOAD_SPARSE_STREAM
+                 * @acl: storage_vol:data_read
+                 * @aclfilter: storage_vol:getattr
+                 */
+                REMOTE_PROC_STORAGE_VOL_DOWNLOAD = 209
+            };
+        "#;
+        let result = parse_protocol(input).unwrap();
+        let close = &result.procedures[0];
+        assert_eq!(close.generate, Generate::None);
+        assert_eq!(close.priority, Priority::High);
+        assert!(close.acl.is_empty());
+        assert!(close.stream.is_none());
+
+        let download = &result.procedures[1];
+        assert_eq!(download.priority, Priority::Low);
+        assert_eq!(download.acl, ["storage_vol:data_read"]);
+        assert_eq!(download.acl_filter, ["storage_vol:getattr"]);
+        assert_eq!(
+            download.stream,
+            Some(StreamSpec {
+                direction: StreamDirection::Read,
+                param: 1
+            })
+        );
+        assert_eq!(
+            download.sparse_flag.as_deref(),
+            Some("VIR_STORAGE_VOL_DOWNLOAD_SPARSE_STREAM")
+        );
+    }
+
+    #[test]
+    fn test_parse_doc_comments() {
+        let input = r#"
+            /* License header. */
+
+            %#include <libvirt/libvirt.h>
+
+            /* Upper limit on lists of domains. */
+            const REMOTE_DOMAIN_LIST_MAX = 16384;
+
+            /* A domain. */
+            struct remote_node_get_info_ret { /* insert@1 */
+                /* CPU model. */
+                char model[32];
+                int cpus; /* online CPUs */
+                int mhz;
+            };
+
+            enum remote_auth_type {
+                REMOTE_AUTH_NONE = 0, /* No auth */
+                /* SASL */
+                REMOTE_AUTH_SASL = 1
+            };
+        "#;
+        let result = parse_protocol(input).unwrap();
+        assert_eq!(
+            result.constants[0].doc.as_deref(),
+            Some("Upper limit on lists of domains.")
+        );
+
+        let TypeDef::Struct(s) = &result.types[0] else { panic!("expected struct") };
+        assert_eq!(s.doc.as_deref(), Some("A domain.\ninsert@1"));
+        assert_eq!(s.fields[0].doc.as_deref(), Some("CPU model."));
+        assert_eq!(s.fields[1].doc.as_deref(), Some("online CPUs"));
+        assert_eq!(s.fields[2].doc, None);
+
+        let TypeDef::Enum(e) = &result.types[1] else { panic!("expected enum") };
+        assert_eq!(e.doc, None);
+        assert_eq!(e.variants[0].doc.as_deref(), Some("No auth"));
+        assert_eq!(e.variants[1].doc.as_deref(), Some("SASL"));
+    }
+
+    #[test]
+    fn test_parse_protocol_with_config() {
+        let input = r#"
+            const ACME_SVC_PROGRAM = 0x41434d45;
+            struct acme_ping_args {
+                int seq;
+            };
+            enum acme_proc {
+                ACME_CALL_PING = 1
+            };
+        "#;
+        let config = ProtocolConfig {
+            program_const: "ACME_SVC_PROGRAM".to_string(),
+            procedure_enum: "acme_proc".to_string(),
+            proc_prefix: "ACME_CALL_".to_string(),
+            ..ProtocolConfig::for_name("acme")
+        };
+        let result = parse_protocol_with(input, "acme", config).unwrap();
+        assert_eq!(result.name, "acme");
+        assert_eq!(result.program_id, Some(0x41434d45));
+        assert_eq!(result.procedures.len(), 1);
+        assert_eq!(result.procedures[0].args.as_deref(), Some("acme_ping_args"));
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let input = "/* header */\nstruct point {\n    int x\n    int y;\n};\n";
+        let err = parse_protocol(input).unwrap_err();
+        assert_eq!(err.message, "expected `;`, found `int`");
+        assert_eq!(err.note.as_deref(), Some("while parsing a field in a struct"));
+        let location = err.location.unwrap();
+        assert_eq!((location.line, location.column), (3, 10));
+        assert_eq!(location.source_line, "    int x");
+
+        let err = parse_protocol("const A = 1;\nfoo bar;\n").unwrap_err();
+        assert!(err.message.ends_with("found `foo`"), "{}", err.message);
+        assert_eq!(err.location.map(|l| (l.line, l.column)), Some((2, 1)));
+    }
+
+    #[test]
+    fn test_parse_warnings() {
+        let input = r#"
+            const LOCAL = 4;
+            const EXTERNAL = VIR_UNKNOWN_BUFLEN;
+            enum e {
+                E_A = 1,
+                E_B = EXTERNAL,
+                E_C = LOCAL
+            };
+            typedef opaque blob<VIR_UNKNOWN_BUFLEN>;
+        "#;
+        let result = parse_protocol(input).unwrap();
+        let warnings: Vec<_> = result
+            .warnings
+            .iter()
+            .map(|w| (w.message.as_str(), w.location.as_ref().unwrap().line))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                (
+                    "constant `EXTERNAL` is skipped: its value refers to `VIR_UNKNOWN_BUFLEN`, \
+                     which is not defined in this file or libvirt.h",
+                    3
+                ),
+                (
+                    "variant `E_B` of enum `e` is skipped: its value refers to `EXTERNAL`, \
+                     which could not be resolved",
+                    6
+                ),
+                (
+                    "the maximum length of `blob` refers to `VIR_UNKNOWN_BUFLEN`, which is not \
+                     defined in this file or libvirt.h and is not enforced",
+                    9
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_unresolved_fixed_length() {
+        let input = "const A = 1;\nstruct s {\n    opaque uuid[VIR_UNKNOWN_BUFLEN];\n};\n";
+        let err = parse_protocol(input).unwrap_err();
+        assert_eq!(err.severity, Severity::Error);
+        assert_eq!(
+            err.message,
+            "the length of `uuid` refers to `VIR_UNKNOWN_BUFLEN`, which is not defined in this file or libvirt.h"
+        );
+        assert_eq!(err.location.map(|l| (l.line, l.column)), Some((3, 12)));
+
+        let err = parse_protocol("const N = -1;\ntypedef int ints[N];\n").unwrap_err();
+        assert_eq!(err.message, "the length of `ints` is -1, which is not a valid length");
+    }
 }
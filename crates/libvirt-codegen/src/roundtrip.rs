@@ -0,0 +1,432 @@
+//! Property-test generator.
+//!
+//! Emits a proptest strategy for every generated type and a test per
+//! struct, enum and union asserting `from_bytes(to_bytes(x)) == x`. The
+//! output is meant to be `include!`d into a `#[cfg(test)]` module next to
+//! the generated code (see `libvirt-pure`'s build script).
+
+use crate::ast::*;
+use crate::generator::{
+    enum_variant_ident, fixed_len, prepare_bundle, to_rust_field_name, to_rust_type_name,
+    type_to_tokens, union_variants,
+};
+use heck::ToSnakeCase;
+use proc_macro2::{Ident, TokenStream};
+use quote::{format_ident, quote};
+use std::collections::HashSet;
+
+/// Longest string or opaque value generated, whatever the declared bound.
+const MAX_GENERATED_BYTES: u32 = 16;
+
+/// Most elements generated for a variable-length array. Kept small since
+/// arrays nest (e.g. domain stats records holding typed parameters).
+const MAX_GENERATED_ELEMS: u32 = 4;
+
+/// How deep strategies for recursive types (linked lists, trees) go
+/// before ending with `None` or an empty array.
+const MAX_GENERATED_DEPTH: u32 = 3;
+
+/// Fields per tuple when building struct strategies; proptest implements
+/// `Strategy` for tuples of up to 12 elements.
+const TUPLE_CHUNK: usize = 10;
+
+/// Generate round-trip property tests for all protocols in a bundle.
+pub fn generate_roundtrip_tests(bundle: &ProtocolBundle) -> String {
+    let bundle = &prepare_bundle(bundle);
+    // Each type with its protocol's type prefix, for naming variants
+    let types: Vec<(&TypeDef, &str)> = [&bundle.remote, &bundle.qemu, &bundle.lxc]
+        .into_iter()
+        .flatten()
+        .flat_map(|p| p.types.iter().map(|t| (t, p.config.type_prefix.as_str())))
+        .collect();
+
+    let mut tokens = quote! {
+        // Generated round-trip property tests. Do not edit manually.
+
+        use super::generated::*;
+        use libvirt_xdr::{XdrDecode, XdrEncode};
+        use proptest::prelude::*;
+        // Not imported by name, as generated types may share these names
+        use proptest::test_runner as runner;
+
+        fn config() -> runner::Config {
+            runner::Config {
+                cases: 32,
+                // Relative to the crate root, where `cargo test` runs.
+                failure_persistence: Some(Box::new(runner::FileFailurePersistence::Direct(
+                    "proptest-regressions/roundtrip.txt",
+                ))),
+                ..runner::Config::default()
+            }
+        }
+
+        fn check_roundtrip<T>(strategy: BoxedStrategy<T>)
+        where
+            T: XdrEncode + XdrDecode + PartialEq + std::fmt::Debug,
+        {
+            runner::TestRunner::new(config())
+                .run(&strategy, |value| {
+                    let bytes = libvirt_xdr::to_bytes(&value)
+                        .map_err(|e| runner::TestCaseError::fail(e.to_string()))?;
+                    let decoded = libvirt_xdr::from_bytes_exact::<T>(&bytes)
+                        .map_err(|e| runner::TestCaseError::fail(e.to_string()))?;
+                    prop_assert_eq!(decoded, value);
+                    Ok(())
+                })
+                .unwrap_or_else(|e| panic!("{}", e));
+        }
+    };
+
+    let all: Vec<&TypeDef> = types.iter().map(|(type_def, _)| *type_def).collect();
+    let recursive = recursive_types(&all);
+    let outside = Recursion {
+        types: &recursive,
+        inside: false,
+    };
+
+    // Types without any constructible value (e.g. enums whose variants all
+    // reference other constants) get neither a strategy nor a test.
+    let mut seen = HashSet::new();
+    for (type_def, type_prefix) in types {
+        let name = type_def.name();
+        let rec = Recursion {
+            inside: recursive.contains(name),
+            ..outside
+        };
+        let (strategy, tested) = match type_def {
+            TypeDef::Struct(s) => (Some(struct_strategy(s, rec)), true),
+            TypeDef::Enum(e) => (enum_strategy(e, type_prefix), true),
+            TypeDef::Union(u) => (union_strategy(u, type_prefix, rec), true),
+            TypeDef::Typedef(t) => (Some(type_strategy(&t.target, rec)), false),
+        };
+        let Some(strategy) = strategy else { continue };
+        if !seen.insert(name) {
+            continue;
+        }
+
+        let ty = format_ident!("{}", to_rust_type_name(name));
+        let arb = arb_fn(name);
+        let (param, call) = if rec.inside {
+            (quote! { depth: u32 }, quote! { #arb(#MAX_GENERATED_DEPTH) })
+        } else {
+            (quote! {}, quote! { #arb() })
+        };
+        tokens.extend(quote! {
+            fn #arb(#param) -> BoxedStrategy<#ty> {
+                #strategy.boxed()
+            }
+        });
+
+        if tested {
+            let test = format_ident!("roundtrip_{}", to_rust_type_name(name).to_snake_case());
+            tokens.extend(quote! {
+                #[test]
+                fn #test() {
+                    check_roundtrip(#call);
+                }
+            });
+        }
+    }
+
+    let file = syn::parse2(tokens).expect("generated invalid Rust code");
+    prettyplease::unparse(&file)
+}
+
+/// Which types have recursive strategies, taking a `depth` argument, and
+/// whether the strategy being built is one of them.
+#[derive(Clone, Copy)]
+struct Recursion<'a> {
+    types: &'a HashSet<&'a str>,
+    inside: bool,
+}
+
+/// Types whose values can contain values of the same type.
+fn recursive_types<'a>(types: &[&'a TypeDef]) -> HashSet<&'a str> {
+    let mut refers: std::collections::HashMap<&str, Vec<&str>> = std::collections::HashMap::new();
+    for type_def in types {
+        let members: Vec<&Type> = match type_def {
+            TypeDef::Struct(s) => s.fields.iter().map(|f| &f.ty).collect(),
+            TypeDef::Union(u) => {
+                let arms = u.cases.iter().filter_map(|c| c.field.as_ref()).map(|f| &f.ty);
+                arms.chain(u.default.as_deref()).collect()
+            }
+            TypeDef::Typedef(t) => vec![&t.target],
+            TypeDef::Enum(_) => Vec::new(),
+        };
+        let names = refers.entry(type_def.name()).or_default();
+        for ty in members {
+            named_types(ty, names);
+        }
+    }
+
+    let reaches_itself = |start: &str| {
+        let mut stack: Vec<&str> = refers.get(start).cloned().unwrap_or_default();
+        let mut seen = HashSet::new();
+        while let Some(name) = stack.pop() {
+            if name == start {
+                return true;
+            }
+            if seen.insert(name) {
+                stack.extend(refers.get(name).into_iter().flatten());
+            }
+        }
+        false
+    };
+    refers.keys().copied().filter(|name| reaches_itself(name)).collect()
+}
+
+/// Names of the types `ty` refers to.
+fn named_types<'a>(ty: &'a Type, names: &mut Vec<&'a str>) {
+    match ty {
+        Type::Named(name) => names.push(name),
+        Type::Optional(inner) | Type::RecursiveOptional(inner) | Type::Array { elem: inner, .. } => {
+            named_types(inner, names)
+        }
+        _ => {}
+    }
+}
+
+/// Whether `ty` refers to a recursive type.
+fn mentions_recursive(ty: &Type, rec: Recursion<'_>) -> bool {
+    let mut names = Vec::new();
+    named_types(ty, &mut names);
+    names.iter().any(|name| rec.types.contains(name))
+}
+
+/// Name of the strategy function for a named type.
+fn arb_fn(name: &str) -> Ident {
+    format_ident!("arb_{}", to_rust_type_name(name).to_snake_case())
+}
+
+fn struct_strategy(s: &StructDef, rec: Recursion<'_>) -> TokenStream {
+    let name = format_ident!("{}", to_rust_type_name(&s.name));
+    if s.fields.is_empty() {
+        return quote! { Just(#name {}) };
+    }
+
+    let idents: Vec<_> = s
+        .fields
+        .iter()
+        .map(|f| format_ident!("{}", to_rust_field_name(&f.name)))
+        .collect();
+    let vars: Vec<_> = (0..s.fields.len()).map(|i| format_ident!("f{}", i)).collect();
+    let strategies: Vec<_> = s.fields.iter().map(|f| type_strategy(&f.ty, rec)).collect();
+
+    let groups = strategies.chunks(TUPLE_CHUNK).map(|c| quote! { (#(#c,)*) });
+    let patterns = vars.chunks(TUPLE_CHUNK).map(|c| quote! { (#(#c,)*) });
+    assert!(
+        strategies.len() <= TUPLE_CHUNK * 12,
+        "struct {} has too many fields for a tuple strategy",
+        s.name
+    );
+
+    quote! {
+        (#(#groups,)*).prop_map(|(#(#patterns,)*)| #name { #(#idents: #vars),* })
+    }
+}
+
+fn enum_strategy(e: &EnumDef, type_prefix: &str) -> Option<TokenStream> {
+    let name = format_ident!("{}", to_rust_type_name(&e.name));
+    let variants: Vec<_> = e
+        .variants
+        .iter()
+        .filter_map(|v| enum_variant_ident(v, &e.name, type_prefix))
+        .collect();
+    if variants.is_empty() {
+        return None;
+    }
+    Some(quote! {
+        proptest::sample::select(vec![#(#name::#variants),*])
+    })
+}
+
+fn union_strategy(u: &UnionDef, type_prefix: &str, rec: Recursion<'_>) -> Option<TokenStream> {
+    let name = format_ident!("{}", to_rust_type_name(&u.name));
+    let variants = union_variants(u, type_prefix);
+    let labels: Vec<_> = variants.iter().filter_map(|v| v.discriminant.as_ref()).collect();
+    let arms: Vec<_> = variants
+        .iter()
+        .map(|v| {
+            let variant = &v.ident;
+            let strategy = v.ty.map(|ty| type_strategy(ty, rec));
+            match (&v.discriminant, strategy) {
+                (Some(_), Some(strategy)) => quote! { #strategy.prop_map(#name::#variant).boxed() },
+                (Some(_), None) => quote! { Just(#name::#variant).boxed() },
+                // The default arm takes any discriminant but the labelled ones
+                (None, strategy) => {
+                    let discriminant = quote! {
+                        any::<i32>().prop_filter("case label", |d: &i32| ![#((#labels) as i32),*].contains(d))
+                    };
+                    match strategy {
+                        Some(strategy) => quote! {
+                            (#discriminant, #strategy).prop_map(|(d, v)| #name::#variant(d, v)).boxed()
+                        },
+                        None => quote! { #discriminant.prop_map(#name::#variant).boxed() },
+                    }
+                }
+            }
+        })
+        .collect();
+    if arms.is_empty() {
+        return None;
+    }
+    Some(quote! {
+        proptest::strategy::Union::new(vec![#(#arms),*])
+    })
+}
+
+/// Strategy expression producing values of `ty`.
+///
+/// Within a recursive strategy, optional values and arrays that lead back
+/// to a recursive type use up one level of `depth`, and are `None` or
+/// empty once it runs out.
+fn type_strategy(ty: &Type, rec: Recursion<'_>) -> TokenStream {
+    let guarded = rec.inside && mentions_recursive(ty, rec);
+    match ty {
+        Type::Void => quote! { Just(()) },
+        Type::Int => quote! { any::<i32>() },
+        Type::UInt => quote! { any::<u32>() },
+        Type::Hyper => quote! { any::<i64>() },
+        Type::UHyper => quote! { any::<u64>() },
+        // NaN would never compare equal after a round trip.
+        Type::Float => quote! { proptest::num::f32::NORMAL },
+        Type::Double => quote! { proptest::num::f64::NORMAL },
+        Type::Quadruple => quote! { any::<[u8; 16]>().prop_map(libvirt_xdr::opaque::Quadruple) },
+        Type::Bool => quote! { any::<bool>() },
+        Type::String { max_len } => {
+            // Printable ASCII, so the byte length equals the char count.
+            let regex = format!("[ -~]{{0,{}}}", bounded(max_len.as_ref(), MAX_GENERATED_BYTES));
+            quote! { #regex }
+        }
+        Type::Opaque { len } => match len {
+            LengthSpec::Fixed(n) if n.as_u32() == Some(16) => {
+                quote! { any::<[u8; 16]>().prop_map(FixedOpaque16) }
+            }
+            LengthSpec::Fixed(n) => {
+                let n = fixed_len(n);
+                quote! {
+                    proptest::collection::vec(any::<u8>(), #n)
+                        .prop_map(|v| <[u8; #n]>::try_from(v).unwrap())
+                }
+            }
+            LengthSpec::Variable { max } => {
+                let max = bounded(max.as_ref(), MAX_GENERATED_BYTES) as usize;
+                quote! { proptest::collection::vec(any::<u8>(), 0..=#max) }
+            }
+        },
+        Type::Array { elem, len } => {
+            let elem_strategy = type_strategy(elem, rec);
+            match len {
+                LengthSpec::Fixed(n) => {
+                    let n = fixed_len(n);
+                    let elem_type = type_to_tokens(elem);
+                    quote! {
+                        proptest::collection::vec(#elem_strategy, #n)
+                            .prop_map(|v| <[#elem_type; #n]>::try_from(v).unwrap())
+                    }
+                }
+                LengthSpec::Variable { max } => {
+                    let max = bounded(max.as_ref(), MAX_GENERATED_ELEMS) as usize;
+                    let strategy = quote! { proptest::collection::vec(#elem_strategy, 0..=#max) };
+                    if guarded {
+                        depth_limited(quote! { Vec::new() }, strategy)
+                    } else {
+                        strategy
+                    }
+                }
+            }
+        }
+        Type::Optional(inner) => {
+            let inner = type_strategy(inner, rec);
+            let strategy = quote! { proptest::option::of(#inner) };
+            if guarded {
+                depth_limited(quote! { None }, strategy)
+            } else {
+                strategy
+            }
+        }
+        Type::RecursiveOptional(inner) => {
+            let inner = type_strategy(inner, rec);
+            let strategy = quote! { proptest::option::of(#inner.prop_map(Box::new)) };
+            if guarded {
+                depth_limited(quote! { None }, strategy)
+            } else {
+                strategy
+            }
+        }
+        // `char`, `short` and friends are parsed as named Rust primitives.
+        Type::Named(name) if is_primitive(name) => {
+            let ty = format_ident!("{}", name);
+            quote! { any::<#ty>() }
+        }
+        Type::Named(name) if rec.types.contains(name.as_str()) => {
+            let arb = arb_fn(name);
+            if rec.inside {
+                quote! { #arb(depth) }
+            } else {
+                quote! { #arb(#MAX_GENERATED_DEPTH) }
+            }
+        }
+        Type::Named(name) => {
+            let arb = arb_fn(name);
+            quote! { #arb() }
+        }
+        Type::Flags(name) => {
+            let ident = format_ident!("{}", name);
+            quote! { any::<u32>().prop_map(#ident::from_bits_retain) }
+        }
+    }
+}
+
+/// `strategy`, one level deeper, or just `last` at the depth limit.
+fn depth_limited(last: TokenStream, strategy: TokenStream) -> TokenStream {
+    quote! {
+        if depth == 0 {
+            Just(#last).boxed()
+        } else {
+            let depth = depth - 1;
+            #strategy.boxed()
+        }
+    }
+}
+
+fn is_primitive(name: &str) -> bool {
+    matches!(
+        name,
+        "u8" | "u16" | "u32" | "u64" | "i8" | "i16" | "i32" | "i64" | "bool"
+    )
+}
+
+fn bounded(max: Option<&ConstValue>, cap: u32) -> u32 {
+    max.and_then(ConstValue::as_u32).map_or(cap, |m| m.min(cap))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_protocol;
+
+    #[test]
+    fn test_generate_roundtrip_tests() {
+        let input = r#"
+            const NAME_MAX = 64;
+            struct remote_pair {
+                string name<NAME_MAX>;
+                int value;
+            };
+            enum remote_state {
+                REMOTE_STATE_OFF = 0,
+                REMOTE_STATE_ON = 1
+            };
+        "#;
+        let bundle = ProtocolBundle {
+            remote: Some(parse_protocol(input).unwrap()),
+            ..ProtocolBundle::default()
+        };
+        let code = generate_roundtrip_tests(&bundle);
+        assert!(code.contains("fn arb_pair() -> BoxedStrategy<Pair>"));
+        assert!(code.contains("fn roundtrip_pair()"));
+        assert!(code.contains("fn roundtrip_state()"));
+        assert!(code.contains("select(vec![State::Off, State::On])"));
+    }
+}
@@ -0,0 +1,23 @@
+/* Recursive types: the linked list from RFC 4506 and a tree. */
+
+struct stringentry {
+    string item<>;
+    stringentry *next;
+};
+
+typedef stringentry *stringlist;
+
+struct node {
+    int value;
+    node *left;
+    node *right;
+    node children<>;
+};
+
+/* Recursion through a typedef, as in rpcgen's classic examples. */
+typedef struct namenode *namelist;
+
+struct namenode {
+    string name<255>;
+    namelist next;
+};
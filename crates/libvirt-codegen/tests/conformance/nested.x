@@ -0,0 +1,21 @@
+/* Anonymous types declared inside other declarations. */
+
+struct config {
+    struct {
+        string host<>;
+        unsigned int port;
+    } server;
+    enum {
+        MODE_ACTIVE,
+        MODE_PASSIVE
+    } mode;
+    union switch (bool enabled) {
+    case TRUE:
+        struct {
+            int interval;
+            int timeout;
+        } settings;
+    case FALSE:
+        void;
+    } keepalive;
+};
@@ -0,0 +1,23 @@
+/* An rpcgen-style program definition. */
+
+struct ping_args {
+    unsigned int seq;
+    opaque payload<>;
+};
+
+struct ping_ret {
+    unsigned int seq;
+};
+
+program PING_PROGRAM {
+    version PING_V1 {
+        void PING_NULL(void) = 0;
+        int PING_ECHO(int) = 1;
+    } = 1;
+    version PING_V2 {
+        void PING_NULL(void) = 0;
+        ping_ret PING_PING(ping_args) = 1;
+        string PING_HOSTNAME(void) = 2;
+        int PING_ADD(int, int) = 3;
+    } = 2;
+} = 0x20000099;
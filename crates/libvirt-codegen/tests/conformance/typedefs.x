@@ -0,0 +1,41 @@
+/* Typedefs of every kind of declaration, and the less common literals. */
+
+const OCTAL = 017;
+const HEX = 0x1F;
+const NEGATIVE = -1;
+
+typedef int counter;
+typedef unsigned flags;
+typedef opaque digest[20];
+typedef string name<64>;
+typedef counter counters<>;
+typedef name *optional_name;
+typedef quadruple precise;
+
+typedef struct {
+    counter hits;
+    unsigned hyper bytes;
+} stats;
+
+typedef enum {
+    LEVEL_LOW = 1,
+    LEVEL_HIGH = 2
+} level;
+
+typedef union switch (level which) {
+case LEVEL_LOW:
+    int low;
+case LEVEL_HIGH:
+    hyper high;
+} reading;
+
+struct sample {
+    struct stats totals;
+    enum level alert;
+    union reading last;
+    precise exact;
+    digest sum;
+    interval_ms interval;
+};
+
+typedef unsigned int interval_ms;
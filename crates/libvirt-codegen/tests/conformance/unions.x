@@ -0,0 +1,28 @@
+/* Case labels sharing an arm, and default arms. */
+
+const ANSWER = 42;
+
+union shared switch (int code) {
+case 1:
+case 2:
+case ANSWER:
+    string message<>;
+case 3:
+    void;
+default:
+    int other;
+};
+
+union open_ended switch (unsigned int tag) {
+case 0:
+    hyper value;
+default:
+    void;
+};
+
+union flag switch (bool set) {
+case TRUE:
+    int value;
+case FALSE:
+    void;
+};
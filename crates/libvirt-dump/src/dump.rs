@@ -0,0 +1,358 @@
+//! Decoding, filtering and printing of packets.
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use bytes::Bytes;
+use libvirt_pure::generated::payload::{decode_payload, PayloadVisitor};
+use libvirt_pure::generated::{procedure_info, program_info, PROGRAMS};
+use libvirt_pure::packet::{MessageType, Packet, Status};
+use libvirt_pure::server::RemoteError;
+use libvirt_pure::transport::Direction;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+/// A decoded payload, kept both as JSON (for `--json` and the domain
+/// filter) and as `Debug` output (for the text format).
+struct Payload {
+    type_name: &'static str,
+    json: Value,
+    debug: String,
+}
+
+struct Decoder;
+
+impl PayloadVisitor for Decoder {
+    type Output = Payload;
+
+    fn visit<T: Serialize + std::fmt::Debug>(self, type_name: &'static str, value: &T) -> Payload {
+        Payload {
+            type_name,
+            json: serde_json::to_value(value).unwrap_or(Value::Null),
+            debug: format!("{:#?}", value),
+        }
+    }
+}
+
+/// Turns the packets of one session into printable entries.
+///
+/// Packets are shown if they pass every filter. Replies and stream data
+/// carry no domain, so they are shown when the call they answer was.
+#[derive(Debug, Clone, Default)]
+pub struct Dumper {
+    json: bool,
+    /// `(program, procedure)` of the procedures to show; all if empty.
+    procedures: Vec<(u32, u32)>,
+    domain: Option<String>,
+    /// Serials of the shown calls, whose replies and streams to show.
+    followed: HashSet<i32>,
+}
+
+impl Dumper {
+    /// A dumper printing JSON lines if `json` is set, text otherwise.
+    pub fn new(json: bool) -> Self {
+        Self { json, ..Self::default() }
+    }
+
+    /// Only show packets of the procedure with the given `.x` name
+    /// (`REMOTE_PROC_DOMAIN_CREATE`) or method name (`domain_create`).
+    pub fn procedure(mut self, name: &str) -> Result<Self, String> {
+        let found: Vec<_> = PROGRAMS
+            .iter()
+            .filter_map(|program| Some((program.number, program.procedure_by_name(name)?.number)))
+            .collect();
+        if found.is_empty() {
+            return Err(format!("unknown procedure {}", name));
+        }
+        self.procedures.extend(found);
+        Ok(self)
+    }
+
+    /// Only show packets naming the domain `name`, and the replies and
+    /// streams of such calls.
+    pub fn domain(mut self, name: impl Into<String>) -> Self {
+        self.domain = Some(name.into());
+        self
+    }
+
+    /// The entry to print for a packet (without its length prefix), or
+    /// `None` if the filters hide it.
+    pub fn entry(&mut self, direction: Direction, elapsed: Duration, data: Bytes) -> Option<String> {
+        let len = data.len();
+        let packet = match Packet::decode(data) {
+            Ok(packet) => packet,
+            Err(e) => {
+                if !self.procedures.is_empty() || self.domain.is_some() {
+                    return None;
+                }
+                return Some(self.invalid(direction, elapsed, len, &e.to_string()));
+            }
+        };
+
+        if !self.procedures.is_empty() && !self.procedures.contains(&(packet.program, packet.procedure)) {
+            return None;
+        }
+
+        let payload = decode(&packet);
+        if let Some(domain) = &self.domain {
+            let named = matches!(&payload, Some(Ok(payload)) if names_domain(&payload.json, domain));
+            let followed = matches!(packet.msg_type, MessageType::Reply | MessageType::Stream | MessageType::StreamHole)
+                && self.followed.contains(&packet.serial);
+            if !named && !followed {
+                return None;
+            }
+            if named && packet.msg_type == MessageType::Call {
+                self.followed.insert(packet.serial);
+            }
+        }
+
+        Some(if self.json {
+            json_entry(direction, elapsed, &packet, payload)
+        } else {
+            text_entry(direction, elapsed, &packet, payload)
+        })
+    }
+
+    fn invalid(&self, direction: Direction, elapsed: Duration, len: usize, error: &str) -> String {
+        if self.json {
+            json!({
+                "elapsed": elapsed.as_secs_f64(),
+                "direction": direction_name(direction),
+                "length": len,
+                "error": error,
+            })
+            .to_string()
+        } else {
+            format!(
+                "{:>12.6} {} invalid packet of {} bytes: {}",
+                elapsed.as_secs_f64(),
+                arrow(direction),
+                len,
+                error
+            )
+        }
+    }
+}
+
+/// Decode the payload of `packet`: `None` if it has no type (stream data,
+/// calls without arguments, unknown procedures), or why it failed to
+/// decode.
+fn decode(packet: &Packet) -> Option<Result<Payload, String>> {
+    let payload = &packet.payload[..];
+    let decoded = match (packet.msg_type, packet.status) {
+        (MessageType::Stream | MessageType::StreamHole, _) => return None,
+        (_, Status::Error) => libvirt_xdr::from_bytes::<RemoteError>(payload)
+            .map(|error| Decoder.visit("Error", &error))
+            .map_err(|e| error_chain(&e)),
+        (MessageType::Reply, _) => decode_payload(packet.program, packet.procedure, true, payload, Decoder)?
+            .map_err(|e| error_chain(&e)),
+        _ => decode_payload(packet.program, packet.procedure, false, payload, Decoder)?
+            .map_err(|e| error_chain(&e)),
+    };
+    Some(decoded)
+}
+
+/// `e` followed by the errors it wraps, e.g. `invalid value at byte 8 in
+/// DomainGetXmlDescRet.xml: unexpected end of input`.
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        message += ": ";
+        message += &e.to_string();
+        source = e.source();
+    }
+    message
+}
+
+/// Whether `value` contains a domain (an object with a `name`, a `uuid`
+/// and an `id`, like `remote_nonnull_domain`) called `name`.
+fn names_domain(value: &Value, name: &str) -> bool {
+    match value {
+        Value::Object(fields) => {
+            let is_domain = fields.contains_key("uuid")
+                && fields.contains_key("id")
+                && fields.get("name").and_then(Value::as_str) == Some(name);
+            is_domain || fields.values().any(|v| names_domain(v, name))
+        }
+        Value::Array(values) => values.iter().any(|v| names_domain(v, name)),
+        _ => false,
+    }
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Sent => "sent",
+        Direction::Received => "received",
+    }
+}
+
+fn arrow(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Sent => "->",
+        Direction::Received => "<-",
+    }
+}
+
+fn type_name(msg_type: MessageType) -> &'static str {
+    match msg_type {
+        MessageType::Call => "call",
+        MessageType::Reply => "reply",
+        MessageType::Message => "event",
+        MessageType::Stream => "stream",
+        MessageType::StreamHole => "hole",
+    }
+}
+
+fn status_name(status: Status) -> &'static str {
+    match status {
+        Status::Ok => "ok",
+        Status::Error => "error",
+        Status::Continue => "continue",
+    }
+}
+
+fn json_entry(
+    direction: Direction,
+    elapsed: Duration,
+    packet: &Packet,
+    payload: Option<Result<Payload, String>>,
+) -> String {
+    let mut entry = Map::new();
+    entry.insert("elapsed".into(), json!(elapsed.as_secs_f64()));
+    entry.insert("direction".into(), json!(direction_name(direction)));
+    entry.insert("program".into(), json!(program_info(packet.program).map(|p| p.name)));
+    entry.insert("program_number".into(), json!(packet.program));
+    entry.insert("version".into(), json!(packet.version));
+    entry.insert(
+        "procedure".into(),
+        json!(procedure_info(packet.program, packet.procedure).map(|p| p.name)),
+    );
+    entry.insert("procedure_number".into(), json!(packet.procedure));
+    entry.insert("type".into(), json!(type_name(packet.msg_type)));
+    entry.insert("serial".into(), json!(packet.serial));
+    entry.insert("status".into(), json!(status_name(packet.status)));
+    entry.insert("length".into(), json!(packet.payload.len()));
+    if let Some(length) = packet.hole_length() {
+        entry.insert("hole_length".into(), json!(length));
+    }
+    match payload {
+        Some(Ok(payload)) => {
+            entry.insert("payload_type".into(), json!(payload.type_name));
+            entry.insert("payload".into(), payload.json);
+        }
+        Some(Err(e)) => {
+            entry.insert("error".into(), json!(e));
+        }
+        None => {}
+    }
+    Value::Object(entry).to_string()
+}
+
+fn text_entry(
+    direction: Direction,
+    elapsed: Duration,
+    packet: &Packet,
+    payload: Option<Result<Payload, String>>,
+) -> String {
+    let procedure = match procedure_info(packet.program, packet.procedure) {
+        Some(procedure) => procedure.name.to_string(),
+        None => format!("program {:#x} procedure {}", packet.program, packet.procedure),
+    };
+    let mut entry = format!(
+        "{:>12.6} {} {:<6} {} serial={} status={}",
+        elapsed.as_secs_f64(),
+        arrow(direction),
+        type_name(packet.msg_type),
+        procedure,
+        packet.serial,
+        status_name(packet.status),
+    );
+    match payload {
+        Some(Ok(payload)) => {
+            for line in payload.debug.lines() {
+                let _ = write!(entry, "\n    {}", line);
+            }
+        }
+        Some(Err(e)) => {
+            let _ = write!(entry, "\n    undecodable payload of {} bytes: {}", packet.payload.len(), e);
+        }
+        None => match packet.hole_length() {
+            Some(length) => {
+                let _ = write!(entry, "\n    <hole of {} bytes>", length);
+            }
+            None if !packet.payload.is_empty() => {
+                let _ = write!(entry, "\n    <{} bytes>", packet.payload.len());
+            }
+            None => {}
+        },
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libvirt_pure::generated::{DomainGetInfoArgs, DomainGetInfoRet, FixedOpaque16, NonnullDomain};
+
+    const GET_INFO: u32 = 16;
+
+    fn domain(name: &str) -> NonnullDomain {
+        NonnullDomain { name: name.to_string(), uuid: FixedOpaque16::new([0; 16]), id: 1 }
+    }
+
+    fn call(serial: i32, name: &str) -> Packet {
+        let args = DomainGetInfoArgs { dom: domain(name) };
+        Packet::new_call(GET_INFO, serial, Bytes::from(libvirt_xdr::to_bytes(&args).unwrap()))
+    }
+
+    fn reply(call: &Packet) -> Packet {
+        let ret = DomainGetInfoRet { state: 1, max_mem: 1024, memory: 512, nr_virt_cpu: 2, cpu_time: 0 };
+        Packet::new_reply(call, Status::Ok, Bytes::from(libvirt_xdr::to_bytes(&ret).unwrap()))
+    }
+
+    fn entry(dumper: &mut Dumper, direction: Direction, packet: &Packet) -> Option<String> {
+        let data = packet.encode().freeze().split_off(4);
+        dumper.entry(direction, Duration::from_millis(5), data)
+    }
+
+    #[test]
+    fn test_json_entry() {
+        let mut dumper = Dumper::new(true);
+        let call = call(3, "vm1");
+        let line = entry(&mut dumper, Direction::Sent, &call).unwrap();
+        let value: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["program"], "remote");
+        assert_eq!(value["procedure"], "REMOTE_PROC_DOMAIN_GET_INFO");
+        assert_eq!((value["type"].as_str(), value["serial"].as_i64()), (Some("call"), Some(3)));
+        assert_eq!(value["payload_type"], "DomainGetInfoArgs");
+        assert_eq!(value["payload"]["dom"]["name"], "vm1");
+
+        let line = entry(&mut dumper, Direction::Received, &reply(&call)).unwrap();
+        let value: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["payload_type"], "DomainGetInfoRet");
+        assert_eq!(value["payload"]["memory"], 512);
+
+        let text = entry(&mut Dumper::new(false), Direction::Sent, &call).unwrap();
+        assert!(text.contains("-> call   REMOTE_PROC_DOMAIN_GET_INFO serial=3 status=ok"));
+        assert!(text.contains("DomainGetInfoArgs {"));
+    }
+
+    #[test]
+    fn test_filters() {
+        let mut dumper = Dumper::new(false).domain("vm1");
+        let (vm1, vm2) = (call(1, "vm1"), call(2, "vm2"));
+        assert!(entry(&mut dumper, Direction::Sent, &vm1).is_some());
+        assert!(entry(&mut dumper, Direction::Sent, &vm2).is_none());
+        // Replies follow their call
+        assert!(entry(&mut dumper, Direction::Received, &reply(&vm1)).is_some());
+        assert!(entry(&mut dumper, Direction::Received, &reply(&vm2)).is_none());
+
+        let mut dumper = Dumper::new(false).procedure("domain_suspend").unwrap();
+        assert!(entry(&mut dumper, Direction::Sent, &vm1).is_none());
+        let mut dumper = Dumper::new(false).procedure("REMOTE_PROC_DOMAIN_GET_INFO").unwrap();
+        assert!(entry(&mut dumper, Direction::Sent, &vm1).is_some());
+        assert!(Dumper::new(false).procedure("no_such_call").is_err());
+    }
+}
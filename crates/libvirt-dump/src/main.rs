@@ -0,0 +1,297 @@
+//! Packet dump and protocol analyzer for the libvirt RPC protocol.
+//!
+//! Prints the packets of a session recorded with `RecordingTransport`, or
+//! of live sessions, sitting as a proxy between clients and the daemon:
+//!
+//! ```text
+//! libvirt-dump session.rec --domain vm1
+//! libvirt-dump --listen /tmp/libvirt-dump.sock --json
+//! virsh -c 'qemu+unix:///system?socket=/tmp/libvirt-dump.sock' list
+//! ```
+
+mod dump;
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
+
+use bytes::{Bytes, BytesMut};
+use libvirt_pure::packet::MAX_PACKET_SIZE;
+use libvirt_pure::transport::{Direction, Recording};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+
+use dump::Dumper;
+
+const USAGE: &str = "\
+Usage: libvirt-dump [OPTIONS] <RECORDING>
+       libvirt-dump [OPTIONS] --listen <SOCKET>
+
+Decode and print libvirt RPC packets: those of a session recorded with
+RecordingTransport, or those of the clients connecting to SOCKET, which
+are forwarded to the daemon.
+
+Options:
+  -l, --listen <SOCKET>     Proxy the clients connecting to SOCKET
+  -c, --connect <SOCKET>    Daemon socket to proxy to
+                            [default: /var/run/libvirt/libvirt-sock]
+  -p, --procedure <NAME>    Only show packets of procedure NAME, given as
+                            REMOTE_PROC_DOMAIN_CREATE or domain_create;
+                            may be repeated
+  -d, --domain <NAME>       Only show packets naming domain NAME, and the
+                            replies and streams of such calls
+      --json                Print one JSON object per packet
+  -h, --help                Print this help";
+
+const DEFAULT_DAEMON_SOCKET: &str = "/var/run/libvirt/libvirt-sock";
+
+/// Options from the command line.
+#[derive(Debug, Default)]
+struct Options {
+    recording: Option<PathBuf>,
+    listen: Option<PathBuf>,
+    connect: Option<PathBuf>,
+    procedures: Vec<String>,
+    domain: Option<String>,
+    json: bool,
+}
+
+impl Options {
+    /// A dumper applying the filters.
+    fn dumper(&self) -> Result<Dumper, String> {
+        let mut dumper = Dumper::new(self.json);
+        for procedure in &self.procedures {
+            dumper = dumper.procedure(procedure)?;
+        }
+        if let Some(domain) = &self.domain {
+            dumper = dumper.domain(domain.clone());
+        }
+        Ok(dumper)
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |flag: &str| {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", flag))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "--json" => options.json = true,
+            "-l" | "--listen" => options.listen = Some(value(&flag)?.into()),
+            "-c" | "--connect" => options.connect = Some(value(&flag)?.into()),
+            "-p" | "--procedure" => options.procedures.push(value(&flag)?),
+            "-d" | "--domain" => options.domain = Some(value(&flag)?),
+            _ if flag.starts_with('-') => {
+                return Err(format!("unknown option {}\n\n{}", flag, USAGE));
+            }
+            _ if options.recording.is_none() => options.recording = Some(arg.into()),
+            _ => return Err(format!("unexpected argument {}\n\n{}", arg, USAGE)),
+        }
+    }
+
+    Ok(options)
+}
+
+/// Print the packets of a recording.
+fn dump_recording(path: &Path, mut dumper: Dumper) -> Result<(), String> {
+    let recording =
+        Recording::open(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    for packet in recording.packets {
+        if let Some(entry) = dumper.entry(packet.direction, packet.elapsed, packet.data) {
+            println!("{}", entry);
+        }
+    }
+    Ok(())
+}
+
+/// Forward the clients connecting to `listen` to the daemon at `connect`,
+/// printing the packets of each session.
+async fn proxy(listen: &Path, connect: PathBuf, dumper: Dumper) -> Result<(), String> {
+    let listener =
+        UnixListener::bind(listen).map_err(|e| format!("failed to listen on {}: {}", listen.display(), e))?;
+    eprintln!("listening on {}, forwarding to {}", listen.display(), connect.display());
+
+    loop {
+        let (client, _) = listener.accept().await.map_err(|e| format!("accept failed: {}", e))?;
+        let daemon = match UnixStream::connect(&connect).await {
+            Ok(daemon) => daemon,
+            Err(e) => {
+                eprintln!("failed to connect to {}: {}", connect.display(), e);
+                continue;
+            }
+        };
+        // Each session tracks the serials of its own calls
+        tokio::spawn(session(client, daemon, dumper.clone()));
+    }
+}
+
+/// Forward packets between a client and the daemon until either side
+/// closes its connection, printing them as they pass.
+async fn session(client: UnixStream, daemon: UnixStream, mut dumper: Dumper) {
+    let started = Instant::now();
+    let (client_read, client_write) = client.into_split();
+    let (daemon_read, daemon_write) = daemon.into_split();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let sent = tokio::spawn(forward(client_read, daemon_write, Direction::Sent, tx.clone()));
+    let received = tokio::spawn(forward(daemon_read, client_write, Direction::Received, tx));
+
+    while let Some((direction, data)) = rx.recv().await {
+        if let Some(entry) = dumper.entry(direction, started.elapsed(), data) {
+            println!("{}", entry);
+        }
+    }
+    sent.abort();
+    received.abort();
+}
+
+/// Copy packets from `reader` to `writer`, passing each to `tx` without
+/// its length prefix.
+async fn forward<R, W>(mut reader: R, mut writer: W, direction: Direction, tx: mpsc::UnboundedSender<(Direction, Bytes)>)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    while let Ok(Some(frame)) = read_frame(&mut reader).await {
+        if writer.write_all(&frame).await.is_err() {
+            break;
+        }
+        if tx.send((direction, frame.freeze().split_off(4))).is_err() {
+            break;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// Read a packet with its length prefix, or `None` at the end of the
+/// stream.
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<Option<BytesMut>> {
+    let mut prefix = [0; 4];
+    match reader.read_exact(&mut prefix).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_be_bytes(prefix) as usize;
+    if !(4..=MAX_PACKET_SIZE).contains(&len) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid packet length {}", len),
+        ));
+    }
+
+    let mut frame = BytesMut::zeroed(len);
+    frame[..4].copy_from_slice(&prefix);
+    reader.read_exact(&mut frame[4..]).await?;
+    Ok(Some(frame))
+}
+
+async fn run() -> Result<(), String> {
+    let options = parse_args(std::env::args().skip(1))?;
+
+    match (&options.recording, &options.listen) {
+        (Some(path), None) => dump_recording(path, options.dumper()?),
+        (None, Some(listen)) => {
+            let connect = options.connect.clone().unwrap_or_else(|| DEFAULT_DAEMON_SOCKET.into());
+            proxy(listen, connect, options.dumper()?).await
+        }
+        (Some(_), Some(_)) => Err(format!("give either a recording or --listen\n\n{}", USAGE)),
+        (None, None) => Err(format!("no recording or --listen given\n\n{}", USAGE)),
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse(&["session.rec", "-p", "domain_create", "--procedure=domain_destroy", "--domain", "vm1"])
+            .unwrap();
+        assert_eq!(options.recording, Some(PathBuf::from("session.rec")));
+        assert_eq!(options.procedures, ["domain_create", "domain_destroy"]);
+        assert_eq!(options.domain.as_deref(), Some("vm1"));
+        assert!(!options.json && options.listen.is_none());
+
+        let options = parse(&["--listen", "/tmp/dump.sock", "-c", "/tmp/daemon.sock", "--json"]).unwrap();
+        assert_eq!(options.listen, Some(PathBuf::from("/tmp/dump.sock")));
+        assert_eq!(options.connect, Some(PathBuf::from("/tmp/daemon.sock")));
+        assert!(options.json && options.recording.is_none());
+
+        assert_eq!(parse(&["--domain"]).unwrap_err(), "--domain needs a value");
+        assert!(parse(&["--verbose"]).unwrap_err().starts_with("unknown option --verbose"));
+        assert!(parse(&["a.rec", "b.rec"]).unwrap_err().starts_with("unexpected argument b.rec"));
+        assert!(parse(&["-p", "no_such_procedure"]).unwrap().dumper().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_frame() {
+        let packet = |payload: &[u8]| {
+            let mut frame = ((4 + payload.len()) as u32).to_be_bytes().to_vec();
+            frame.extend_from_slice(payload);
+            frame
+        };
+        let input = [packet(b"first"), packet(b""), packet(b"second")].concat();
+        let mut reader = &input[..];
+        assert_eq!(&read_frame(&mut reader).await.unwrap().unwrap()[..], &packet(b"first")[..]);
+        assert_eq!(&read_frame(&mut reader).await.unwrap().unwrap()[..], &packet(b"")[..]);
+        assert_eq!(&read_frame(&mut reader).await.unwrap().unwrap()[..], &packet(b"second")[..]);
+        assert!(read_frame(&mut reader).await.unwrap().is_none());
+
+        // Cut short in the payload
+        let mut reader = &packet(b"truncated")[..8];
+        let error = read_frame(&mut reader).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        // A length shorter than its own prefix, or over the limit
+        for len in [3, MAX_PACKET_SIZE as u32 + 1] {
+            let prefix = len.to_be_bytes();
+            let error = read_frame(&mut &prefix[..]).await.unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forward() {
+        let input = [&[0, 0, 0, 7][..], b"abc", &[0, 0, 0, 5], b"d"].concat();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut output = Vec::new();
+        forward(&input[..], &mut output, Direction::Sent, tx).await;
+
+        // Forwarded as is, passed on without the length prefix
+        assert_eq!(output, input);
+        assert_eq!(rx.recv().await, Some((Direction::Sent, Bytes::from_static(b"abc"))));
+        assert_eq!(rx.recv().await, Some((Direction::Sent, Bytes::from_static(b"d"))));
+        assert_eq!(rx.recv().await, None);
+    }
+}
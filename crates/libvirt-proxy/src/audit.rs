@@ -0,0 +1,97 @@
+//! Audit trail of the calls going through the proxy.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// What became of an audited call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Refused by the policy, without reaching the daemon.
+    Denied,
+    /// Forwarded; the daemon answered with success.
+    Ok,
+    /// Forwarded; the daemon answered with an error.
+    Error,
+    /// Forwarded; the connection closed before the daemon answered.
+    Unanswered,
+}
+
+/// A domain named by an audited call.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditDomain {
+    pub name: String,
+    pub uuid: String,
+}
+
+/// One entry of the audit trail.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditRecord {
+    /// When the call was received, in seconds since the Unix epoch.
+    pub time: f64,
+    /// The client's address.
+    pub client: String,
+    /// Procedure name, e.g. `REMOTE_PROC_DOMAIN_DESTROY`.
+    pub procedure: String,
+    pub serial: i32,
+    /// Domains named by the call's arguments.
+    pub domains: Vec<AuditDomain>,
+    pub outcome: Outcome,
+    /// Message of the daemon's error, or why the call was denied.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl AuditRecord {
+    /// A record of a call received now, yet to be answered.
+    pub fn new(client: &str, procedure: &str, serial: i32, domains: Vec<AuditDomain>) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or_default();
+        Self {
+            time,
+            client: client.to_string(),
+            procedure: procedure.to_string(),
+            serial,
+            domains,
+            outcome: Outcome::Unanswered,
+            message: None,
+        }
+    }
+}
+
+/// Writes audit records as JSON lines.
+///
+/// Each record is flushed as soon as it is written. The log is shared by
+/// the sessions of a proxy, so it takes `&self`.
+pub struct AuditLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AuditLog {
+    /// Append to the file at `path`, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file: File = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+
+    /// Write the records to `writer`.
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self { writer: Mutex::new(Box::new(writer)) }
+    }
+
+    /// Write a record.
+    pub fn record(&self, record: &AuditRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&line)?;
+        writer.flush()
+    }
+}
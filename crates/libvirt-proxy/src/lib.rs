@@ -0,0 +1,576 @@
+//! Transparent libvirt RPC proxy with access rules and an audit trail.
+//!
+//! A [`Proxy`] sits between a client and the daemon and forwards packets
+//! both ways. Each call is checked against a [`Policy`] first: refused
+//! calls never reach the daemon, and the client gets the
+//! `VIR_ERR_ACCESS_DENIED` error libvirtd's own access control would
+//! return. Events about domains the client may not see are dropped. Calls
+//! needing more than read access, and every refused call, are written to
+//! an [`AuditLog`].
+//!
+//! ```ignore
+//! use libvirt_proxy::{AuditLog, DefaultPolicy, Policy, Proxy, Rule};
+//! use libvirt_pure::transport::UnixTransport;
+//!
+//! let policy = Policy::new(DefaultPolicy::ReadOnly).rule(Rule::allow("domain_*").domain("tenant-*"));
+//! let proxy = Proxy::new(policy).audit(AuditLog::open("audit.log")?);
+//!
+//! let (socket, _) = listener.accept().await?;
+//! let mut client = UnixTransport::from_stream(socket);
+//! let mut daemon = UnixTransport::connect("/var/run/libvirt/libvirt-sock").await?;
+//! proxy.serve(&mut client, &mut daemon, "tenant-a").await?;
+//! ```
+//!
+//! Domains are recognized in the arguments of calls and events, not in
+//! replies: lookups and listings return every domain, but calls on the
+//! ones the policy denies are refused. Calls name a domain by both name
+//! and UUID; when rules match names, the proxy looks the UUID up and
+//! refuses calls whose name is not that of the domain.
+
+mod audit;
+mod policy;
+
+pub use audit::{AuditDomain, AuditLog, AuditRecord, Outcome};
+pub use policy::{is_read_only, Action, DefaultPolicy, Policy, Rule};
+
+use std::collections::HashMap;
+
+use libvirt_pure::generated::payload::{decode_payload, PayloadVisitor};
+use bytes::Bytes;
+use libvirt_pure::generated::{
+    procedure_info, DomainLookupByUuidArgs, DomainLookupByUuidRet, FixedOpaque16, NonnullDomain, Procedure,
+    ProcedureInfo, ServiceError, VIR_ERR_ACCESS_DENIED, VIR_FROM_ACCESS,
+};
+use libvirt_pure::packet::{MessageType, Packet, Status};
+use libvirt_pure::server::{error_reply, RemoteError};
+use libvirt_pure::transport::Transport;
+use libvirt_pure::{Error, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Forwards sessions between clients and the daemon, applying a policy.
+pub struct Proxy {
+    policy: Policy,
+    audit: Option<AuditLog>,
+}
+
+impl Proxy {
+    /// A proxy enforcing `policy`, without audit trail.
+    pub fn new(policy: Policy) -> Self {
+        Self { policy, audit: None }
+    }
+
+    /// Write the audit trail to `log`.
+    pub fn audit(mut self, log: AuditLog) -> Self {
+        self.audit = Some(log);
+        self
+    }
+
+    /// Get the policy.
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Forward packets between `client` and `daemon` until either closes
+    /// its connection. `peer` names the client in the audit trail.
+    pub async fn serve<C, D>(&self, client: &mut C, daemon: &mut D, peer: &str) -> Result<()>
+    where
+        C: Transport + ?Sized,
+        D: Transport + ?Sized,
+    {
+        let mut session = Session {
+            proxy: self,
+            peer,
+            pending: HashMap::new(),
+            next_serial: -1,
+        };
+        // Every error ends the loop, so the calls forwarded so far are
+        // always audited
+        let mut result = loop {
+            match session.forward(client, daemon).await {
+                Ok(()) => continue,
+                Err(e) => break end_of_session(e),
+            }
+        };
+
+        for (_, record) in session.pending {
+            let logged = self.log(record);
+            if result.is_ok() {
+                result = logged;
+            }
+        }
+        result
+    }
+
+    /// Log a refused call and build the error reply to it.
+    fn deny(
+        &self,
+        call: &Packet,
+        peer: &str,
+        procedure: &str,
+        domains: Vec<AuditDomain>,
+        message: String,
+    ) -> Result<Packet> {
+        let mut record = AuditRecord::new(peer, procedure, call.serial, domains);
+        record.outcome = Outcome::Denied;
+        record.message = Some(message.clone());
+        self.log(record)?;
+
+        let error = RemoteError::new(VIR_ERR_ACCESS_DENIED, VIR_FROM_ACCESS, format!("access denied: {}", message));
+        Ok(error_reply(call, ServiceError::from(error)))
+    }
+
+    /// Whether to pass a packet from the daemon on to the client: events
+    /// are dropped if the policy denies their procedure for their domain.
+    fn forward_event(&self, packet: &Packet) -> bool {
+        if packet.msg_type != MessageType::Message {
+            return true;
+        }
+        // Keepalives and events of other programs name no domain
+        let Some(procedure) = procedure_info(packet.program, packet.procedure) else {
+            return true;
+        };
+        match domains(packet) {
+            Some(domains) => self.policy.check(procedure, &domains) == Action::Allow,
+            None => false,
+        }
+    }
+
+    /// Write `record` to the audit trail. An error ends the session, so the
+    /// proxy forwards no more calls it cannot audit.
+    fn log(&self, record: AuditRecord) -> Result<()> {
+        match &self.audit {
+            Some(audit) => audit
+                .record(&record)
+                .map_err(|e| Error::Connection(format!("cannot write the audit trail: {}", e))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The state of a session between a client and the daemon.
+struct Session<'a> {
+    proxy: &'a Proxy,
+    peer: &'a str,
+    /// Audited calls waiting for their reply, by serial.
+    pending: HashMap<i32, AuditRecord>,
+    /// Serial of the next call of the proxy's own. The proxy counts down
+    /// from -1, and refuses calls with negative serials from the client.
+    next_serial: i32,
+}
+
+impl Session<'_> {
+    /// Forward the next packet from either side.
+    async fn forward<C, D>(&mut self, client: &mut C, daemon: &mut D) -> Result<()>
+    where
+        C: Transport + ?Sized,
+        D: Transport + ?Sized,
+    {
+        tokio::select! {
+            data = client.recv() => {
+                let packet = Packet::decode(data?)?;
+                match self.check_call(&packet, client, daemon).await? {
+                    Ok(Some(record)) => {
+                        self.pending.insert(packet.serial, record);
+                        daemon.send(&packet.encode()).await
+                    }
+                    Ok(None) => daemon.send(&packet.encode()).await,
+                    Err(reply) => client.send(&reply.encode()).await,
+                }
+            }
+            data = daemon.recv() => {
+                let packet = Packet::decode(data?)?;
+                self.handle_daemon_packet(packet, client).await
+            }
+        }
+    }
+
+    /// Check a packet from the client. Returns the audit record to complete
+    /// when the reply arrives, if the call is audited, or the reply to send
+    /// back if it is refused.
+    ///
+    /// If the policy matches domains by name, the domains the call names
+    /// are looked up by UUID first, and the call is refused if a name is
+    /// not that of the domain with the UUID: the client chooses both.
+    async fn check_call<C, D>(
+        &mut self,
+        packet: &Packet,
+        client: &mut C,
+        daemon: &mut D,
+    ) -> Result<std::result::Result<Option<AuditRecord>, Packet>>
+    where
+        C: Transport + ?Sized,
+        D: Transport + ?Sized,
+    {
+        // Stream data and keepalives belong to calls checked before
+        if packet.msg_type != MessageType::Call {
+            return Ok(Ok(None));
+        }
+
+        let (proxy, peer) = (self.proxy, self.peer);
+        let Some(procedure) = procedure_info(packet.program, packet.procedure) else {
+            let name = format!("procedure {} of program {:#x}", packet.procedure, packet.program);
+            return Ok(Err(proxy.deny(packet, peer, &name, Vec::new(), format!("unknown {}", name))?));
+        };
+        if packet.serial < 0 {
+            let message = format!(
+                "{} uses serial {}, but negative serials are the proxy's",
+                procedure.name, packet.serial
+            );
+            return Ok(Err(proxy.deny(packet, peer, procedure.name, Vec::new(), message)?));
+        }
+        let Some(domains) = domains(packet) else {
+            let message = format!("cannot decode the arguments of {}", procedure.name);
+            return Ok(Err(proxy.deny(packet, peer, procedure.name, Vec::new(), message)?));
+        };
+
+        let audited = audit_domains(&domains);
+        if proxy.policy.matches_names() {
+            for domain in &domains {
+                let message = match self.lookup(&domain.uuid, client, daemon).await? {
+                    Some(actual) if actual.name == domain.name => continue,
+                    Some(actual) => format!(
+                        "{} names domain '{}', but its UUID is that of '{}'",
+                        procedure.name, domain.name, actual.name
+                    ),
+                    None => format!("{} names domain '{}', but no domain has its UUID", procedure.name, domain.name),
+                };
+                return Ok(Err(proxy.deny(packet, peer, procedure.name, audited, message)?));
+            }
+        }
+        if proxy.policy.check(procedure, &domains) == Action::Deny {
+            let message = denied_message(procedure, &domains);
+            return Ok(Err(proxy.deny(packet, peer, procedure.name, audited, message)?));
+        }
+        if is_read_only(procedure) || proxy.audit.is_none() {
+            return Ok(Ok(None));
+        }
+        Ok(Ok(Some(AuditRecord::new(peer, procedure.name, packet.serial, audited))))
+    }
+
+    /// Handle a packet from the daemon: complete the audit record of the
+    /// call it answers, and pass it on to the client unless it is an event
+    /// the client may not see.
+    async fn handle_daemon_packet<C: Transport + ?Sized>(&mut self, packet: Packet, client: &mut C) -> Result<()> {
+        if packet.msg_type == MessageType::Reply {
+            if let Some(record) = self.pending.remove(&packet.serial) {
+                self.proxy.log(answered(record, &packet))?;
+            }
+        }
+        if self.proxy.forward_event(&packet) {
+            client.send(&packet.encode()).await?;
+        }
+        Ok(())
+    }
+
+    /// The domain with UUID `uuid`, as the daemon knows it, or `None` if
+    /// the lookup fails. Packets arriving from the daemon meanwhile are
+    /// handled as usual.
+    async fn lookup<C, D>(
+        &mut self,
+        uuid: &FixedOpaque16,
+        client: &mut C,
+        daemon: &mut D,
+    ) -> Result<Option<NonnullDomain>>
+    where
+        C: Transport + ?Sized,
+        D: Transport + ?Sized,
+    {
+        let serial = self.next_serial;
+        self.next_serial = self.next_serial.checked_sub(1).unwrap_or(-1);
+        let args = DomainLookupByUuidArgs { uuid: *uuid };
+        let procedure = Procedure::ProcDomainLookupByUuid as u32;
+        let call = Packet::new_call(procedure, serial, Bytes::from(libvirt_xdr::to_bytes(&args)?));
+        daemon.send(&call.encode()).await?;
+
+        loop {
+            let packet = Packet::decode(daemon.recv().await?)?;
+            let ours = packet.msg_type == MessageType::Reply
+                && (packet.program, packet.procedure, packet.serial) == (call.program, procedure, serial);
+            if !ours {
+                self.handle_daemon_packet(packet, client).await?;
+                continue;
+            }
+            if packet.status != Status::Ok {
+                return Ok(None);
+            }
+            let ret: DomainLookupByUuidRet = libvirt_xdr::from_bytes(&packet.payload)?;
+            return Ok(Some(ret.dom));
+        }
+    }
+}
+
+/// The result of a session ended by `error`: the normal end if the peer
+/// closed the connection.
+fn end_of_session(error: Error) -> Result<()> {
+    match error {
+        Error::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(()),
+        e => Err(e),
+    }
+}
+
+/// Complete the record of a call with its reply.
+fn answered(mut record: AuditRecord, reply: &Packet) -> AuditRecord {
+    if reply.status == Status::Error {
+        record.outcome = Outcome::Error;
+        record.message = libvirt_xdr::from_bytes::<RemoteError>(&reply.payload)
+            .ok()
+            .and_then(|error| error.message);
+    } else {
+        record.outcome = Outcome::Ok;
+    }
+    record
+}
+
+fn denied_message(procedure: &ProcedureInfo, domains: &[NonnullDomain]) -> String {
+    let names: Vec<_> = domains.iter().map(|d| format!("'{}'", d.name)).collect();
+    match names.as_slice() {
+        [] => format!("{} is not allowed", procedure.name),
+        _ => format!("{} is not allowed on domain {}", procedure.name, names.join(", ")),
+    }
+}
+
+fn audit_domains(domains: &[NonnullDomain]) -> Vec<AuditDomain> {
+    domains
+        .iter()
+        .map(|d| AuditDomain { name: d.name.clone(), uuid: d.uuid.to_uuid_string() })
+        .collect()
+}
+
+/// Collects the domains of a decoded payload.
+struct DomainCollector;
+
+impl PayloadVisitor for DomainCollector {
+    type Output = Vec<NonnullDomain>;
+
+    fn visit<T: Serialize + std::fmt::Debug>(self, _type_name: &'static str, value: &T) -> Vec<NonnullDomain> {
+        let mut domains = Vec::new();
+        if let Ok(value) = serde_json::to_value(value) {
+            collect_domains(value, &mut domains);
+        }
+        domains
+    }
+}
+
+/// The domains named by the arguments of a call or event, or `None` if
+/// they cannot be decoded.
+fn domains(packet: &Packet) -> Option<Vec<NonnullDomain>> {
+    match decode_payload(packet.program, packet.procedure, false, &packet.payload, DomainCollector) {
+        Some(Ok(domains)) => Some(domains),
+        Some(Err(_)) => None,
+        // No arguments
+        None => Some(Vec::new()),
+    }
+}
+
+/// Add the `remote_nonnull_domain` values within `value` to `domains`.
+fn collect_domains(value: Value, domains: &mut Vec<NonnullDomain>) {
+    match value {
+        Value::Object(fields) => {
+            if let Some(domain) = as_domain(&fields) {
+                domains.push(domain);
+                return;
+            }
+            for value in fields.into_iter().map(|(_, value)| value) {
+                collect_domains(value, domains);
+            }
+        }
+        Value::Array(values) => values.into_iter().for_each(|value| collect_domains(value, domains)),
+        _ => {}
+    }
+}
+
+/// The domain serialized as `fields`, if they are those of a
+/// `remote_nonnull_domain`: a name, a 16-byte UUID and an id.
+fn as_domain(fields: &Map<String, Value>) -> Option<NonnullDomain> {
+    if fields.len() != 3 {
+        return None;
+    }
+    let name = fields.get("name")?.as_str()?;
+    let id = fields.get("id")?.as_i64()?;
+    let bytes = fields.get("uuid")?.as_array()?;
+    let mut uuid = [0; 16];
+    if bytes.len() != uuid.len() {
+        return None;
+    }
+    for (byte, value) in uuid.iter_mut().zip(bytes) {
+        *byte = u8::try_from(value.as_u64()?).ok()?;
+    }
+    Some(NonnullDomain {
+        name: name.to_string(),
+        uuid: FixedOpaque16::new(uuid),
+        id: i32::try_from(id).ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use libvirt_pure::generated::{
+        DomainCreateFlags, DomainDefineFlags, DomainEventCallbackLifecycleMsg, DomainSuspendArgs, Uuid,
+        VIR_DOMAIN_EVENT_ID_LIFECYCLE, VIR_DOMAIN_PAUSED,
+    };
+    use libvirt_pure::testing::FakeHypervisor;
+    use libvirt_pure::transport::MemoryTransport;
+    use libvirt_pure::{Client, Connection, RpcError};
+
+    /// An audit trail kept in memory.
+    #[derive(Clone, Default)]
+    struct Trail(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Trail {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Trail {
+        fn records(&self) -> Vec<Value> {
+            let text = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            text.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+        }
+    }
+
+    /// Connect to `fake` through a proxy applying `policy`.
+    async fn connect(fake: &FakeHypervisor, policy: &str, trail: &Trail) -> Connection {
+        let proxy = Proxy::new(Policy::parse(policy).unwrap()).audit(AuditLog::new(trail.clone()));
+        let (client, mut proxy_client) = MemoryTransport::pair();
+        let (mut proxy_daemon, daemon) = MemoryTransport::pair();
+        let mock = fake.daemon().clone();
+        tokio::spawn(async move { mock.serve(daemon).await });
+        tokio::spawn(async move { proxy.serve(&mut proxy_client, &mut proxy_daemon, "tenant").await });
+        Connection::from_transport(client).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_proxy() {
+        let fake = FakeHypervisor::new();
+        let trail = Trail::default();
+        let policy = "allow domain_define_xml_flags\nallow domain_* domain=vm*\ndeny * domain=test\n";
+        let connection = connect(&fake, policy, &trail).await;
+        let mut events = connection.events();
+        let client = Client::open(connection, "test:///default").await.unwrap();
+        let api = client.api();
+        api.connect_domain_event_callback_register_any(VIR_DOMAIN_EVENT_ID_LIFECYCLE, None)
+            .await
+            .unwrap();
+
+        let xml = "<domain type='test'><name>vm1</name><memory unit='MiB'>512</memory></domain>";
+        let vm1 = api.domain_define_xml_flags(xml, DomainDefineFlags::empty()).await.unwrap();
+        let vm1 = api.domain_create_with_flags(&vm1, DomainCreateFlags::empty()).await.unwrap();
+
+        // Lookups are not filtered, calls on the domain are
+        let test = api.domain_lookup_by_name("test").await.unwrap();
+        match api.domain_suspend(&test).await {
+            Err(RpcError::Server(e)) => {
+                assert_eq!((e.code, e.domain), (VIR_ERR_ACCESS_DENIED, VIR_FROM_ACCESS));
+                assert_eq!(
+                    e.message.as_deref(),
+                    Some("access denied: REMOTE_PROC_DOMAIN_SUSPEND is not allowed on domain 'test'")
+                );
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Events about `test` are dropped
+        let direct = fake.client().await.unwrap();
+        direct.api().domain_suspend(&test).await.unwrap();
+        api.domain_suspend(&vm1).await.unwrap();
+        let mut names = Vec::new();
+        for _ in 0..3 {
+            let packet = events.recv().await.unwrap();
+            let event: DomainEventCallbackLifecycleMsg = libvirt_xdr::from_bytes(&packet.payload).unwrap();
+            names.push(event.msg.dom.name);
+        }
+        assert_eq!(names, ["vm1", "vm1", "vm1"]);
+
+        let records = trail.records();
+        let summary: Vec<_> = records
+            .iter()
+            .map(|r| (r["procedure"].as_str().unwrap(), r["outcome"].as_str().unwrap()))
+            .collect();
+        assert_eq!(summary, [
+            ("REMOTE_PROC_DOMAIN_DEFINE_XML_FLAGS", "ok"),
+            ("REMOTE_PROC_DOMAIN_CREATE_WITH_FLAGS", "ok"),
+            ("REMOTE_PROC_DOMAIN_SUSPEND", "denied"),
+            ("REMOTE_PROC_DOMAIN_SUSPEND", "ok"),
+        ]);
+        assert_eq!(records[2]["client"], "tenant");
+        assert_eq!(records[2]["domains"][0]["uuid"], "6695eb01-f6a4-8304-79aa-97f2502e193f");
+
+        // The name of `vm1` with the UUID of `test`
+        let spoofed = NonnullDomain { name: "vm1".to_string(), ..test.clone() };
+        match api.domain_resume(&spoofed).await {
+            Err(RpcError::Server(e)) => assert_eq!(
+                e.message.as_deref(),
+                Some("access denied: REMOTE_PROC_DOMAIN_RESUME names domain 'vm1', but its UUID is that of 'test'")
+            ),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let records = trail.records();
+        assert_eq!(records[4]["outcome"], "denied");
+        assert_eq!(records[4]["domains"][0]["uuid"], "6695eb01-f6a4-8304-79aa-97f2502e193f");
+        let state = direct.api().domain_get_state(&test, 0).await.unwrap();
+        assert_eq!(state.state, VIR_DOMAIN_PAUSED);
+    }
+
+    /// A call to suspend `vm1`, with serial `serial`.
+    fn suspend_call(serial: i32) -> Packet {
+        let args = DomainSuspendArgs {
+            dom: NonnullDomain { name: "vm1".to_string(), uuid: Uuid::new([1; 16]), id: 1 },
+        };
+        let payload = Bytes::from(libvirt_xdr::to_bytes(&args).unwrap());
+        Packet::new_call(Procedure::ProcDomainSuspend as u32, serial, payload)
+    }
+
+    #[tokio::test]
+    async fn test_audit_on_error() {
+        // A forwarded call is audited even if the session ends in garbage
+        // before the daemon answers
+        let trail = Trail::default();
+        let proxy = Proxy::new(Policy::parse("allow *").unwrap()).audit(AuditLog::new(trail.clone()));
+        let (mut client, mut proxy_client) = MemoryTransport::pair();
+        let (mut proxy_daemon, mut daemon) = MemoryTransport::pair();
+        client.send(&suspend_call(1).encode()).await.unwrap();
+        client.send(&[0; 8]).await.unwrap();
+        assert!(proxy.serve(&mut proxy_client, &mut proxy_daemon, "tenant").await.is_err());
+        assert_eq!(Packet::decode(daemon.recv().await.unwrap()).unwrap().serial, 1);
+        let records = trail.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["procedure"], "REMOTE_PROC_DOMAIN_SUSPEND");
+        assert_eq!(records[0]["outcome"], "unanswered");
+    }
+
+    #[tokio::test]
+    async fn test_audit_write_failure() {
+        struct Broken;
+
+        impl Write for Broken {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("disk full"))
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // A call that cannot be audited ends the session before it is
+        // forwarded
+        let proxy = Proxy::new(Policy::parse("deny *").unwrap()).audit(AuditLog::new(Broken));
+        let (mut client, mut proxy_client) = MemoryTransport::pair();
+        let (mut proxy_daemon, _daemon) = MemoryTransport::pair();
+        client.send(&suspend_call(1).encode()).await.unwrap();
+        match proxy.serve(&mut proxy_client, &mut proxy_daemon, "tenant").await {
+            Err(Error::Connection(message)) => assert!(message.contains("disk full"), "{}", message),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Command-line front end: serve a proxy to the daemon on a socket.
+//!
+//! ```text
+//! libvirt-proxy --listen /run/tenant-a.sock --policy tenant-a.policy --audit tenant-a.log
+//! libvirt-proxy --listen tcp:127.0.0.1:16510 --connect unix:/var/run/libvirt/libvirt-sock
+//! ```
+//!
+//! TCP clients are neither authenticated nor encrypted, so only loopback
+//! addresses are accepted for `--listen`; use a Unix socket, whose
+//! permissions decide who may connect, to serve other users.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use libvirt_proxy::{AuditLog, Policy, Proxy};
+use libvirt_pure::transport::{TcpTransport, Transport, UnixTransport};
+use tokio::net::{TcpListener, UnixListener};
+
+const USAGE: &str = "\
+Usage: libvirt-proxy [OPTIONS] --listen <ADDRESS>
+
+Forward the libvirt clients connecting to ADDRESS to the daemon, refusing
+the calls the policy denies and logging the others that need more than
+read access.
+
+Addresses are Unix socket paths, optionally prefixed with `unix:`, or
+`tcp:HOST:PORT`. TCP clients are not authenticated, so the proxy only
+listens on loopback TCP addresses.
+
+Options:
+  -l, --listen <ADDRESS>    Address to accept clients on
+  -c, --connect <ADDRESS>   Address of the daemon
+                            [default: /var/run/libvirt/libvirt-sock]
+  -p, --policy <FILE>       Rules to apply [default: allow read-only calls]
+  -a, --audit <FILE>        Append the audit trail to FILE, as JSON lines
+  -h, --help                Print this help";
+
+const DEFAULT_DAEMON_SOCKET: &str = "/var/run/libvirt/libvirt-sock";
+
+/// A socket address.
+#[derive(Debug, Clone)]
+enum Address {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl Address {
+    fn parse(address: &str) -> Self {
+        match address.strip_prefix("tcp:") {
+            Some(tcp) => Address::Tcp(tcp.to_string()),
+            None => Address::Unix(address.strip_prefix("unix:").unwrap_or(address).into()),
+        }
+    }
+
+    async fn connect(&self) -> libvirt_pure::Result<Box<dyn Transport>> {
+        Ok(match self {
+            Address::Unix(path) => Box::new(UnixTransport::connect(&path.to_string_lossy()).await?),
+            Address::Tcp(addr) => Box::new(TcpTransport::connect(addr.as_str()).await?),
+        })
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+            Address::Tcp(addr) => write!(f, "tcp:{}", addr),
+        }
+    }
+}
+
+/// Options from the command line.
+#[derive(Debug, Default)]
+struct Options {
+    listen: Option<String>,
+    connect: Option<String>,
+    policy: Option<PathBuf>,
+    audit: Option<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |flag: &str| {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", flag))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "-l" | "--listen" => options.listen = Some(value(&flag)?),
+            "-c" | "--connect" => options.connect = Some(value(&flag)?),
+            "-p" | "--policy" => options.policy = Some(value(&flag)?.into()),
+            "-a" | "--audit" => options.audit = Some(value(&flag)?.into()),
+            _ if flag.starts_with('-') => {
+                return Err(format!("unknown option {}\n\n{}", flag, USAGE));
+            }
+            _ => return Err(format!("unexpected argument {}\n\n{}", arg, USAGE)),
+        }
+    }
+
+    Ok(options)
+}
+
+/// Serve the session of a client connected to the proxy.
+fn spawn_session(proxy: Arc<Proxy>, daemon: Address, mut client: Box<dyn Transport>, peer: String) {
+    tokio::spawn(async move {
+        let mut daemon = match daemon.connect().await {
+            Ok(daemon) => daemon,
+            Err(e) => {
+                eprintln!("{}: failed to connect to {}: {}", peer, daemon, e);
+                return;
+            }
+        };
+        if let Err(e) = proxy.serve(&mut *client, &mut *daemon, &peer).await {
+            eprintln!("{}: {}", peer, e);
+        }
+    });
+}
+
+/// The addresses `addr` resolves to, if they are all loopback ones.
+async fn loopback_addrs(addr: &str) -> Result<Vec<SocketAddr>, String> {
+    let addrs: Vec<_> = tokio::net::lookup_host(addr)
+        .await
+        .map_err(|e| format!("invalid address tcp:{}: {}", addr, e))?
+        .collect();
+    if let Some(remote) = addrs.iter().find(|a| !a.ip().is_loopback()) {
+        return Err(format!(
+            "refusing to listen on {}: TCP clients are not authenticated, so only loopback addresses are allowed",
+            remote
+        ));
+    }
+    Ok(addrs)
+}
+
+async fn run() -> Result<(), String> {
+    let options = parse_args(std::env::args().skip(1))?;
+    let listen = options.listen.as_deref().ok_or_else(|| format!("no --listen address\n\n{}", USAGE))?;
+    let listen = Address::parse(listen);
+    let daemon = Address::parse(options.connect.as_deref().unwrap_or(DEFAULT_DAEMON_SOCKET));
+
+    let policy = match &options.policy {
+        Some(path) => Policy::open(path)?,
+        None => Policy::default(),
+    };
+    let mut proxy = Proxy::new(policy);
+    if let Some(path) = &options.audit {
+        let log = AuditLog::open(path).map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+        proxy = proxy.audit(log);
+    }
+    let proxy = Arc::new(proxy);
+
+    let listen_err = |e: std::io::Error| format!("failed to listen on {}: {}", listen, e);
+    eprintln!("listening on {}, forwarding to {}", listen, daemon);
+    match &listen {
+        Address::Unix(path) => {
+            let listener = UnixListener::bind(path).map_err(listen_err)?;
+            let mut clients = 0u64;
+            loop {
+                let (socket, _) = listener.accept().await.map_err(|e| format!("accept failed: {}", e))?;
+                // Unix peers have no address: number them
+                clients += 1;
+                let peer = format!("unix-client-{}", clients);
+                spawn_session(proxy.clone(), daemon.clone(), Box::new(UnixTransport::from_stream(socket)), peer);
+            }
+        }
+        Address::Tcp(addr) => {
+            let addrs = loopback_addrs(addr).await?;
+            let listener = TcpListener::bind(&addrs[..]).await.map_err(listen_err)?;
+            loop {
+                let (socket, addr) = listener.accept().await.map_err(|e| format!("accept failed: {}", e))?;
+                spawn_session(proxy.clone(), daemon.clone(), Box::new(TcpTransport::from_stream(socket)), addr.to_string());
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_loopback_addrs() {
+        assert_eq!(loopback_addrs("127.0.0.1:16510").await.unwrap(), ["127.0.0.1:16510".parse().unwrap()]);
+        assert!(loopback_addrs("[::1]:16510").await.is_ok());
+        let error = loopback_addrs("0.0.0.0:16510").await.unwrap_err();
+        assert!(error.starts_with("refusing to listen on 0.0.0.0:16510"), "{}", error);
+        assert!(loopback_addrs("192.0.2.1:16510").await.is_err());
+    }
+}
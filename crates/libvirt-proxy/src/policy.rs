@@ -0,0 +1,294 @@
+//! Access rules.
+//!
+//! A policy is a list of rules, tried in order, and a default for calls no
+//! rule matches. A policy file has one rule per line:
+//!
+//! ```text
+//! # Tenant A manages its own domains and may only look at the others
+//! default read-only
+//! allow domain_* domain=tenant-a-*
+//! deny * uuid=6695eb01-f6a4-8304-79aa-97f2502e193f
+//! deny REMOTE_PROC_DOMAIN_GET_XML_DESC
+//! ```
+//!
+//! Procedures are named as in the `.x` file or as client methods, and may
+//! contain `*` wildcards. A rule with a `domain=` (name, with wildcards)
+//! or `uuid=` condition only matches calls naming such a domain.
+
+use std::path::Path;
+
+use libvirt_pure::generated::{NonnullDomain, ProcedureInfo};
+
+/// Whether a call may go through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+/// What to do with calls no rule matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DefaultPolicy {
+    /// Allow the procedures whose `@acl` only requires read access
+    /// (`getattr`, `read` and `search_*` permissions), deny the others.
+    #[default]
+    ReadOnly,
+    /// Allow every procedure.
+    Allow,
+    /// Deny every procedure that has an `@acl`.
+    Deny,
+}
+
+/// Domain condition of a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DomainMatch {
+    Name(String),
+    Uuid(String),
+}
+
+/// A rule: the action to take for calls to the matching procedures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    action: Action,
+    procedure: String,
+    domain: Option<DomainMatch>,
+}
+
+impl Rule {
+    /// Allow the procedures matching `procedure`.
+    pub fn allow(procedure: impl Into<String>) -> Self {
+        Self { action: Action::Allow, procedure: procedure.into(), domain: None }
+    }
+
+    /// Deny the procedures matching `procedure`.
+    pub fn deny(procedure: impl Into<String>) -> Self {
+        Self { action: Action::Deny, procedure: procedure.into(), domain: None }
+    }
+
+    /// Only match calls naming a domain whose name matches `name`.
+    pub fn domain(mut self, name: impl Into<String>) -> Self {
+        self.domain = Some(DomainMatch::Name(name.into()));
+        self
+    }
+
+    /// Only match calls naming the domain with this UUID.
+    pub fn uuid(mut self, uuid: impl AsRef<str>) -> Self {
+        self.domain = Some(DomainMatch::Uuid(uuid.as_ref().to_ascii_lowercase()));
+        self
+    }
+
+    fn matches(&self, procedure: &ProcedureInfo, domain: Option<&NonnullDomain>) -> bool {
+        if !glob_match(&self.procedure, procedure.name) && !glob_match(&self.procedure, procedure.method) {
+            return false;
+        }
+        match (&self.domain, domain) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(DomainMatch::Name(name)), Some(domain)) => glob_match(name, &domain.name),
+            (Some(DomainMatch::Uuid(uuid)), Some(domain)) => domain.uuid.to_uuid_string() == *uuid,
+        }
+    }
+}
+
+/// Rules deciding which calls the proxy forwards.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    rules: Vec<Rule>,
+    default: DefaultPolicy,
+}
+
+impl Policy {
+    /// A policy without rules.
+    pub fn new(default: DefaultPolicy) -> Self {
+        Self { rules: Vec::new(), default }
+    }
+
+    /// Add a rule, tried after the ones added before.
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Read a policy file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}:{}", path.display(), e))
+    }
+
+    /// Parse the text of a policy file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut policy = Policy::default();
+        for (i, line) in text.lines().enumerate() {
+            let err = |msg: String| format!("{}: {}", i + 1, msg);
+            let line = line.split('#').next().unwrap_or_default();
+            let words: Vec<_> = line.split_whitespace().collect();
+            let rule = match words.as_slice() {
+                [] => continue,
+                ["default", default] => {
+                    policy.default = match *default {
+                        "read-only" => DefaultPolicy::ReadOnly,
+                        "allow" => DefaultPolicy::Allow,
+                        "deny" => DefaultPolicy::Deny,
+                        _ => return Err(err(format!("unknown default `{}`", default))),
+                    };
+                    continue;
+                }
+                ["allow", procedure, ..] => Rule::allow(*procedure),
+                ["deny", procedure, ..] => Rule::deny(*procedure),
+                [word, ..] => return Err(err(format!("expected allow, deny or default, found `{}`", word))),
+            };
+            let rule = match &words[2..] {
+                [] => rule,
+                [condition] => match condition.split_once('=') {
+                    Some(("domain", name)) => rule.domain(name),
+                    Some(("uuid", uuid)) => rule.uuid(uuid),
+                    _ => return Err(err(format!("expected domain=NAME or uuid=UUID, found `{}`", condition))),
+                },
+                _ => return Err(err("a rule takes at most one condition".to_string())),
+            };
+            policy.rules.push(rule);
+        }
+        Ok(policy)
+    }
+
+    /// Whether a rule matches domains by name. Calls name domains by both
+    /// name and UUID, so those names must be checked against the UUIDs.
+    pub fn matches_names(&self) -> bool {
+        self.rules.iter().any(|rule| matches!(rule.domain, Some(DomainMatch::Name(_))))
+    }
+
+    /// Decide on a call to `procedure` naming `domains`.
+    ///
+    /// Each domain is decided on separately, by the first rule matching
+    /// the procedure and that domain, and the call is denied if any is.
+    pub fn check(&self, procedure: &ProcedureInfo, domains: &[NonnullDomain]) -> Action {
+        if domains.is_empty() {
+            return self.check_domain(procedure, None);
+        }
+        let denied = domains
+            .iter()
+            .any(|domain| self.check_domain(procedure, Some(domain)) == Action::Deny);
+        if denied {
+            Action::Deny
+        } else {
+            Action::Allow
+        }
+    }
+
+    fn check_domain(&self, procedure: &ProcedureInfo, domain: Option<&NonnullDomain>) -> Action {
+        if let Some(rule) = self.rules.iter().find(|rule| rule.matches(procedure, domain)) {
+            return rule.action;
+        }
+        match self.default {
+            DefaultPolicy::ReadOnly if is_read_only(procedure) => Action::Allow,
+            DefaultPolicy::Allow => Action::Allow,
+            // The daemon checks nothing for these (authentication, closing
+            // the connection, events)
+            DefaultPolicy::Deny if procedure.acl.is_empty() => Action::Allow,
+            _ => Action::Deny,
+        }
+    }
+}
+
+/// Whether the `@acl` of `procedure` only requires read access.
+///
+/// Permissions that only apply with some flag (`domain:read_secure:...`)
+/// count too, as the flags are not looked at.
+pub fn is_read_only(procedure: &ProcedureInfo) -> bool {
+    procedure.acl.iter().all(|acl| {
+        let permission = acl.split(':').nth(1).unwrap_or_default();
+        matches!(permission, "getattr" | "read") || permission.starts_with("search_")
+    })
+}
+
+/// Match `text` against `pattern`, where `*` stands for any run of
+/// characters.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let Some((first, rest)) = pattern.split_once('*') else {
+        return pattern == text;
+    };
+    let Some(mut text) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<_> = rest.split('*').collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match text.find(part) {
+            Some(index) => text = &text[index + part.len()..],
+            None => return false,
+        }
+    }
+    text.len() >= last.len() && text.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libvirt_pure::generated::{program_info, FixedOpaque16, REMOTE_PROGRAM};
+
+    fn procedure(name: &str) -> &'static ProcedureInfo {
+        program_info(REMOTE_PROGRAM as u32).unwrap().procedure_by_name(name).unwrap()
+    }
+
+    fn domain(name: &str, uuid: u8) -> NonnullDomain {
+        NonnullDomain { name: name.to_string(), uuid: FixedOpaque16::new([uuid; 16]), id: -1 }
+    }
+
+    fn check(policy: &Policy, name: &str, domain: &NonnullDomain) -> Action {
+        policy.check(procedure(name), std::slice::from_ref(domain))
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("domain_*", "domain_create"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbc"));
+        assert!(!glob_match("a*b*c", "aXc"));
+        assert!(!glob_match("ab*ba", "aba"));
+        assert!(glob_match("exact", "exact"));
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let policy = Policy::default();
+        assert_eq!(policy.check(procedure("domain_get_info"), &[]), Action::Allow);
+        assert_eq!(policy.check(procedure("connect_list_all_domains"), &[]), Action::Allow);
+        assert_eq!(policy.check(procedure("domain_destroy"), &[]), Action::Deny);
+        // read_secure with VIR_DOMAIN_XML_SECURE
+        assert_eq!(policy.check(procedure("domain_get_xml_desc"), &[]), Action::Deny);
+        assert_eq!(policy.check(procedure("auth_list"), &[]), Action::Allow);
+
+        let policy = Policy::new(DefaultPolicy::Deny);
+        assert_eq!(policy.check(procedure("domain_get_info"), &[]), Action::Deny);
+        assert_eq!(policy.check(procedure("connect_close"), &[]), Action::Allow);
+    }
+
+    #[test]
+    fn test_rules() {
+        let policy = Policy::parse(
+            "# comment\n\
+             default read-only\n\
+             deny * uuid=02020202-0202-0202-0202-020202020202\n\
+             allow domain_* domain=tenant-*  # own domains\n\
+             deny REMOTE_PROC_DOMAIN_GET_INFO\n",
+        )
+        .unwrap();
+        let (own, other) = (domain("tenant-1", 1), domain("other", 3));
+
+        assert_eq!(check(&policy, "domain_destroy", &own), Action::Allow);
+        assert_eq!(check(&policy, "domain_destroy", &other), Action::Deny);
+        assert_eq!(check(&policy, "domain_get_info", &own), Action::Allow);
+        assert_eq!(check(&policy, "domain_get_info", &other), Action::Deny);
+        // The UUID rule comes first
+        let banned = domain("tenant-2", 2);
+        assert_eq!(check(&policy, "domain_get_state", &banned), Action::Deny);
+        // Every domain named must be allowed
+        assert_eq!(policy.check(procedure("domain_destroy"), &[own, banned]), Action::Deny);
+
+        assert!(Policy::parse("default maybe").unwrap_err().starts_with("1: "));
+        assert!(Policy::parse("\nallow * name=x").is_err());
+        assert!(Policy::parse("permit *").is_err());
+    }
+}
@@ -0,0 +1,346 @@
+//! Derive macros for `libvirt_xdr::XdrEncode` and `libvirt_xdr::XdrDecode`.
+//!
+//! Structs are encoded field by field in declaration order. Enums whose
+//! variants are all unit variants are encoded as a 4-byte discriminant.
+//! Enums carrying data are encoded as XDR discriminated unions: the
+//! discriminant followed by the variant's fields.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DataEnum, DeriveInput, Expr, Fields, Variant};
+
+/// Derive `libvirt_xdr::XdrEncode`.
+#[proc_macro_derive(XdrEncode, attributes(xdr))]
+pub fn derive_xdr_encode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_encode(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derive `libvirt_xdr::XdrDecode`.
+#[proc_macro_derive(XdrDecode, attributes(xdr))]
+pub fn derive_xdr_decode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_decode(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Parsed `#[xdr(...)]` attributes.
+#[derive(Default)]
+struct XdrAttrs {
+    discriminant: Option<Expr>,
+    max_len: Option<Expr>,
+    default: bool,
+}
+
+fn parse_attrs(attrs: &[syn::Attribute]) -> syn::Result<XdrAttrs> {
+    let mut out = XdrAttrs::default();
+    for attr in attrs {
+        if !attr.path().is_ident("xdr") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("discriminant") {
+                out.discriminant = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("max_len") {
+                out.max_len = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("default") {
+                out.default = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported xdr attribute"))
+            }
+        })?;
+    }
+    Ok(out)
+}
+
+/// Bound check emitted for fields carrying `#[xdr(max_len = N)]`.
+///
+/// `context` maps the error, e.g. to add the field to its path.
+fn bound_check(value: &TokenStream2, attrs: &XdrAttrs, context: &TokenStream2) -> TokenStream2 {
+    match &attrs.max_len {
+        Some(max) => quote! {
+            ::libvirt_xdr::XdrBounded::check_len(#value, (#max) as usize).map_err(#context)?;
+        },
+        None => TokenStream2::new(),
+    }
+}
+
+/// Name of a field as it appears in error paths.
+fn field_name(field: &syn::Field, index: usize) -> String {
+    match &field.ident {
+        Some(ident) => {
+            let name = ident.to_string();
+            name.strip_prefix("r#").unwrap_or(&name).to_string()
+        }
+        None => index.to_string(),
+    }
+}
+
+fn expand_encode(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let mut stmts = Vec::new();
+            for (i, field) in data.fields.iter().enumerate() {
+                let attrs = parse_attrs(&field.attrs)?;
+                let access = match &field.ident {
+                    Some(ident) => quote! { &self.#ident },
+                    None => {
+                        let index = syn::Index::from(i);
+                        quote! { &self.#index }
+                    }
+                };
+                let name = field_name(field, i);
+                let context = quote! { |e: ::libvirt_xdr::Error| e.in_field(#name) };
+                stmts.push(bound_check(&access, &attrs, &context));
+                stmts.push(quote! {
+                    ::libvirt_xdr::XdrEncode::encode(#access, ser).map_err(#context)?;
+                });
+            }
+            quote! {
+                #(#stmts)*
+                Ok(())
+            }
+        }
+        Data::Enum(data) => encode_enum(data)?,
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "XdrEncode cannot be derived for Rust unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::libvirt_xdr::XdrEncode for #name #ty_generics #where_clause {
+            fn encode(&self, ser: &mut ::libvirt_xdr::XdrSerializer) -> ::libvirt_xdr::Result<()> {
+                #body
+            }
+        }
+    })
+}
+
+fn expand_decode(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let construct = decode_fields(quote! { Self }, &data.fields, None, false)?;
+            quote! { Ok(#construct) }
+        }
+        Data::Enum(data) => decode_enum(data)?,
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "XdrDecode cannot be derived for Rust unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::libvirt_xdr::XdrDecode for #name #ty_generics #where_clause {
+            fn decode(de: &mut ::libvirt_xdr::XdrDeserializer<'_>) -> ::libvirt_xdr::Result<Self> {
+                #body
+            }
+        }
+    })
+}
+
+/// Build an expression constructing `path` from fields decoded in order.
+///
+/// When `discriminant` is set, the first field is filled from it instead of
+/// being read from the input (used by `#[xdr(default)]` union arms).
+/// Unnamed fields of union arms do not show up in error paths, so a failure
+/// inside `value: TypedParamValue` reads as `...value` rather than `...value.0`.
+fn decode_fields(
+    path: TokenStream2,
+    fields: &Fields,
+    discriminant: Option<&TokenStream2>,
+    in_variant: bool,
+) -> syn::Result<TokenStream2> {
+    let mut lets = Vec::new();
+    let mut names = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let attrs = parse_attrs(&field.attrs)?;
+        let var = format_ident!("__field{}", i);
+        match (i, discriminant) {
+            (0, Some(disc)) => lets.push(quote! { let #var = #disc; }),
+            _ => {
+                let segment = if in_variant && field.ident.is_none() {
+                    TokenStream2::new()
+                } else {
+                    let name = field_name(field, i);
+                    quote! { .in_field(#name) }
+                };
+                let check = bound_check(
+                    &quote! { &#var },
+                    &attrs,
+                    &quote! { |e: ::libvirt_xdr::Error| e.at(__start) #segment },
+                );
+                lets.push(quote! {
+                    let __start = de.position();
+                    let #var = ::libvirt_xdr::XdrDecode::decode(de)
+                        .map_err(|e: ::libvirt_xdr::Error| e #segment)?;
+                    #check
+                });
+            }
+        }
+        names.push(var);
+    }
+
+    let construct = match fields {
+        Fields::Named(named) => {
+            let idents = named.named.iter().map(|f| f.ident.as_ref().unwrap());
+            quote! { #path { #(#idents: #names),* } }
+        }
+        Fields::Unnamed(_) => quote! { #path(#(#names),*) },
+        Fields::Unit => quote! { #path },
+    };
+
+    Ok(quote! {{
+        #(#lets)*
+        #construct
+    }})
+}
+
+/// Whether every variant is a unit variant (a plain XDR enum).
+fn is_plain_enum(data: &DataEnum) -> bool {
+    data.variants.iter().all(|v| matches!(v.fields, Fields::Unit))
+}
+
+/// Discriminant expression for a variant.
+fn variant_discriminant(variant: &Variant, index: usize, plain: bool) -> syn::Result<TokenStream2> {
+    let attrs = parse_attrs(&variant.attrs)?;
+    let ident = &variant.ident;
+    Ok(match attrs.discriminant {
+        Some(expr) => quote! { (#expr) as i32 },
+        // Plain enums may rely on Rust's own discriminants (explicit or implicit).
+        None if plain => quote! { Self::#ident as i32 },
+        None => {
+            let index = index as i32;
+            quote! { #index }
+        }
+    })
+}
+
+fn encode_enum(data: &DataEnum) -> syn::Result<TokenStream2> {
+    let plain = is_plain_enum(data);
+    let mut arms = Vec::new();
+
+    for (index, variant) in data.variants.iter().enumerate() {
+        let attrs = parse_attrs(&variant.attrs)?;
+        let ident = &variant.ident;
+        let bindings: Vec<_> = (0..variant.fields.len())
+            .map(|i| format_ident!("__field{}", i))
+            .collect();
+
+        let pattern = match &variant.fields {
+            Fields::Named(named) => {
+                let idents = named.named.iter().map(|f| f.ident.as_ref().unwrap());
+                quote! { Self::#ident { #(#idents: #bindings),* } }
+            }
+            Fields::Unnamed(_) => quote! { Self::#ident(#(#bindings),*) },
+            Fields::Unit => quote! { Self::#ident },
+        };
+
+        let mut field_attrs = Vec::new();
+        for field in variant.fields.iter() {
+            field_attrs.push(parse_attrs(&field.attrs)?);
+        }
+
+        let (disc, data_fields) = if attrs.default {
+            // The first field of a default arm holds the discriminant itself.
+            let Some(first) = bindings.first() else {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "#[xdr(default)] variant must hold the discriminant as its first field",
+                ));
+            };
+            (quote! { *#first as i32 }, &bindings[1..])
+        } else {
+            (variant_discriminant(variant, index, plain)?, &bindings[..])
+        };
+
+        let offset = bindings.len() - data_fields.len();
+        let writes = data_fields.iter().enumerate().map(|(i, b)| {
+            let check = bound_check(&quote! { #b }, &field_attrs[i + offset], &quote! { |e| e });
+            quote! {
+                #check
+                ::libvirt_xdr::XdrEncode::encode(#b, ser)?;
+            }
+        });
+
+        arms.push(quote! {
+            #pattern => {
+                ser.write_i32(#disc);
+                #(#writes)*
+            }
+        });
+    }
+
+    if arms.is_empty() {
+        return Ok(quote! { match *self {} });
+    }
+
+    Ok(quote! {
+        match self {
+            #(#arms)*
+        }
+        Ok(())
+    })
+}
+
+fn decode_enum(data: &DataEnum) -> syn::Result<TokenStream2> {
+    let plain = is_plain_enum(data);
+    let mut arms = Vec::new();
+    let mut default_arm = None;
+
+    for (index, variant) in data.variants.iter().enumerate() {
+        let attrs = parse_attrs(&variant.attrs)?;
+        let ident = &variant.ident;
+
+        if attrs.default {
+            if default_arm.is_some() {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "only one #[xdr(default)] variant is allowed",
+                ));
+            }
+            let disc = quote! { discriminant.into() };
+            let construct = decode_fields(quote! { Self::#ident }, &variant.fields, Some(&disc), true)?;
+            default_arm = Some(quote! { _ => Ok(#construct), });
+            continue;
+        }
+
+        let disc = variant_discriminant(variant, index, plain)?;
+        let construct = decode_fields(quote! { Self::#ident }, &variant.fields, None, true)?;
+        arms.push(quote! {
+            d if d == #disc => Ok(#construct),
+        });
+    }
+
+    let fallback = default_arm.unwrap_or_else(|| {
+        quote! {
+            d => Err(::libvirt_xdr::Error::InvalidEnumDiscriminant(d).at(start)),
+        }
+    });
+
+    Ok(quote! {
+        let start = de.position();
+        let discriminant = de.read_i32()?;
+        match discriminant {
+            #(#arms)*
+            #fallback
+        }
+    })
+}
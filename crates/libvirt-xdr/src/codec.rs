@@ -0,0 +1,520 @@
+//! Native XDR encoding/decoding traits.
+//!
+//! Unlike the serde data model, these traits map one-to-one onto XDR:
+//! discriminants are written as declared, fixed-length opaque data has no
+//! length prefix and `opaque<>` is written as a byte string rather than a
+//! sequence of 4-byte words.
+//!
+//! The traits are normally implemented with `#[derive(XdrEncode, XdrDecode)]`
+//! (see the `libvirt-xdr-derive` crate).
+
+use crate::de::XdrDeserializer;
+use crate::error::{Error, Result};
+use crate::ser::XdrSerializer;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// A type that can be written in XDR format.
+pub trait XdrEncode {
+    /// Append the XDR encoding of `self` to the serializer.
+    fn encode(&self, ser: &mut XdrSerializer) -> Result<()>;
+
+    /// Encode a slice of values without a length prefix.
+    ///
+    /// Overridden by `u8` so that `[u8; N]` and `Vec<u8>` are written as
+    /// XDR opaque data.
+    #[doc(hidden)]
+    fn encode_slice(items: &[Self], ser: &mut XdrSerializer) -> Result<()>
+    where
+        Self: Sized,
+    {
+        for item in items {
+            item.encode(ser)?;
+        }
+        Ok(())
+    }
+}
+
+/// A type that can be read from XDR format.
+pub trait XdrDecode: Sized {
+    /// Read a value from the deserializer.
+    fn decode(de: &mut XdrDeserializer<'_>) -> Result<Self>;
+
+    /// Decode `len` consecutive values.
+    ///
+    /// Overridden by `u8` so that `[u8; N]` and `Vec<u8>` are read as
+    /// XDR opaque data.
+    #[doc(hidden)]
+    fn decode_vec(de: &mut XdrDeserializer<'_>, len: usize) -> Result<Vec<Self>> {
+        // Every XDR item takes at least 4 bytes, so never trust `len` for
+        // the allocation size beyond what the input can hold.
+        let mut items = Vec::with_capacity(len.min(de.remaining() / 4));
+        for index in 0..len {
+            items.push(Self::decode(de).map_err(|e| e.in_index(index))?);
+        }
+        Ok(items)
+    }
+}
+
+/// Variable-length data with an XDR upper bound (`string<N>`, `T<N>`).
+///
+/// Used by `#[xdr(max_len = N)]` fields.
+pub trait XdrBounded {
+    /// Check that the data does not exceed `max` elements.
+    fn check_len(&self, max: usize) -> Result<()>;
+}
+
+impl XdrBounded for String {
+    fn check_len(&self, max: usize) -> Result<()> {
+        if self.len() > max {
+            return Err(Error::StringTooLong(self.len(), max));
+        }
+        Ok(())
+    }
+}
+
+impl<T> XdrBounded for Vec<T> {
+    fn check_len(&self, max: usize) -> Result<()> {
+        if self.len() > max {
+            return Err(Error::ArrayTooLong(self.len(), max));
+        }
+        Ok(())
+    }
+}
+
+impl<T: XdrBounded> XdrBounded for Option<T> {
+    fn check_len(&self, max: usize) -> Result<()> {
+        match self {
+            Some(inner) => inner.check_len(max),
+            None => Ok(()),
+        }
+    }
+}
+
+macro_rules! impl_int {
+    ($ty:ty, $write:ident, $read:ident, $wire:ty) => {
+        impl XdrEncode for $ty {
+            fn encode(&self, ser: &mut XdrSerializer) -> Result<()> {
+                ser.$write(*self as $wire);
+                Ok(())
+            }
+        }
+
+        impl XdrDecode for $ty {
+            fn decode(de: &mut XdrDeserializer<'_>) -> Result<Self> {
+                Ok(de.$read()? as $ty)
+            }
+        }
+    };
+}
+
+/// Integers narrower than the 4-byte `int` they are sent as, rejecting
+/// values that do not fit instead of truncating them.
+macro_rules! impl_narrow_int {
+    ($ty:ty, $write:ident, $read:ident, $wire:ty) => {
+        impl XdrEncode for $ty {
+            fn encode(&self, ser: &mut XdrSerializer) -> Result<()> {
+                ser.$write(<$wire>::from(*self));
+                Ok(())
+            }
+        }
+
+        impl XdrDecode for $ty {
+            fn decode(de: &mut XdrDeserializer<'_>) -> Result<Self> {
+                de.$read(stringify!($ty))
+            }
+        }
+    };
+}
+
+impl_narrow_int!(i8, write_i32, read_i32_as, i32);
+impl_narrow_int!(i16, write_i32, read_i32_as, i32);
+impl_narrow_int!(u16, write_u32, read_u32_as, u32);
+impl_int!(i32, write_i32, read_i32, i32);
+impl_int!(i64, write_i64, read_i64, i64);
+impl_int!(u32, write_u32, read_u32, u32);
+impl_int!(u64, write_u64, read_u64, u64);
+impl_int!(f32, write_f32, read_f32, f32);
+impl_int!(f64, write_f64, read_f64, f64);
+
+impl XdrEncode for u8 {
+    fn encode(&self, ser: &mut XdrSerializer) -> Result<()> {
+        ser.write_u32(*self as u32);
+        Ok(())
+    }
+
+    fn encode_slice(items: &[Self], ser: &mut XdrSerializer) -> Result<()> {
+        ser.write_fixed_opaque(items);
+        Ok(())
+    }
+}
+
+impl XdrDecode for u8 {
+    fn decode(de: &mut XdrDeserializer<'_>) -> Result<Self> {
+        de.read_u32_as("u8")
+    }
+
+    fn decode_vec(de: &mut XdrDeserializer<'_>, len: usize) -> Result<Vec<Self>> {
+        Ok(de.read_fixed_opaque(len)?.to_vec())
+    }
+}
+
+impl XdrEncode for bool {
+    fn encode(&self, ser: &mut XdrSerializer) -> Result<()> {
+        ser.write_bool(*self);
+        Ok(())
+    }
+}
+
+impl XdrDecode for bool {
+    fn decode(de: &mut XdrDeserializer<'_>) -> Result<Self> {
+        de.read_bool()
+    }
+}
+
+impl XdrEncode for () {
+    fn encode(&self, _ser: &mut XdrSerializer) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl XdrDecode for () {
+    fn decode(_de: &mut XdrDeserializer<'_>) -> Result<Self> {
+        Ok(())
+    }
+}
+
+impl XdrEncode for str {
+    fn encode(&self, ser: &mut XdrSerializer) -> Result<()> {
+        ser.write_opaque(self.as_bytes())
+    }
+}
+
+impl XdrEncode for String {
+    fn encode(&self, ser: &mut XdrSerializer) -> Result<()> {
+        self.as_str().encode(ser)
+    }
+}
+
+impl XdrDecode for String {
+    fn decode(de: &mut XdrDeserializer<'_>) -> Result<Self> {
+        de.read_string()
+    }
+}
+
+impl<T: XdrEncode> XdrEncode for [T] {
+    fn encode(&self, ser: &mut XdrSerializer) -> Result<()> {
+        ser.write_length(self.len())?;
+        T::encode_slice(self, ser)
+    }
+}
+
+impl<T: XdrEncode> XdrEncode for Vec<T> {
+    fn encode(&self, ser: &mut XdrSerializer) -> Result<()> {
+        self.as_slice().encode(ser)
+    }
+}
+
+impl<T: XdrDecode> XdrDecode for Vec<T> {
+    fn decode(de: &mut XdrDeserializer<'_>) -> Result<Self> {
+        let len = de.read_u32()? as usize;
+        T::decode_vec(de, len)
+    }
+}
+
+impl<T: XdrEncode, const N: usize> XdrEncode for [T; N] {
+    fn encode(&self, ser: &mut XdrSerializer) -> Result<()> {
+        T::encode_slice(self, ser)
+    }
+}
+
+impl<T: XdrDecode, const N: usize> XdrDecode for [T; N] {
+    fn decode(de: &mut XdrDeserializer<'_>) -> Result<Self> {
+        let items = T::decode_vec(de, N)?;
+        <[T; N]>::try_from(items)
+            .map_err(|items| Error::Message(format!("expected {} items, got {}", N, items.len())))
+    }
+}
+
+/// XDR optional-data (`T *`): a boolean followed by the value when present.
+impl<T: XdrEncode> XdrEncode for Option<T> {
+    fn encode(&self, ser: &mut XdrSerializer) -> Result<()> {
+        match self {
+            Some(value) => {
+                ser.write_bool(true);
+                value.encode(ser)
+            }
+            None => {
+                ser.write_bool(false);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<T: XdrDecode> XdrDecode for Option<T> {
+    fn decode(de: &mut XdrDeserializer<'_>) -> Result<Self> {
+        let start = de.position();
+        match de.read_u32()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(de)?)),
+            v => Err(Error::Message(format!("invalid option discriminant: {}", v)).at(start)),
+        }
+    }
+}
+
+impl<T: XdrEncode + ?Sized> XdrEncode for Box<T> {
+    fn encode(&self, ser: &mut XdrSerializer) -> Result<()> {
+        (**self).encode(ser)
+    }
+}
+
+impl<T: XdrDecode> XdrDecode for Box<T> {
+    fn decode(de: &mut XdrDeserializer<'_>) -> Result<Self> {
+        Ok(Box::new(T::decode(de)?))
+    }
+}
+
+impl<T: XdrEncode + ?Sized> XdrEncode for &T {
+    fn encode(&self, ser: &mut XdrSerializer) -> Result<()> {
+        (**self).encode(ser)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{from_bytes, to_bytes, Error, XdrDecode, XdrEncode};
+
+    #[test]
+    fn test_opaque_is_byte_string() {
+        let data: Vec<u8> = vec![1, 2, 3];
+        let bytes = to_bytes(&data).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 3, 1, 2, 3, 0]);
+        assert_eq!(from_bytes::<Vec<u8>>(&bytes).unwrap(), data);
+    }
+
+    #[test]
+    fn test_fixed_opaque_array() {
+        let data = [0xaau8; 6];
+        let bytes = to_bytes(&data).unwrap();
+        assert_eq!(bytes, vec![0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0, 0]);
+        assert_eq!(from_bytes::<[u8; 6]>(&bytes).unwrap(), data);
+    }
+
+    #[test]
+    fn test_char_array_uses_words() {
+        // `char model[2]` is a fixed array of 4-byte XDR ints.
+        let data = [b'a' as i8, b'b' as i8];
+        assert_eq!(to_bytes(&data).unwrap(), vec![0, 0, 0, 97, 0, 0, 0, 98]);
+    }
+
+    #[test]
+    fn test_narrow_int_range() {
+        assert_eq!(from_bytes::<i8>(&to_bytes(&-128i32).unwrap()).unwrap(), -128);
+        let err = from_bytes::<i8>(&to_bytes(&200i32).unwrap()).unwrap_err();
+        assert!(matches!(err.kind(), Error::IntegerOutOfRange(200, "i8")));
+        assert_eq!(err.offset(), Some(0));
+        assert!(from_bytes::<i16>(&to_bytes(&70000i32).unwrap()).is_err());
+        assert!(from_bytes::<u8>(&to_bytes(&256u32).unwrap()).is_err());
+        assert!(from_bytes::<u16>(&to_bytes(&65536u32).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_derive_struct() {
+        #[derive(Debug, PartialEq, XdrEncode, XdrDecode)]
+        struct Point {
+            x: i32,
+            y: i32,
+            name: Option<String>,
+        }
+
+        let p = Point {
+            x: 10,
+            y: 20,
+            name: None,
+        };
+        let bytes = to_bytes(&p).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 10, 0, 0, 0, 20, 0, 0, 0, 0]);
+        assert_eq!(from_bytes::<Point>(&bytes).unwrap(), p);
+    }
+
+    #[test]
+    fn test_derive_plain_enum() {
+        #[derive(Debug, Clone, Copy, PartialEq, XdrEncode, XdrDecode)]
+        #[repr(i32)]
+        enum State {
+            Running = 1,
+            Paused = 3,
+        }
+
+        assert_eq!(to_bytes(&State::Paused).unwrap(), vec![0, 0, 0, 3]);
+        assert_eq!(from_bytes::<State>(&[0, 0, 0, 1]).unwrap(), State::Running);
+        let err = from_bytes::<State>(&[0, 0, 0, 2]).unwrap_err();
+        assert!(matches!(err.kind(), Error::InvalidEnumDiscriminant(2)));
+        assert_eq!(err.offset(), Some(0));
+    }
+
+    #[test]
+    fn test_derive_union_discriminants() {
+        const KIND_STRING: i32 = 7;
+
+        #[derive(Debug, PartialEq, XdrEncode, XdrDecode)]
+        enum Value {
+            #[xdr(discriminant = 1)]
+            Int(i32),
+            #[xdr(discriminant = KIND_STRING)]
+            Str(String),
+            #[xdr(default)]
+            Unknown(i32),
+        }
+
+        let bytes = to_bytes(&Value::Int(5)).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 1, 0, 0, 0, 5]);
+        assert_eq!(from_bytes::<Value>(&bytes).unwrap(), Value::Int(5));
+
+        let bytes = to_bytes(&Value::Str("hi".into())).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 7, 0, 0, 0, 2, b'h', b'i', 0, 0]);
+        assert_eq!(from_bytes::<Value>(&bytes).unwrap(), Value::Str("hi".into()));
+
+        assert_eq!(from_bytes::<Value>(&[0, 0, 0, 9]).unwrap(), Value::Unknown(9));
+        assert_eq!(to_bytes(&Value::Unknown(9)).unwrap(), vec![0, 0, 0, 9]);
+    }
+
+    #[test]
+    fn test_derive_max_len() {
+        #[derive(Debug, PartialEq, XdrEncode, XdrDecode)]
+        struct Label {
+            #[xdr(max_len = 2)]
+            items: Vec<u32>,
+        }
+
+        let ok = Label { items: vec![1, 2] };
+        let bytes = to_bytes(&ok).unwrap();
+        assert_eq!(from_bytes::<Label>(&bytes).unwrap(), ok);
+
+        let too_long = Label {
+            items: vec![1, 2, 3],
+        };
+        let err = to_bytes(&too_long).unwrap_err();
+        assert!(matches!(err.kind(), Error::ArrayTooLong(3, 2)));
+
+        let bytes = [0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3];
+        let err = from_bytes::<Label>(&bytes).unwrap_err();
+        assert!(matches!(err.kind(), Error::ArrayTooLong(3, 2)));
+        assert_eq!(err.to_string(), "invalid value at byte 0 in Label.items");
+    }
+
+    #[test]
+    fn test_recursive_optional() {
+        #[derive(Debug, PartialEq, XdrEncode, XdrDecode)]
+        struct Node {
+            value: u32,
+            next: Option<Box<Node>>,
+        }
+
+        let list = Node {
+            value: 1,
+            next: Some(Box::new(Node {
+                value: 2,
+                next: None,
+            })),
+        };
+        let bytes = to_bytes(&list).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0]);
+        assert_eq!(from_bytes::<Node>(&bytes).unwrap(), list);
+    }
+
+    #[test]
+    fn test_error_path_and_offset() {
+        #[derive(Debug, PartialEq, XdrEncode, XdrDecode)]
+        struct Param {
+            field: String,
+            value: bool,
+        }
+
+        #[derive(Debug, PartialEq, XdrEncode, XdrDecode)]
+        struct Record {
+            id: u32,
+            params: Vec<Param>,
+        }
+
+        let record = Record {
+            id: 1,
+            params: vec![
+                Param {
+                    field: "a".into(),
+                    value: true,
+                },
+                Param {
+                    field: "b".into(),
+                    value: false,
+                },
+            ],
+        };
+        let mut bytes = to_bytes(&record).unwrap();
+        // Corrupt the second parameter's boolean.
+        let last = bytes.len() - 1;
+        bytes[last] = 2;
+
+        let err = from_bytes::<Record>(&bytes).unwrap_err();
+        assert!(matches!(err.kind(), Error::InvalidBool(2)));
+        assert_eq!(err.offset(), Some(last - 3));
+        assert_eq!(err.path().unwrap().to_string(), "Record.params[1].value");
+
+        // Truncated input reports where the data ran out.
+        let err = from_bytes::<Record>(&bytes[..10]).unwrap_err();
+        assert!(matches!(err.kind(), Error::Eof));
+        assert_eq!(err.offset(), Some(8));
+        assert_eq!(err.to_string(), "invalid value at byte 8 in Record.params[0].field");
+        // The cause is only in the source, so reports print it once.
+        let source = std::error::Error::source(&err).unwrap();
+        assert_eq!(source.to_string(), "unexpected end of input");
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let bytes = [0, 0, 0, 1, 0, 0, 0, 2];
+        assert_eq!(from_bytes::<u32>(&bytes).unwrap(), 1);
+
+        let err = crate::from_bytes_exact::<u32>(&bytes).unwrap_err();
+        assert!(matches!(err.kind(), Error::TrailingData(4)));
+        assert_eq!(err.offset(), Some(4));
+        assert_eq!(crate::from_bytes_exact::<u32>(&bytes[..4]).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_strict_padding() {
+        use crate::{from_bytes_with, DecodeOptions};
+
+        let bytes = [0, 0, 0, 2, b'h', b'i', 0, 7];
+        assert_eq!(from_bytes::<String>(&bytes).unwrap(), "hi");
+
+        let err = from_bytes_with::<String>(&bytes, &DecodeOptions::strict()).unwrap_err();
+        assert!(matches!(err.kind(), Error::NonZeroPadding));
+        assert_eq!(err.offset(), Some(6));
+
+        let clean = [0, 0, 0, 2, b'h', b'i', 0, 0];
+        assert_eq!(from_bytes_with::<String>(&clean, &DecodeOptions::strict()).unwrap(), "hi");
+    }
+
+    #[test]
+    fn test_utf8_policy() {
+        use crate::{from_bytes_with, DecodeOptions, Utf8Policy};
+
+        let bytes = [0, 0, 0, 3, b'a', 0xff, b'b', 0];
+        let err = from_bytes::<String>(&bytes).unwrap_err();
+        assert!(matches!(err.kind(), Error::InvalidUtf8));
+
+        let lossy = from_bytes_with::<String>(&bytes, &DecodeOptions::lossy()).unwrap();
+        assert_eq!(lossy, "a\u{fffd}b");
+
+        let options = DecodeOptions {
+            utf8: Utf8Policy::Latin1,
+            ..DecodeOptions::default()
+        };
+        let latin1 = from_bytes_with::<String>(&bytes, &options).unwrap();
+        assert_eq!(latin1.chars().map(|c| c as u8).collect::<Vec<_>>(), b"a\xffb");
+    }
+}
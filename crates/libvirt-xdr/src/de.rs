@@ -1,18 +1,85 @@
 //! XDR Deserializer implementation.
 
 use crate::error::{Error, Result};
+use alloc::string::{String, ToString};
+#[cfg(feature = "serde")]
+use alloc::format;
+#[cfg(feature = "serde")]
 use serde::de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor};
 
+/// How strings that are not valid UTF-8 are decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Utf8Policy {
+    /// Fail with [`Error::InvalidUtf8`].
+    #[default]
+    Strict,
+    /// Replace invalid sequences with U+FFFD.
+    Lossy,
+    /// Decode every string as Latin-1, mapping each byte to the char with
+    /// the same value, so the original bytes can be recovered with
+    /// `s.chars().map(|c| c as u8)`.
+    ///
+    /// Applies to all strings, valid UTF-8 or not. Strings decoded this
+    /// way must not be sent back as-is: the encoder writes them as UTF-8.
+    Latin1,
+}
+
+/// Options controlling how strictly input is validated.
+///
+/// The default matches what libvirt itself accepts: padding bytes are
+/// skipped unchecked, booleans must be 0 or 1 and strings must be UTF-8.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Reject padding bytes that are not zero.
+    pub strict_padding: bool,
+    /// Handling of strings that are not valid UTF-8.
+    pub utf8: Utf8Policy,
+}
+
+impl DecodeOptions {
+    /// Options rejecting anything RFC 4506 does not allow.
+    pub fn strict() -> Self {
+        Self {
+            strict_padding: true,
+            utf8: Utf8Policy::Strict,
+        }
+    }
+
+    /// Options for fields that may carry guest-originated, non-UTF-8
+    /// data, such as hostnames or filesystem info.
+    pub fn lossy() -> Self {
+        Self {
+            utf8: Utf8Policy::Lossy,
+            ..Self::default()
+        }
+    }
+}
+
 /// XDR Deserializer.
 pub struct XdrDeserializer<'de> {
     input: &'de [u8],
     pos: usize,
+    options: DecodeOptions,
 }
 
 impl<'de> XdrDeserializer<'de> {
-    /// Create a new XDR deserializer.
+    /// Create a new XDR deserializer with default options.
     pub fn new(input: &'de [u8]) -> Self {
-        Self { input, pos: 0 }
+        Self::with_options(input, DecodeOptions::default())
+    }
+
+    /// Create a new XDR deserializer with the given options.
+    pub fn with_options(input: &'de [u8], options: DecodeOptions) -> Self {
+        Self {
+            input,
+            pos: 0,
+            options,
+        }
+    }
+
+    /// Options in effect for this deserializer.
+    pub fn options(&self) -> &DecodeOptions {
+        &self.options
     }
 
     /// Get remaining bytes.
@@ -20,10 +87,23 @@ impl<'de> XdrDeserializer<'de> {
         self.input.len() - self.pos
     }
 
+    /// Current byte offset into the input.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Check that the whole input has been consumed.
+    pub fn end(&self) -> Result<()> {
+        match self.remaining() {
+            0 => Ok(()),
+            n => Err(Error::TrailingData(n).at(self.pos)),
+        }
+    }
+
     /// Read exactly `n` bytes.
     fn read_bytes(&mut self, n: usize) -> Result<&'de [u8]> {
-        if self.pos + n > self.input.len() {
-            return Err(Error::Eof);
+        if n > self.remaining() {
+            return Err(Error::Eof.at(self.pos));
         }
         let bytes = &self.input[self.pos..self.pos + n];
         self.pos += n;
@@ -34,37 +114,115 @@ impl<'de> XdrDeserializer<'de> {
     fn skip_padding(&mut self, len: usize) -> Result<()> {
         let padding = (4 - (len % 4)) % 4;
         if padding > 0 {
-            self.read_bytes(padding)?;
+            let start = self.pos;
+            let bytes = self.read_bytes(padding)?;
+            if self.options.strict_padding && bytes.iter().any(|&b| b != 0) {
+                return Err(Error::NonZeroPadding.at(start));
+            }
         }
         Ok(())
     }
 
-    fn read_i32(&mut self) -> Result<i32> {
+    /// Read a 32-bit signed integer.
+    pub fn read_i32(&mut self) -> Result<i32> {
         let bytes = self.read_bytes(4)?;
         Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
     }
 
-    fn read_u32(&mut self) -> Result<u32> {
+    /// Read a 32-bit unsigned integer.
+    pub fn read_u32(&mut self) -> Result<u32> {
         let bytes = self.read_bytes(4)?;
         Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
     }
 
-    fn read_i64(&mut self) -> Result<i64> {
+    /// Read an `int` holding a narrower signed integer, such as a `char` or
+    /// `short`, failing if its value does not fit.
+    pub fn read_i32_as<T: TryFrom<i32>>(&mut self, type_name: &'static str) -> Result<T> {
+        let start = self.pos;
+        let value = self.read_i32()?;
+        T::try_from(value).map_err(|_| Error::IntegerOutOfRange(value.into(), type_name).at(start))
+    }
+
+    /// Read an `unsigned int` holding a narrower unsigned integer, failing
+    /// if its value does not fit.
+    pub fn read_u32_as<T: TryFrom<u32>>(&mut self, type_name: &'static str) -> Result<T> {
+        let start = self.pos;
+        let value = self.read_u32()?;
+        T::try_from(value).map_err(|_| Error::IntegerOutOfRange(value.into(), type_name).at(start))
+    }
+
+    /// Read a 64-bit signed integer (XDR hyper).
+    pub fn read_i64(&mut self) -> Result<i64> {
         let bytes = self.read_bytes(8)?;
         Ok(i64::from_be_bytes([
             bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
         ]))
     }
 
-    fn read_u64(&mut self) -> Result<u64> {
+    /// Read a 64-bit unsigned integer (XDR unsigned hyper).
+    pub fn read_u64(&mut self) -> Result<u64> {
         let bytes = self.read_bytes(8)?;
         Ok(u64::from_be_bytes([
             bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
         ]))
     }
+
+    /// Read a single-precision float.
+    pub fn read_f32(&mut self) -> Result<f32> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+    /// Read a double-precision float.
+    pub fn read_f64(&mut self) -> Result<f64> {
+        Ok(f64::from_bits(self.read_u64()?))
+    }
+
+    /// Read a boolean (must be 0 or 1).
+    pub fn read_bool(&mut self) -> Result<bool> {
+        let start = self.pos;
+        match self.read_u32()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            v => Err(Error::InvalidBool(v).at(start)),
+        }
+    }
+
+    /// Read variable-length opaque data: length, bytes and padding.
+    pub fn read_opaque(&mut self) -> Result<&'de [u8]> {
+        let len = self.read_u32()? as usize;
+        self.read_fixed_opaque(len)
+    }
+
+    /// Read fixed-length opaque data: `len` bytes and padding.
+    pub fn read_fixed_opaque(&mut self, len: usize) -> Result<&'de [u8]> {
+        let bytes = self.read_bytes(len)?;
+        self.skip_padding(len)?;
+        Ok(bytes)
+    }
+
+    /// Read a string borrowed from the input.
+    ///
+    /// Always requires valid UTF-8, whatever the [`Utf8Policy`]; use
+    /// [`read_string`](Self::read_string) to honour it.
+    pub fn read_str(&mut self) -> Result<&'de str> {
+        let start = self.pos;
+        let bytes = self.read_opaque()?;
+        core::str::from_utf8(bytes).map_err(|_| Error::InvalidUtf8.at(start))
+    }
+
+    /// Read a string into an owned `String`, following the configured
+    /// [`Utf8Policy`].
+    pub fn read_string(&mut self) -> Result<String> {
+        match self.options.utf8 {
+            Utf8Policy::Strict => self.read_str().map(str::to_string),
+            Utf8Policy::Lossy => Ok(String::from_utf8_lossy(self.read_opaque()?).into_owned()),
+            Utf8Policy::Latin1 => Ok(self.read_opaque()?.iter().map(|&b| char::from(b)).collect()),
+        }
+    }
 }
 
-impl<'de, 'a> de::Deserializer<'de> for &'a mut XdrDeserializer<'de> {
+#[cfg(feature = "serde")]
+impl<'de> de::Deserializer<'de> for &mut XdrDeserializer<'de> {
     type Error = Error;
 
     fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
@@ -74,20 +232,15 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut XdrDeserializer<'de> {
     }
 
     fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        let v = self.read_u32()?;
-        match v {
-            0 => visitor.visit_bool(false),
-            1 => visitor.visit_bool(true),
-            _ => Err(Error::InvalidBool(v)),
-        }
+        visitor.visit_bool(self.read_bool()?)
     }
 
     fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        visitor.visit_i8(self.read_i32()? as i8)
+        visitor.visit_i8(self.read_i32_as("i8")?)
     }
 
     fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        visitor.visit_i16(self.read_i32()? as i16)
+        visitor.visit_i16(self.read_i32_as("i16")?)
     }
 
     fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
@@ -99,11 +252,11 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut XdrDeserializer<'de> {
     }
 
     fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        visitor.visit_u8(self.read_u32()? as u8)
+        visitor.visit_u8(self.read_u32_as("u8")?)
     }
 
     fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        visitor.visit_u16(self.read_u32()? as u16)
+        visitor.visit_u16(self.read_u32_as("u16")?)
     }
 
     fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
@@ -115,17 +268,11 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut XdrDeserializer<'de> {
     }
 
     fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        let bytes = self.read_bytes(4)?;
-        let v = f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
-        visitor.visit_f32(v)
+        visitor.visit_f32(self.read_f32()?)
     }
 
     fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        let bytes = self.read_bytes(8)?;
-        let v = f64::from_be_bytes([
-            bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
-        ]);
-        visitor.visit_f64(v)
+        visitor.visit_f64(self.read_f64()?)
     }
 
     fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
@@ -137,36 +284,26 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut XdrDeserializer<'de> {
     }
 
     fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        let len = self.read_u32()? as usize;
-        let bytes = self.read_bytes(len)?;
-        self.skip_padding(len)?;
-        let s = std::str::from_utf8(bytes).map_err(|_| Error::InvalidUtf8)?;
-        visitor.visit_borrowed_str(s)
+        match self.options.utf8 {
+            Utf8Policy::Strict => visitor.visit_borrowed_str(self.read_str()?),
+            _ => visitor.visit_string(self.read_string()?),
+        }
     }
 
     fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        let len = self.read_u32()? as usize;
-        let bytes = self.read_bytes(len)?;
-        self.skip_padding(len)?;
-        let s = std::str::from_utf8(bytes).map_err(|_| Error::InvalidUtf8)?;
-        visitor.visit_string(s.to_string())
+        visitor.visit_string(self.read_string()?)
     }
 
     fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        let len = self.read_u32()? as usize;
-        let bytes = self.read_bytes(len)?;
-        self.skip_padding(len)?;
-        visitor.visit_borrowed_bytes(bytes)
+        visitor.visit_borrowed_bytes(self.read_opaque()?)
     }
 
     fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        let len = self.read_u32()? as usize;
-        let bytes = self.read_bytes(len)?;
-        self.skip_padding(len)?;
-        visitor.visit_byte_buf(bytes.to_vec())
+        visitor.visit_byte_buf(self.read_opaque()?.to_vec())
     }
 
     fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let start = self.pos;
         let present = self.read_u32()?;
         match present {
             0 => visitor.visit_none(),
@@ -174,7 +311,8 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut XdrDeserializer<'de> {
             _ => Err(Error::Message(format!(
                 "invalid option discriminant: {}",
                 present
-            ))),
+            ))
+            .at(start)),
         }
     }
 
@@ -257,18 +395,21 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut XdrDeserializer<'de> {
     }
 }
 
+#[cfg(feature = "serde")]
 struct SeqAccessor<'a, 'de: 'a> {
     de: &'a mut XdrDeserializer<'de>,
     remaining: usize,
 }
 
+#[cfg(feature = "serde")]
 impl<'a, 'de> SeqAccessor<'a, 'de> {
     fn new(de: &'a mut XdrDeserializer<'de>, len: usize) -> Self {
         Self { de, remaining: len }
     }
 }
 
-impl<'de, 'a> SeqAccess<'de> for SeqAccessor<'a, 'de> {
+#[cfg(feature = "serde")]
+impl<'de> SeqAccess<'de> for SeqAccessor<'_, 'de> {
     type Error = Error;
 
     fn next_element_seed<T: DeserializeSeed<'de>>(
@@ -283,18 +424,21 @@ impl<'de, 'a> SeqAccess<'de> for SeqAccessor<'a, 'de> {
     }
 }
 
+#[cfg(feature = "serde")]
 struct MapAccessor<'a, 'de: 'a> {
     de: &'a mut XdrDeserializer<'de>,
     remaining: usize,
 }
 
+#[cfg(feature = "serde")]
 impl<'a, 'de> MapAccessor<'a, 'de> {
     fn new(de: &'a mut XdrDeserializer<'de>, len: usize) -> Self {
         Self { de, remaining: len }
     }
 }
 
-impl<'de, 'a> MapAccess<'de> for MapAccessor<'a, 'de> {
+#[cfg(feature = "serde")]
+impl<'de> MapAccess<'de> for MapAccessor<'_, 'de> {
     type Error = Error;
 
     fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
@@ -310,17 +454,20 @@ impl<'de, 'a> MapAccess<'de> for MapAccessor<'a, 'de> {
     }
 }
 
+#[cfg(feature = "serde")]
 struct EnumAccessor<'a, 'de: 'a> {
     de: &'a mut XdrDeserializer<'de>,
 }
 
+#[cfg(feature = "serde")]
 impl<'a, 'de> EnumAccessor<'a, 'de> {
     fn new(de: &'a mut XdrDeserializer<'de>) -> Self {
         Self { de }
     }
 }
 
-impl<'de, 'a> de::EnumAccess<'de> for EnumAccessor<'a, 'de> {
+#[cfg(feature = "serde")]
+impl<'de> de::EnumAccess<'de> for EnumAccessor<'_, 'de> {
     type Error = Error;
     type Variant = Self;
 
@@ -330,7 +477,8 @@ impl<'de, 'a> de::EnumAccess<'de> for EnumAccessor<'a, 'de> {
     }
 }
 
-impl<'de, 'a> de::VariantAccess<'de> for EnumAccessor<'a, 'de> {
+#[cfg(feature = "serde")]
+impl<'de> de::VariantAccess<'de> for EnumAccessor<'_, 'de> {
     type Error = Error;
 
     fn unit_variant(self) -> Result<()> {
@@ -354,13 +502,13 @@ impl<'de, 'a> de::VariantAccess<'de> for EnumAccessor<'a, 'de> {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "serde"))]
 mod tests {
     use super::*;
     use serde::Deserialize;
 
     fn from_bytes<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T> {
-        crate::from_bytes(bytes)
+        crate::serde::from_bytes(bytes)
     }
 
     #[test]
@@ -462,8 +610,8 @@ mod tests {
             optional: Some(12345),
         };
 
-        let bytes = crate::to_bytes(&original).unwrap();
-        let decoded: TestStruct = crate::from_bytes(&bytes).unwrap();
+        let bytes = crate::serde::to_bytes(&original).unwrap();
+        let decoded: TestStruct = crate::serde::from_bytes(&bytes).unwrap();
 
         assert_eq!(original, decoded);
     }
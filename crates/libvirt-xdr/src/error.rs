@@ -1,9 +1,15 @@
 //! Error types for XDR serialization/deserialization.
 
-use std::fmt;
+use alloc::boxed::Box;
+use alloc::string::String;
+#[cfg(feature = "serde")]
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Write as _;
 
 /// Result type for XDR operations.
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 /// Errors that can occur during XDR serialization/deserialization.
 #[derive(Debug, thiserror::Error)]
@@ -24,6 +30,15 @@ pub enum Error {
     #[error("invalid enum discriminant: {0}")]
     InvalidEnumDiscriminant(i32),
 
+    /// Integer that does not fit the type it is decoded into, e.g. an
+    /// `int` above 127 for an `i8`.
+    #[error("integer {0} out of range for {1}")]
+    IntegerOutOfRange(i64, &'static str),
+
+    /// Padding byte is not zero (strict mode only).
+    #[error("non-zero padding byte")]
+    NonZeroPadding,
+
     /// String is not valid UTF-8.
     #[error("invalid UTF-8 string")]
     InvalidUtf8,
@@ -39,14 +54,208 @@ pub enum Error {
     /// Trailing data after deserialization.
     #[error("trailing data: {0} bytes remaining")]
     TrailingData(usize),
+
+    /// An error annotated with where in the input it occurred. The
+    /// message only gives the location: the error itself is the
+    /// [`source`](core::error::Error::source).
+    #[error("invalid value{}", context_suffix(*.offset, .path))]
+    Context {
+        /// The underlying error.
+        source: Box<Error>,
+        /// Byte offset into the input, if known.
+        offset: Option<usize>,
+        /// Field path leading to the failing value.
+        path: FieldPath,
+    },
+}
+
+impl Error {
+    /// Record the byte offset at which the error occurred.
+    ///
+    /// The innermost offset wins: an error that already carries an
+    /// offset keeps it.
+    pub fn at(self, offset: usize) -> Self {
+        match self {
+            Error::Context {
+                source,
+                offset: None,
+                path,
+            } => Error::Context {
+                source,
+                offset: Some(offset),
+                path,
+            },
+            Error::Context { .. } => self,
+            source => Error::Context {
+                source: Box::new(source),
+                offset: Some(offset),
+                path: FieldPath::default(),
+            },
+        }
+    }
+
+    /// Prepend a struct field to the error's path.
+    pub fn in_field(self, name: &'static str) -> Self {
+        self.with_segment(PathSegment::Field(name))
+    }
+
+    /// Prepend an array index to the error's path.
+    pub fn in_index(self, index: usize) -> Self {
+        self.with_segment(PathSegment::Index(index))
+    }
+
+    /// Set the name of the outermost type being decoded.
+    pub fn in_type(self, name: &'static str) -> Self {
+        match self {
+            Error::Context {
+                source,
+                offset,
+                mut path,
+            } => {
+                path.root = Some(name);
+                Error::Context {
+                    source,
+                    offset,
+                    path,
+                }
+            }
+            source => Error::Context {
+                source: Box::new(source),
+                offset: None,
+                path: FieldPath {
+                    root: Some(name),
+                    segments: Vec::new(),
+                },
+            },
+        }
+    }
+
+    /// Byte offset into the input where the error occurred, if known.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Error::Context { offset, .. } => *offset,
+            _ => None,
+        }
+    }
+
+    /// Field path leading to the failing value, if known.
+    pub fn path(&self) -> Option<&FieldPath> {
+        match self {
+            Error::Context { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The underlying error, without offset and path.
+    pub fn kind(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.kind(),
+            _ => self,
+        }
+    }
+
+    fn with_segment(self, segment: PathSegment) -> Self {
+        match self {
+            Error::Context {
+                source,
+                offset,
+                mut path,
+            } => {
+                path.segments.push(segment);
+                Error::Context {
+                    source,
+                    offset,
+                    path,
+                }
+            }
+            // Errors raised outside the deserializer (e.g. bound checks)
+            // still get a path, even though the offset is unknown.
+            source => {
+                let mut path = FieldPath::default();
+                path.segments.push(segment);
+                Error::Context {
+                    source: Box::new(source),
+                    offset: None,
+                    path,
+                }
+            }
+        }
+    }
+}
+
+fn context_suffix(offset: Option<usize>, path: &FieldPath) -> String {
+    let mut suffix = String::new();
+    if let Some(offset) = offset {
+        let _ = write!(suffix, " at byte {}", offset);
+    }
+    if !path.is_empty() {
+        let _ = write!(suffix, " in {}", path);
+    }
+    suffix
+}
+
+/// Location of a value within a decoded type, e.g.
+/// `ConnectGetAllDomainStatsRet.ret_stats[3].params[7].value`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldPath {
+    root: Option<&'static str>,
+    /// Segments from innermost to outermost.
+    segments: Vec<PathSegment>,
+}
+
+/// One step of a [`FieldPath`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathSegment {
+    /// A named struct field.
+    Field(&'static str),
+    /// An element of an array.
+    Index(usize),
+}
+
+impl FieldPath {
+    /// Name of the outermost type, if known.
+    pub fn root(&self) -> Option<&'static str> {
+        self.root
+    }
+
+    /// Path segments from outermost to innermost.
+    pub fn segments(&self) -> impl Iterator<Item = &PathSegment> {
+        self.segments.iter().rev()
+    }
+
+    /// Whether the path has neither a root type nor any segment.
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none() && self.segments.is_empty()
+    }
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        if let Some(root) = self.root {
+            f.write_str(root)?;
+            first = false;
+        }
+        for segment in self.segments() {
+            match segment {
+                PathSegment::Field(name) if first => f.write_str(name)?,
+                PathSegment::Field(name) => write!(f, ".{}", name)?,
+                PathSegment::Index(index) => write!(f, "[{}]", index)?,
+            }
+            first = false;
+        }
+        Ok(())
+    }
 }
 
+#[cfg(feature = "serde")]
 impl serde::ser::Error for Error {
     fn custom<T: fmt::Display>(msg: T) -> Self {
         Error::Message(msg.to_string())
     }
 }
 
+#[cfg(feature = "serde")]
 impl serde::de::Error for Error {
     fn custom<T: fmt::Display>(msg: T) -> Self {
         Error::Message(msg.to_string())
@@ -1,28 +1,99 @@
 //! XDR (External Data Representation) serialization for libvirt protocol.
 //!
-//! This crate provides serde-based serialization and deserialization
-//! for the XDR binary format used by libvirt's RPC protocol.
+//! This crate provides serialization and deserialization for the XDR
+//! binary format used by libvirt's RPC protocol.
+//!
+//! Types implement [`XdrEncode`] and [`XdrDecode`], usually through
+//! `#[derive(XdrEncode, XdrDecode)]` (feature `derive`, on by default).
+//! A serde-based codec is available under the `serde` feature.
+//!
+//! Without the default `std` feature the crate is `#![no_std]` and only
+//! requires `alloc`. Its errors then implement `core::error::Error`,
+//! stable since Rust 1.81, the crate's minimum version.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
+// Lets the derive macros refer to `::libvirt_xdr` from within this crate.
+extern crate self as libvirt_xdr;
+
+use alloc::vec::Vec;
+
+mod codec;
 mod de;
 mod error;
 pub mod opaque;
 mod ser;
 
-pub use de::XdrDeserializer;
-pub use error::{Error, Result};
+pub use codec::{XdrBounded, XdrDecode, XdrEncode};
+pub use de::{DecodeOptions, Utf8Policy, XdrDeserializer};
+pub use error::{Error, FieldPath, PathSegment, Result};
 pub use ser::XdrSerializer;
 
-use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "derive")]
+pub use libvirt_xdr_derive::{XdrDecode, XdrEncode};
 
 /// Serialize a value to XDR bytes.
-pub fn to_bytes<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+pub fn to_bytes<T: XdrEncode + ?Sized>(value: &T) -> Result<Vec<u8>> {
     let mut serializer = XdrSerializer::new();
-    value.serialize(&mut serializer)?;
+    value.encode(&mut serializer)?;
     Ok(serializer.into_bytes())
 }
 
 /// Deserialize a value from XDR bytes.
-pub fn from_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+///
+/// Bytes left over after the value are ignored; use [`from_bytes_exact`]
+/// to reject them.
+pub fn from_bytes<T: XdrDecode>(bytes: &[u8]) -> Result<T> {
+    from_bytes_with(bytes, &DecodeOptions::default())
+}
+
+/// Deserialize a value from XDR bytes with the given [`DecodeOptions`].
+///
+/// Like [`from_bytes`], bytes left over after the value are ignored.
+pub fn from_bytes_with<T: XdrDecode>(bytes: &[u8], options: &DecodeOptions) -> Result<T> {
+    let mut deserializer = XdrDeserializer::with_options(bytes, *options);
+    T::decode(&mut deserializer).map_err(|e| e.in_type(type_name::<T>()))
+}
+
+/// Deserialize a value from XDR bytes, failing with
+/// [`Error::TrailingData`] if any input is left over.
+///
+/// Extra bytes usually mean the peer speaks a newer protocol revision
+/// with fields this side does not know about.
+pub fn from_bytes_exact<T: XdrDecode>(bytes: &[u8]) -> Result<T> {
     let mut deserializer = XdrDeserializer::new(bytes);
-    T::deserialize(&mut deserializer)
+    T::decode(&mut deserializer)
+        .and_then(|value| deserializer.end().map(|_| value))
+        .map_err(|e| e.in_type(type_name::<T>()))
+}
+
+/// Short name of a type for error paths (`Vec<u32>` -> `Vec`).
+fn type_name<T>() -> &'static str {
+    let full = core::any::type_name::<T>();
+    let base = full.split('<').next().unwrap_or(full);
+    base.rsplit("::").next().unwrap_or(base)
+}
+
+/// serde-based XDR encoding, for types that only implement
+/// `Serialize`/`Deserialize`.
+#[cfg(feature = "serde")]
+pub mod serde {
+    use crate::{Result, XdrDeserializer, XdrSerializer};
+    use alloc::vec::Vec;
+    use ::serde::{de::DeserializeOwned, Serialize};
+
+    /// Serialize a value to XDR bytes through serde.
+    pub fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        let mut serializer = XdrSerializer::new();
+        value.serialize(&mut serializer)?;
+        Ok(serializer.into_bytes())
+    }
+
+    /// Deserialize a value from XDR bytes through serde.
+    pub fn from_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        let mut deserializer = XdrDeserializer::new(bytes);
+        T::deserialize(&mut deserializer)
+    }
 }
@@ -4,8 +4,18 @@
 //! just the raw bytes with padding to 4-byte alignment.
 //!
 //! This module provides `FixedOpaque16` type that correctly handles
-//! XDR serialization for 16-byte fixed opaque data (UUID).
+//! XDR serialization for 16-byte fixed opaque data (UUID), and
+//! `Quadruple`, which carries the 16 bytes of an XDR quadruple-precision
+//! float the same way.
 
+use crate::codec::{XdrDecode, XdrEncode};
+use crate::de::XdrDeserializer;
+use crate::error;
+use crate::ser::XdrSerializer;
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "serde")]
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 /// Wrapper type for 16-byte fixed-length opaque data (UUID).
@@ -39,12 +49,28 @@ impl FixedOpaque16 {
     }
 }
 
-impl std::fmt::Display for FixedOpaque16 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for FixedOpaque16 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.to_uuid_string())
     }
 }
 
+impl XdrEncode for FixedOpaque16 {
+    fn encode(&self, ser: &mut XdrSerializer) -> error::Result<()> {
+        ser.write_fixed_opaque(&self.0);
+        Ok(())
+    }
+}
+
+impl XdrDecode for FixedOpaque16 {
+    fn decode(de: &mut XdrDeserializer<'_>) -> error::Result<Self> {
+        let mut arr = [0u8; 16];
+        arr.copy_from_slice(de.read_fixed_opaque(16)?);
+        Ok(FixedOpaque16(arr))
+    }
+}
+
+#[cfg(feature = "serde")]
 impl Serialize for FixedOpaque16 {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -57,8 +83,10 @@ impl Serialize for FixedOpaque16 {
 }
 
 /// Helper to serialize raw bytes for fixed opaque.
+#[cfg(feature = "serde")]
 struct FixedOpaqueBytes<'a>(&'a [u8]);
 
+#[cfg(feature = "serde")]
 impl Serialize for FixedOpaqueBytes<'_> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -68,6 +96,7 @@ impl Serialize for FixedOpaqueBytes<'_> {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for FixedOpaque16 {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -78,7 +107,7 @@ impl<'de> Deserialize<'de> for FixedOpaque16 {
         impl<'de> de::Visitor<'de> for FixedOpaque16Visitor {
             type Value = FixedOpaque16;
 
-            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
                 formatter.write_str("16 bytes of opaque data")
             }
 
@@ -97,8 +126,8 @@ impl<'de> Deserialize<'de> for FixedOpaque16 {
                 A: de::SeqAccess<'de>,
             {
                 let mut arr = [0u8; 16];
-                for i in 0..16 {
-                    arr[i] = seq
+                for (i, byte) in arr.iter_mut().enumerate() {
+                    *byte = seq
                         .next_element()?
                         .ok_or_else(|| de::Error::invalid_length(i, &self))?;
                 }
@@ -110,3 +139,42 @@ impl<'de> Deserialize<'de> for FixedOpaque16 {
         deserializer.deserialize_newtype_struct("FixedOpaque16", FixedOpaque16Visitor)
     }
 }
+
+/// An XDR `quadruple` (IEEE 754 binary128), which Rust has no stable type
+/// for, kept as its 16 bytes in XDR (big-endian) order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Quadruple(pub [u8; 16]);
+
+impl XdrEncode for Quadruple {
+    fn encode(&self, ser: &mut XdrSerializer) -> error::Result<()> {
+        ser.write_fixed_opaque(&self.0);
+        Ok(())
+    }
+}
+
+impl XdrDecode for Quadruple {
+    fn decode(de: &mut XdrDeserializer<'_>) -> error::Result<Self> {
+        FixedOpaque16::decode(de).map(|bytes| Quadruple(bytes.0))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Quadruple {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // Same raw 16 bytes as a UUID
+        FixedOpaque16(self.0).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Quadruple {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        FixedOpaque16::deserialize(deserializer).map(|bytes| Quadruple(bytes.0))
+    }
+}
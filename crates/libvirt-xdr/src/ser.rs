@@ -1,6 +1,8 @@
 //! XDR Serializer implementation.
 
 use crate::error::{Error, Result};
+use alloc::vec::Vec;
+#[cfg(feature = "serde")]
 use serde::{ser, Serialize};
 
 /// XDR Serializer.
@@ -26,10 +28,70 @@ impl XdrSerializer {
         self.output
     }
 
+    /// Get the bytes written so far.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.output
+    }
+
     /// Write padding bytes for 4-byte alignment.
     fn write_padding(&mut self, len: usize) {
         let padding = (4 - (len % 4)) % 4;
-        self.output.extend(std::iter::repeat(0u8).take(padding));
+        self.output.extend(core::iter::repeat(0u8).take(padding));
+    }
+
+    /// Write a 32-bit signed integer.
+    pub fn write_i32(&mut self, v: i32) {
+        self.output.extend_from_slice(&v.to_be_bytes());
+    }
+
+    /// Write a 32-bit unsigned integer.
+    pub fn write_u32(&mut self, v: u32) {
+        self.output.extend_from_slice(&v.to_be_bytes());
+    }
+
+    /// Write a 64-bit signed integer (XDR hyper).
+    pub fn write_i64(&mut self, v: i64) {
+        self.output.extend_from_slice(&v.to_be_bytes());
+    }
+
+    /// Write a 64-bit unsigned integer (XDR unsigned hyper).
+    pub fn write_u64(&mut self, v: u64) {
+        self.output.extend_from_slice(&v.to_be_bytes());
+    }
+
+    /// Write a single-precision float.
+    pub fn write_f32(&mut self, v: f32) {
+        self.output.extend_from_slice(&v.to_be_bytes());
+    }
+
+    /// Write a double-precision float.
+    pub fn write_f64(&mut self, v: f64) {
+        self.output.extend_from_slice(&v.to_be_bytes());
+    }
+
+    /// Write a boolean as a 4-byte 0 or 1.
+    pub fn write_bool(&mut self, v: bool) {
+        self.write_u32(v as u32);
+    }
+
+    /// Write a length prefix for variable-length data.
+    pub fn write_length(&mut self, len: usize) -> Result<()> {
+        let len = u32::try_from(len).map_err(|_| Error::ArrayTooLong(len, u32::MAX as usize))?;
+        self.write_u32(len);
+        Ok(())
+    }
+
+    /// Write variable-length opaque data: length, bytes and padding.
+    pub fn write_opaque(&mut self, v: &[u8]) -> Result<()> {
+        self.write_length(v.len())?;
+        self.write_fixed_opaque(v);
+        Ok(())
+    }
+
+    /// Write fixed-length opaque data: bytes and padding, no length prefix.
+    pub fn write_fixed_opaque(&mut self, v: &[u8]) {
+        self.output.extend_from_slice(v);
+        self.write_padding(v.len());
     }
 }
 
@@ -40,10 +102,12 @@ impl Default for XdrSerializer {
 }
 
 /// Special serializer for fixed-length opaque that writes bytes without length prefix.
+#[cfg(feature = "serde")]
 struct FixedOpaqueSerializer<'a> {
     output: &'a mut Vec<u8>,
 }
 
+#[cfg(feature = "serde")]
 impl<'a> ser::Serializer for &'a mut FixedOpaqueSerializer<'a> {
     type Ok = ();
     type Error = Error;
@@ -60,7 +124,7 @@ impl<'a> ser::Serializer for &'a mut FixedOpaqueSerializer<'a> {
         self.output.extend_from_slice(v);
         // Add padding for 4-byte alignment
         let padding = (4 - (v.len() % 4)) % 4;
-        self.output.extend(std::iter::repeat(0u8).take(padding));
+        self.output.extend(core::iter::repeat(0u8).take(padding));
         Ok(())
     }
 
@@ -94,7 +158,8 @@ impl<'a> ser::Serializer for &'a mut FixedOpaqueSerializer<'a> {
     fn serialize_struct_variant(self, _: &'static str, _: u32, _: &'static str, _: usize) -> Result<Self::SerializeStructVariant> { Err(Error::Message("unsupported".into())) }
 }
 
-impl<'a> ser::Serializer for &'a mut XdrSerializer {
+#[cfg(feature = "serde")]
+impl ser::Serializer for &mut XdrSerializer {
     type Ok = ();
     type Error = Error;
 
@@ -119,12 +184,12 @@ impl<'a> ser::Serializer for &'a mut XdrSerializer {
     }
 
     fn serialize_i32(self, v: i32) -> Result<()> {
-        self.output.extend_from_slice(&v.to_be_bytes());
+        self.write_i32(v);
         Ok(())
     }
 
     fn serialize_i64(self, v: i64) -> Result<()> {
-        self.output.extend_from_slice(&v.to_be_bytes());
+        self.write_i64(v);
         Ok(())
     }
 
@@ -137,22 +202,22 @@ impl<'a> ser::Serializer for &'a mut XdrSerializer {
     }
 
     fn serialize_u32(self, v: u32) -> Result<()> {
-        self.output.extend_from_slice(&v.to_be_bytes());
+        self.write_u32(v);
         Ok(())
     }
 
     fn serialize_u64(self, v: u64) -> Result<()> {
-        self.output.extend_from_slice(&v.to_be_bytes());
+        self.write_u64(v);
         Ok(())
     }
 
     fn serialize_f32(self, v: f32) -> Result<()> {
-        self.output.extend_from_slice(&v.to_be_bytes());
+        self.write_f32(v);
         Ok(())
     }
 
     fn serialize_f64(self, v: f64) -> Result<()> {
-        self.output.extend_from_slice(&v.to_be_bytes());
+        self.write_f64(v);
         Ok(())
     }
 
@@ -161,18 +226,11 @@ impl<'a> ser::Serializer for &'a mut XdrSerializer {
     }
 
     fn serialize_str(self, v: &str) -> Result<()> {
-        let bytes = v.as_bytes();
-        self.serialize_u32(bytes.len() as u32)?;
-        self.output.extend_from_slice(bytes);
-        self.write_padding(bytes.len());
-        Ok(())
+        self.write_opaque(v.as_bytes())
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<()> {
-        self.serialize_u32(v.len() as u32)?;
-        self.output.extend_from_slice(v);
-        self.write_padding(v.len());
-        Ok(())
+        self.write_opaque(v)
     }
 
     fn serialize_none(self) -> Result<()> {
@@ -280,7 +338,8 @@ impl<'a> ser::Serializer for &'a mut XdrSerializer {
     }
 }
 
-impl<'a> ser::SerializeSeq for &'a mut XdrSerializer {
+#[cfg(feature = "serde")]
+impl ser::SerializeSeq for &mut XdrSerializer {
     type Ok = ();
     type Error = Error;
 
@@ -293,7 +352,8 @@ impl<'a> ser::SerializeSeq for &'a mut XdrSerializer {
     }
 }
 
-impl<'a> ser::SerializeTuple for &'a mut XdrSerializer {
+#[cfg(feature = "serde")]
+impl ser::SerializeTuple for &mut XdrSerializer {
     type Ok = ();
     type Error = Error;
 
@@ -306,7 +366,8 @@ impl<'a> ser::SerializeTuple for &'a mut XdrSerializer {
     }
 }
 
-impl<'a> ser::SerializeTupleStruct for &'a mut XdrSerializer {
+#[cfg(feature = "serde")]
+impl ser::SerializeTupleStruct for &mut XdrSerializer {
     type Ok = ();
     type Error = Error;
 
@@ -319,7 +380,8 @@ impl<'a> ser::SerializeTupleStruct for &'a mut XdrSerializer {
     }
 }
 
-impl<'a> ser::SerializeTupleVariant for &'a mut XdrSerializer {
+#[cfg(feature = "serde")]
+impl ser::SerializeTupleVariant for &mut XdrSerializer {
     type Ok = ();
     type Error = Error;
 
@@ -332,7 +394,8 @@ impl<'a> ser::SerializeTupleVariant for &'a mut XdrSerializer {
     }
 }
 
-impl<'a> ser::SerializeMap for &'a mut XdrSerializer {
+#[cfg(feature = "serde")]
+impl ser::SerializeMap for &mut XdrSerializer {
     type Ok = ();
     type Error = Error;
 
@@ -349,7 +412,8 @@ impl<'a> ser::SerializeMap for &'a mut XdrSerializer {
     }
 }
 
-impl<'a> ser::SerializeStruct for &'a mut XdrSerializer {
+#[cfg(feature = "serde")]
+impl ser::SerializeStruct for &mut XdrSerializer {
     type Ok = ();
     type Error = Error;
 
@@ -366,7 +430,8 @@ impl<'a> ser::SerializeStruct for &'a mut XdrSerializer {
     }
 }
 
-impl<'a> ser::SerializeStructVariant for &'a mut XdrSerializer {
+#[cfg(feature = "serde")]
+impl ser::SerializeStructVariant for &mut XdrSerializer {
     type Ok = ();
     type Error = Error;
 
@@ -383,13 +448,13 @@ impl<'a> ser::SerializeStructVariant for &'a mut XdrSerializer {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "serde"))]
 mod tests {
     use super::*;
     use serde::Serialize;
 
     fn to_bytes<T: Serialize>(value: &T) -> Result<Vec<u8>> {
-        crate::to_bytes(value)
+        crate::serde::to_bytes(value)
     }
 
     #[test]
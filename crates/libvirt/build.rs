@@ -17,7 +17,7 @@ fn main() {
     let remote_path = proto_dir.join("remote_protocol.x");
     bundle.remote = Some(
         libvirt_codegen::parse_file(remote_path.to_str().unwrap())
-            .expect("failed to parse remote protocol"),
+            .unwrap_or_else(|e| panic!("{}", e)),
     );
 
     // Parse QEMU protocol (optional)
@@ -25,7 +25,7 @@ fn main() {
     if qemu_path.exists() {
         bundle.qemu = Some(
             libvirt_codegen::parse_file(qemu_path.to_str().unwrap())
-                .expect("failed to parse qemu protocol"),
+                .unwrap_or_else(|e| panic!("{}", e)),
         );
     }
 
@@ -34,10 +34,31 @@ fn main() {
     if lxc_path.exists() {
         bundle.lxc = Some(
             libvirt_codegen::parse_file(lxc_path.to_str().unwrap())
-                .expect("failed to parse lxc protocol"),
+                .unwrap_or_else(|e| panic!("{}", e)),
         );
     }
 
+    // Public API constants, to type the remote procedures' flags
+    let api_path = proto_dir.join("libvirt-api.xml");
+    if api_path.exists() {
+        bundle.api = Some(libvirt_codegen::Api::from_file(&api_path).unwrap_or_else(|e| panic!("{}", e)));
+    }
+
+    // Surface definitions the generator will skip
+    for protocol in [&bundle.remote, &bundle.qemu, &bundle.lxc].into_iter().flatten() {
+        for warning in &protocol.warnings {
+            match &warning.location {
+                Some(location) => println!(
+                    "cargo:warning={}:{}: {}",
+                    warning.path.as_ref().map_or(String::new(), |p| p.display().to_string()),
+                    location.line,
+                    warning.message
+                ),
+                None => println!("cargo:warning={}", warning.message),
+            }
+        }
+    }
+
     // Generate Rust code from all protocols
     let code = libvirt_codegen::generate_bundle(&bundle);
 
@@ -45,9 +66,15 @@ fn main() {
     let dest = std::path::Path::new(&out_dir).join("generated.rs");
     std::fs::write(&dest, code).expect("failed to write generated code");
 
+    // Round-trip property tests for every generated type (used by `cargo test`)
+    let tests = libvirt_codegen::generate_roundtrip_tests(&bundle);
+    let dest = std::path::Path::new(&out_dir).join("roundtrip_tests.rs");
+    std::fs::write(&dest, tests).expect("failed to write generated tests");
+
     // Tell Cargo to rerun if these files change
     println!("cargo:rerun-if-changed=proto/remote_protocol.x");
     println!("cargo:rerun-if-changed=proto/qemu_protocol.x");
     println!("cargo:rerun-if-changed=proto/lxc_protocol.x");
+    println!("cargo:rerun-if-changed=proto/libvirt-api.xml");
     println!("cargo:rerun-if-changed=build.rs");
 }
@@ -3,7 +3,7 @@
 //! This example connects to the local libvirt daemon and shows
 //! detailed information about all domains using the auto-generated API.
 
-use libvirt::{Client, ConnectListAllDomainsArgs, ConnectListDomainsArgs};
+use libvirt_pure::{Client, ConnectListAllDomainsArgs, ConnectListAllDomainsFlags, ConnectListDomainsArgs};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -89,7 +89,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("=== All Domains (active + inactive) ===\n");
     let args = ConnectListAllDomainsArgs {
         need_results: 1,
-        flags: 0, // 0 = all domains
+        flags: ConnectListAllDomainsFlags::empty(), // no filter = all domains
     };
     match client.rpc().connect_list_all_domains(args).await {
         Ok(ret) => {
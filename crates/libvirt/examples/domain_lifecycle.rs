@@ -15,11 +15,7 @@
 //!   domain_lifecycle destroy <name>    - Force stop a domain
 //!   domain_lifecycle reboot <name>     - Reboot a domain
 
-use libvirt::{
-    Client, ConnectListAllDomainsArgs, DomainCreateArgs, DomainDestroyArgs,
-    DomainLookupByNameArgs, DomainRebootArgs, DomainResumeArgs, DomainShutdownArgs,
-    DomainSuspendArgs, NonnullDomain,
-};
+use libvirt_pure::{Client, ConnectListAllDomainsFlags, Domain, DomainRebootFlags};
 use std::env;
 
 #[tokio::main]
@@ -98,14 +94,10 @@ fn print_usage(program: &str) {
 async fn list_domains(client: &Client) -> Result<(), Box<dyn std::error::Error>> {
     println!("\n=== Domain List ===\n");
 
-    let args = ConnectListAllDomainsArgs {
-        need_results: 1,
-        flags: 0, // All domains
-    };
-
-    match client.rpc().connect_list_all_domains(args).await {
-        Ok(ret) => {
-            if ret.domains.is_empty() {
+    // All domains
+    match client.list_all_domains(ConnectListAllDomainsFlags::empty()).await {
+        Ok(domains) => {
+            if domains.is_empty() {
                 println!("No domains found.");
                 return Ok(());
             }
@@ -116,16 +108,16 @@ async fn list_domains(client: &Client) -> Result<(), Box<dyn std::error::Error>>
             );
             println!("{}", "-".repeat(70));
 
-            for dom in &ret.domains {
-                let state = if dom.id == -1 { "shut off" } else { "running" };
+            for dom in &domains {
+                let state = if dom.id().await?.is_some() { "running" } else { "shut off" };
                 println!(
                     "{:<20} {:<38} {:<10}",
-                    dom.name,
-                    dom.uuid,
+                    dom.name(),
+                    dom.uuid(),
                     state
                 );
             }
-            println!("\nTotal: {} domain(s)", ret.domains.len());
+            println!("\nTotal: {} domain(s)", domains.len());
         }
         Err(e) => {
             println!("Failed to list domains: {}", e);
@@ -135,32 +127,20 @@ async fn list_domains(client: &Client) -> Result<(), Box<dyn std::error::Error>>
     Ok(())
 }
 
-async fn lookup_domain(
-    client: &Client,
-    name: &str,
-) -> Result<NonnullDomain, Box<dyn std::error::Error>> {
-    let args = DomainLookupByNameArgs {
-        name: name.to_string(),
-    };
-
-    let ret = client
-        .rpc()
-        .domain_lookup_by_name(args)
+async fn lookup_domain(client: &Client, name: &str) -> Result<Domain, Box<dyn std::error::Error>> {
+    let dom = client
+        .lookup_domain_by_name(name)
         .await
         .map_err(|e| format!("Failed to find domain '{}': {}", name, e))?;
 
-    Ok(ret.dom)
+    Ok(dom)
 }
 
 async fn suspend_domain(client: &Client, name: &str) -> Result<(), Box<dyn std::error::Error>> {
     println!("Suspending domain '{}'...", name);
 
     let dom = lookup_domain(client, name).await?;
-    let args = DomainSuspendArgs { dom };
-
-    client
-        .rpc()
-        .domain_suspend(args)
+    dom.suspend()
         .await
         .map_err(|e| format!("Failed to suspend: {}", e))?;
 
@@ -172,11 +152,7 @@ async fn resume_domain(client: &Client, name: &str) -> Result<(), Box<dyn std::e
     println!("Resuming domain '{}'...", name);
 
     let dom = lookup_domain(client, name).await?;
-    let args = DomainResumeArgs { dom };
-
-    client
-        .rpc()
-        .domain_resume(args)
+    dom.resume()
         .await
         .map_err(|e| format!("Failed to resume: {}", e))?;
 
@@ -188,11 +164,7 @@ async fn shutdown_domain(client: &Client, name: &str) -> Result<(), Box<dyn std:
     println!("Shutting down domain '{}'...", name);
 
     let dom = lookup_domain(client, name).await?;
-    let args = DomainShutdownArgs { dom };
-
-    client
-        .rpc()
-        .domain_shutdown(args)
+    dom.shutdown()
         .await
         .map_err(|e| format!("Failed to shutdown: {}", e))?;
 
@@ -204,11 +176,7 @@ async fn start_domain(client: &Client, name: &str) -> Result<(), Box<dyn std::er
     println!("Starting domain '{}'...", name);
 
     let dom = lookup_domain(client, name).await?;
-    let args = DomainCreateArgs { dom };
-
-    client
-        .rpc()
-        .domain_create(args)
+    dom.create()
         .await
         .map_err(|e| format!("Failed to start: {}", e))?;
 
@@ -220,11 +188,7 @@ async fn destroy_domain(client: &Client, name: &str) -> Result<(), Box<dyn std::
     println!("Destroying domain '{}'...", name);
 
     let dom = lookup_domain(client, name).await?;
-    let args = DomainDestroyArgs { dom };
-
-    client
-        .rpc()
-        .domain_destroy(args)
+    dom.destroy()
         .await
         .map_err(|e| format!("Failed to destroy: {}", e))?;
 
@@ -236,11 +200,7 @@ async fn reboot_domain(client: &Client, name: &str) -> Result<(), Box<dyn std::e
     println!("Rebooting domain '{}'...", name);
 
     let dom = lookup_domain(client, name).await?;
-    let args = DomainRebootArgs { dom, flags: 0 };
-
-    client
-        .rpc()
-        .domain_reboot(args)
+    dom.reboot(DomainRebootFlags::empty())
         .await
         .map_err(|e| format!("Failed to reboot: {}", e))?;
 
@@ -11,7 +11,7 @@
 //!
 //! Note: Requires libvirtd to be running.
 
-use libvirt::{Client, ConnectListDomainsArgs};
+use libvirt_pure::{Client, ConnectListDomainsArgs};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
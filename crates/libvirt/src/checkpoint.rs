@@ -0,0 +1,224 @@
+//! Domain checkpoint handles.
+
+use crate::error::{missing_as_none, Result};
+use crate::generated::{
+    DomainCheckpointCreateFlags, DomainCheckpointDeleteFlags, DomainCheckpointListFlags, DomainCheckpointXmlFlags,
+    NonnullDomainCheckpoint, VIR_ERR_NO_DOMAIN_CHECKPOINT,
+};
+use crate::handle::handle;
+use crate::xml::escape;
+use crate::Domain;
+
+/// Builder of the `<domaincheckpoint>` XML [`Domain::create_checkpoint`]
+/// takes. Disks not given get a bitmap named after the checkpoint.
+///
+/// ```ignore
+/// let xml = DomainCheckpointXml::new().name("backup-1").skip_disk("vdb");
+/// let checkpoint = domain.create_checkpoint(&xml, DomainCheckpointCreateFlags::empty()).await?;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DomainCheckpointXml {
+    name: Option<String>,
+    description: Option<String>,
+    /// The disks by target, with their bitmap if they have one.
+    disks: Vec<(String, Option<String>)>,
+}
+
+impl DomainCheckpointXml {
+    /// An empty description, leaving everything to libvirt.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name the checkpoint.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Describe the checkpoint.
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Track the changes to the disk `target` in the bitmap `bitmap`.
+    pub fn disk_bitmap(mut self, target: impl Into<String>, bitmap: impl Into<String>) -> Self {
+        self.disks.push((target.into(), Some(bitmap.into())));
+        self
+    }
+
+    /// Leave the disk `target` out of the checkpoint.
+    pub fn skip_disk(mut self, target: impl Into<String>) -> Self {
+        self.disks.push((target.into(), None));
+        self
+    }
+
+    /// The XML document.
+    pub fn to_xml(&self) -> String {
+        let mut xml = "<domaincheckpoint>".to_string();
+        if let Some(name) = &self.name {
+            xml += &format!("<name>{}</name>", escape(name));
+        }
+        if let Some(description) = &self.description {
+            xml += &format!("<description>{}</description>", escape(description));
+        }
+        if !self.disks.is_empty() {
+            xml += "<disks>";
+            for (target, bitmap) in &self.disks {
+                xml += &match bitmap {
+                    Some(bitmap) => format!(
+                        "<disk name='{}' checkpoint='bitmap' bitmap='{}'/>",
+                        escape(target),
+                        escape(bitmap)
+                    ),
+                    None => format!("<disk name='{}' checkpoint='no'/>", escape(target)),
+                };
+            }
+            xml += "</disks>";
+        }
+        xml += "</domaincheckpoint>";
+        xml
+    }
+}
+
+handle! {
+    /// A checkpoint of a [`Domain`], like libvirt's `virDomainCheckpointPtr`:
+    /// the point from which the disks' changes are tracked, for incremental
+    /// backups. Like snapshots, checkpoints form a tree.
+    pub struct DomainCheckpoint(NonnullDomainCheckpoint) {
+        what: "domain checkpoint",
+        wrap: domain_checkpoint,
+        debug: { domain: dom.name, name: name },
+        eq: [dom.uuid, name],
+    }
+}
+
+impl DomainCheckpoint {
+    /// Name of the checkpoint, unique among the domain's.
+    pub fn name(&self) -> &str {
+        &self.raw.name
+    }
+
+    /// The domain of the checkpoint.
+    pub fn domain(&self) -> Domain {
+        self.client.domain(self.raw.dom.clone())
+    }
+
+    /// XML description of the checkpoint.
+    pub async fn xml_desc(&self, flags: DomainCheckpointXmlFlags) -> Result<String> {
+        Ok(self.client.api().domain_checkpoint_get_xml_desc(&self.raw, flags).await?)
+    }
+
+    /// The checkpoint this one follows, if any.
+    pub async fn parent(&self) -> Result<Option<DomainCheckpoint>> {
+        let parent = self.client.api().domain_checkpoint_get_parent(&self.raw, 0).await;
+        Ok(missing_as_none(parent, VIR_ERR_NO_DOMAIN_CHECKPOINT)?.map(|raw| self.client.domain_checkpoint(raw)))
+    }
+
+    /// The checkpoints following this one, or with
+    /// `DomainCheckpointListFlags::DESCENDANTS` all of its descendants,
+    /// filtered by the other flags.
+    pub async fn children(&self, flags: DomainCheckpointListFlags) -> Result<Vec<DomainCheckpoint>> {
+        let ret = self.client.api().domain_checkpoint_list_all_children(&self.raw, 1, flags).await?;
+        Ok(ret.checkpoints.into_iter().map(|raw| self.client.domain_checkpoint(raw)).collect())
+    }
+
+    /// Delete the checkpoint and, as the flags say, its children, or only
+    /// its metadata.
+    pub async fn delete(&self, flags: DomainCheckpointDeleteFlags) -> Result<()> {
+        Ok(self.client.api().domain_checkpoint_delete(&self.raw, flags).await?)
+    }
+}
+
+impl Domain {
+    /// Create a checkpoint as `xml` describes it.
+    pub async fn create_checkpoint(
+        &self,
+        xml: &DomainCheckpointXml,
+        flags: DomainCheckpointCreateFlags,
+    ) -> Result<DomainCheckpoint> {
+        self.create_checkpoint_xml(&xml.to_xml(), flags).await
+    }
+
+    /// Create a checkpoint from its `<domaincheckpoint>` XML.
+    pub async fn create_checkpoint_xml(
+        &self,
+        xml: &str,
+        flags: DomainCheckpointCreateFlags,
+    ) -> Result<DomainCheckpoint> {
+        let raw = self.client().api().domain_checkpoint_create_xml(self.raw(), xml, flags).await?;
+        Ok(self.client().domain_checkpoint(raw))
+    }
+
+    /// List the checkpoints of the domain, filtered by the flags, e.g. only
+    /// the roots.
+    pub async fn list_checkpoints(&self, flags: DomainCheckpointListFlags) -> Result<Vec<DomainCheckpoint>> {
+        let ret = self.client().api().domain_list_all_checkpoints(self.raw(), 1, flags).await?;
+        Ok(ret.checkpoints.into_iter().map(|raw| self.client().domain_checkpoint(raw)).collect())
+    }
+
+    /// Look up a checkpoint of the domain by name.
+    pub async fn lookup_checkpoint_by_name(&self, name: &str) -> Result<DomainCheckpoint> {
+        let raw = self.client().api().domain_checkpoint_lookup_by_name(self.raw(), name, 0).await?;
+        Ok(self.client().domain_checkpoint(raw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::{
+        DomainCheckpointCreateXmlArgs, DomainCheckpointCreateXmlRet, DomainCheckpointDeleteArgs,
+        DomainCheckpointListAllChildrenRet, NonnullDomain, Uuid, VIR_FROM_TEST,
+    };
+    use crate::server::RemoteError;
+    use crate::testing::MockDaemon;
+
+    #[tokio::test]
+    async fn test_checkpoint_handle() {
+        let daemon = MockDaemon::new();
+        let client = daemon.client().await.unwrap();
+
+        let dom = NonnullDomain {
+            name: "vm1".to_string(),
+            uuid: Uuid::new([3; 16]),
+            id: -1,
+        };
+        let checkpoint = |name: &str| NonnullDomainCheckpoint {
+            name: name.to_string(),
+            dom: dom.clone(),
+        };
+        daemon.respond("domain_checkpoint_create_xml", DomainCheckpointCreateXmlRet {
+            checkpoint: checkpoint("backup-1"),
+        });
+        daemon.respond("domain_checkpoint_list_all_children", DomainCheckpointListAllChildrenRet {
+            checkpoints: vec![checkpoint("backup-2")],
+            ret: 1,
+        });
+        daemon.fail(
+            "domain_checkpoint_get_parent",
+            RemoteError::new(VIR_ERR_NO_DOMAIN_CHECKPOINT, VIR_FROM_TEST, "no parent"),
+        );
+        daemon.respond("domain_checkpoint_delete", ());
+
+        let domain = client.domain(dom.clone());
+        let xml = DomainCheckpointXml::new().name("backup-1").skip_disk("vdb");
+        let backup1 = domain.create_checkpoint(&xml, DomainCheckpointCreateFlags::QUIESCE).await.unwrap();
+        assert_eq!(backup1.name(), "backup-1");
+        assert_eq!(backup1.domain(), domain);
+        let calls = daemon.calls_to::<DomainCheckpointCreateXmlArgs>("domain_checkpoint_create_xml");
+        assert_eq!(
+            calls[0].xml_desc,
+            "<domaincheckpoint><name>backup-1</name>\
+             <disks><disk name='vdb' checkpoint='no'/></disks></domaincheckpoint>"
+        );
+
+        let children = backup1.children(DomainCheckpointListFlags::empty()).await.unwrap();
+        assert_eq!(children, [client.domain_checkpoint(checkpoint("backup-2"))]);
+        assert_eq!(backup1.parent().await.unwrap(), None);
+        backup1.delete(DomainCheckpointDeleteFlags::METADATA_ONLY).await.unwrap();
+        let calls = daemon.calls_to::<DomainCheckpointDeleteArgs>("domain_checkpoint_delete");
+        assert_eq!(calls[0].flags, DomainCheckpointDeleteFlags::METADATA_ONLY);
+    }
+}
@@ -4,17 +4,19 @@
 //! - Serial number generation
 //! - Request/response matching
 //! - Concurrent request dispatch
+//! - Routing events and stream data sent by the daemon
 
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 
 use bytes::Bytes;
-use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
 
 use crate::error::{Error, Result};
-use crate::generated::{LibvirtRpc, RpcError};
-use crate::packet::{Packet, Status};
+use crate::generated::{LibvirtRpc, RpcError, StreamHandle};
+use crate::packet::{MessageType, Packet, Status};
+use crate::server::RemoteError;
 use crate::transport::{Transport, UnixTransport};
 
 /// Default Unix socket path for system connections.
@@ -23,6 +25,9 @@ pub const SYSTEM_SOCKET_PATH: &str = "/var/run/libvirt/libvirt-sock";
 /// Default Unix socket path for session connections (relative to XDG_RUNTIME_DIR).
 pub const SESSION_SOCKET_PATH: &str = "libvirt/libvirt-sock";
 
+/// Events buffered for each subscriber of [`Connection::events`].
+const EVENT_CAPACITY: usize = 256;
+
 /// A connection to a libvirt daemon.
 pub struct Connection {
     inner: Arc<ConnectionInner>,
@@ -31,15 +36,30 @@ pub struct Connection {
 struct ConnectionInner {
     /// Serial number counter.
     serial: AtomicU32,
-    /// Sender to the writer task.
-    tx: mpsc::Sender<WriteRequest>,
+    /// Sender to the I/O task.
+    tx: mpsc::Sender<Packet>,
     /// Pending requests waiting for responses (keyed by serial as i32).
     pending: Mutex<HashMap<i32, oneshot::Sender<Result<Bytes>>>>,
+    /// Open data streams, keyed by the serial of the call that opened them.
+    streams: Mutex<HashMap<i32, Stream>>,
+    /// Events sent by the daemon.
+    events: broadcast::Sender<Packet>,
+}
+
+/// A piece of a data stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamChunk {
+    /// Data.
+    Data(Bytes),
+    /// A run of zeros of this many bytes, sent as its length on sparse
+    /// streams.
+    Hole(u64),
 }
 
-struct WriteRequest {
-    packet: Packet,
-    response_tx: oneshot::Sender<Result<Bytes>>,
+/// Stream packets received for an open data stream.
+struct Stream {
+    tx: mpsc::UnboundedSender<Packet>,
+    rx: Arc<Mutex<mpsc::UnboundedReceiver<Packet>>>,
 }
 
 impl Connection {
@@ -63,22 +83,20 @@ impl Connection {
     }
 
     /// Create a connection from an existing transport.
-    async fn from_transport<T: Transport + 'static>(transport: T) -> Result<Self> {
-        let (tx, rx) = mpsc::channel::<WriteRequest>(32);
+    pub async fn from_transport<T: Transport + 'static>(transport: T) -> Result<Self> {
+        let (tx, rx) = mpsc::channel::<Packet>(32);
 
         let inner = Arc::new(ConnectionInner {
             serial: AtomicU32::new(1),
             tx,
             pending: Mutex::new(HashMap::new()),
+            streams: Mutex::new(HashMap::new()),
+            events: broadcast::channel(EVENT_CAPACITY).0,
         });
 
         // Spawn the I/O task
         let inner_clone = inner.clone();
-        tokio::spawn(async move {
-            if let Err(e) = io_task(transport, rx, inner_clone).await {
-                eprintln!("libvirt connection I/O error: {}", e);
-            }
-        });
+        tokio::spawn(io_task(transport, rx, inner_clone));
 
         Ok(Self { inner })
     }
@@ -90,33 +108,7 @@ impl Connection {
 
     /// Make an RPC call with a specific program ID.
     pub async fn call_program(&self, program: u32, procedure: u32, payload: Bytes) -> Result<Bytes> {
-        let serial = self.inner.serial.fetch_add(1, Ordering::SeqCst) as i32;
-        let packet = Packet::new_call_program(program, procedure, serial, payload);
-
-        // Create response channel
-        let (tx, rx) = oneshot::channel();
-
-        // Register pending request
-        {
-            let mut pending = self.inner.pending.lock().await;
-            pending.insert(serial, tx);
-        }
-
-        // Send write request
-        let write_req = WriteRequest {
-            packet,
-            response_tx: {
-                // Dummy tx - response comes through pending map
-                let (tx, _) = oneshot::channel();
-                tx
-            },
-        };
-
-        self.inner
-            .tx
-            .send(write_req)
-            .await
-            .map_err(|_| Error::ConnectionClosed)?;
+        let (_, rx) = self.send_call(program, procedure, payload, false).await?;
 
         // Wait for response
         rx.await.map_err(|_| Error::ConnectionClosed)?
@@ -125,82 +117,270 @@ impl Connection {
     /// Make a typed RPC call with XDR serialization.
     pub async fn call_xdr<Req, Resp>(&self, procedure: u32, args: &Req) -> Result<Resp>
     where
-        Req: serde::Serialize,
-        Resp: serde::de::DeserializeOwned,
+        Req: libvirt_xdr::XdrEncode,
+        Resp: libvirt_xdr::XdrDecode,
     {
         let payload = libvirt_xdr::to_bytes(args)?;
         let response = self.call(procedure, Bytes::from(payload)).await?;
         let result = libvirt_xdr::from_bytes(&response)?;
         Ok(result)
     }
+
+    /// Subscribe to the events the daemon sends, as `Message` packets
+    /// whose procedure identifies the event, e.g.
+    /// `REMOTE_PROC_DOMAIN_EVENT_CALLBACK_LIFECYCLE`.
+    ///
+    /// Only events sent after subscribing are received, and only once
+    /// registered with one of the `*_event_register_any` calls.
+    pub fn events(&self) -> broadcast::Receiver<Packet> {
+        self.inner.events.subscribe()
+    }
+
+    /// Register a call and send it, returning its serial and the channel
+    /// its reply arrives on.
+    async fn send_call(
+        &self,
+        program: u32,
+        procedure: u32,
+        payload: Bytes,
+        stream: bool,
+    ) -> Result<(i32, oneshot::Receiver<Result<Bytes>>)> {
+        let serial = self.inner.serial.fetch_add(1, Ordering::SeqCst) as i32;
+        let (tx, rx) = oneshot::channel();
+        self.inner.pending.lock().await.insert(serial, tx);
+
+        // Stream data may follow the reply immediately
+        if stream {
+            let (tx, rx) = mpsc::unbounded_channel();
+            let rx = Arc::new(Mutex::new(rx));
+            self.inner.streams.lock().await.insert(serial, Stream { tx, rx });
+        }
+
+        self.send(Packet::new_call_program(program, procedure, serial, payload)).await?;
+        Ok((serial, rx))
+    }
+
+    async fn send(&self, packet: Packet) -> Result<()> {
+        self.inner.tx.send(packet).await.map_err(|_| Error::ConnectionClosed)
+    }
+
+    /// Make a call opening a data stream.
+    async fn call_stream(&self, program: u32, procedure: u32, payload: Bytes) -> Result<(Bytes, StreamHandle)> {
+        let (serial, rx) = self.send_call(program, procedure, payload, true).await?;
+        match rx.await.map_err(|_| Error::ConnectionClosed)? {
+            Ok(reply) => Ok((reply, StreamHandle { program, procedure, serial })),
+            Err(e) => {
+                self.inner.streams.lock().await.remove(&serial);
+                Err(e)
+            }
+        }
+    }
+
+    /// Receiver of an open stream's packets, or `None` once it has ended.
+    async fn stream_rx(&self, stream: &StreamHandle) -> Option<Arc<Mutex<mpsc::UnboundedReceiver<Packet>>>> {
+        let streams = self.inner.streams.lock().await;
+        streams.get(&stream.serial).map(|s| s.rx.clone())
+    }
+
+    async fn send_stream(&self, stream: &StreamHandle, status: Status, data: Bytes) -> Result<()> {
+        let packet = Packet::new_stream(stream.program, stream.procedure, stream.serial, status, data);
+        self.send(packet).await
+    }
+
+    async fn recv_stream(&self, stream: &StreamHandle) -> Result<Option<Bytes>> {
+        match self.recv_stream_chunk(stream).await? {
+            Some(StreamChunk::Data(data)) => Ok(Some(data)),
+            Some(StreamChunk::Hole(_)) => Err(Error::Protocol("hole in a stream not opened as sparse".to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Receive the next data or hole of a stream, or `None` once the
+    /// daemon has finished sending.
+    ///
+    /// Daemons only send holes on streams opened with a sparse flag, e.g.
+    /// `VIR_STORAGE_VOL_DOWNLOAD_SPARSE_STREAM`.
+    pub async fn recv_stream_chunk(&self, stream: &StreamHandle) -> Result<Option<StreamChunk>> {
+        let Some(rx) = self.stream_rx(stream).await else {
+            return Ok(None);
+        };
+        let packet = rx.lock().await.recv().await.ok_or(Error::ConnectionClosed)?;
+        if packet.status == Status::Continue {
+            return match (packet.msg_type, packet.hole_length()) {
+                (MessageType::StreamHole, Some(length)) => Ok(Some(StreamChunk::Hole(length))),
+                (MessageType::StreamHole, None) => Err(Error::Protocol("invalid stream hole".to_string())),
+                _ => Ok(Some(StreamChunk::Data(packet.payload))),
+            };
+        }
+        self.inner.streams.lock().await.remove(&stream.serial);
+        match packet.status {
+            Status::Error => Err(remote_error(&packet.payload)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Send a hole of `length` bytes on a stream opened with a sparse
+    /// flag, e.g. `VIR_STORAGE_VOL_UPLOAD_SPARSE_STREAM`.
+    pub async fn send_stream_hole(&self, stream: &StreamHandle, length: u64) -> Result<()> {
+        self.send(Packet::new_stream_hole(stream.program, stream.procedure, stream.serial, length)).await
+    }
+
+    async fn finish_stream(&self, stream: &StreamHandle) -> Result<()> {
+        // Already ended by the daemon
+        let Some(rx) = self.stream_rx(stream).await else {
+            return Ok(());
+        };
+        self.send_stream(stream, Status::Ok, Bytes::new()).await?;
+
+        // The daemon confirms with a packet of its own; skip data sent
+        // before it saw ours
+        let mut rx = rx.lock().await;
+        let result = loop {
+            match rx.recv().await {
+                Some(packet) if packet.status == Status::Continue => continue,
+                Some(packet) if packet.status == Status::Error => break Err(remote_error(&packet.payload)),
+                Some(_) => break Ok(()),
+                None => break Err(Error::ConnectionClosed),
+            }
+        };
+        self.inner.streams.lock().await.remove(&stream.serial);
+        result
+    }
+
+    async fn abort_stream(&self, stream: &StreamHandle) -> Result<()> {
+        if self.inner.streams.lock().await.remove(&stream.serial).is_none() {
+            return Ok(());
+        }
+        self.send_stream(stream, Status::Error, Bytes::new()).await
+    }
+}
+
+impl ConnectionInner {
+    /// Hand a packet from the daemon to whoever waits for it.
+    async fn deliver(&self, packet: Packet) {
+        match packet.msg_type {
+            MessageType::Reply => {
+                if let Some(tx) = self.pending.lock().await.remove(&packet.serial) {
+                    let result = match packet.status {
+                        Status::Error => Err(remote_error(&packet.payload)),
+                        _ => Ok(packet.payload),
+                    };
+                    let _ = tx.send(result);
+                }
+            }
+            MessageType::Stream | MessageType::StreamHole => {
+                if let Some(stream) = self.streams.lock().await.get(&packet.serial) {
+                    let _ = stream.tx.send(packet);
+                }
+            }
+            MessageType::Message => {
+                // No subscribers is fine
+                let _ = self.events.send(packet);
+            }
+            // Daemons do not call clients
+            MessageType::Call => {}
+        }
+    }
+}
+
+/// The error in the payload of a reply or stream packet with status
+/// `Error`.
+fn remote_error(payload: &[u8]) -> Error {
+    match libvirt_xdr::from_bytes::<RemoteError>(payload) {
+        Ok(error) => Error::Rpc {
+            code: error.code,
+            domain: error.domain,
+            message: error.message.unwrap_or_default(),
+        },
+        Err(_) => Error::RemoteError(String::from_utf8_lossy(payload).to_string()),
+    }
+}
+
+/// Keep the daemon's error code for callers of the generated clients.
+fn rpc_error(error: Error) -> RpcError {
+    match error {
+        Error::Rpc { code, domain, message } => RpcError::Server(RemoteError::new(code, domain, message)),
+        error => RpcError::Transport(error.to_string()),
+    }
 }
 
 /// Implement LibvirtRpc trait for Connection to enable generated API methods.
 impl LibvirtRpc for Connection {
     async fn rpc_call(&self, procedure: u32, payload: Vec<u8>) -> std::result::Result<Vec<u8>, RpcError> {
-        let response = self.call(procedure, Bytes::from(payload)).await
-            .map_err(|e| RpcError::Transport(e.to_string()))?;
+        let response = self.call(procedure, Bytes::from(payload)).await.map_err(rpc_error)?;
         Ok(response.to_vec())
     }
 
     async fn rpc_call_program(&self, program: u32, procedure: u32, payload: Vec<u8>) -> std::result::Result<Vec<u8>, RpcError> {
-        let response = self.call_program(program, procedure, Bytes::from(payload)).await
-            .map_err(|e| RpcError::Transport(e.to_string()))?;
+        let response = self.call_program(program, procedure, Bytes::from(payload)).await.map_err(rpc_error)?;
         Ok(response.to_vec())
     }
+
+    async fn rpc_call_stream(&self, program: u32, procedure: u32, payload: Vec<u8>) -> std::result::Result<(Vec<u8>, StreamHandle), RpcError> {
+        let (response, stream) = self.call_stream(program, procedure, Bytes::from(payload)).await.map_err(rpc_error)?;
+        Ok((response.to_vec(), stream))
+    }
+
+    async fn stream_send(&self, stream: &StreamHandle, data: Vec<u8>) -> std::result::Result<(), RpcError> {
+        self.send_stream(stream, Status::Continue, Bytes::from(data)).await.map_err(rpc_error)
+    }
+
+    async fn stream_recv(&self, stream: &StreamHandle) -> std::result::Result<Option<Vec<u8>>, RpcError> {
+        let data = self.recv_stream(stream).await.map_err(rpc_error)?;
+        Ok(data.map(|data| data.to_vec()))
+    }
+
+    async fn stream_finish(&self, stream: &StreamHandle) -> std::result::Result<(), RpcError> {
+        self.finish_stream(stream).await.map_err(rpc_error)
+    }
+
+    async fn stream_abort(&self, stream: &StreamHandle) -> std::result::Result<(), RpcError> {
+        self.abort_stream(stream).await.map_err(rpc_error)
+    }
 }
 
-/// Background I/O task that handles reading and writing.
+/// Background I/O task: sends the packets queued by the connection and
+/// delivers the ones the daemon sends, whether replies, events or
+/// stream data.
+///
+/// Runs until the connection closes or fails; the calls still waiting
+/// then get the error.
 async fn io_task<T: Transport>(
     mut transport: T,
-    mut write_rx: mpsc::Receiver<WriteRequest>,
+    mut write_rx: mpsc::Receiver<Packet>,
     inner: Arc<ConnectionInner>,
-) -> Result<()> {
-    // For simplicity, we'll use a single task that alternates between reading and writing.
-    // A more robust implementation would use split streams or select!.
-
-    loop {
+) {
+    let result = loop {
         tokio::select! {
             // Handle write requests
-            Some(req) = write_rx.recv() => {
-                let encoded = req.packet.encode();
-                if let Err(e) = transport.send(&encoded).await {
-                    // Notify the caller
-                    let _ = req.response_tx.send(Err(e));
-                    continue;
+            Some(packet) = write_rx.recv() => {
+                if let Err(e) = transport.send(&packet.encode()).await {
+                    break Err(e);
                 }
-
-                // Read the response
-                match transport.recv().await {
-                    Ok(data) => {
-                        match Packet::decode(data) {
-                            Ok(packet) => {
-                                // Find and notify the pending request
-                                let mut pending = inner.pending.lock().await;
-                                if let Some(tx) = pending.remove(&packet.serial) {
-                                    if packet.status == Status::Ok {
-                                        let _ = tx.send(Ok(packet.payload));
-                                    } else {
-                                        let _ = tx.send(Err(Error::RemoteError(
-                                            String::from_utf8_lossy(&packet.payload).to_string()
-                                        )));
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("Failed to decode packet: {}", e);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to receive packet: {}", e);
-                        break;
-                    }
+            }
+            // Handle packets from the daemon; `recv` is cancel safe
+            data = transport.recv() => {
+                let data = match data {
+                    Ok(data) => data,
+                    Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break Ok(()),
+                    Err(e) => break Err(e),
+                };
+                match Packet::decode(data) {
+                    Ok(packet) => inner.deliver(packet).await,
+                    Err(e) => break Err(Error::from(e)),
                 }
             }
-            else => break,
         }
-    }
+    };
 
-    Ok(())
+    // Fail the calls still waiting with the error, or ConnectionClosed if
+    // the daemon closed the connection; streams get ConnectionClosed
+    let pending = std::mem::take(&mut *inner.pending.lock().await);
+    if let Err(e) = result {
+        let message = e.to_string();
+        for tx in pending.into_values() {
+            let _ = tx.send(Err(Error::Connection(message.clone())));
+        }
+    }
+    inner.streams.lock().await.clear();
 }
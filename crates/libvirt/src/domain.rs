@@ -0,0 +1,255 @@
+//! Domain handles.
+
+use crate::error::Result;
+use crate::generated::{
+    ConnectListAllDomainsFlags, DomainBlockStatsRet, DomainCreateFlags, DomainDefineFlags, DomainGetInfoRet,
+    DomainGetStateRet, DomainInterfaceStatsRet, DomainMemoryModFlags, DomainRebootFlags, DomainShutdownFlags,
+    DomainUndefineFlags, DomainVcpuFlags, DomainXmlFlags, NonnullDomain, Uuid,
+};
+use crate::handle::handle;
+use crate::Client;
+
+handle! {
+    /// A domain of a [`Client`]'s hypervisor, like libvirt's `virDomainPtr`.
+    ///
+    /// The handle keeps the client it came from, so its methods make the calls
+    /// themselves instead of taking the domain as an argument:
+    ///
+    /// ```ignore
+    /// let domain = client.lookup_domain_by_name("vm1").await?;
+    /// domain.suspend().await?;
+    /// println!("{} is {:?}", domain.name(), domain.state().await?);
+    /// ```
+    ///
+    /// The name and UUID are those known when the handle was made and never
+    /// change; the ID is asked of the daemon, since it changes each time the
+    /// domain starts or stops.
+    pub struct Domain(NonnullDomain) {
+        what: "domain",
+        wrap: domain,
+        debug: { name: name, uuid: uuid },
+        eq: [uuid],
+    }
+}
+
+impl Domain {
+    /// Name of the domain.
+    pub fn name(&self) -> &str {
+        &self.raw.name
+    }
+
+    /// UUID of the domain.
+    pub fn uuid(&self) -> &Uuid {
+        &self.raw.uuid
+    }
+
+    /// ID of the domain, if it is running.
+    pub async fn id(&self) -> Result<Option<u32>> {
+        let raw = self.client.api().domain_lookup_by_uuid(self.raw.uuid).await?;
+        Ok(u32::try_from(raw.id).ok())
+    }
+
+    /// State, memory, vCPU count and CPU time of the domain.
+    pub async fn info(&self) -> Result<DomainGetInfoRet> {
+        Ok(self.client.api().domain_get_info(&self.raw).await?)
+    }
+
+    /// State of the domain (`VIR_DOMAIN_RUNNING`, ...) and the reason for it.
+    pub async fn state(&self) -> Result<DomainGetStateRet> {
+        Ok(self.client.api().domain_get_state(&self.raw, 0).await?)
+    }
+
+    /// XML description of the domain.
+    pub async fn xml_desc(&self, flags: DomainXmlFlags) -> Result<String> {
+        Ok(self.client.api().domain_get_xml_desc(&self.raw, flags).await?)
+    }
+
+    /// Whether the domain is running.
+    pub async fn is_active(&self) -> Result<bool> {
+        Ok(self.client.api().domain_is_active(&self.raw).await?)
+    }
+
+    /// Whether the domain has a persistent definition.
+    pub async fn is_persistent(&self) -> Result<bool> {
+        Ok(self.client.api().domain_is_persistent(&self.raw).await?)
+    }
+
+    /// Start the defined domain.
+    pub async fn create(&self) -> Result<()> {
+        self.create_with_flags(DomainCreateFlags::empty()).await
+    }
+
+    /// Start the defined domain, e.g. paused.
+    pub async fn create_with_flags(&self, flags: DomainCreateFlags) -> Result<()> {
+        self.client.api().domain_create_with_flags(&self.raw, flags).await?;
+        Ok(())
+    }
+
+    /// Ask the guest to shut down.
+    pub async fn shutdown(&self) -> Result<()> {
+        Ok(self.client.api().domain_shutdown(&self.raw).await?)
+    }
+
+    /// Ask the guest to shut down, by the given methods.
+    pub async fn shutdown_flags(&self, flags: DomainShutdownFlags) -> Result<()> {
+        Ok(self.client.api().domain_shutdown_flags(&self.raw, flags).await?)
+    }
+
+    /// Stop the domain immediately.
+    pub async fn destroy(&self) -> Result<()> {
+        Ok(self.client.api().domain_destroy(&self.raw).await?)
+    }
+
+    /// Pause the domain's vCPUs.
+    pub async fn suspend(&self) -> Result<()> {
+        Ok(self.client.api().domain_suspend(&self.raw).await?)
+    }
+
+    /// Resume a suspended domain.
+    pub async fn resume(&self) -> Result<()> {
+        Ok(self.client.api().domain_resume(&self.raw).await?)
+    }
+
+    /// Ask the guest to reboot.
+    pub async fn reboot(&self, flags: DomainRebootFlags) -> Result<()> {
+        Ok(self.client.api().domain_reboot(&self.raw, flags).await?)
+    }
+
+    /// Remove the persistent definition of the domain.
+    pub async fn undefine(&self) -> Result<()> {
+        Ok(self.client.api().domain_undefine(&self.raw).await?)
+    }
+
+    /// Remove the persistent definition of the domain, and the data the
+    /// flags name, e.g. its snapshot metadata.
+    pub async fn undefine_flags(&self, flags: DomainUndefineFlags) -> Result<()> {
+        Ok(self.client.api().domain_undefine_flags(&self.raw, flags).await?)
+    }
+
+    /// Whether the domain starts when the host boots.
+    pub async fn autostart(&self) -> Result<bool> {
+        Ok(self.client.api().domain_get_autostart(&self.raw).await?)
+    }
+
+    /// Set whether the domain starts when the host boots.
+    pub async fn set_autostart(&self, autostart: bool) -> Result<()> {
+        Ok(self.client.api().domain_set_autostart(&self.raw, autostart).await?)
+    }
+
+    /// Number of vCPUs, of the running domain or of its definition as the
+    /// flags say.
+    pub async fn vcpus(&self, flags: DomainVcpuFlags) -> Result<u32> {
+        Ok(self.client.api().domain_get_vcpus_flags(&self.raw, flags).await? as u32)
+    }
+
+    /// Set the number of vCPUs.
+    pub async fn set_vcpus(&self, vcpus: u32, flags: DomainVcpuFlags) -> Result<()> {
+        Ok(self.client.api().domain_set_vcpus_flags(&self.raw, vcpus, flags).await?)
+    }
+
+    /// Memory the domain currently has, in KiB.
+    pub async fn memory(&self) -> Result<u64> {
+        Ok(self.info().await?.memory)
+    }
+
+    /// Memory the domain may have at most, in KiB.
+    pub async fn max_memory(&self) -> Result<u64> {
+        Ok(self.client.api().domain_get_max_memory(&self.raw).await?)
+    }
+
+    /// Set the memory of the domain, in KiB.
+    pub async fn set_memory(&self, memory: u64, flags: DomainMemoryModFlags) -> Result<()> {
+        Ok(self.client.api().domain_set_memory_flags(&self.raw, memory, flags).await?)
+    }
+
+    /// I/O statistics of the disk `path`, given as a target (`vda`) or
+    /// source path.
+    pub async fn block_stats(&self, path: &str) -> Result<DomainBlockStatsRet> {
+        Ok(self.client.api().domain_block_stats(&self.raw, path).await?)
+    }
+
+    /// Traffic statistics of the network interface `device`, given as a
+    /// target (`vnet0`) or MAC address.
+    pub async fn interface_stats(&self, device: &str) -> Result<DomainInterfaceStatsRet> {
+        Ok(self.client.api().domain_interface_stats(&self.raw, device).await?)
+    }
+}
+
+impl Client {
+    /// List the domains, filtered by the flags, e.g. only the active ones.
+    pub async fn list_all_domains(&self, flags: ConnectListAllDomainsFlags) -> Result<Vec<Domain>> {
+        let ret = self.api().connect_list_all_domains(1, flags).await?;
+        Ok(ret.domains.into_iter().map(|raw| self.domain(raw)).collect())
+    }
+
+    /// Look up a domain by name.
+    pub async fn lookup_domain_by_name(&self, name: &str) -> Result<Domain> {
+        Ok(self.domain(self.api().domain_lookup_by_name(name).await?))
+    }
+
+    /// Look up a domain by UUID.
+    pub async fn lookup_domain_by_uuid(&self, uuid: Uuid) -> Result<Domain> {
+        Ok(self.domain(self.api().domain_lookup_by_uuid(uuid).await?))
+    }
+
+    /// Look up a running domain by ID.
+    pub async fn lookup_domain_by_id(&self, id: u32) -> Result<Domain> {
+        Ok(self.domain(self.api().domain_lookup_by_id(id as i32).await?))
+    }
+
+    /// Define a persistent domain from its XML, without starting it.
+    pub async fn define_domain_xml(&self, xml: &str, flags: DomainDefineFlags) -> Result<Domain> {
+        Ok(self.domain(self.api().domain_define_xml_flags(xml, flags).await?))
+    }
+
+    /// Create and start a transient domain from its XML.
+    pub async fn create_domain_xml(&self, xml: &str, flags: DomainCreateFlags) -> Result<Domain> {
+        Ok(self.domain(self.api().domain_create_xml(xml, flags).await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::{VIR_DOMAIN_PAUSED, VIR_DOMAIN_RUNNING, VIR_DOMAIN_SHUTOFF, VIR_ERR_NO_DOMAIN};
+    use crate::testing::FakeHypervisor;
+    use crate::Error;
+
+    #[tokio::test]
+    async fn test_domain_handle() {
+        let client = FakeHypervisor::new().client().await.unwrap();
+
+        let domains = client.list_all_domains(ConnectListAllDomainsFlags::empty()).await.unwrap();
+        assert_eq!(domains.len(), 1);
+        let test = &domains[0];
+        assert_eq!((test.name(), test.id().await.unwrap()), ("test", Some(1)));
+        assert_eq!(test.uuid().to_uuid_string(), "6695eb01-f6a4-8304-79aa-97f2502e193f");
+        assert_eq!(test.memory().await.unwrap(), 2 * 1024 * 1024);
+        assert!(test.is_active().await.unwrap());
+
+        let xml = "<domain type='test'><name>vm1</name><memory unit='MiB'>512</memory><vcpu>4</vcpu></domain>";
+        let vm1 = client.define_domain_xml(xml, DomainDefineFlags::empty()).await.unwrap();
+        assert_eq!(vm1.id().await.unwrap(), None);
+        assert_eq!(vm1.max_memory().await.unwrap(), 512 * 1024);
+        vm1.set_autostart(true).await.unwrap();
+        assert!(vm1.autostart().await.unwrap());
+
+        vm1.create().await.unwrap();
+        assert_eq!(vm1.id().await.unwrap(), Some(2));
+        assert_eq!(client.lookup_domain_by_id(2).await.unwrap(), vm1);
+        vm1.suspend().await.unwrap();
+        assert_eq!(vm1.state().await.unwrap().state, VIR_DOMAIN_PAUSED);
+        vm1.resume().await.unwrap();
+        assert_eq!(vm1.info().await.unwrap().state, VIR_DOMAIN_RUNNING as u8);
+
+        vm1.destroy().await.unwrap();
+        assert_eq!(vm1.id().await.unwrap(), None);
+        assert_eq!(vm1.state().await.unwrap().state, VIR_DOMAIN_SHUTOFF);
+        assert!(vm1.xml_desc(DomainXmlFlags::empty()).await.unwrap().contains("<name>vm1</name>"));
+        vm1.undefine().await.unwrap();
+        match client.lookup_domain_by_name("vm1").await {
+            Err(Error::Rpc { code, .. }) => assert_eq!(code, VIR_ERR_NO_DOMAIN),
+            other => panic!("unexpected lookup result: {:?}", other),
+        }
+    }
+}
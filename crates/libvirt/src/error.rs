@@ -58,3 +58,29 @@ pub enum Error {
     #[error("packet error: {0}")]
     Packet(#[from] crate::packet::PacketError),
 }
+
+impl From<crate::generated::RpcError> for Error {
+    fn from(error: crate::generated::RpcError) -> Self {
+        use crate::generated::RpcError;
+        match error {
+            RpcError::Server(e) => Error::Rpc {
+                code: e.code,
+                domain: e.domain,
+                message: e.message.unwrap_or_default(),
+            },
+            RpcError::Transport(message) => Error::Connection(message),
+            RpcError::Encode(message) | RpcError::Decode(message) => Error::Protocol(message),
+        }
+    }
+}
+
+/// `None` for the error `code` a call fails with when the object asked
+/// for doesn't exist, e.g. `VIR_ERR_NO_DOMAIN_SNAPSHOT` for the parent of
+/// a root snapshot.
+pub(crate) fn missing_as_none<T, E: Into<Error>>(result: std::result::Result<T, E>, code: i32) -> Result<Option<T>> {
+    match result.map_err(Into::into) {
+        Ok(value) => Ok(Some(value)),
+        Err(Error::Rpc { code: error, .. }) if error == code => Ok(None),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,82 @@
+//! The parts the handle types share.
+
+/// Define a handle type binding a value of the generated API to a
+/// [`Client`](crate::Client), like [`Domain`](crate::Domain) does for a
+/// `NonnullDomain`.
+///
+/// The handle gets `new`, `client` and `raw`, a `Debug` showing the
+/// `debug` fields of the raw value, a `PartialEq` comparing its `eq`
+/// fields, and `Client` a method `wrap` binding a raw value to the client.
+/// `what` names the object in the generated docs.
+///
+/// ```ignore
+/// handle! {
+///     /// A network of a [`Client`]'s host, like libvirt's `virNetworkPtr`.
+///     pub struct Network(NonnullNetwork) {
+///         what: "network",
+///         wrap: network,
+///         debug: { name: name, uuid: uuid },
+///         eq: [uuid],
+///     }
+/// }
+/// ```
+macro_rules! handle {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident($raw:ty) {
+            what: $what:literal,
+            wrap: $wrap:ident,
+            debug: { $($field:ident: $($path:ident).+),+ $(,)? },
+            eq: [$($first:ident).+ $(, $($key:ident).+)*],
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone)]
+        pub struct $name {
+            client: $crate::Client,
+            raw: $raw,
+        }
+
+        impl $name {
+            /// Bind `raw`, as returned by the generated API, to `client`.
+            pub fn new(client: &$crate::Client, raw: $raw) -> Self {
+                Self { client: client.clone(), raw }
+            }
+
+            #[doc = concat!("The client the ", $what, " belongs to.")]
+            pub fn client(&self) -> &$crate::Client {
+                &self.client
+            }
+
+            #[doc = concat!("The ", $what, " as passed to the generated API, for calls without a method here.")]
+            pub fn raw(&self) -> &$raw {
+                &self.raw
+            }
+        }
+
+        impl ::std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                f.debug_struct(stringify!($name))
+                    $(.field(stringify!($field), &self.raw.$($path).+))+
+                    .finish()
+            }
+        }
+
+        impl PartialEq for $name {
+            #[doc = concat!("Handles are equal if they name the same ", $what, ".")]
+            fn eq(&self, other: &Self) -> bool {
+                self.raw.$($first).+ == other.raw.$($first).+
+                    $(&& self.raw.$($key).+ == other.raw.$($key).+)*
+            }
+        }
+
+        impl $crate::Client {
+            #[doc = concat!("Wrap a ", $what, " returned by the generated API.")]
+            pub fn $wrap(&self, raw: $raw) -> $name {
+                $name::new(self, raw)
+            }
+        }
+    };
+}
+
+pub(crate) use handle;
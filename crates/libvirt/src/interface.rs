@@ -0,0 +1,159 @@
+//! Host network interface handles.
+
+use crate::error::Result;
+use crate::generated::{ConnectListAllInterfacesFlags, InterfaceDefineFlags, InterfaceXmlFlags, NonnullInterface};
+use crate::handle::handle;
+use crate::Client;
+
+handle! {
+    /// A network interface of a [`Client`]'s host, like libvirt's
+    /// `virInterfacePtr`.
+    ///
+    /// Changes to the host's interfaces can be made in a transaction, rolled
+    /// back if they cut the host off:
+    ///
+    /// ```ignore
+    /// client.interface_change_begin().await?;
+    /// let br0 = client.define_interface_xml(xml, InterfaceDefineFlags::empty()).await?;
+    /// br0.create().await?;
+    /// if host_reachable().await {
+    ///     client.interface_change_commit().await?;
+    /// } else {
+    ///     client.interface_change_rollback().await?;
+    /// }
+    /// ```
+    pub struct Interface(NonnullInterface) {
+        what: "host interface",
+        wrap: interface,
+        debug: { name: name, mac: mac },
+        eq: [name],
+    }
+}
+
+impl Interface {
+    /// Name of the interface, e.g. `eth0`.
+    pub fn name(&self) -> &str {
+        &self.raw.name
+    }
+
+    /// MAC address of the interface.
+    pub fn mac(&self) -> &str {
+        &self.raw.mac
+    }
+
+    /// XML description of the interface.
+    pub async fn xml_desc(&self, flags: InterfaceXmlFlags) -> Result<String> {
+        Ok(self.client.api().interface_get_xml_desc(&self.raw, flags).await?)
+    }
+
+    /// Whether the interface is up.
+    pub async fn is_active(&self) -> Result<bool> {
+        Ok(self.client.api().interface_is_active(&self.raw).await?)
+    }
+
+    /// Bring the defined interface up.
+    pub async fn create(&self) -> Result<()> {
+        Ok(self.client.api().interface_create(&self.raw, 0).await?)
+    }
+
+    /// Bring the interface down.
+    pub async fn destroy(&self) -> Result<()> {
+        Ok(self.client.api().interface_destroy(&self.raw, 0).await?)
+    }
+
+    /// Remove the definition of the interface.
+    pub async fn undefine(&self) -> Result<()> {
+        Ok(self.client.api().interface_undefine(&self.raw).await?)
+    }
+}
+
+impl Client {
+    /// List the host's interfaces, filtered by the flags, e.g. only the
+    /// ones up.
+    pub async fn list_interfaces(&self, flags: ConnectListAllInterfacesFlags) -> Result<Vec<Interface>> {
+        let ret = self.api().connect_list_all_interfaces(1, flags).await?;
+        Ok(ret.ifaces.into_iter().map(|raw| self.interface(raw)).collect())
+    }
+
+    /// Look up a host interface by name.
+    pub async fn lookup_interface_by_name(&self, name: &str) -> Result<Interface> {
+        Ok(self.interface(self.api().interface_lookup_by_name(name).await?))
+    }
+
+    /// Look up a host interface by MAC address.
+    pub async fn lookup_interface_by_mac(&self, mac: &str) -> Result<Interface> {
+        Ok(self.interface(self.api().interface_lookup_by_mac_string(mac).await?))
+    }
+
+    /// Define a host interface from its XML, without bringing it up.
+    pub async fn define_interface_xml(&self, xml: &str, flags: InterfaceDefineFlags) -> Result<Interface> {
+        Ok(self.interface(self.api().interface_define_xml(xml, flags).await?))
+    }
+
+    /// Snapshot the host's interface configuration, to commit or roll back
+    /// the changes made after.
+    pub async fn interface_change_begin(&self) -> Result<()> {
+        Ok(self.api().interface_change_begin(0).await?)
+    }
+
+    /// Keep the interface changes made since
+    /// [`interface_change_begin`](Self::interface_change_begin).
+    pub async fn interface_change_commit(&self) -> Result<()> {
+        Ok(self.api().interface_change_commit(0).await?)
+    }
+
+    /// Restore the interface configuration saved by
+    /// [`interface_change_begin`](Self::interface_change_begin).
+    pub async fn interface_change_rollback(&self) -> Result<()> {
+        Ok(self.api().interface_change_rollback(0).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::{ConnectListAllInterfacesRet, InterfaceDefineXmlArgs, InterfaceDefineXmlRet, Procedure};
+    use crate::testing::MockDaemon;
+
+    #[tokio::test]
+    async fn test_interface_handle() {
+        let daemon = MockDaemon::new();
+        let client = daemon.client().await.unwrap();
+
+        let eth0 = NonnullInterface {
+            name: "eth0".to_string(),
+            mac: "52:54:00:12:34:56".to_string(),
+        };
+        daemon.respond("connect_list_all_interfaces", ConnectListAllInterfacesRet {
+            ifaces: vec![eth0.clone()],
+            ret: 1,
+        });
+        let ifaces = client.list_interfaces(ConnectListAllInterfacesFlags::ACTIVE).await.unwrap();
+        assert_eq!(ifaces, [client.interface(eth0)]);
+        assert_eq!(ifaces[0].mac(), "52:54:00:12:34:56");
+
+        let br0 = NonnullInterface {
+            name: "br0".to_string(),
+            mac: "52:54:00:65:43:21".to_string(),
+        };
+        for procedure in ["interface_change_begin", "interface_create", "interface_change_rollback"] {
+            daemon.respond(procedure, ());
+        }
+        daemon.respond("interface_define_xml", InterfaceDefineXmlRet { iface: br0 });
+        client.interface_change_begin().await.unwrap();
+        let xml = "<interface type='bridge' name='br0'/>";
+        let br0 = client.define_interface_xml(xml, InterfaceDefineFlags::VALIDATE).await.unwrap();
+        br0.create().await.unwrap();
+        client.interface_change_rollback().await.unwrap();
+
+        let calls = daemon.calls_to::<InterfaceDefineXmlArgs>("interface_define_xml");
+        assert_eq!((calls[0].xml.as_str(), calls[0].flags), (xml, InterfaceDefineFlags::VALIDATE));
+        let procedures: Vec<_> = daemon.calls().iter().map(|call| call.procedure).collect();
+        assert!(procedures.ends_with(&[
+            Procedure::ProcInterfaceChangeBegin as u32,
+            Procedure::ProcInterfaceDefineXml as u32,
+            Procedure::ProcInterfaceCreate as u32,
+            Procedure::ProcInterfaceChangeRollback as u32,
+        ]));
+    }
+}
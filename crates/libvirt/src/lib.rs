@@ -6,25 +6,39 @@
 //! # Example
 //!
 //! ```ignore
-//! use libvirt::Client;
+//! use libvirt::{Client, ConnectListAllDomainsFlags};
 //!
 //! #[tokio::main]
 //! async fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!     let client = Client::connect("qemu:///system").await?;
-//!     let domains = client.list_all_domains(0).await?;
+//!     let domains = client.list_all_domains(ConnectListAllDomainsFlags::empty()).await?;
 //!
 //!     for domain in domains {
-//!         println!("Domain: {}", domain.name().await?);
+//!         println!("Domain: {} ({:?})", domain.name(), domain.info().await?);
 //!     }
 //!
 //!     Ok(())
 //! }
 //! ```
 
+mod checkpoint;
 mod connection;
+mod domain;
 mod error;
-mod packet;
-mod transport;
+mod handle;
+mod interface;
+mod network;
+mod node_device;
+mod nwfilter;
+pub mod packet;
+mod secret;
+mod snapshot;
+pub mod server;
+mod storage;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod transport;
+mod xml;
 
 /// Generated types and constants from libvirt protocol definition.
 #[allow(dead_code)]
@@ -34,9 +48,31 @@ pub mod generated {
     include!(concat!(env!("OUT_DIR"), "/generated.rs"));
 }
 
-pub use connection::Connection;
+/// Generated `from_bytes(to_bytes(x)) == x` property tests.
+#[cfg(test)]
+#[allow(dead_code)]
+#[allow(clippy::all)]
+mod roundtrip_tests {
+    include!(concat!(env!("OUT_DIR"), "/roundtrip_tests.rs"));
+}
+
+use std::sync::Arc;
+
+use tokio::sync::OnceCell;
+
+pub use checkpoint::{DomainCheckpoint, DomainCheckpointXml};
+pub use connection::{Connection, StreamChunk};
+pub use domain::Domain;
 pub use error::{Error, Result};
+pub use interface::Interface;
+pub use network::{Network, NetworkPort, NetworkUpdateCommand, NetworkUpdateSection};
+pub use node_device::{NodeDevice, NodeDeviceCapability};
+pub use nwfilter::{Nwfilter, NwfilterBinding};
+pub use secret::{Secret, SecretUsageType};
+pub use snapshot::{DomainSnapshot, DomainSnapshotXml, SnapshotLocation, SnapshotNode};
+pub use storage::{StoragePool, StorageVol, StorageVolWipeAlgorithm};
 pub use generated::*;
+pub use libvirt_xdr::{DecodeOptions, Utf8Policy};
 
 /// Re-export GeneratedClient for convenient API access.
 pub type LibvirtClient = GeneratedClient<Connection>;
@@ -50,7 +86,8 @@ pub type LxcRpcClient = LxcClient<Connection>;
 /// High-level libvirt client that wraps the generated API.
 ///
 /// This client provides a convenient interface for connecting to libvirt
-/// and using the auto-generated RPC methods.
+/// and using the auto-generated RPC methods. Clones are cheap and share the
+/// connection, which is how handles such as [`Domain`] keep theirs.
 ///
 /// # Example
 ///
@@ -67,7 +104,7 @@ pub type LxcRpcClient = LxcClient<Connection>;
 ///
 ///     // List domains
 ///     let ret = client.rpc().connect_list_all_domains(
-///         ConnectListAllDomainsArgs { need_results: 1, flags: 0 }
+///         ConnectListAllDomainsArgs { need_results: 1, flags: ConnectListAllDomainsFlags::empty() }
 ///     ).await?;
 ///     println!("Found {} domains", ret.domains.len());
 ///
@@ -75,8 +112,12 @@ pub type LxcRpcClient = LxcClient<Connection>;
 ///     Ok(())
 /// }
 /// ```
+#[derive(Clone)]
 pub struct Client {
-    rpc: GeneratedClient<Connection>,
+    rpc: Arc<GeneratedClient<Connection>>,
+    /// Whether the daemon takes `network_update`'s command and section in
+    /// the documented order, once asked.
+    network_update_order: Arc<OnceCell<bool>>,
 }
 
 impl Client {
@@ -98,7 +139,12 @@ impl Client {
         } else {
             return Err(Error::UnsupportedUri(uri.to_string()));
         };
+        Self::open(conn, uri).await
+    }
 
+    /// Open `uri` on an established connection, e.g. one to the
+    /// `testing` feature's `MockDaemon`.
+    pub async fn open(conn: Connection, uri: &str) -> Result<Self> {
         let rpc = GeneratedClient::new(conn);
 
         // Perform authentication (AUTH_NONE for local connections)
@@ -113,7 +159,10 @@ impl Client {
         rpc.connect_open(args).await
             .map_err(|e| Error::Protocol(format!("connect_open failed: {}", e)))?;
 
-        Ok(Self { rpc })
+        Ok(Self {
+            rpc: Arc::new(rpc),
+            network_update_order: Arc::new(OnceCell::new()),
+        })
     }
 
     /// Get access to all generated RPC methods.
@@ -124,6 +173,33 @@ impl Client {
         &self.rpc
     }
 
+    /// Get access to the generated RPC methods in their flattened form,
+    /// taking arguments as parameters instead of `*Args` structs.
+    ///
+    /// ```ignore
+    /// let hostname: String = client.api().connect_get_hostname().await?;
+    /// client.api().domain_set_autostart(&dom, true).await?;
+    /// ```
+    pub fn api(&self) -> GeneratedApi<'_, Connection> {
+        self.rpc.api()
+    }
+
+    /// Get access to the generated RPC methods, decoding responses with
+    /// the given options.
+    ///
+    /// Useful for calls returning guest-originated strings that may not
+    /// be valid UTF-8:
+    ///
+    /// ```ignore
+    /// let ret = client
+    ///     .rpc_with(DecodeOptions::lossy())
+    ///     .domain_get_hostname(args)
+    ///     .await?;
+    /// ```
+    pub fn rpc_with(&self, options: DecodeOptions) -> GeneratedClient<&Connection> {
+        GeneratedClient::new(self.connection()).with_decode_options(options)
+    }
+
     /// Get the underlying connection.
     pub fn connection(&self) -> &Connection {
         self.rpc.inner()
@@ -166,4 +242,10 @@ impl QemuClientWrapper {
     pub fn rpc(&self) -> &QemuClient<Connection> {
         &self.inner
     }
+
+    /// Get access to QEMU-specific RPC methods taking their arguments as
+    /// parameters.
+    pub fn api(&self) -> QemuApi<'_, Connection> {
+        self.inner.api()
+    }
 }
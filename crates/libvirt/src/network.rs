@@ -0,0 +1,373 @@
+//! Network and network port handles.
+
+use crate::error::Result;
+use crate::generated::{
+    ConnectListAllNetworksFlags, NetworkDhcpLease, NetworkPortCreateFlags, NetworkUpdateFlags, NetworkXmlFlags,
+    NonnullNetwork, NonnullNetworkPort, TypedParam, Uuid, REMOTE_NETWORK_PORT_PARAMETERS_MAX,
+    VIR_NETWORK_SECTION_BRIDGE, VIR_NETWORK_SECTION_DNS_HOST, VIR_NETWORK_SECTION_DNS_SRV,
+    VIR_NETWORK_SECTION_DNS_TXT, VIR_NETWORK_SECTION_DOMAIN, VIR_NETWORK_SECTION_FORWARD,
+    VIR_NETWORK_SECTION_FORWARD_INTERFACE, VIR_NETWORK_SECTION_FORWARD_PF, VIR_NETWORK_SECTION_IP,
+    VIR_NETWORK_SECTION_IP_DHCP_HOST, VIR_NETWORK_SECTION_IP_DHCP_RANGE, VIR_NETWORK_SECTION_PORTGROUP,
+    VIR_NETWORK_UPDATE_COMMAND_ADD_FIRST, VIR_NETWORK_UPDATE_COMMAND_ADD_LAST, VIR_NETWORK_UPDATE_COMMAND_DELETE,
+    VIR_NETWORK_UPDATE_COMMAND_MODIFY,
+};
+use crate::handle::handle;
+use crate::Client;
+
+/// Feature [`Client`]s ask `connect_supports_feature` about before
+/// [`Network::update`]. libvirt's driver features are internal, so the
+/// value isn't among the generated constants.
+const VIR_DRV_FEATURE_NETWORK_UPDATE_HAS_CORRECT_ORDER: i32 = 16;
+
+/// What [`Network::update`] does with its XML, libvirt's
+/// `virNetworkUpdateCommand`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NetworkUpdateCommand {
+    /// Replace the existing element the XML matches.
+    Modify,
+    /// Remove the existing element the XML matches.
+    Delete,
+    /// Add the XML as the last element of its list.
+    AddLast,
+    /// Add the XML as the first element of its list.
+    AddFirst,
+}
+
+impl From<NetworkUpdateCommand> for u32 {
+    fn from(command: NetworkUpdateCommand) -> Self {
+        let value = match command {
+            NetworkUpdateCommand::Modify => VIR_NETWORK_UPDATE_COMMAND_MODIFY,
+            NetworkUpdateCommand::Delete => VIR_NETWORK_UPDATE_COMMAND_DELETE,
+            NetworkUpdateCommand::AddLast => VIR_NETWORK_UPDATE_COMMAND_ADD_LAST,
+            NetworkUpdateCommand::AddFirst => VIR_NETWORK_UPDATE_COMMAND_ADD_FIRST,
+        };
+        value as u32
+    }
+}
+
+/// The part of the network's XML [`Network::update`] changes, libvirt's
+/// `virNetworkUpdateSection`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NetworkUpdateSection {
+    /// `<bridge>`
+    Bridge,
+    /// `<domain>`
+    Domain,
+    /// `<ip>`
+    Ip,
+    /// `<ip>/<dhcp>/<host>`
+    IpDhcpHost,
+    /// `<ip>/<dhcp>/<range>`
+    IpDhcpRange,
+    /// `<forward>`
+    Forward,
+    /// `<forward>/<interface>`
+    ForwardInterface,
+    /// `<forward>/<pf>`
+    ForwardPf,
+    /// `<portgroup>`
+    Portgroup,
+    /// `<dns>/<host>`
+    DnsHost,
+    /// `<dns>/<txt>`
+    DnsTxt,
+    /// `<dns>/<srv>`
+    DnsSrv,
+}
+
+impl From<NetworkUpdateSection> for u32 {
+    fn from(section: NetworkUpdateSection) -> Self {
+        let value = match section {
+            NetworkUpdateSection::Bridge => VIR_NETWORK_SECTION_BRIDGE,
+            NetworkUpdateSection::Domain => VIR_NETWORK_SECTION_DOMAIN,
+            NetworkUpdateSection::Ip => VIR_NETWORK_SECTION_IP,
+            NetworkUpdateSection::IpDhcpHost => VIR_NETWORK_SECTION_IP_DHCP_HOST,
+            NetworkUpdateSection::IpDhcpRange => VIR_NETWORK_SECTION_IP_DHCP_RANGE,
+            NetworkUpdateSection::Forward => VIR_NETWORK_SECTION_FORWARD,
+            NetworkUpdateSection::ForwardInterface => VIR_NETWORK_SECTION_FORWARD_INTERFACE,
+            NetworkUpdateSection::ForwardPf => VIR_NETWORK_SECTION_FORWARD_PF,
+            NetworkUpdateSection::Portgroup => VIR_NETWORK_SECTION_PORTGROUP,
+            NetworkUpdateSection::DnsHost => VIR_NETWORK_SECTION_DNS_HOST,
+            NetworkUpdateSection::DnsTxt => VIR_NETWORK_SECTION_DNS_TXT,
+            NetworkUpdateSection::DnsSrv => VIR_NETWORK_SECTION_DNS_SRV,
+        };
+        value as u32
+    }
+}
+
+handle! {
+    /// A virtual network of a [`Client`]'s host, like libvirt's
+    /// `virNetworkPtr`.
+    ///
+    /// ```ignore
+    /// let network = client.lookup_network_by_name("default").await?;
+    /// network.update(
+    ///     NetworkUpdateCommand::AddLast,
+    ///     NetworkUpdateSection::IpDhcpHost,
+    ///     None,
+    ///     "<host mac='52:54:00:00:00:01' ip='192.168.122.10'/>",
+    ///     NetworkUpdateFlags::LIVE | NetworkUpdateFlags::CONFIG,
+    /// ).await?;
+    /// ```
+    pub struct Network(NonnullNetwork) {
+        what: "network",
+        wrap: network,
+        debug: { name: name, uuid: uuid },
+        eq: [uuid],
+    }
+}
+
+impl Network {
+    /// Name of the network.
+    pub fn name(&self) -> &str {
+        &self.raw.name
+    }
+
+    /// UUID of the network.
+    pub fn uuid(&self) -> &Uuid {
+        &self.raw.uuid
+    }
+
+    /// XML description of the network.
+    pub async fn xml_desc(&self, flags: NetworkXmlFlags) -> Result<String> {
+        Ok(self.client.api().network_get_xml_desc(&self.raw, flags).await?)
+    }
+
+    /// Whether the network is running.
+    pub async fn is_active(&self) -> Result<bool> {
+        Ok(self.client.api().network_is_active(&self.raw).await?)
+    }
+
+    /// Whether the network has a persistent definition.
+    pub async fn is_persistent(&self) -> Result<bool> {
+        Ok(self.client.api().network_is_persistent(&self.raw).await?)
+    }
+
+    /// Start the defined network.
+    pub async fn create(&self) -> Result<()> {
+        Ok(self.client.api().network_create(&self.raw).await?)
+    }
+
+    /// Stop the network.
+    pub async fn destroy(&self) -> Result<()> {
+        Ok(self.client.api().network_destroy(&self.raw).await?)
+    }
+
+    /// Remove the persistent definition of the network.
+    pub async fn undefine(&self) -> Result<()> {
+        Ok(self.client.api().network_undefine(&self.raw).await?)
+    }
+
+    /// Whether the network starts when the host boots.
+    pub async fn autostart(&self) -> Result<bool> {
+        Ok(self.client.api().network_get_autostart(&self.raw).await?)
+    }
+
+    /// Set whether the network starts when the host boots.
+    pub async fn set_autostart(&self, autostart: bool) -> Result<()> {
+        Ok(self.client.api().network_set_autostart(&self.raw, autostart).await?)
+    }
+
+    /// Name of the host bridge device the network uses.
+    pub async fn bridge_name(&self) -> Result<String> {
+        Ok(self.client.api().network_get_bridge_name(&self.raw).await?)
+    }
+
+    /// The DHCP leases handed out by the network, only those of `mac` if
+    /// given.
+    pub async fn dhcp_leases(&self, mac: Option<&str>) -> Result<Vec<NetworkDhcpLease>> {
+        let ret = self.client.api().network_get_dhcp_leases(&self.raw, mac, 1, 0).await?;
+        Ok(ret.leases)
+    }
+
+    /// Change a section of the network's definition without redefining it.
+    ///
+    /// `parent_index` picks the parent element when there are several,
+    /// e.g. the `<ip>` whose `<dhcp>` hosts to change; `None` lets libvirt
+    /// find it.
+    ///
+    /// Older daemons read the command and section the other way around, so
+    /// they are swapped for daemons that don't report
+    /// `VIR_DRV_FEATURE_NETWORK_UPDATE_HAS_CORRECT_ORDER`.
+    pub async fn update(
+        &self,
+        command: NetworkUpdateCommand,
+        section: NetworkUpdateSection,
+        parent_index: Option<u32>,
+        xml: &str,
+        flags: NetworkUpdateFlags,
+    ) -> Result<()> {
+        let parent_index = parent_index.map_or(-1, |index| index as i32);
+        let (mut command, mut section) = (u32::from(command), u32::from(section));
+        if !self.client.network_update_has_correct_order().await? {
+            std::mem::swap(&mut command, &mut section);
+        }
+        Ok(self
+            .client
+            .api()
+            .network_update(&self.raw, command, section, parent_index, xml, flags)
+            .await?)
+    }
+
+    /// List the ports of the network.
+    pub async fn ports(&self) -> Result<Vec<NetworkPort>> {
+        let ret = self.client.api().network_list_all_ports(&self.raw, 1, 0).await?;
+        Ok(ret.ports.into_iter().map(|raw| NetworkPort::new(&self.client, raw)).collect())
+    }
+
+    /// Look up a port of the network by UUID.
+    pub async fn lookup_port_by_uuid(&self, uuid: Uuid) -> Result<NetworkPort> {
+        let raw = self.client.api().network_port_lookup_by_uuid(&self.raw, uuid).await?;
+        Ok(NetworkPort::new(&self.client, raw))
+    }
+
+    /// Create a port on the network from its XML, as a hypervisor does to
+    /// plug a guest interface in.
+    pub async fn create_port(&self, xml: &str, flags: NetworkPortCreateFlags) -> Result<NetworkPort> {
+        let raw = self.client.api().network_port_create_xml(&self.raw, xml, flags).await?;
+        Ok(NetworkPort::new(&self.client, raw))
+    }
+}
+
+handle! {
+    /// A port of a [`Network`], like libvirt's `virNetworkPortPtr`.
+    pub struct NetworkPort(NonnullNetworkPort) {
+        what: "network port",
+        wrap: network_port,
+        debug: { network: net.name, uuid: uuid },
+        eq: [uuid],
+    }
+}
+
+impl NetworkPort {
+    /// UUID of the port.
+    pub fn uuid(&self) -> &Uuid {
+        &self.raw.uuid
+    }
+
+    /// The network the port is on.
+    pub fn network(&self) -> Network {
+        Network::new(&self.client, self.raw.net.clone())
+    }
+
+    /// XML description of the port.
+    pub async fn xml_desc(&self) -> Result<String> {
+        Ok(self.client.api().network_port_get_xml_desc(&self.raw, 0).await?)
+    }
+
+    /// Tunables of the port, e.g. its `inbound.average` bandwidth.
+    pub async fn parameters(&self) -> Result<Vec<TypedParam>> {
+        let max = REMOTE_NETWORK_PORT_PARAMETERS_MAX as i32;
+        let ret = self.client.api().network_port_get_parameters(&self.raw, max, 0).await?;
+        Ok(ret.params)
+    }
+
+    /// Set tunables of the port.
+    pub async fn set_parameters(&self, params: &[TypedParam]) -> Result<()> {
+        Ok(self.client.api().network_port_set_parameters(&self.raw, params, 0).await?)
+    }
+
+    /// Remove the port from its network.
+    pub async fn delete(&self) -> Result<()> {
+        Ok(self.client.api().network_port_delete(&self.raw, 0).await?)
+    }
+}
+
+impl Client {
+    /// List the networks, filtered by the flags, e.g. only the active ones.
+    pub async fn list_networks(&self, flags: ConnectListAllNetworksFlags) -> Result<Vec<Network>> {
+        let ret = self.api().connect_list_all_networks(1, flags).await?;
+        Ok(ret.nets.into_iter().map(|raw| self.network(raw)).collect())
+    }
+
+    /// Look up a network by name.
+    pub async fn lookup_network_by_name(&self, name: &str) -> Result<Network> {
+        Ok(self.network(self.api().network_lookup_by_name(name).await?))
+    }
+
+    /// Look up a network by UUID.
+    pub async fn lookup_network_by_uuid(&self, uuid: Uuid) -> Result<Network> {
+        Ok(self.network(self.api().network_lookup_by_uuid(uuid).await?))
+    }
+
+    /// Define a persistent network from its XML, without starting it.
+    pub async fn define_network_xml(&self, xml: &str) -> Result<Network> {
+        Ok(self.network(self.api().network_define_xml(xml).await?))
+    }
+
+    /// Create and start a transient network from its XML.
+    pub async fn create_network_xml(&self, xml: &str) -> Result<Network> {
+        Ok(self.network(self.api().network_create_xml(xml).await?))
+    }
+
+    /// Whether the daemon takes `network_update`'s command and section in
+    /// the order of its arguments, asked once per client.
+    async fn network_update_has_correct_order(&self) -> Result<bool> {
+        let feature = VIR_DRV_FEATURE_NETWORK_UPDATE_HAS_CORRECT_ORDER;
+        let supported = self
+            .network_update_order
+            .get_or_try_init(|| async { self.api().connect_supports_feature(feature).await })
+            .await?;
+        Ok(*supported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::{ConnectSupportsFeatureArgs, ConnectSupportsFeatureRet, NetworkUpdateArgs};
+    use crate::testing::FakeHypervisor;
+
+    #[tokio::test]
+    async fn test_network_handle() {
+        let fake = FakeHypervisor::new();
+        let client = fake.client().await.unwrap();
+
+        let networks = client.list_networks(ConnectListAllNetworksFlags::ACTIVE).await.unwrap();
+        assert_eq!(networks.len(), 1);
+        let default = &networks[0];
+        assert_eq!(default.name(), "default");
+        assert_eq!(default.bridge_name().await.unwrap(), "virbr0");
+        assert!(default.autostart().await.unwrap());
+
+        let xml = "<network><name>net1</name><bridge name='virbr1'/></network>";
+        let net1 = client.define_network_xml(xml).await.unwrap();
+        assert!(!net1.is_active().await.unwrap());
+        net1.create().await.unwrap();
+        assert!(net1.is_active().await.unwrap());
+        assert_eq!(client.lookup_network_by_uuid(*net1.uuid()).await.unwrap(), net1);
+        assert!(net1.xml_desc(NetworkXmlFlags::empty()).await.unwrap().contains("virbr1"));
+        net1.destroy().await.unwrap();
+        net1.undefine().await.unwrap();
+        assert!(client.lookup_network_by_name("net1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_network_update_order() {
+        let fake = FakeHypervisor::new();
+        fake.daemon().respond("network_update", ());
+        let host = "<host mac='52:54:00:00:00:01' ip='192.168.122.10'/>";
+        let (command, section) = (NetworkUpdateCommand::AddLast, NetworkUpdateSection::IpDhcpHost);
+
+        fake.daemon().respond("connect_supports_feature", ConnectSupportsFeatureRet { supported: 1 });
+        let client = fake.client().await.unwrap();
+        let default = client.lookup_network_by_name("default").await.unwrap();
+        default.update(command, section, None, host, NetworkUpdateFlags::LIVE).await.unwrap();
+        default.update(command, section, Some(0), host, NetworkUpdateFlags::LIVE).await.unwrap();
+        let calls = fake.daemon().calls_to::<ConnectSupportsFeatureArgs>("connect_supports_feature");
+        assert_eq!(calls.len(), 1, "the feature is asked about once per client");
+        assert_eq!(calls[0].feature, VIR_DRV_FEATURE_NETWORK_UPDATE_HAS_CORRECT_ORDER);
+        let calls = fake.daemon().calls_to::<NetworkUpdateArgs>("network_update");
+        assert_eq!((calls[0].command, calls[0].section, calls[0].parent_index), (3, 4, -1));
+        assert_eq!((calls[0].xml.as_str(), calls[0].flags), (host, NetworkUpdateFlags::LIVE));
+        assert_eq!(calls[1].parent_index, 0);
+
+        // A daemon without the fix gets them swapped.
+        fake.daemon().respond("connect_supports_feature", ConnectSupportsFeatureRet { supported: 0 });
+        let client = fake.client().await.unwrap();
+        let default = client.lookup_network_by_name("default").await.unwrap();
+        default.update(command, section, None, host, NetworkUpdateFlags::LIVE).await.unwrap();
+        let calls = fake.daemon().calls_to::<NetworkUpdateArgs>("network_update");
+        assert_eq!((calls[2].command, calls[2].section), (4, 3));
+    }
+}
@@ -0,0 +1,331 @@
+//! Node device handles.
+
+use crate::error::Result;
+use crate::generated::{
+    ConnectListAllNodeDeviceFlags, NodeDeviceCreateXmlFlags, NodeDeviceDefineXmlFlags, NonnullNodeDevice,
+    REMOTE_NODE_DEVICE_CAPS_LIST_MAX,
+};
+use crate::handle::handle;
+use crate::Client;
+
+/// A capability of a node device, the kind of device it is or a feature it
+/// has, libvirt's `<capability type='...'>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeDeviceCapability {
+    /// The host system, `computer`.
+    System,
+    /// PCI device.
+    PciDev,
+    /// USB device.
+    UsbDev,
+    /// USB interface.
+    UsbInterface,
+    /// Network interface.
+    Net,
+    /// SCSI host bus adapter.
+    ScsiHost,
+    /// SCSI target.
+    ScsiTarget,
+    /// SCSI device.
+    Scsi,
+    /// Storage device.
+    Storage,
+    /// Fibre Channel host bus adapter.
+    FcHost,
+    /// SCSI host able to create vports.
+    Vports,
+    /// SCSI generic device.
+    ScsiGeneric,
+    /// DRM device.
+    Drm,
+    /// Parent device able to create mediated devices.
+    MdevTypes,
+    /// Mediated device.
+    Mdev,
+    /// s390 CCW device.
+    CcwDev,
+    /// s390 CSS device.
+    CssDev,
+    /// vDPA device.
+    Vdpa,
+    /// s390 AP card.
+    ApCard,
+    /// s390 AP queue.
+    ApQueue,
+    /// s390 AP matrix.
+    ApMatrix,
+    /// Device with vital product data.
+    Vpd,
+    /// s390 CCW group device.
+    CcwgroupDev,
+    /// s390 CCW device member of a CCW group.
+    CcwgroupMember,
+}
+
+impl NodeDeviceCapability {
+    const ALL: [Self; 24] = [
+        Self::System,
+        Self::PciDev,
+        Self::UsbDev,
+        Self::UsbInterface,
+        Self::Net,
+        Self::ScsiHost,
+        Self::ScsiTarget,
+        Self::Scsi,
+        Self::Storage,
+        Self::FcHost,
+        Self::Vports,
+        Self::ScsiGeneric,
+        Self::Drm,
+        Self::MdevTypes,
+        Self::Mdev,
+        Self::CcwDev,
+        Self::CssDev,
+        Self::Vdpa,
+        Self::ApCard,
+        Self::ApQueue,
+        Self::ApMatrix,
+        Self::Vpd,
+        Self::CcwgroupDev,
+        Self::CcwgroupMember,
+    ];
+
+    /// Name of the capability in device XML and in
+    /// [`NodeDevice::capabilities`], e.g. `pci`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::System => "system",
+            Self::PciDev => "pci",
+            Self::UsbDev => "usb_device",
+            Self::UsbInterface => "usb",
+            Self::Net => "net",
+            Self::ScsiHost => "scsi_host",
+            Self::ScsiTarget => "scsi_target",
+            Self::Scsi => "scsi",
+            Self::Storage => "storage",
+            Self::FcHost => "fc_host",
+            Self::Vports => "vports",
+            Self::ScsiGeneric => "scsi_generic",
+            Self::Drm => "drm",
+            Self::MdevTypes => "mdev_types",
+            Self::Mdev => "mdev",
+            Self::CcwDev => "ccw",
+            Self::CssDev => "css",
+            Self::Vdpa => "vdpa",
+            Self::ApCard => "ap_card",
+            Self::ApQueue => "ap_queue",
+            Self::ApMatrix => "ap_matrix",
+            Self::Vpd => "vpd",
+            Self::CcwgroupDev => "ccwgroup",
+            Self::CcwgroupMember => "ccwgroup_member",
+        }
+    }
+
+    /// The capability named `name`, if this crate knows it.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|cap| cap.name() == name)
+    }
+}
+
+impl From<NodeDeviceCapability> for ConnectListAllNodeDeviceFlags {
+    /// The flag listing only the devices with the capability.
+    fn from(cap: NodeDeviceCapability) -> Self {
+        match cap {
+            NodeDeviceCapability::System => Self::CAP_SYSTEM,
+            NodeDeviceCapability::PciDev => Self::CAP_PCI_DEV,
+            NodeDeviceCapability::UsbDev => Self::CAP_USB_DEV,
+            NodeDeviceCapability::UsbInterface => Self::CAP_USB_INTERFACE,
+            NodeDeviceCapability::Net => Self::CAP_NET,
+            NodeDeviceCapability::ScsiHost => Self::CAP_SCSI_HOST,
+            NodeDeviceCapability::ScsiTarget => Self::CAP_SCSI_TARGET,
+            NodeDeviceCapability::Scsi => Self::CAP_SCSI,
+            NodeDeviceCapability::Storage => Self::CAP_STORAGE,
+            NodeDeviceCapability::FcHost => Self::CAP_FC_HOST,
+            NodeDeviceCapability::Vports => Self::CAP_VPORTS,
+            NodeDeviceCapability::ScsiGeneric => Self::CAP_SCSI_GENERIC,
+            NodeDeviceCapability::Drm => Self::CAP_DRM,
+            NodeDeviceCapability::MdevTypes => Self::CAP_MDEV_TYPES,
+            NodeDeviceCapability::Mdev => Self::CAP_MDEV,
+            NodeDeviceCapability::CcwDev => Self::CAP_CCW_DEV,
+            NodeDeviceCapability::CssDev => Self::CAP_CSS_DEV,
+            NodeDeviceCapability::Vdpa => Self::CAP_VDPA,
+            NodeDeviceCapability::ApCard => Self::CAP_AP_CARD,
+            NodeDeviceCapability::ApQueue => Self::CAP_AP_QUEUE,
+            NodeDeviceCapability::ApMatrix => Self::CAP_AP_MATRIX,
+            NodeDeviceCapability::Vpd => Self::CAP_VPD,
+            NodeDeviceCapability::CcwgroupDev => Self::CAP_CCWGROUP_DEV,
+            NodeDeviceCapability::CcwgroupMember => Self::CAP_CCWGROUP_MEMBER,
+        }
+    }
+}
+
+handle! {
+    /// A device of a [`Client`]'s host, like libvirt's `virNodeDevicePtr`.
+    ///
+    /// Passing a PCI device through to a guest takes it from its host driver:
+    ///
+    /// ```ignore
+    /// let gpu = client.lookup_node_device_by_name("pci_0000_01_00_0").await?;
+    /// gpu.detach(Some("vfio")).await?;
+    /// gpu.reset().await?;
+    /// ```
+    pub struct NodeDevice(NonnullNodeDevice) {
+        what: "node device",
+        wrap: node_device,
+        debug: { name: name },
+        eq: [name],
+    }
+}
+
+impl NodeDevice {
+    /// Name of the device, e.g. `pci_0000_00_1f_2`.
+    pub fn name(&self) -> &str {
+        &self.raw.name
+    }
+
+    /// XML description of the device.
+    pub async fn xml_desc(&self) -> Result<String> {
+        Ok(self.client.api().node_device_get_xml_desc(&self.raw.name, 0).await?)
+    }
+
+    /// The device this one hangs off, if any: only the host system has
+    /// none.
+    pub async fn parent(&self) -> Result<Option<NodeDevice>> {
+        let name = self.client.api().node_device_get_parent(&self.raw.name).await?;
+        Ok(name.map(|name| self.client.node_device(NonnullNodeDevice { name })))
+    }
+
+    /// Capabilities of the device. Those this crate has no variant for are
+    /// left out.
+    pub async fn capabilities(&self) -> Result<Vec<NodeDeviceCapability>> {
+        let max = REMOTE_NODE_DEVICE_CAPS_LIST_MAX as i32;
+        let names = self.client.api().node_device_list_caps(&self.raw.name, max).await?;
+        Ok(names.iter().filter_map(|name| NodeDeviceCapability::from_name(name)).collect())
+    }
+
+    /// Whether the device is present, for mediated devices whether it is
+    /// started.
+    pub async fn is_active(&self) -> Result<bool> {
+        Ok(self.client.api().node_device_is_active(&self.raw.name).await?)
+    }
+
+    /// Whether the device has a persistent definition.
+    pub async fn is_persistent(&self) -> Result<bool> {
+        Ok(self.client.api().node_device_is_persistent(&self.raw.name).await?)
+    }
+
+    /// Detach the PCI device from its host driver, binding it to `driver`
+    /// (`vfio`, ...) or the hypervisor's default, for passthrough.
+    pub async fn detach(&self, driver: Option<&str>) -> Result<()> {
+        Ok(self.client.api().node_device_detach_flags(&self.raw.name, driver, 0).await?)
+    }
+
+    /// Give the detached PCI device back to its host driver.
+    pub async fn reattach(&self) -> Result<()> {
+        Ok(self.client.api().node_device_re_attach(&self.raw.name).await?)
+    }
+
+    /// Reset the PCI device, e.g. between two guests using it.
+    pub async fn reset(&self) -> Result<()> {
+        Ok(self.client.api().node_device_reset(&self.raw.name).await?)
+    }
+
+    /// Start the defined mediated device.
+    pub async fn create(&self) -> Result<()> {
+        Ok(self.client.api().node_device_create(&self.raw.name, 0).await?)
+    }
+
+    /// Remove the mediated or NPIV device from the host.
+    pub async fn destroy(&self) -> Result<()> {
+        Ok(self.client.api().node_device_destroy(&self.raw.name).await?)
+    }
+
+    /// Remove the persistent definition of the mediated device.
+    pub async fn undefine(&self) -> Result<()> {
+        Ok(self.client.api().node_device_undefine(&self.raw.name, 0).await?)
+    }
+
+    /// Whether the mediated device starts when the host boots.
+    pub async fn autostart(&self) -> Result<bool> {
+        Ok(self.client.api().node_device_get_autostart(&self.raw.name).await?)
+    }
+
+    /// Set whether the mediated device starts when the host boots.
+    pub async fn set_autostart(&self, autostart: bool) -> Result<()> {
+        Ok(self.client.api().node_device_set_autostart(&self.raw.name, autostart).await?)
+    }
+}
+
+impl Client {
+    /// List the host's devices, filtered by the flags, e.g. to those with
+    /// a capability:
+    ///
+    /// ```ignore
+    /// client.list_node_devices(NodeDeviceCapability::PciDev.into()).await?
+    /// ```
+    pub async fn list_node_devices(&self, flags: ConnectListAllNodeDeviceFlags) -> Result<Vec<NodeDevice>> {
+        let ret = self.api().connect_list_all_node_devices(1, flags).await?;
+        Ok(ret.devices.into_iter().map(|raw| self.node_device(raw)).collect())
+    }
+
+    /// Look up a node device by name.
+    pub async fn lookup_node_device_by_name(&self, name: &str) -> Result<NodeDevice> {
+        Ok(self.node_device(self.api().node_device_lookup_by_name(name).await?))
+    }
+
+    /// Create a transient device from its XML, e.g. a mediated device.
+    pub async fn create_node_device_xml(&self, xml: &str, flags: NodeDeviceCreateXmlFlags) -> Result<NodeDevice> {
+        Ok(self.node_device(self.api().node_device_create_xml(xml, flags).await?))
+    }
+
+    /// Define a persistent mediated device from its XML, without starting
+    /// it.
+    pub async fn define_node_device_xml(&self, xml: &str, flags: NodeDeviceDefineXmlFlags) -> Result<NodeDevice> {
+        Ok(self.node_device(self.api().node_device_define_xml(xml, flags).await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::{
+        ConnectListAllNodeDevicesArgs, ConnectListAllNodeDevicesRet, NodeDeviceDetachFlagsArgs,
+        NodeDeviceGetParentRet, NodeDeviceListCapsRet,
+    };
+    use crate::testing::MockDaemon;
+
+    #[tokio::test]
+    async fn test_node_device_handle() {
+        let daemon = MockDaemon::new();
+        let client = daemon.client().await.unwrap();
+
+        let gpu = NonnullNodeDevice {
+            name: "pci_0000_01_00_0".to_string(),
+        };
+        daemon.respond("connect_list_all_node_devices", ConnectListAllNodeDevicesRet {
+            devices: vec![gpu.clone()],
+            ret: 1,
+        });
+        let devices = client.list_node_devices(NodeDeviceCapability::PciDev.into()).await.unwrap();
+        assert_eq!(devices, [client.node_device(gpu)]);
+        let calls = daemon.calls_to::<ConnectListAllNodeDevicesArgs>("connect_list_all_node_devices");
+        assert_eq!(calls[0].flags, ConnectListAllNodeDeviceFlags::CAP_PCI_DEV);
+
+        let gpu = &devices[0];
+        daemon.respond("node_device_list_caps", NodeDeviceListCapsRet {
+            names: vec!["pci".to_string(), "mdev_types".to_string(), "future_cap".to_string()],
+        });
+        let caps = gpu.capabilities().await.unwrap();
+        assert_eq!(caps, [NodeDeviceCapability::PciDev, NodeDeviceCapability::MdevTypes]);
+
+        daemon.respond("node_device_get_parent", NodeDeviceGetParentRet {
+            parent_name: Some("pci_0000_00_01_0".to_string()),
+        });
+        assert_eq!(gpu.parent().await.unwrap().unwrap().name(), "pci_0000_00_01_0");
+
+        daemon.respond("node_device_detach_flags", ());
+        gpu.detach(Some("vfio")).await.unwrap();
+        let calls = daemon.calls_to::<NodeDeviceDetachFlagsArgs>("node_device_detach_flags");
+        assert_eq!(calls[0].driver_name.as_deref(), Some("vfio"));
+    }
+}
@@ -0,0 +1,177 @@
+//! Network filter and network filter binding handles.
+
+use crate::error::Result;
+use crate::generated::{NonnullNwfilter, NonnullNwfilterBinding, Uuid};
+use crate::handle::handle;
+use crate::Client;
+
+handle! {
+    /// A network filter of a [`Client`]'s host, like libvirt's
+    /// `virNWFilterPtr`.
+    pub struct Nwfilter(NonnullNwfilter) {
+        what: "network filter",
+        wrap: nwfilter,
+        debug: { name: name, uuid: uuid },
+        eq: [uuid],
+    }
+}
+
+impl Nwfilter {
+    /// Name of the filter.
+    pub fn name(&self) -> &str {
+        &self.raw.name
+    }
+
+    /// UUID of the filter.
+    pub fn uuid(&self) -> &Uuid {
+        &self.raw.uuid
+    }
+
+    /// XML description of the filter.
+    pub async fn xml_desc(&self) -> Result<String> {
+        Ok(self.client.api().nwfilter_get_xml_desc(&self.raw, 0).await?)
+    }
+
+    /// Remove the filter.
+    pub async fn undefine(&self) -> Result<()> {
+        Ok(self.client.api().nwfilter_undefine(&self.raw).await?)
+    }
+}
+
+handle! {
+    /// A network filter applied to a guest interface, like libvirt's
+    /// `virNWFilterBindingPtr`.
+    pub struct NwfilterBinding(NonnullNwfilterBinding) {
+        what: "network filter binding",
+        wrap: nwfilter_binding,
+        debug: { port_dev: portdev, filter_name: filtername },
+        // An interface has at most one binding
+        eq: [portdev],
+    }
+}
+
+impl NwfilterBinding {
+    /// Host device of the interface the filter applies to, e.g. `vnet0`.
+    pub fn port_dev(&self) -> &str {
+        &self.raw.portdev
+    }
+
+    /// Name of the filter applied.
+    pub fn filter_name(&self) -> &str {
+        &self.raw.filtername
+    }
+
+    /// XML description of the binding.
+    pub async fn xml_desc(&self) -> Result<String> {
+        Ok(self.client.api().nwfilter_binding_get_xml_desc(&self.raw, 0).await?)
+    }
+
+    /// Remove the filter from the interface.
+    pub async fn delete(&self) -> Result<()> {
+        Ok(self.client.api().nwfilter_binding_delete(&self.raw).await?)
+    }
+}
+
+impl Client {
+    /// List the network filters.
+    pub async fn list_nwfilters(&self) -> Result<Vec<Nwfilter>> {
+        let ret = self.api().connect_list_all_nwfilters(1, 0).await?;
+        Ok(ret.filters.into_iter().map(|raw| self.nwfilter(raw)).collect())
+    }
+
+    /// Look up a network filter by name.
+    pub async fn lookup_nwfilter_by_name(&self, name: &str) -> Result<Nwfilter> {
+        Ok(self.nwfilter(self.api().nwfilter_lookup_by_name(name).await?))
+    }
+
+    /// Look up a network filter by UUID.
+    pub async fn lookup_nwfilter_by_uuid(&self, uuid: Uuid) -> Result<Nwfilter> {
+        Ok(self.nwfilter(self.api().nwfilter_lookup_by_uuid(uuid).await?))
+    }
+
+    /// Define a network filter from its XML, or replace the one of the
+    /// same name.
+    pub async fn define_nwfilter_xml(&self, xml: &str) -> Result<Nwfilter> {
+        Ok(self.nwfilter(self.api().nwfilter_define_xml(xml).await?))
+    }
+
+    /// List the network filter bindings.
+    pub async fn list_nwfilter_bindings(&self) -> Result<Vec<NwfilterBinding>> {
+        let ret = self.api().connect_list_all_nwfilter_bindings(1, 0).await?;
+        Ok(ret.bindings.into_iter().map(|raw| self.nwfilter_binding(raw)).collect())
+    }
+
+    /// Look up the network filter binding of the interface `port_dev`.
+    pub async fn lookup_nwfilter_binding_by_port_dev(&self, port_dev: &str) -> Result<NwfilterBinding> {
+        Ok(self.nwfilter_binding(self.api().nwfilter_binding_lookup_by_port_dev(port_dev).await?))
+    }
+
+    /// Apply a network filter to an interface, as described by the
+    /// binding's XML.
+    pub async fn create_nwfilter_binding_xml(&self, xml: &str) -> Result<NwfilterBinding> {
+        Ok(self.nwfilter_binding(self.api().nwfilter_binding_create_xml(xml, 0).await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::{
+        ConnectListAllNwfiltersRet, NwfilterBindingCreateXmlArgs, NwfilterBindingCreateXmlRet,
+        NwfilterBindingDeleteArgs, NwfilterBindingLookupByPortDevRet, NwfilterDefineXmlRet, NwfilterGetXmlDescRet,
+        Procedure,
+    };
+    use crate::testing::MockDaemon;
+
+    #[tokio::test]
+    async fn test_nwfilter_handles() {
+        let daemon = MockDaemon::new();
+        let client = daemon.client().await.unwrap();
+
+        let clean_traffic = NonnullNwfilter {
+            name: "clean-traffic".to_string(),
+            uuid: Uuid::new([3; 16]),
+        };
+        daemon.respond("connect_list_all_nwfilters", ConnectListAllNwfiltersRet {
+            filters: vec![clean_traffic.clone()],
+            ret: 1,
+        });
+        daemon.respond("nwfilter_define_xml", NwfilterDefineXmlRet { nwfilter: clean_traffic.clone() });
+        daemon.respond("nwfilter_get_xml_desc", NwfilterGetXmlDescRet {
+            xml: "<filter name='clean-traffic'/>".to_string(),
+        });
+        let filters = client.list_nwfilters().await.unwrap();
+        assert_eq!(filters, [client.nwfilter(clean_traffic)]);
+        assert_eq!(filters[0].name(), "clean-traffic");
+        let filter = client.define_nwfilter_xml("<filter name='clean-traffic'/>").await.unwrap();
+        assert_eq!(filter, filters[0]);
+        assert!(filter.xml_desc().await.unwrap().contains("clean-traffic"));
+
+        let binding = NonnullNwfilterBinding {
+            portdev: "vnet0".to_string(),
+            filtername: "clean-traffic".to_string(),
+        };
+        daemon.respond("nwfilter_binding_create_xml", NwfilterBindingCreateXmlRet { nwfilter: binding.clone() });
+        daemon.respond("nwfilter_binding_lookup_by_port_dev", NwfilterBindingLookupByPortDevRet {
+            nwfilter: binding,
+        });
+        daemon.respond("nwfilter_binding_delete", ());
+        let xml = "<filterbinding><portdev name='vnet0'/><filterref filter='clean-traffic'/></filterbinding>";
+        let created = client.create_nwfilter_binding_xml(xml).await.unwrap();
+        assert_eq!((created.port_dev(), created.filter_name()), ("vnet0", "clean-traffic"));
+        let found = client.lookup_nwfilter_binding_by_port_dev("vnet0").await.unwrap();
+        assert_eq!(found, created);
+        found.delete().await.unwrap();
+
+        let calls = daemon.calls_to::<NwfilterBindingCreateXmlArgs>("nwfilter_binding_create_xml");
+        assert_eq!((calls[0].xml.as_str(), calls[0].flags), (xml, 0));
+        let calls = daemon.calls_to::<NwfilterBindingDeleteArgs>("nwfilter_binding_delete");
+        assert_eq!(calls[0].nwfilter.portdev, "vnet0");
+        let procedures: Vec<_> = daemon.calls().iter().map(|call| call.procedure).collect();
+        assert!(procedures.ends_with(&[
+            Procedure::ProcNwfilterBindingCreateXml as u32,
+            Procedure::ProcNwfilterBindingLookupByPortDev as u32,
+            Procedure::ProcNwfilterBindingDelete as u32,
+        ]));
+    }
+}
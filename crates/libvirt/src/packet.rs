@@ -34,6 +34,8 @@ pub enum MessageType {
     Message = 2,
     /// Stream data.
     Stream = 3,
+    /// Hole in a sparse data stream: a run of zeros sent as its length.
+    StreamHole = 6,
 }
 
 impl MessageType {
@@ -43,6 +45,7 @@ impl MessageType {
             1 => Some(Self::Reply),
             2 => Some(Self::Message),
             3 => Some(Self::Stream),
+            6 => Some(Self::StreamHole),
             _ => None,
         }
     }
@@ -109,6 +112,59 @@ impl Packet {
         }
     }
 
+    /// Create the reply to a call packet, with the same program, version,
+    /// procedure and serial.
+    pub fn new_reply(call: &Packet, status: Status, payload: Bytes) -> Self {
+        Self {
+            program: call.program,
+            version: call.version,
+            procedure: call.procedure,
+            msg_type: MessageType::Reply,
+            serial: call.serial,
+            status,
+            payload,
+        }
+    }
+
+    /// Create a packet of the data stream opened by call `serial`.
+    ///
+    /// `Continue` packets carry data; an empty `Ok` packet ends the stream
+    /// and an `Error` packet aborts it.
+    pub fn new_stream(program: u32, procedure: u32, serial: i32, status: Status, payload: Bytes) -> Self {
+        Self {
+            program,
+            version: REMOTE_PROTOCOL_VERSION as u32,
+            procedure,
+            msg_type: MessageType::Stream,
+            serial,
+            status,
+            payload,
+        }
+    }
+
+    /// Create a hole of `length` bytes in the sparse data stream opened by
+    /// call `serial`.
+    ///
+    /// The payload is a `virNetStreamHole`: the length as a hyper, then
+    /// flags, none of which are defined.
+    pub fn new_stream_hole(program: u32, procedure: u32, serial: i32, length: u64) -> Self {
+        let mut payload = BytesMut::with_capacity(12);
+        payload.put_i64(length as i64);
+        payload.put_u32(0);
+        Self {
+            msg_type: MessageType::StreamHole,
+            ..Self::new_stream(program, procedure, serial, Status::Continue, payload.freeze())
+        }
+    }
+
+    /// Length of the hole a `StreamHole` packet stands for.
+    pub fn hole_length(&self) -> Option<u64> {
+        if self.msg_type != MessageType::StreamHole || self.payload.len() < 12 {
+            return None;
+        }
+        u64::try_from((&self.payload[..8]).get_i64()).ok()
+    }
+
     /// Encode the packet to bytes.
     pub fn encode(&self) -> BytesMut {
         let payload_len = self.payload.len();
@@ -183,6 +239,7 @@ pub enum PacketError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::generated::{procedure_info, program_info, StreamDirection, QEMU_PROGRAM};
 
     #[test]
     fn test_packet_encode_decode() {
@@ -202,5 +259,29 @@ mod tests {
         assert_eq!(decoded.serial, 1);
         assert_eq!(decoded.status, Status::Ok);
         assert_eq!(decoded.payload, payload);
+
+        let hole = Packet::new_stream_hole(REMOTE_PROGRAM as u32, 209, 7, 1 << 33);
+        let decoded = Packet::decode(hole.encode().freeze().split_off(4)).unwrap();
+        assert_eq!((decoded.msg_type, decoded.status), (MessageType::StreamHole, Status::Continue));
+        assert_eq!(decoded.hole_length(), Some(1 << 33));
+        assert_eq!(packet.hole_length(), None);
+    }
+
+    #[test]
+    fn test_procedure_tables() {
+        let open = procedure_info(REMOTE_PROGRAM as u32, 1).unwrap();
+        assert_eq!(open.name, "REMOTE_PROC_CONNECT_OPEN");
+        assert_eq!(open.args, Some("ConnectOpenArgs"));
+        assert!(open.high_priority);
+
+        let remote = program_info(REMOTE_PROGRAM as u32).unwrap();
+        assert_eq!(remote.version, REMOTE_PROTOCOL_VERSION as u32);
+        let screenshot = remote.procedure_by_name("domain_screenshot").unwrap();
+        assert_eq!(screenshot.stream, Some(StreamDirection::Read));
+        assert_eq!(screenshot.acl, ["domain:screenshot"]);
+
+        let qemu = program_info(QEMU_PROGRAM as u32).unwrap();
+        assert!(qemu.procedure_by_name("QEMU_PROC_DOMAIN_MONITOR_COMMAND").is_some());
+        assert!(procedure_info(0, 1).is_none());
     }
 }
@@ -0,0 +1,184 @@
+//! Secret handles.
+
+use zeroize::Zeroizing;
+
+use crate::error::Result;
+use crate::generated::{
+    ConnectListAllSecretsFlags, NonnullSecret, SecretDefineFlags, Uuid, VIR_SECRET_USAGE_TYPE_CEPH,
+    VIR_SECRET_USAGE_TYPE_ISCSI, VIR_SECRET_USAGE_TYPE_NONE, VIR_SECRET_USAGE_TYPE_TLS, VIR_SECRET_USAGE_TYPE_VTPM,
+    VIR_SECRET_USAGE_TYPE_VOLUME,
+};
+use crate::handle::handle;
+use crate::Client;
+
+/// What a secret is used for, libvirt's `virSecretUsageType`. Together
+/// with the usage ID it names the secret, e.g. the Ceph user
+/// `client.libvirt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SecretUsageType {
+    /// No usage: the secret is only known by UUID.
+    None,
+    /// Passphrase of an encrypted volume, named by its path.
+    Volume,
+    /// Key of a Ceph user, named by the user.
+    Ceph,
+    /// CHAP password of an iSCSI target, named by the target.
+    Iscsi,
+    /// Passphrase of a TLS key.
+    Tls,
+    /// Passphrase of a vTPM's encrypted state.
+    Vtpm,
+}
+
+impl From<SecretUsageType> for i32 {
+    fn from(usage_type: SecretUsageType) -> Self {
+        match usage_type {
+            SecretUsageType::None => VIR_SECRET_USAGE_TYPE_NONE,
+            SecretUsageType::Volume => VIR_SECRET_USAGE_TYPE_VOLUME,
+            SecretUsageType::Ceph => VIR_SECRET_USAGE_TYPE_CEPH,
+            SecretUsageType::Iscsi => VIR_SECRET_USAGE_TYPE_ISCSI,
+            SecretUsageType::Tls => VIR_SECRET_USAGE_TYPE_TLS,
+            SecretUsageType::Vtpm => VIR_SECRET_USAGE_TYPE_VTPM,
+        }
+    }
+}
+
+impl TryFrom<i32> for SecretUsageType {
+    /// The value, unknown to this crate.
+    type Error = i32;
+
+    fn try_from(value: i32) -> std::result::Result<Self, i32> {
+        match value {
+            VIR_SECRET_USAGE_TYPE_NONE => Ok(Self::None),
+            VIR_SECRET_USAGE_TYPE_VOLUME => Ok(Self::Volume),
+            VIR_SECRET_USAGE_TYPE_CEPH => Ok(Self::Ceph),
+            VIR_SECRET_USAGE_TYPE_ISCSI => Ok(Self::Iscsi),
+            VIR_SECRET_USAGE_TYPE_TLS => Ok(Self::Tls),
+            VIR_SECRET_USAGE_TYPE_VTPM => Ok(Self::Vtpm),
+            _ => Err(value),
+        }
+    }
+}
+
+handle! {
+    /// A secret of a [`Client`]'s host, like libvirt's `virSecretPtr`: a
+    /// password or key libvirt hands to the guests' storage and devices.
+    ///
+    /// ```ignore
+    /// let secret = client.lookup_secret_by_usage(SecretUsageType::Ceph, "client.libvirt").await?;
+    /// secret.set_value(&key).await?;
+    /// ```
+    pub struct Secret(NonnullSecret) {
+        what: "secret",
+        wrap: secret,
+        debug: { uuid: uuid, usage_type: usage_type, usage_id: usage_id },
+        eq: [uuid],
+    }
+}
+
+impl Secret {
+    /// UUID of the secret.
+    pub fn uuid(&self) -> &Uuid {
+        &self.raw.uuid
+    }
+
+    /// What the secret is used for, or the raw value if this crate does
+    /// not know it.
+    pub fn usage_type(&self) -> std::result::Result<SecretUsageType, i32> {
+        SecretUsageType::try_from(self.raw.usage_type)
+    }
+
+    /// Name of the secret's user, e.g. a volume path or Ceph user.
+    pub fn usage_id(&self) -> &str {
+        &self.raw.usage_id
+    }
+
+    /// XML description of the secret. It never contains the value.
+    pub async fn xml_desc(&self) -> Result<String> {
+        Ok(self.client.api().secret_get_xml_desc(&self.raw, 0).await?)
+    }
+
+    /// Value of the secret, wiped from memory when dropped.
+    ///
+    /// Only the returned buffer is wiped: the value also passes through the
+    /// connection's buffers, which are not. libvirt refuses to give out
+    /// the values of private secrets.
+    pub async fn value(&self) -> Result<Zeroizing<Vec<u8>>> {
+        Ok(Zeroizing::new(self.client.api().secret_get_value(&self.raw, 0).await?))
+    }
+
+    /// Set the value of the secret.
+    pub async fn set_value(&self, value: &[u8]) -> Result<()> {
+        Ok(self.client.api().secret_set_value(&self.raw, value, 0).await?)
+    }
+
+    /// Remove the secret and its value.
+    pub async fn undefine(&self) -> Result<()> {
+        Ok(self.client.api().secret_undefine(&self.raw).await?)
+    }
+}
+
+impl Client {
+    /// List the secrets, filtered by the flags, e.g. only the private ones.
+    pub async fn list_secrets(&self, flags: ConnectListAllSecretsFlags) -> Result<Vec<Secret>> {
+        let ret = self.api().connect_list_all_secrets(1, flags).await?;
+        Ok(ret.secrets.into_iter().map(|raw| self.secret(raw)).collect())
+    }
+
+    /// Look up a secret by UUID.
+    pub async fn lookup_secret_by_uuid(&self, uuid: Uuid) -> Result<Secret> {
+        Ok(self.secret(self.api().secret_lookup_by_uuid(uuid).await?))
+    }
+
+    /// Look up a secret by what it is used for.
+    pub async fn lookup_secret_by_usage(&self, usage_type: SecretUsageType, usage_id: &str) -> Result<Secret> {
+        Ok(self.secret(self.api().secret_lookup_by_usage(usage_type.into(), usage_id).await?))
+    }
+
+    /// Define a secret from its XML, or replace the one of the same UUID
+    /// or usage. The value is set separately.
+    pub async fn define_secret_xml(&self, xml: &str, flags: SecretDefineFlags) -> Result<Secret> {
+        Ok(self.secret(self.api().secret_define_xml(xml, flags).await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::{
+        SecretDefineXmlRet, SecretGetValueRet, SecretLookupByUsageArgs, SecretLookupByUsageRet, SecretSetValueArgs,
+    };
+    use crate::testing::MockDaemon;
+
+    #[tokio::test]
+    async fn test_secret_handle() {
+        let daemon = MockDaemon::new();
+        let client = daemon.client().await.unwrap();
+
+        let raw = NonnullSecret {
+            uuid: Uuid::new([7; 16]),
+            usage_type: VIR_SECRET_USAGE_TYPE_CEPH,
+            usage_id: "client.libvirt".to_string(),
+        };
+        daemon.respond("secret_define_xml", SecretDefineXmlRet { secret: raw.clone() });
+        daemon.respond("secret_lookup_by_usage", SecretLookupByUsageRet { secret: raw });
+        daemon.respond("secret_set_value", ());
+        daemon.respond("secret_get_value", SecretGetValueRet {
+            value: b"AQBl".to_vec(),
+        });
+
+        let xml = "<secret ephemeral='no' private='no'><usage type='ceph'><name>client.libvirt</name></usage></secret>";
+        let secret = client.define_secret_xml(xml, SecretDefineFlags::empty()).await.unwrap();
+        assert_eq!(secret.usage_type(), Ok(SecretUsageType::Ceph));
+        assert_eq!(secret.usage_id(), "client.libvirt");
+        secret.set_value(b"AQBl").await.unwrap();
+        let calls = daemon.calls_to::<SecretSetValueArgs>("secret_set_value");
+        assert_eq!(calls[0].value, b"AQBl");
+        assert_eq!(secret.value().await.unwrap().as_slice(), b"AQBl");
+
+        let found = client.lookup_secret_by_usage(SecretUsageType::Ceph, "client.libvirt").await.unwrap();
+        assert_eq!(found, secret);
+        let calls = daemon.calls_to::<SecretLookupByUsageArgs>("secret_lookup_by_usage");
+        assert_eq!((calls[0].usage_type, calls[0].usage_id.as_str()), (VIR_SECRET_USAGE_TYPE_CEPH, "client.libvirt"));
+    }
+}
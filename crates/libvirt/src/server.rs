@@ -0,0 +1,258 @@
+//! Serving the libvirt RPC protocol.
+//!
+//! The generated [`RemoteService`], [`QemuService`] and [`LxcService`]
+//! traits have one method per procedure of their program, taking the
+//! procedure's `*Args` and returning its `*Ret`. A [`Dispatcher`] decodes
+//! call packets, routes them by program and procedure to a [`Service`],
+//! such as one implementing all three traits, and encodes the replies,
+//! turning errors into the `remote_error` libvirt clients expect.
+//!
+//! ```ignore
+//! use libvirt::server::Dispatcher;
+//! use libvirt::*;
+//!
+//! struct Fake;
+//!
+//! impl RemoteService for Fake {
+//!     async fn connect_get_hostname(&self) -> Result<ConnectGetHostnameRet, ServiceError> {
+//!         Ok(ConnectGetHostnameRet { hostname: "fake".to_string() })
+//!     }
+//! }
+//!
+//! // Leave the QEMU and LXC programs unsupported
+//! impl QemuService for Fake {}
+//! impl LxcService for Fake {}
+//!
+//! Dispatcher::new(Fake).serve(&mut transport).await?;
+//! ```
+
+use bytes::Bytes;
+
+use crate::error::{Error, Result};
+use crate::generated::{
+    self, dispatch_lxc, dispatch_qemu, dispatch_remote, procedure_info, LxcService, QemuService,
+    RemoteService, ServiceError, LXC_PROGRAM, QEMU_PROGRAM, REMOTE_PROGRAM,
+};
+use crate::packet::{MessageType, Packet, Status};
+use crate::transport::Transport;
+
+/// The `remote_error` payload of a failed call's reply.
+pub type RemoteError = generated::Error;
+
+impl RemoteError {
+    /// An error with the given `VIR_ERR_*` code and `VIR_FROM_*` domain.
+    pub fn new(code: i32, domain: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            domain,
+            message: Some(message.into()),
+            level: generated::VIR_ERR_ERROR,
+            dom: None,
+            str1: None,
+            str2: None,
+            str3: None,
+            int1: 0,
+            int2: 0,
+            net: None,
+        }
+    }
+}
+
+/// Answers calls given their program, procedure and encoded arguments.
+///
+/// Implemented for services of the generated traits, decoding the
+/// arguments and encoding the reply. Programs other than remote, QEMU and
+/// LXC are answered with `VIR_ERR_NO_SUPPORT`, like procedures the service
+/// does not implement.
+#[allow(async_fn_in_trait)]
+pub trait Service {
+    /// The encoded reply to a call of `procedure` of `program`.
+    async fn call(&self, program: u32, procedure: u32, payload: &[u8]) -> std::result::Result<Vec<u8>, ServiceError>;
+}
+
+impl<S> Service for S
+where
+    S: RemoteService + QemuService + LxcService,
+{
+    async fn call(&self, program: u32, procedure: u32, payload: &[u8]) -> std::result::Result<Vec<u8>, ServiceError> {
+        match program {
+            program if program == REMOTE_PROGRAM as u32 => dispatch_remote(self, procedure, payload).await,
+            program if program == QEMU_PROGRAM as u32 => dispatch_qemu(self, procedure, payload).await,
+            program if program == LXC_PROGRAM as u32 => dispatch_lxc(self, procedure, payload).await,
+            _ => Err(ServiceError::Unsupported { procedure }),
+        }
+    }
+}
+
+/// Routes call packets to a [`Service`] and builds the replies.
+pub struct Dispatcher<S> {
+    service: S,
+}
+
+impl<S: Service> Dispatcher<S> {
+    /// Create a dispatcher calling `service`.
+    pub fn new(service: S) -> Self {
+        Self { service }
+    }
+
+    /// Get a reference to the service.
+    pub fn service(&self) -> &S {
+        &self.service
+    }
+
+    /// Handle a packet, returning the reply to send.
+    ///
+    /// Only calls are answered; other messages (replies, events and
+    /// stream data) return `None`.
+    pub async fn dispatch(&self, packet: &Packet) -> Option<Packet> {
+        if packet.msg_type != MessageType::Call {
+            return None;
+        }
+
+        let result = self.service.call(packet.program, packet.procedure, &packet.payload).await;
+        Some(match result {
+            Ok(payload) => Packet::new_reply(packet, Status::Ok, Bytes::from(payload)),
+            Err(error) => error_reply(packet, error),
+        })
+    }
+
+    /// Answer the calls received on `transport` until the peer closes it.
+    pub async fn serve<T: Transport>(&self, transport: &mut T) -> Result<()> {
+        loop {
+            let data = match transport.recv().await {
+                Ok(data) => data,
+                Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let packet = Packet::decode(data)?;
+            if let Some(reply) = self.dispatch(&packet).await {
+                transport.send(&reply.encode()).await?;
+            }
+        }
+    }
+}
+
+/// The error reply to `call`, carrying the `remote_error` for `error`.
+pub fn error_reply(call: &Packet, error: ServiceError) -> Packet {
+    let error = remote_error(call, error);
+    // Only fails for messages over REMOTE_STRING_MAX; the client then
+    // still sees an error status
+    let payload = libvirt_xdr::to_bytes(&error).unwrap_or_default();
+    Packet::new_reply(call, Status::Error, Bytes::from(payload))
+}
+
+/// The `remote_error` libvirtd would send for `error` in reply to `call`.
+fn remote_error(call: &Packet, error: ServiceError) -> RemoteError {
+    let procedure = procedure_info(call.program, call.procedure);
+    let name = match procedure {
+        Some(procedure) => procedure.name.to_string(),
+        None => format!("procedure {} of program {:#x}", call.procedure, call.program),
+    };
+    match error {
+        ServiceError::Unsupported { .. } if procedure.is_some() => RemoteError::new(
+            generated::VIR_ERR_NO_SUPPORT,
+            generated::VIR_FROM_RPC,
+            format!("this function is not supported by the connection driver: {}", name),
+        ),
+        ServiceError::Unsupported { .. } => RemoteError::new(
+            generated::VIR_ERR_NO_SUPPORT,
+            generated::VIR_FROM_RPC,
+            format!("unknown {}", name),
+        ),
+        ServiceError::Decode(e) => RemoteError::new(
+            generated::VIR_ERR_RPC,
+            generated::VIR_FROM_RPC,
+            format!("cannot decode the arguments of {}: {}", name, e),
+        ),
+        ServiceError::Encode(e) => RemoteError::new(
+            generated::VIR_ERR_RPC,
+            generated::VIR_FROM_RPC,
+            format!("cannot encode the reply to {}: {}", name, e),
+        ),
+        ServiceError::Server(error) => *error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::{ConnectGetHostnameRet, ConnectGetMaxVcpusArgs, ConnectGetMaxVcpusRet};
+
+    struct Fake;
+
+    impl RemoteService for Fake {
+        async fn connect_get_hostname(&self) -> std::result::Result<ConnectGetHostnameRet, ServiceError> {
+            Ok(ConnectGetHostnameRet { hostname: "fake".to_string() })
+        }
+
+        async fn connect_get_max_vcpus(
+            &self,
+            args: ConnectGetMaxVcpusArgs,
+        ) -> std::result::Result<ConnectGetMaxVcpusRet, ServiceError> {
+            match args.r#type.as_deref() {
+                Some("kvm") => Ok(ConnectGetMaxVcpusRet { max_vcpus: 4096 }),
+                _ => Err(RemoteError::new(
+                    generated::VIR_ERR_INVALID_ARG,
+                    generated::VIR_FROM_TEST,
+                    "unknown type",
+                )
+                .into()),
+            }
+        }
+    }
+
+    impl QemuService for Fake {}
+    impl LxcService for Fake {}
+
+    async fn call(procedure: u32, payload: Vec<u8>) -> Packet {
+        let call = Packet::new_call(procedure, 7, Bytes::from(payload));
+        let reply = Dispatcher::new(Fake).dispatch(&call).await.unwrap();
+        assert_eq!(reply.msg_type, MessageType::Reply);
+        assert_eq!((reply.procedure, reply.serial), (procedure, 7));
+        reply
+    }
+
+    fn decode_error(reply: &Packet) -> RemoteError {
+        assert_eq!(reply.status, Status::Error);
+        libvirt_xdr::from_bytes(&reply.payload).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let reply = call(59, Vec::new()).await;
+        assert_eq!(reply.status, Status::Ok);
+        let ret: ConnectGetHostnameRet = libvirt_xdr::from_bytes(&reply.payload).unwrap();
+        assert_eq!(ret.hostname, "fake");
+
+        let args = ConnectGetMaxVcpusArgs { r#type: Some("kvm".to_string()) };
+        let reply = call(5, libvirt_xdr::to_bytes(&args).unwrap()).await;
+        let ret: ConnectGetMaxVcpusRet = libvirt_xdr::from_bytes(&reply.payload).unwrap();
+        assert_eq!(ret.max_vcpus, 4096);
+
+        let args = ConnectGetMaxVcpusArgs { r#type: None };
+        let error = decode_error(&call(5, libvirt_xdr::to_bytes(&args).unwrap()).await);
+        assert_eq!(error.code, generated::VIR_ERR_INVALID_ARG);
+        assert_eq!(error.message.as_deref(), Some("unknown type"));
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_errors() {
+        // Not implemented by the service
+        let error = decode_error(&call(3, Vec::new()).await);
+        assert_eq!(error.code, generated::VIR_ERR_NO_SUPPORT);
+        assert!(error.message.unwrap().ends_with("REMOTE_PROC_CONNECT_GET_TYPE"));
+
+        // Truncated arguments
+        let error = decode_error(&call(5, vec![0, 0]).await);
+        assert_eq!(error.code, generated::VIR_ERR_RPC);
+
+        // Unknown program
+        let packet = Packet::new_call_program(0x1234, 1, 1, Bytes::new());
+        let reply = Dispatcher::new(Fake).dispatch(&packet).await.unwrap();
+        assert_eq!(decode_error(&reply).code, generated::VIR_ERR_NO_SUPPORT);
+
+        // Replies are not answered
+        let reply = Packet::new_reply(&packet, Status::Ok, Bytes::new());
+        assert!(Dispatcher::new(Fake).dispatch(&reply).await.is_none());
+    }
+}
@@ -0,0 +1,420 @@
+//! Domain snapshot handles and snapshot trees.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::error::{missing_as_none, Error, Result};
+use crate::generated::{
+    DomainSnapshotCreateFlags, DomainSnapshotDeleteFlags, DomainSnapshotListFlags, DomainSnapshotRevertFlags,
+    DomainSnapshotXmlFlags, NonnullDomainSnapshot, VIR_ERR_NO_DOMAIN_SNAPSHOT,
+};
+use crate::handle::handle;
+use crate::xml::escape;
+use crate::Domain;
+
+/// Where a snapshot keeps the state of the memory or of a disk, the
+/// `snapshot` attribute of their elements.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SnapshotLocation {
+    /// Not in the snapshot.
+    No,
+    /// In the disk image itself, e.g. a qcow2 internal snapshot.
+    Internal,
+    /// In a file of its own, at the path given or one libvirt picks.
+    External(Option<String>),
+}
+
+impl SnapshotLocation {
+    fn to_xml(&self, element: &str, attrs: &str) -> String {
+        match self {
+            Self::No => format!("<{}{} snapshot='no'/>", element, attrs),
+            Self::Internal => format!("<{}{} snapshot='internal'/>", element, attrs),
+            Self::External(None) => format!("<{}{} snapshot='external'/>", element, attrs),
+            Self::External(Some(file)) if element == "memory" => {
+                format!("<memory snapshot='external' file='{}'/>", escape(file))
+            }
+            Self::External(Some(file)) => format!(
+                "<{}{} snapshot='external'><source file='{}'/></{}>",
+                element,
+                attrs,
+                escape(file),
+                element
+            ),
+        }
+    }
+}
+
+/// Builder of the `<domainsnapshot>` XML [`Domain::create_snapshot`]
+/// takes. What is left unset is up to libvirt, e.g. the name defaults to
+/// the creation time.
+///
+/// ```ignore
+/// let xml = DomainSnapshotXml::new()
+///     .name("before-upgrade")
+///     .memory(SnapshotLocation::No)
+///     .disk("vda", SnapshotLocation::External(Some("/var/lib/libvirt/images/vm1.upgrade".into())));
+/// let snapshot = domain.create_snapshot(&xml, DomainSnapshotCreateFlags::DISK_ONLY).await?;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DomainSnapshotXml {
+    name: Option<String>,
+    description: Option<String>,
+    memory: Option<SnapshotLocation>,
+    disks: Vec<(String, SnapshotLocation)>,
+}
+
+impl DomainSnapshotXml {
+    /// An empty description, leaving everything to libvirt.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name the snapshot.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Describe the snapshot.
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Where to keep the memory of the running domain.
+    pub fn memory(mut self, location: SnapshotLocation) -> Self {
+        self.memory = Some(location);
+        self
+    }
+
+    /// Where to keep the disk `target` (`vda`, ...). Disks not given
+    /// follow their `<disk>` definition.
+    pub fn disk(mut self, target: impl Into<String>, location: SnapshotLocation) -> Self {
+        self.disks.push((target.into(), location));
+        self
+    }
+
+    /// The XML document.
+    pub fn to_xml(&self) -> String {
+        let mut xml = "<domainsnapshot>".to_string();
+        if let Some(name) = &self.name {
+            xml += &format!("<name>{}</name>", escape(name));
+        }
+        if let Some(description) = &self.description {
+            xml += &format!("<description>{}</description>", escape(description));
+        }
+        if let Some(memory) = &self.memory {
+            xml += &memory.to_xml("memory", "");
+        }
+        if !self.disks.is_empty() {
+            xml += "<disks>";
+            for (target, location) in &self.disks {
+                xml += &location.to_xml("disk", &format!(" name='{}'", escape(target)));
+            }
+            xml += "</disks>";
+        }
+        xml += "</domainsnapshot>";
+        xml
+    }
+}
+
+handle! {
+    /// A snapshot of a [`Domain`], like libvirt's `virDomainSnapshotPtr`.
+    ///
+    /// Snapshots form a tree: each one taken while another was current is
+    /// its child. [`Domain::snapshot_tree`] fetches the whole tree.
+    pub struct DomainSnapshot(NonnullDomainSnapshot) {
+        what: "domain snapshot",
+        wrap: domain_snapshot,
+        debug: { domain: dom.name, name: name },
+        eq: [dom.uuid, name],
+    }
+}
+
+impl DomainSnapshot {
+    /// Name of the snapshot, unique among the domain's.
+    pub fn name(&self) -> &str {
+        &self.raw.name
+    }
+
+    /// The domain of the snapshot.
+    pub fn domain(&self) -> Domain {
+        self.client.domain(self.raw.dom.clone())
+    }
+
+    /// XML description of the snapshot, including the domain's definition
+    /// when it was taken.
+    pub async fn xml_desc(&self, flags: DomainSnapshotXmlFlags) -> Result<String> {
+        Ok(self.client.api().domain_snapshot_get_xml_desc(&self.raw, flags).await?)
+    }
+
+    /// The snapshot this one was taken from, if any.
+    pub async fn parent(&self) -> Result<Option<DomainSnapshot>> {
+        let parent = self.client.api().domain_snapshot_get_parent(&self.raw, 0).await;
+        Ok(missing_as_none(parent, VIR_ERR_NO_DOMAIN_SNAPSHOT)?.map(|raw| self.client.domain_snapshot(raw)))
+    }
+
+    /// The snapshots taken from this one, or with
+    /// `DomainSnapshotListFlags::DESCENDANTS` from it or its descendants,
+    /// filtered by the other flags.
+    pub async fn children(&self, flags: DomainSnapshotListFlags) -> Result<Vec<DomainSnapshot>> {
+        let ret = self.client.api().domain_snapshot_list_all_children(&self.raw, 1, flags).await?;
+        Ok(ret.snapshots.into_iter().map(|raw| self.client.domain_snapshot(raw)).collect())
+    }
+
+    /// Whether the snapshot is the domain's current one, the parent of the
+    /// next.
+    pub async fn is_current(&self) -> Result<bool> {
+        Ok(self.client.api().domain_snapshot_is_current(&self.raw, 0).await?)
+    }
+
+    /// Whether libvirt has metadata for the snapshot, without which it
+    /// cannot revert to it.
+    pub async fn has_metadata(&self) -> Result<bool> {
+        Ok(self.client.api().domain_snapshot_has_metadata(&self.raw, 0).await?)
+    }
+
+    /// Bring the domain back to the snapshot, running or paused as when it
+    /// was taken unless the flags say otherwise.
+    pub async fn revert(&self, flags: DomainSnapshotRevertFlags) -> Result<()> {
+        Ok(self.client.api().domain_revert_to_snapshot(&self.raw, flags).await?)
+    }
+
+    /// Delete the snapshot and, as the flags say, its children, or only
+    /// its metadata.
+    pub async fn delete(&self, flags: DomainSnapshotDeleteFlags) -> Result<()> {
+        Ok(self.client.api().domain_snapshot_delete(&self.raw, flags).await?)
+    }
+}
+
+/// A snapshot and the subtree of those taken from it, as built by
+/// [`Domain::snapshot_tree`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotNode {
+    /// The snapshot.
+    pub snapshot: DomainSnapshot,
+    /// The subtrees of its children, oldest first.
+    pub children: Vec<SnapshotNode>,
+}
+
+impl SnapshotNode {
+    /// The snapshots of the subtree with their depth below this node,
+    /// parents first, like `virsh snapshot-list --tree` prints them.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &DomainSnapshot)> {
+        let mut stack = vec![(0, self)];
+        std::iter::from_fn(move || {
+            let (depth, node) = stack.pop()?;
+            stack.extend(node.children.iter().rev().map(|child| (depth + 1, child)));
+            Some((depth, &node.snapshot))
+        })
+    }
+
+    /// The node of the snapshot `name` in the subtree.
+    pub fn find(&self, name: &str) -> Option<&SnapshotNode> {
+        if self.snapshot.name() == name {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find(name))
+    }
+}
+
+impl Domain {
+    /// Take a snapshot as `xml` describes it.
+    pub async fn create_snapshot(
+        &self,
+        xml: &DomainSnapshotXml,
+        flags: DomainSnapshotCreateFlags,
+    ) -> Result<DomainSnapshot> {
+        self.create_snapshot_xml(&xml.to_xml(), flags).await
+    }
+
+    /// Take a snapshot from its `<domainsnapshot>` XML.
+    pub async fn create_snapshot_xml(&self, xml: &str, flags: DomainSnapshotCreateFlags) -> Result<DomainSnapshot> {
+        let raw = self.client().api().domain_snapshot_create_xml(self.raw(), xml, flags).await?;
+        Ok(self.client().domain_snapshot(raw))
+    }
+
+    /// List the snapshots of the domain, filtered by the flags, e.g. only
+    /// the roots of the tree.
+    pub async fn list_snapshots(&self, flags: DomainSnapshotListFlags) -> Result<Vec<DomainSnapshot>> {
+        let ret = self.client().api().domain_list_all_snapshots(self.raw(), 1, flags).await?;
+        Ok(ret.snapshots.into_iter().map(|raw| self.client().domain_snapshot(raw)).collect())
+    }
+
+    /// Look up a snapshot of the domain by name.
+    pub async fn lookup_snapshot_by_name(&self, name: &str) -> Result<DomainSnapshot> {
+        let raw = self.client().api().domain_snapshot_lookup_by_name(self.raw(), name, 0).await?;
+        Ok(self.client().domain_snapshot(raw))
+    }
+
+    /// The current snapshot, the parent of the next one, if any.
+    pub async fn current_snapshot(&self) -> Result<Option<DomainSnapshot>> {
+        let current = self.client().api().domain_snapshot_current(self.raw(), 0).await;
+        Ok(missing_as_none(current, VIR_ERR_NO_DOMAIN_SNAPSHOT)?.map(|raw| self.client().domain_snapshot(raw)))
+    }
+
+    /// Fetch the snapshots of the domain as trees, one per root snapshot.
+    ///
+    /// This takes a call per snapshot, to list its children: the list of
+    /// all snapshots does not say who their parents are.
+    pub async fn snapshot_tree(&self) -> Result<Vec<SnapshotNode>> {
+        let all = self.list_snapshots(DomainSnapshotListFlags::TOPOLOGICAL).await?;
+        let mut children = HashMap::new();
+        for snapshot in &all {
+            let names: Vec<String> = snapshot
+                .children(DomainSnapshotListFlags::empty())
+                .await?
+                .iter()
+                .map(|child| child.name().to_string())
+                .collect();
+            children.insert(snapshot.name().to_string(), names);
+        }
+
+        let is_child: HashSet<&str> = children.values().flatten().map(String::as_str).collect();
+        let roots: Vec<String> = all
+            .iter()
+            .map(|s| s.name().to_string())
+            .filter(|name| !is_child.contains(name.as_str()))
+            .collect();
+        let mut snapshots: HashMap<String, DomainSnapshot> =
+            all.into_iter().map(|s| (s.name().to_string(), s)).collect();
+        let tree = roots
+            .iter()
+            .map(|root| build_node(root, &mut snapshots, &children))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Snapshots no root reaches are children of each other
+        let mut left: Vec<&str> = snapshots.keys().map(String::as_str).collect();
+        if !left.is_empty() {
+            left.sort_unstable();
+            return Err(Error::Protocol(format!(
+                "snapshots {} are in a cycle of the snapshot tree",
+                left.join(", ")
+            )));
+        }
+        Ok(tree)
+    }
+}
+
+/// The subtree of the snapshot `name`, taking its snapshots out of
+/// `snapshots`.
+///
+/// Fails if the listings don't make a tree, e.g. with a snapshot listed
+/// as the child of two others.
+fn build_node(
+    name: &str,
+    snapshots: &mut HashMap<String, DomainSnapshot>,
+    children: &HashMap<String, Vec<String>>,
+) -> Result<SnapshotNode> {
+    let snapshot = snapshots
+        .remove(name)
+        .ok_or_else(|| Error::Protocol(format!("snapshot {} is listed twice in the snapshot tree", name)))?;
+    let mut nodes = Vec::new();
+    for child in children.get(name).into_iter().flatten() {
+        // A snapshot created since the listing is left out of the tree
+        if children.contains_key(child) {
+            nodes.push(build_node(child, snapshots, children)?);
+        }
+    }
+    Ok(SnapshotNode { snapshot, children: nodes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::{
+        DomainListAllSnapshotsRet, DomainSnapshotListAllChildrenArgs, DomainSnapshotListAllChildrenRet, VIR_DOMAIN_SHUTOFF,
+    };
+    use crate::testing::FakeHypervisor;
+
+    #[tokio::test]
+    async fn test_snapshot_tree() {
+        let client = FakeHypervisor::new().client().await.unwrap();
+        let domain = client.lookup_domain_by_name("test").await.unwrap();
+        assert_eq!(domain.current_snapshot().await.unwrap(), None);
+
+        // s1 -> s2 -> s3, and s1 -> s4 after reverting to s1
+        let flags = DomainSnapshotCreateFlags::empty();
+        let s1 = domain.create_snapshot(&DomainSnapshotXml::new().name("s1"), flags).await.unwrap();
+        let s2 = domain.create_snapshot(&DomainSnapshotXml::new().name("s2"), flags).await.unwrap();
+        domain.destroy().await.unwrap();
+        let xml = DomainSnapshotXml::new().name("s3").description("off & <clean>");
+        let s3 = domain.create_snapshot(&xml, flags).await.unwrap();
+        assert!(s3.xml_desc(DomainSnapshotXmlFlags::empty()).await.unwrap().contains("off &amp; &lt;clean&gt;"));
+        s1.revert(DomainSnapshotRevertFlags::empty()).await.unwrap();
+        let s4 = domain.create_snapshot(&DomainSnapshotXml::new().name("s4"), flags).await.unwrap();
+
+        assert_eq!(s1.parent().await.unwrap(), None);
+        assert_eq!(s4.parent().await.unwrap().as_ref(), Some(&s1));
+        assert_eq!(s1.children(DomainSnapshotListFlags::empty()).await.unwrap(), [s2.clone(), s4.clone()]);
+        let descendants = s1.children(DomainSnapshotListFlags::DESCENDANTS).await.unwrap();
+        assert_eq!(descendants.len(), 3);
+        let leaves = domain.list_snapshots(DomainSnapshotListFlags::LEAVES).await.unwrap();
+        assert_eq!(leaves, [s3.clone(), s4.clone()]);
+        assert!(s4.is_current().await.unwrap());
+
+        let tree = domain.snapshot_tree().await.unwrap();
+        assert_eq!(tree.len(), 1);
+        let names: Vec<_> = tree[0].iter().map(|(depth, s)| (depth, s.name())).collect();
+        assert_eq!(names, [(0, "s1"), (1, "s2"), (2, "s3"), (1, "s4")]);
+        assert_eq!(tree[0].find("s2").unwrap().children[0].snapshot, s3);
+
+        s3.revert(DomainSnapshotRevertFlags::empty()).await.unwrap();
+        assert_eq!(domain.state().await.unwrap().state, VIR_DOMAIN_SHUTOFF);
+        s2.delete(DomainSnapshotDeleteFlags::CHILDREN).await.unwrap();
+        assert_eq!(domain.current_snapshot().await.unwrap().as_ref(), Some(&s1));
+        let tree = domain.snapshot_tree().await.unwrap();
+        let names: Vec<_> = tree[0].iter().map(|(_, s)| s.name()).collect();
+        assert_eq!(names, ["s1", "s4"]);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_tree_inconsistent() {
+        // Every snapshot claims s2 as a child, s2 included
+        let fake = FakeHypervisor::new();
+        let domain = fake.client().await.unwrap().lookup_domain_by_name("test").await.unwrap();
+        let snapshot = |name: &str| NonnullDomainSnapshot {
+            name: name.to_string(),
+            dom: domain.raw().clone(),
+        };
+        fake.daemon().respond("domain_list_all_snapshots", DomainListAllSnapshotsRet {
+            snapshots: vec![snapshot("s1"), snapshot("s2")],
+            ret: 2,
+        });
+        fake.daemon().respond("domain_snapshot_list_all_children", DomainSnapshotListAllChildrenRet {
+            snapshots: vec![snapshot("s2")],
+            ret: 1,
+        });
+        match domain.snapshot_tree().await {
+            Err(Error::Protocol(message)) => assert!(message.contains("s2"), "{}", message),
+            other => panic!("unexpected tree: {:?}", other),
+        }
+
+        // s1 and s2 are each other's child, so neither is a root
+        let dom = domain.raw().clone();
+        fake.daemon().on("domain_snapshot_list_all_children", move |args: DomainSnapshotListAllChildrenArgs| {
+            let other = if args.snapshot.name == "s1" { "s2" } else { "s1" };
+            let snapshot = NonnullDomainSnapshot { name: other.to_string(), dom: dom.clone() };
+            Ok(DomainSnapshotListAllChildrenRet { snapshots: vec![snapshot], ret: 1 })
+        });
+        match domain.snapshot_tree().await {
+            Err(Error::Protocol(message)) => assert!(message.contains("s1, s2"), "{}", message),
+            other => panic!("unexpected tree: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_xml() {
+        let xml = DomainSnapshotXml::new()
+            .name("backup")
+            .memory(SnapshotLocation::External(Some("/snap/mem".into())))
+            .disk("vda", SnapshotLocation::External(Some("/snap/vda".into())))
+            .disk("vdb", SnapshotLocation::No)
+            .to_xml();
+        assert_eq!(
+            xml,
+            "<domainsnapshot><name>backup</name><memory snapshot='external' file='/snap/mem'/>\
+             <disks><disk name='vda' snapshot='external'><source file='/snap/vda'/></disk>\
+             <disk name='vdb' snapshot='no'/></disks></domainsnapshot>"
+        );
+    }
+}
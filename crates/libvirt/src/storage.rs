@@ -0,0 +1,568 @@
+//! Storage pool and volume handles.
+
+use std::io::SeekFrom;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+use crate::connection::StreamChunk;
+use crate::error::Result;
+use crate::generated::{
+    ConnectListAllStoragePoolsFlags, LibvirtRpc, NonnullStoragePool, NonnullStorageVol, StoragePoolBuildFlags,
+    StoragePoolCreateFlags, StoragePoolDeleteFlags, StoragePoolGetInfoRet, StorageVolCreateFlags,
+    StorageVolDeleteFlags, StorageVolDownloadFlags, StorageVolGetInfoRet, StorageVolResizeFlags,
+    StorageVolUploadFlags, StorageXmlFlags, StreamHandle, Uuid, VIR_STORAGE_VOL_WIPE_ALG_BSI,
+    VIR_STORAGE_VOL_WIPE_ALG_DOD, VIR_STORAGE_VOL_WIPE_ALG_GUTMANN, VIR_STORAGE_VOL_WIPE_ALG_NNSA,
+    VIR_STORAGE_VOL_WIPE_ALG_PFITZNER33, VIR_STORAGE_VOL_WIPE_ALG_PFITZNER7, VIR_STORAGE_VOL_WIPE_ALG_RANDOM,
+    VIR_STORAGE_VOL_WIPE_ALG_SCHNEIER, VIR_STORAGE_VOL_WIPE_ALG_TRIM, VIR_STORAGE_VOL_WIPE_ALG_ZERO,
+};
+use crate::handle::handle;
+use crate::xml::escape;
+use crate::{Client, Connection};
+
+/// Data sent per stream packet when uploading, libvirt's
+/// `VIR_NET_MESSAGE_LEGACY_PAYLOAD_MAX`.
+const STREAM_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Size of the blocks of zeros [`StorageVol::upload`] sends as holes.
+const HOLE_BLOCK_SIZE: usize = 4096;
+
+/// How [`StorageVol::wipe_pattern`] overwrites a volume, libvirt's
+/// `virStorageVolWipeAlgorithm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageVolWipeAlgorithm {
+    /// 1 pass of zeros.
+    Zero,
+    /// 4 passes, NNSA Policy Letter NAP-14.1-C (XVI-8).
+    Nnsa,
+    /// 4 passes, DoD 5220.22-M section 8-306.
+    Dod,
+    /// 9 passes, as recommended by the German Center of Security in
+    /// Information Technologies.
+    Bsi,
+    /// The canonical 35 passes.
+    Gutmann,
+    /// 7 passes, as described by Bruce Schneier.
+    Schneier,
+    /// 7 random passes.
+    Pfitzner7,
+    /// 33 random passes.
+    Pfitzner33,
+    /// 1 random pass.
+    Random,
+    /// Discard the data of the underlying storage, if it supports it.
+    Trim,
+}
+
+impl From<StorageVolWipeAlgorithm> for u32 {
+    fn from(algorithm: StorageVolWipeAlgorithm) -> Self {
+        let value = match algorithm {
+            StorageVolWipeAlgorithm::Zero => VIR_STORAGE_VOL_WIPE_ALG_ZERO,
+            StorageVolWipeAlgorithm::Nnsa => VIR_STORAGE_VOL_WIPE_ALG_NNSA,
+            StorageVolWipeAlgorithm::Dod => VIR_STORAGE_VOL_WIPE_ALG_DOD,
+            StorageVolWipeAlgorithm::Bsi => VIR_STORAGE_VOL_WIPE_ALG_BSI,
+            StorageVolWipeAlgorithm::Gutmann => VIR_STORAGE_VOL_WIPE_ALG_GUTMANN,
+            StorageVolWipeAlgorithm::Schneier => VIR_STORAGE_VOL_WIPE_ALG_SCHNEIER,
+            StorageVolWipeAlgorithm::Pfitzner7 => VIR_STORAGE_VOL_WIPE_ALG_PFITZNER7,
+            StorageVolWipeAlgorithm::Pfitzner33 => VIR_STORAGE_VOL_WIPE_ALG_PFITZNER33,
+            StorageVolWipeAlgorithm::Random => VIR_STORAGE_VOL_WIPE_ALG_RANDOM,
+            StorageVolWipeAlgorithm::Trim => VIR_STORAGE_VOL_WIPE_ALG_TRIM,
+        };
+        value as u32
+    }
+}
+
+handle! {
+    /// A storage pool of a [`Client`]'s host, like libvirt's
+    /// `virStoragePoolPtr`.
+    pub struct StoragePool(NonnullStoragePool) {
+        what: "storage pool",
+        wrap: storage_pool,
+        debug: { name: name, uuid: uuid },
+        eq: [uuid],
+    }
+}
+
+impl StoragePool {
+    /// Name of the pool.
+    pub fn name(&self) -> &str {
+        &self.raw.name
+    }
+
+    /// UUID of the pool.
+    pub fn uuid(&self) -> &Uuid {
+        &self.raw.uuid
+    }
+
+    /// State (`VIR_STORAGE_POOL_RUNNING`, ...), capacity, allocation and
+    /// available space of the pool, in bytes.
+    pub async fn info(&self) -> Result<StoragePoolGetInfoRet> {
+        Ok(self.client.api().storage_pool_get_info(&self.raw).await?)
+    }
+
+    /// XML description of the pool.
+    pub async fn xml_desc(&self, flags: StorageXmlFlags) -> Result<String> {
+        Ok(self.client.api().storage_pool_get_xml_desc(&self.raw, flags).await?)
+    }
+
+    /// Whether the pool is running.
+    pub async fn is_active(&self) -> Result<bool> {
+        Ok(self.client.api().storage_pool_is_active(&self.raw).await?)
+    }
+
+    /// Whether the pool has a persistent definition.
+    pub async fn is_persistent(&self) -> Result<bool> {
+        Ok(self.client.api().storage_pool_is_persistent(&self.raw).await?)
+    }
+
+    /// Build the pool's underlying storage, e.g. make its directory or
+    /// format its disk.
+    pub async fn build(&self, flags: StoragePoolBuildFlags) -> Result<()> {
+        Ok(self.client.api().storage_pool_build(&self.raw, flags).await?)
+    }
+
+    /// Start the defined pool, building it first if the flags say so.
+    pub async fn create(&self, flags: StoragePoolCreateFlags) -> Result<()> {
+        Ok(self.client.api().storage_pool_create(&self.raw, flags).await?)
+    }
+
+    /// Stop the pool. Its data is left alone.
+    pub async fn destroy(&self) -> Result<()> {
+        Ok(self.client.api().storage_pool_destroy(&self.raw).await?)
+    }
+
+    /// Delete the pool's underlying storage, the opposite of
+    /// [`build`](Self::build).
+    pub async fn delete(&self, flags: StoragePoolDeleteFlags) -> Result<()> {
+        Ok(self.client.api().storage_pool_delete(&self.raw, flags).await?)
+    }
+
+    /// Remove the persistent definition of the pool.
+    pub async fn undefine(&self) -> Result<()> {
+        Ok(self.client.api().storage_pool_undefine(&self.raw).await?)
+    }
+
+    /// Rescan the pool for volumes added or removed behind libvirt's back.
+    pub async fn refresh(&self) -> Result<()> {
+        Ok(self.client.api().storage_pool_refresh(&self.raw, 0).await?)
+    }
+
+    /// Whether the pool starts when the host boots.
+    pub async fn autostart(&self) -> Result<bool> {
+        Ok(self.client.api().storage_pool_get_autostart(&self.raw).await?)
+    }
+
+    /// Set whether the pool starts when the host boots.
+    pub async fn set_autostart(&self, autostart: bool) -> Result<()> {
+        Ok(self.client.api().storage_pool_set_autostart(&self.raw, autostart).await?)
+    }
+
+    /// List the volumes of the pool.
+    pub async fn volumes(&self) -> Result<Vec<StorageVol>> {
+        let ret = self.client.api().storage_pool_list_all_volumes(&self.raw, 1, 0).await?;
+        Ok(ret.vols.into_iter().map(|raw| self.client.storage_vol(raw)).collect())
+    }
+
+    /// Look up a volume of the pool by name.
+    pub async fn lookup_volume_by_name(&self, name: &str) -> Result<StorageVol> {
+        let raw = self.client.api().storage_vol_lookup_by_name(&self.raw, name).await?;
+        Ok(self.client.storage_vol(raw))
+    }
+
+    /// Create a volume in the pool from its XML.
+    pub async fn create_volume(&self, xml: &str, flags: StorageVolCreateFlags) -> Result<StorageVol> {
+        let raw = self.client.api().storage_vol_create_xml(&self.raw, xml, flags).await?;
+        Ok(self.client.storage_vol(raw))
+    }
+
+    /// Create a volume in the pool from its XML, with the contents of
+    /// `source`, which may be in another pool.
+    pub async fn create_volume_from(
+        &self,
+        xml: &str,
+        source: &StorageVol,
+        flags: StorageVolCreateFlags,
+    ) -> Result<StorageVol> {
+        let raw = self.client.api().storage_vol_create_xml_from(&self.raw, xml, &source.raw, flags).await?;
+        Ok(self.client.storage_vol(raw))
+    }
+}
+
+handle! {
+    /// A volume of a [`StoragePool`], like libvirt's `virStorageVolPtr`.
+    ///
+    /// ```ignore
+    /// let pool = client.lookup_storage_pool_by_name("default").await?;
+    /// let vol = pool.lookup_volume_by_name("base.qcow2").await?;
+    /// let file = tokio::fs::File::create("base.qcow2").await?;
+    /// vol.download(file, StorageVolDownloadFlags::SPARSE_STREAM).await?;
+    /// ```
+    pub struct StorageVol(NonnullStorageVol) {
+        what: "storage volume",
+        wrap: storage_vol,
+        debug: { pool: pool, name: name, key: key },
+        eq: [key],
+    }
+}
+
+impl StorageVol {
+    /// Name of the volume, unique in its pool.
+    pub fn name(&self) -> &str {
+        &self.raw.name
+    }
+
+    /// Key of the volume, unique on the host, e.g. its path.
+    pub fn key(&self) -> &str {
+        &self.raw.key
+    }
+
+    /// Name of the pool of the volume.
+    pub fn pool_name(&self) -> &str {
+        &self.raw.pool
+    }
+
+    /// The pool of the volume.
+    pub async fn pool(&self) -> Result<StoragePool> {
+        let raw = self.client.api().storage_pool_lookup_by_volume(&self.raw).await?;
+        Ok(self.client.storage_pool(raw))
+    }
+
+    /// Type (`VIR_STORAGE_VOL_FILE`, ...), capacity and allocation of the
+    /// volume, in bytes.
+    pub async fn info(&self) -> Result<StorageVolGetInfoRet> {
+        Ok(self.client.api().storage_vol_get_info(&self.raw).await?)
+    }
+
+    /// Path of the volume on the host.
+    pub async fn path(&self) -> Result<String> {
+        Ok(self.client.api().storage_vol_get_path(&self.raw).await?)
+    }
+
+    /// XML description of the volume.
+    pub async fn xml_desc(&self) -> Result<String> {
+        Ok(self.client.api().storage_vol_get_xml_desc(&self.raw, 0).await?)
+    }
+
+    /// Delete the volume and its data.
+    pub async fn delete(&self, flags: StorageVolDeleteFlags) -> Result<()> {
+        Ok(self.client.api().storage_vol_delete(&self.raw, flags).await?)
+    }
+
+    /// Change the capacity of the volume, in bytes.
+    pub async fn resize(&self, capacity: u64, flags: StorageVolResizeFlags) -> Result<()> {
+        Ok(self.client.api().storage_vol_resize(&self.raw, capacity, flags).await?)
+    }
+
+    /// Overwrite the data of the volume with zeros.
+    pub async fn wipe(&self) -> Result<()> {
+        Ok(self.client.api().storage_vol_wipe(&self.raw, 0).await?)
+    }
+
+    /// Overwrite the data of the volume by the given algorithm.
+    pub async fn wipe_pattern(&self, algorithm: StorageVolWipeAlgorithm) -> Result<()> {
+        Ok(self.client.api().storage_vol_wipe_pattern(&self.raw, algorithm.into(), 0).await?)
+    }
+
+    /// Copy the volume to a new volume `name` of the same pool, like
+    /// `virsh vol-clone`.
+    pub async fn clone_as(&self, name: &str, flags: StorageVolCreateFlags) -> Result<StorageVol> {
+        // The other settings, capacity included, are taken from the source
+        let xml = format!("<volume><name>{}</name></volume>", escape(name));
+        let pool = self.client.api().storage_pool_lookup_by_volume(&self.raw).await?;
+        let raw = self.client.api().storage_vol_create_xml_from(&pool, &xml, &self.raw, flags).await?;
+        Ok(self.client.storage_vol(raw))
+    }
+
+    /// Replace the data of the volume with the contents of `reader`,
+    /// returning the number of bytes read.
+    ///
+    /// With `SPARSE_STREAM`, runs of zeros are sent as holes, so they
+    /// neither cross the network nor take space in volumes that can be
+    /// sparse. Daemons older than libvirt 3.4 don't support it.
+    pub async fn upload<R: AsyncRead + Unpin>(&self, mut reader: R, flags: StorageVolUploadFlags) -> Result<u64> {
+        let conn = self.client.connection();
+        let stream = self.client.api().storage_vol_upload(&self.raw, 0, 0, flags).await?;
+        let sparse = flags.contains(StorageVolUploadFlags::SPARSE_STREAM);
+        match send_sparse(conn, &stream, &mut reader, sparse).await {
+            Ok(length) => {
+                conn.stream_finish(&stream).await?;
+                Ok(length)
+            }
+            Err(e) => {
+                let _ = conn.stream_abort(&stream).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Write the data of the volume to `writer` from its current position,
+    /// returning the number of bytes of the volume.
+    ///
+    /// With `SPARSE_STREAM`, holes in the volume come as such: those past
+    /// the end of the writer's data are seeked over, so new files stay
+    /// sparse, and those over existing data are written as zeros. Data the
+    /// writer has past the end of the volume's is left as it was.
+    pub async fn download<W: AsyncWrite + AsyncSeek + Unpin>(
+        &self,
+        mut writer: W,
+        flags: StorageVolDownloadFlags,
+    ) -> Result<u64> {
+        let conn = self.client.connection();
+        let stream = self.client.api().storage_vol_download(&self.raw, 0, 0, flags).await?;
+        let result = recv_sparse(conn, &stream, &mut writer).await;
+        if result.is_err() {
+            let _ = conn.stream_abort(&stream).await;
+        }
+        result
+    }
+}
+
+/// Send the contents of `reader` on `stream`, block-sized runs of zeros
+/// as holes if `sparse`.
+async fn send_sparse<R: AsyncRead + Unpin>(
+    conn: &Connection,
+    stream: &StreamHandle,
+    reader: &mut R,
+    sparse: bool,
+) -> Result<u64> {
+    let mut buf = vec![0; STREAM_CHUNK_SIZE];
+    let mut total = 0;
+    // Zeros not sent yet: holes can span reads
+    let mut hole = 0;
+    loop {
+        let len = read_chunk(reader, &mut buf).await?;
+        if len == 0 {
+            break;
+        }
+        total += len as u64;
+        if !sparse {
+            conn.stream_send(stream, buf[..len].to_vec()).await?;
+            continue;
+        }
+
+        let mut data = 0..0;
+        for (i, block) in buf[..len].chunks(HOLE_BLOCK_SIZE).enumerate() {
+            let start = i * HOLE_BLOCK_SIZE;
+            if block.iter().all(|&b| b == 0) {
+                if !data.is_empty() {
+                    conn.stream_send(stream, buf[data.clone()].to_vec()).await?;
+                }
+                data = start + block.len()..start + block.len();
+                hole += block.len() as u64;
+            } else {
+                if hole > 0 {
+                    conn.send_stream_hole(stream, hole).await?;
+                    hole = 0;
+                }
+                data.end = start + block.len();
+            }
+        }
+        if !data.is_empty() {
+            conn.stream_send(stream, buf[data].to_vec()).await?;
+        }
+    }
+    if hole > 0 {
+        conn.send_stream_hole(stream, hole).await?;
+    }
+    Ok(total)
+}
+
+/// Fill `buf` from `reader`, short only at the end of its data.
+async fn read_chunk<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]).await? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+/// Write the data received on `stream` to `writer`, seeking over holes
+/// past the end of its data.
+async fn recv_sparse<W: AsyncWrite + AsyncSeek + Unpin>(
+    conn: &Connection,
+    stream: &StreamHandle,
+    writer: &mut W,
+) -> Result<u64> {
+    let mut pos = writer.stream_position().await?;
+    let end = writer.seek(SeekFrom::End(0)).await?;
+    writer.seek(SeekFrom::Start(pos)).await?;
+    let mut total = 0;
+    // Holes not skipped yet, to merge consecutive ones
+    let mut hole = 0;
+    while let Some(chunk) = conn.recv_stream_chunk(stream).await? {
+        match chunk {
+            StreamChunk::Data(data) => {
+                if hole > 0 {
+                    pos = skip_hole(writer, pos, hole, end).await?;
+                    hole = 0;
+                }
+                writer.write_all(&data).await?;
+                pos += data.len() as u64;
+                total += data.len() as u64;
+            }
+            StreamChunk::Hole(length) => {
+                hole += length;
+                total += length;
+            }
+        }
+    }
+    if hole > 0 {
+        // The last byte is written to give the writer its full length
+        skip_hole(writer, pos, hole - 1, end).await?;
+        writer.write_all(&[0]).await?;
+    }
+    writer.flush().await?;
+    Ok(total)
+}
+
+/// Move `writer` from `pos` past a hole of `length` bytes, writing zeros
+/// where it covers data before `end` and seeking over the rest. Returns
+/// the position after the hole.
+async fn skip_hole<W: AsyncWrite + AsyncSeek + Unpin>(writer: &mut W, pos: u64, length: u64, end: u64) -> Result<u64> {
+    let overlap = end.saturating_sub(pos).min(length);
+    if overlap > 0 {
+        let zeros = vec![0; overlap.min(STREAM_CHUNK_SIZE as u64) as usize];
+        let mut left = overlap;
+        while left > 0 {
+            let len = left.min(zeros.len() as u64) as usize;
+            writer.write_all(&zeros[..len]).await?;
+            left -= len as u64;
+        }
+    }
+    if length > overlap {
+        writer.seek(SeekFrom::Current((length - overlap) as i64)).await?;
+    }
+    Ok(pos + length)
+}
+
+impl Client {
+    /// List the storage pools, filtered by the flags, e.g. only the
+    /// active ones.
+    pub async fn list_storage_pools(&self, flags: ConnectListAllStoragePoolsFlags) -> Result<Vec<StoragePool>> {
+        let ret = self.api().connect_list_all_storage_pools(1, flags).await?;
+        Ok(ret.pools.into_iter().map(|raw| self.storage_pool(raw)).collect())
+    }
+
+    /// Look up a storage pool by name.
+    pub async fn lookup_storage_pool_by_name(&self, name: &str) -> Result<StoragePool> {
+        Ok(self.storage_pool(self.api().storage_pool_lookup_by_name(name).await?))
+    }
+
+    /// Look up a storage pool by UUID.
+    pub async fn lookup_storage_pool_by_uuid(&self, uuid: Uuid) -> Result<StoragePool> {
+        Ok(self.storage_pool(self.api().storage_pool_lookup_by_uuid(uuid).await?))
+    }
+
+    /// Define a persistent storage pool from its XML, without building or
+    /// starting it.
+    pub async fn define_storage_pool_xml(&self, xml: &str) -> Result<StoragePool> {
+        Ok(self.storage_pool(self.api().storage_pool_define_xml(xml, 0).await?))
+    }
+
+    /// Create and start a transient storage pool from its XML.
+    pub async fn create_storage_pool_xml(&self, xml: &str, flags: StoragePoolCreateFlags) -> Result<StoragePool> {
+        Ok(self.storage_pool(self.api().storage_pool_create_xml(xml, flags).await?))
+    }
+
+    /// Look up a storage volume by key.
+    pub async fn lookup_storage_vol_by_key(&self, key: &str) -> Result<StorageVol> {
+        Ok(self.storage_vol(self.api().storage_vol_lookup_by_key(key).await?))
+    }
+
+    /// Look up a storage volume by path.
+    pub async fn lookup_storage_vol_by_path(&self, path: &str) -> Result<StorageVol> {
+        Ok(self.storage_vol(self.api().storage_vol_lookup_by_path(path).await?))
+    }
+
+    /// Discover the storage pools a host could use, e.g. the NFS exports
+    /// of a server for type `netfs`, as a `<sources>` XML document.
+    ///
+    /// `source_spec` is a `<source>` XML document narrowing the search,
+    /// e.g. to a host.
+    pub async fn find_storage_pool_sources(&self, pool_type: &str, source_spec: Option<&str>) -> Result<String> {
+        Ok(self.api().connect_find_storage_pool_sources(pool_type, source_spec, 0).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::StorageVolDownloadArgs;
+    use crate::testing::FakeHypervisor;
+    use bytes::Bytes;
+
+    #[tokio::test]
+    async fn test_storage_handles() {
+        let client = FakeHypervisor::new().client().await.unwrap();
+
+        let pools = client.list_storage_pools(ConnectListAllStoragePoolsFlags::empty()).await.unwrap();
+        assert_eq!(pools.len(), 1);
+        let pool = &pools[0];
+        assert_eq!(pool.name(), "default-pool");
+        assert!(pool.is_active().await.unwrap());
+
+        let xml = "<volume><name>disk.img</name><capacity>1048576</capacity></volume>";
+        let vol = pool.create_volume(xml, StorageVolCreateFlags::empty()).await.unwrap();
+        assert_eq!(vol.path().await.unwrap(), "/default-pool/disk.img");
+        assert_eq!(vol.info().await.unwrap().capacity, 1048576);
+        assert_eq!(pool.volumes().await.unwrap(), std::slice::from_ref(&vol));
+        assert_eq!(&vol.pool().await.unwrap(), pool);
+        vol.delete(StorageVolDeleteFlags::empty()).await.unwrap();
+        assert!(pool.lookup_volume_by_name("disk.img").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sparse_streams() {
+        let fake = FakeHypervisor::new();
+        let client = fake.client().await.unwrap();
+        let pool = client.lookup_storage_pool_by_name("default-pool").await.unwrap();
+        let xml = "<volume><name>disk.img</name><capacity>1048576</capacity></volume>";
+        let vol = pool.create_volume(xml, StorageVolCreateFlags::empty()).await.unwrap();
+
+        fake.daemon().respond("storage_vol_upload", ());
+        let mut image = vec![b'a'; HOLE_BLOCK_SIZE];
+        image.resize(3 * HOLE_BLOCK_SIZE, 0);
+        image.resize(4 * HOLE_BLOCK_SIZE, b'b');
+        image.resize(4 * HOLE_BLOCK_SIZE + 5000, 0);
+        let flags = StorageVolUploadFlags::SPARSE_STREAM;
+        assert_eq!(vol.upload(&image[..], flags).await.unwrap(), image.len() as u64);
+        assert_eq!(fake.daemon().uploads("storage_vol_upload"), [image.clone()]);
+        let chunks = &fake.daemon().upload_chunks("storage_vol_upload")[0];
+        assert!(matches!(
+            chunks[..],
+            [StreamChunk::Data(_), StreamChunk::Hole(8192), StreamChunk::Data(_), StreamChunk::Hole(5000)]
+        ));
+        vol.upload(&image[..], StorageVolUploadFlags::empty()).await.unwrap();
+        assert_eq!(fake.daemon().uploads("storage_vol_upload")[1], image);
+        let chunks = &fake.daemon().upload_chunks("storage_vol_upload")[1];
+        assert!(chunks.iter().all(|chunk| matches!(chunk, StreamChunk::Data(_))));
+
+        fake.daemon().respond("storage_vol_download", ());
+        fake.daemon().stream_chunks(
+            "storage_vol_download",
+            [
+                StreamChunk::Data(Bytes::from_static(b"abc")),
+                StreamChunk::Hole(2),
+                StreamChunk::Hole(3),
+                StreamChunk::Data(Bytes::from_static(b"de")),
+                StreamChunk::Hole(3),
+            ],
+        );
+        let flags = StorageVolDownloadFlags::SPARSE_STREAM;
+        let mut file = std::io::Cursor::new(Vec::new());
+        assert_eq!(vol.download(&mut file, flags).await.unwrap(), 13);
+        assert_eq!(file.into_inner(), b"abc\0\0\0\0\0de\0\0\0");
+        let calls = fake.daemon().calls_to::<StorageVolDownloadArgs>("storage_vol_download");
+        assert_eq!(calls[0].flags, StorageVolDownloadFlags::SPARSE_STREAM);
+
+        // Holes over existing data are zeroed, data past the volume's is kept
+        let mut file = std::io::Cursor::new(b"xxxxxxxxxxxxxyz".to_vec());
+        assert_eq!(vol.download(&mut file, flags).await.unwrap(), 13);
+        assert_eq!(file.into_inner(), b"abc\0\0\0\0\0de\0\0\0yz");
+        let mut file = std::io::Cursor::new(b"xxxxxxxxx".to_vec());
+        vol.download(&mut file, flags).await.unwrap();
+        assert_eq!(file.into_inner(), b"abc\0\0\0\0\0de\0\0\0");
+    }
+}
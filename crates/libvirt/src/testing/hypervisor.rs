@@ -0,0 +1,1846 @@
+//! A stateful fake of libvirt's `test:///default` driver.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use bitflags::Flags;
+use libvirt_xdr::{XdrDecode, XdrEncode};
+use roxmltree::{Document, Node};
+
+use super::MockDaemon;
+use crate::error::Result;
+use crate::generated::*;
+use crate::server::RemoteError;
+use crate::xml::escape;
+use crate::{Client, Connection};
+
+/// The URI [`FakeHypervisor::client`] opens.
+const URI: &str = "test:///default";
+
+/// Capacity of the pools whose XML does not give one, as in libvirt's
+/// test driver.
+const POOL_CAPACITY: u64 = 100 * 1024 * 1024 * 1024;
+
+type CallResult<T> = std::result::Result<T, ServiceError>;
+
+/// An in-memory hypervisor answering the common remote procedures like
+/// libvirt's `test:///default` driver.
+///
+/// It models domains (with their snapshots), networks, storage pools and
+/// volumes. Defining, starting, stopping and undefining them follows
+/// libvirt's state machine and fails with the same errors, e.g.
+/// `VIR_ERR_OPERATION_INVALID` when destroying a domain that is not
+/// running. Domain lifecycle changes are sent as
+/// `domain_event_callback_lifecycle` events to the callbacks registered
+/// with `connect_domain_event_callback_register_any`, on every
+/// connection.
+///
+/// Objects are described by the subset of their XML the fake keeps: the
+/// name, UUID, memory and vCPUs of domains, the bridge of networks, the
+/// target path and capacity of pools and the capacity and allocation of
+/// volumes. `*_get_xml_desc` regenerates the XML from those.
+///
+/// The fake is built on a [`MockDaemon`]: procedures it does not
+/// implement fail with `VIR_ERR_NO_SUPPORT` unless registered on
+/// [`daemon`](Self::daemon), which also records the calls.
+///
+/// ```ignore
+/// use libvirt::testing::FakeHypervisor;
+///
+/// let client = FakeHypervisor::new().client().await?;
+/// let dom = client.api().domain_lookup_by_name("test").await?;
+/// client.api().domain_suspend(&dom).await?;
+/// ```
+#[derive(Clone)]
+pub struct FakeHypervisor {
+    daemon: MockDaemon,
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for FakeHypervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeHypervisor {
+    /// Create a hypervisor with the objects of `test:///default`: the
+    /// running domain `test`, the active network `default` and the active
+    /// storage pool `default-pool`, all persistent.
+    pub fn new() -> Self {
+        let fake = Self::empty();
+        {
+            let mut state = fake.state.lock().unwrap();
+            state.domains.push(DomainObj::new(
+                DomainDef {
+                    name: "test".to_string(),
+                    uuid: parse_uuid("6695eb01-f6a4-8304-79aa-97f2502e193f").unwrap(),
+                    description: None,
+                    memory: 8 * 1024 * 1024,
+                    current_memory: 2 * 1024 * 1024,
+                    vcpus: 2,
+                },
+                true,
+            ));
+            state.start(0, false);
+            state.networks.push(NetworkObj {
+                name: "default".to_string(),
+                uuid: parse_uuid("dd8fe884-6c02-601e-7551-cca97df1c5df").unwrap(),
+                bridge: Some("virbr0".to_string()),
+                active: true,
+                persistent: true,
+                autostart: true,
+            });
+            state.pools.push(PoolObj {
+                name: "default-pool".to_string(),
+                uuid: parse_uuid("dfe224cb-28fb-8dd0-c4b2-64eb3f0f4566").unwrap(),
+                pool_type: "dir".to_string(),
+                path: "/default-pool".to_string(),
+                capacity: POOL_CAPACITY,
+                active: true,
+                persistent: true,
+                autostart: true,
+                volumes: Vec::new(),
+            });
+            // The initial objects raise no events
+            state.events.clear();
+        }
+        fake
+    }
+
+    /// Create a hypervisor without any object.
+    pub fn empty() -> Self {
+        let fake = Self {
+            daemon: MockDaemon::new(),
+            state: Arc::new(Mutex::new(State {
+                next_id: 1,
+                next_callback_id: 1,
+                ..State::default()
+            })),
+        };
+        fake.register_connect();
+        fake.register_domains();
+        fake.register_snapshots();
+        fake.register_networks();
+        fake.register_storage();
+        fake
+    }
+
+    /// The daemon serving the hypervisor, to inspect the calls received or
+    /// answer more procedures.
+    pub fn daemon(&self) -> &MockDaemon {
+        &self.daemon
+    }
+
+    /// Open a connection to the hypervisor.
+    pub async fn connect(&self) -> Result<Connection> {
+        self.daemon.connect().await
+    }
+
+    /// Open a connection to the hypervisor and authenticate and open
+    /// `test:///default` on it.
+    pub async fn client(&self) -> Result<Client> {
+        Client::open(self.connect().await?, URI).await
+    }
+
+    /// Answer calls to `procedure` with `handler`, run on the state, then
+    /// emit the events it raised.
+    fn on<A, R, F>(&self, procedure: &str, handler: F)
+    where
+        A: XdrDecode,
+        R: XdrEncode,
+        F: Fn(&mut State, A) -> CallResult<R> + Send + Sync + 'static,
+    {
+        let state = self.state.clone();
+        // The daemon owns its handlers: don't keep it alive from them
+        let daemon = Arc::downgrade(&self.daemon.inner);
+        self.daemon.on(procedure, move |args: A| {
+            let mut state = state.lock().unwrap();
+            let ret = handler(&mut state, args);
+            let events = std::mem::take(&mut state.events);
+            drop(state);
+            if let Some(inner) = daemon.upgrade() {
+                let daemon = MockDaemon { inner };
+                for event in &events {
+                    daemon.emit("domain_event_callback_lifecycle", event);
+                }
+            }
+            ret
+        });
+    }
+
+    fn register_connect(&self) {
+        self.on("connect_get_type", |_, ()| Ok(ConnectGetTypeRet { r#type: "TEST".to_string() }));
+        self.on("connect_get_version", |_, ()| Ok(ConnectGetVersionRet { hv_ver: 2 }));
+        self.on("connect_get_hostname", |_, ()| Ok(ConnectGetHostnameRet {
+            hostname: "localhost".to_string(),
+        }));
+        self.on("connect_get_uri", |_, ()| Ok(ConnectGetUriRet { uri: URI.to_string() }));
+        self.on("connect_num_of_domains", |state, ()| {
+            let num = state.domains.iter().filter(|d| d.is_active()).count();
+            Ok(ConnectNumOfDomainsRet { num: num as i32 })
+        });
+        self.on("connect_num_of_defined_domains", |state, ()| {
+            let num = state.domains.iter().filter(|d| !d.is_active()).count();
+            Ok(ConnectNumOfDefinedDomainsRet { num: num as i32 })
+        });
+        self.on("connect_list_all_domains", |state, args: ConnectListAllDomainsArgs| {
+            use ConnectListAllDomainsFlags as F;
+            let flags = args.flags;
+            let domains: Vec<_> = state
+                .domains
+                .iter()
+                .filter(|d| {
+                    let active = d.is_active();
+                    let other = ![VIR_DOMAIN_RUNNING, VIR_DOMAIN_PAUSED, VIR_DOMAIN_SHUTOFF].contains(&d.state);
+                    let snapshots = !d.snapshots.is_empty();
+                    matches(flags, &[(F::ACTIVE, active), (F::INACTIVE, !active)])
+                        && matches(flags, &[(F::PERSISTENT, d.persistent), (F::TRANSIENT, !d.persistent)])
+                        && matches(flags, &[
+                            (F::RUNNING, d.state == VIR_DOMAIN_RUNNING),
+                            (F::PAUSED, d.state == VIR_DOMAIN_PAUSED),
+                            (F::SHUTOFF, d.state == VIR_DOMAIN_SHUTOFF),
+                            (F::OTHER, other),
+                        ])
+                        && matches(flags, &[(F::AUTOSTART, d.autostart), (F::NO_AUTOSTART, !d.autostart)])
+                        && matches(flags, &[(F::HAS_SNAPSHOT, snapshots), (F::NO_SNAPSHOT, !snapshots)])
+                        && matches(flags, &[(F::MANAGEDSAVE, false), (F::NO_MANAGEDSAVE, true)])
+                        && matches(flags, &[(F::HAS_CHECKPOINT, false), (F::NO_CHECKPOINT, true)])
+                })
+                .map(DomainObj::nonnull)
+                .collect();
+            let ret = domains.len() as u32;
+            let domains = if args.need_results != 0 { domains } else { Vec::new() };
+            Ok(ConnectListAllDomainsRet { domains, ret })
+        });
+        self.on("connect_list_all_networks", |state, args: ConnectListAllNetworksArgs| {
+            use ConnectListAllNetworksFlags as F;
+            let flags = args.flags;
+            let nets: Vec<_> = state
+                .networks
+                .iter()
+                .filter(|n| {
+                    matches(flags, &[(F::ACTIVE, n.active), (F::INACTIVE, !n.active)])
+                        && matches(flags, &[(F::PERSISTENT, n.persistent), (F::TRANSIENT, !n.persistent)])
+                        && matches(flags, &[(F::AUTOSTART, n.autostart), (F::NO_AUTOSTART, !n.autostart)])
+                })
+                .map(NetworkObj::nonnull)
+                .collect();
+            let ret = nets.len() as u32;
+            let nets = if args.need_results != 0 { nets } else { Vec::new() };
+            Ok(ConnectListAllNetworksRet { nets, ret })
+        });
+        self.on("connect_list_all_storage_pools", |state, args: ConnectListAllStoragePoolsArgs| {
+            use ConnectListAllStoragePoolsFlags as F;
+            let flags = args.flags;
+            let pools: Vec<_> = state
+                .pools
+                .iter()
+                .filter(|p| {
+                    let types = [
+                        (F::DIR, "dir"),
+                        (F::FS, "fs"),
+                        (F::NETFS, "netfs"),
+                        (F::LOGICAL, "logical"),
+                        (F::DISK, "disk"),
+                        (F::ISCSI, "iscsi"),
+                        (F::SCSI, "scsi"),
+                        (F::MPATH, "mpath"),
+                        (F::RBD, "rbd"),
+                        (F::SHEEPDOG, "sheepdog"),
+                        (F::GLUSTER, "gluster"),
+                        (F::ZFS, "zfs"),
+                        (F::VSTORAGE, "vstorage"),
+                        (F::ISCSI_DIRECT, "iscsi-direct"),
+                    ]
+                    .map(|(flag, name)| (flag, p.pool_type == name));
+                    matches(flags, &[(F::ACTIVE, p.active), (F::INACTIVE, !p.active)])
+                        && matches(flags, &[(F::PERSISTENT, p.persistent), (F::TRANSIENT, !p.persistent)])
+                        && matches(flags, &[(F::AUTOSTART, p.autostart), (F::NO_AUTOSTART, !p.autostart)])
+                        && matches(flags, &types)
+                })
+                .map(PoolObj::nonnull)
+                .collect();
+            let ret = pools.len() as u32;
+            let pools = if args.need_results != 0 { pools } else { Vec::new() };
+            Ok(ConnectListAllStoragePoolsRet { pools, ret })
+        });
+        self.on(
+            "connect_domain_event_callback_register_any",
+            |state, args: ConnectDomainEventCallbackRegisterAnyArgs| {
+                let callback_id = state.next_callback_id;
+                state.next_callback_id += 1;
+                state.callbacks.push(Callback {
+                    id: callback_id,
+                    event_id: args.event_id,
+                    dom: args.dom.map(|dom| dom.uuid),
+                });
+                Ok(ConnectDomainEventCallbackRegisterAnyRet { callback_id })
+            },
+        );
+        self.on(
+            "connect_domain_event_callback_deregister_any",
+            |state, args: ConnectDomainEventCallbackDeregisterAnyArgs| {
+                let Some(i) = state.callbacks.iter().position(|c| c.id == args.callback_id) else {
+                    return Err(error(
+                        VIR_ERR_INVALID_ARG,
+                        format!("could not find event callback {} for deletion", args.callback_id),
+                    ));
+                };
+                state.callbacks.remove(i);
+                Ok(())
+            },
+        );
+    }
+
+    fn register_domains(&self) {
+        self.on("domain_define_xml", |state, args: DomainDefineXmlArgs| {
+            Ok(DomainDefineXmlRet { dom: state.define_domain(&args.xml)? })
+        });
+        self.on("domain_define_xml_flags", |state, args: DomainDefineXmlFlagsArgs| {
+            Ok(DomainDefineXmlFlagsRet { dom: state.define_domain(&args.xml)? })
+        });
+        self.on("domain_create_xml", |state, args: DomainCreateXmlArgs| {
+            Ok(DomainCreateXmlRet { dom: state.create_domain(&args.xml_desc, args.flags)? })
+        });
+        self.on("domain_create", |state, args: DomainCreateArgs| {
+            state.start_domain(&args.dom, DomainCreateFlags::empty())?;
+            Ok(())
+        });
+        self.on("domain_create_with_flags", |state, args: DomainCreateWithFlagsArgs| {
+            Ok(DomainCreateWithFlagsRet { dom: state.start_domain(&args.dom, args.flags)? })
+        });
+        self.on("domain_destroy", |state, args: DomainDestroyArgs| state.destroy_domain(&args.dom));
+        self.on("domain_destroy_flags", |state, args: DomainDestroyFlagsArgs| state.destroy_domain(&args.dom));
+        self.on("domain_shutdown", |state, args: DomainShutdownArgs| state.shutdown_domain(&args.dom));
+        self.on("domain_shutdown_flags", |state, args: DomainShutdownFlagsArgs| {
+            state.shutdown_domain(&args.dom)
+        });
+        self.on("domain_reboot", |state, args: DomainRebootArgs| {
+            state.active_domain(&args.dom)?;
+            Ok(())
+        });
+        self.on("domain_suspend", |state, args: DomainSuspendArgs| {
+            let i = state.active_domain(&args.dom)?;
+            if state.domains[i].state == VIR_DOMAIN_PAUSED {
+                return Err(invalid("domain is already paused"));
+            }
+            state.set_state(i, VIR_DOMAIN_PAUSED, VIR_DOMAIN_PAUSED_USER);
+            state.event(i, VIR_DOMAIN_EVENT_SUSPENDED, VIR_DOMAIN_EVENT_SUSPENDED_PAUSED);
+            Ok(())
+        });
+        self.on("domain_resume", |state, args: DomainResumeArgs| {
+            let i = state.active_domain(&args.dom)?;
+            if state.domains[i].state != VIR_DOMAIN_PAUSED {
+                return Err(invalid("domain is not paused"));
+            }
+            state.set_state(i, VIR_DOMAIN_RUNNING, VIR_DOMAIN_RUNNING_UNPAUSED);
+            state.event(i, VIR_DOMAIN_EVENT_RESUMED, VIR_DOMAIN_EVENT_RESUMED_UNPAUSED);
+            Ok(())
+        });
+        self.on("domain_undefine", |state, args: DomainUndefineArgs| {
+            state.undefine_domain(&args.dom, DomainUndefineFlags::empty())
+        });
+        self.on("domain_undefine_flags", |state, args: DomainUndefineFlagsArgs| {
+            state.undefine_domain(&args.dom, args.flags)
+        });
+        self.on("domain_lookup_by_name", |state, args: DomainLookupByNameArgs| {
+            let Some(dom) = state.domains.iter().find(|d| d.def.name == args.name) else {
+                return Err(no_domain(format!("no domain with matching name '{}'", args.name)));
+            };
+            Ok(DomainLookupByNameRet { dom: dom.nonnull() })
+        });
+        self.on("domain_lookup_by_uuid", |state, args: DomainLookupByUuidArgs| {
+            let Some(dom) = state.domains.iter().find(|d| d.def.uuid == args.uuid) else {
+                return Err(no_domain(format!("no domain with matching uuid '{}'", args.uuid)));
+            };
+            Ok(DomainLookupByUuidRet { dom: dom.nonnull() })
+        });
+        self.on("domain_lookup_by_id", |state, args: DomainLookupByIdArgs| {
+            let Some(dom) = state.domains.iter().find(|d| d.is_active() && d.id == args.id) else {
+                return Err(no_domain(format!("no domain with matching id {}", args.id)));
+            };
+            Ok(DomainLookupByIdRet { dom: dom.nonnull() })
+        });
+        self.on("domain_get_info", |state, args: DomainGetInfoArgs| {
+            let dom = &state.domains[state.domain(&args.dom)?];
+            let cpu_time = dom.started.map_or(0, |started| started.elapsed().as_nanos() as u64);
+            Ok(DomainGetInfoRet {
+                state: dom.state as u8,
+                max_mem: dom.def.memory,
+                memory: dom.def.current_memory,
+                nr_virt_cpu: dom.def.vcpus,
+                cpu_time,
+            })
+        });
+        self.on("domain_get_state", |state, args: DomainGetStateArgs| {
+            let dom = &state.domains[state.domain(&args.dom)?];
+            Ok(DomainGetStateRet {
+                state: dom.state,
+                reason: dom.reason,
+            })
+        });
+        self.on("domain_get_xml_desc", |state, args: DomainGetXmlDescArgs| {
+            let dom = &state.domains[state.domain(&args.dom)?];
+            let id = (dom.is_active() && !args.flags.contains(DomainXmlFlags::INACTIVE)).then_some(dom.id);
+            Ok(DomainGetXmlDescRet { xml: dom.def.to_xml(id) })
+        });
+        self.on("domain_get_os_type", |state, args: DomainGetOsTypeArgs| {
+            state.domain(&args.dom)?;
+            Ok(DomainGetOsTypeRet { r#type: "hvm".to_string() })
+        });
+        self.on("domain_get_max_memory", |state, args: DomainGetMaxMemoryArgs| {
+            let dom = &state.domains[state.domain(&args.dom)?];
+            Ok(DomainGetMaxMemoryRet { memory: dom.def.memory })
+        });
+        self.on("domain_is_active", |state, args: DomainIsActiveArgs| {
+            let dom = &state.domains[state.domain(&args.dom)?];
+            Ok(DomainIsActiveRet { active: dom.is_active() as i32 })
+        });
+        self.on("domain_is_persistent", |state, args: DomainIsPersistentArgs| {
+            let dom = &state.domains[state.domain(&args.dom)?];
+            Ok(DomainIsPersistentRet { persistent: dom.persistent as i32 })
+        });
+        self.on("domain_get_autostart", |state, args: DomainGetAutostartArgs| {
+            let dom = &state.domains[state.domain(&args.dom)?];
+            Ok(DomainGetAutostartRet { autostart: dom.autostart as i32 })
+        });
+        self.on("domain_set_autostart", |state, args: DomainSetAutostartArgs| {
+            let i = state.domain(&args.dom)?;
+            let dom = &mut state.domains[i];
+            if !dom.persistent {
+                return Err(invalid("cannot set autostart for transient domain"));
+            }
+            dom.autostart = args.autostart != 0;
+            Ok(())
+        });
+    }
+
+    fn register_snapshots(&self) {
+        self.on("domain_snapshot_create_xml", |state, args: DomainSnapshotCreateXmlArgs| {
+            Ok(DomainSnapshotCreateXmlRet {
+                snap: state.create_snapshot(&args.dom, &args.xml_desc, args.flags)?,
+            })
+        });
+        self.on("domain_snapshot_lookup_by_name", |state, args: DomainSnapshotLookupByNameArgs| {
+            let i = state.domain(&args.dom)?;
+            let dom = &state.domains[i];
+            let Some(snapshot) = dom.snapshots.iter().find(|s| s.name == args.name) else {
+                return Err(no_snapshot(&args.name));
+            };
+            Ok(DomainSnapshotLookupByNameRet { snap: dom.snapshot(snapshot) })
+        });
+        self.on("domain_snapshot_num", |state, args: DomainSnapshotNumArgs| {
+            let dom = &state.domains[state.domain(&args.dom)?];
+            Ok(DomainSnapshotNumRet { num: dom.snapshots.len() as i32 })
+        });
+        self.on("domain_list_all_snapshots", |state, args: DomainListAllSnapshotsArgs| {
+            let dom = &state.domains[state.domain(&args.dom)?];
+            let snapshots = dom.list_snapshots(None, args.flags);
+            let ret = snapshots.len() as i32;
+            let snapshots = if args.need_results != 0 { snapshots } else { Vec::new() };
+            Ok(DomainListAllSnapshotsRet { snapshots, ret })
+        });
+        self.on("domain_snapshot_list_all_children", |state, args: DomainSnapshotListAllChildrenArgs| {
+            let (i, _) = state.snapshot(&args.snapshot)?;
+            let snapshots = state.domains[i].list_snapshots(Some(&args.snapshot.name), args.flags);
+            let ret = snapshots.len() as i32;
+            let snapshots = if args.need_results != 0 { snapshots } else { Vec::new() };
+            Ok(DomainSnapshotListAllChildrenRet { snapshots, ret })
+        });
+        self.on("domain_snapshot_is_current", |state, args: DomainSnapshotIsCurrentArgs| {
+            let (i, _) = state.snapshot(&args.snap)?;
+            let current = state.domains[i].current_snapshot.as_ref() == Some(&args.snap.name);
+            Ok(DomainSnapshotIsCurrentRet { current: current as i32 })
+        });
+        self.on("domain_snapshot_has_metadata", |state, args: DomainSnapshotHasMetadataArgs| {
+            state.snapshot(&args.snap)?;
+            Ok(DomainSnapshotHasMetadataRet { metadata: 1 })
+        });
+        self.on("domain_snapshot_get_xml_desc", |state, args: DomainSnapshotGetXmlDescArgs| {
+            let (i, s) = state.snapshot(&args.snap)?;
+            Ok(DomainSnapshotGetXmlDescRet { xml: state.domains[i].snapshots[s].to_xml() })
+        });
+        self.on("domain_snapshot_current", |state, args: DomainSnapshotCurrentArgs| {
+            let dom = &state.domains[state.domain(&args.dom)?];
+            let Some(current) = dom.snapshots.iter().find(|s| Some(&s.name) == dom.current_snapshot.as_ref()) else {
+                return Err(error(
+                    VIR_ERR_NO_DOMAIN_SNAPSHOT,
+                    "Domain snapshot not found: the domain does not have a current snapshot",
+                ));
+            };
+            Ok(DomainSnapshotCurrentRet { snap: dom.snapshot(current) })
+        });
+        self.on("domain_has_current_snapshot", |state, args: DomainHasCurrentSnapshotArgs| {
+            let dom = &state.domains[state.domain(&args.dom)?];
+            Ok(DomainHasCurrentSnapshotRet {
+                result: dom.current_snapshot.is_some() as i32,
+            })
+        });
+        self.on("domain_snapshot_get_parent", |state, args: DomainSnapshotGetParentArgs| {
+            let (i, s) = state.snapshot(&args.snap)?;
+            let dom = &state.domains[i];
+            let Some(parent) = &dom.snapshots[s].parent else {
+                return Err(error(
+                    VIR_ERR_NO_DOMAIN_SNAPSHOT,
+                    format!("Domain snapshot not found: snapshot '{}' does not have a parent", args.snap.name),
+                ));
+            };
+            Ok(DomainSnapshotGetParentRet {
+                snap: NonnullDomainSnapshot {
+                    name: parent.clone(),
+                    dom: dom.nonnull(),
+                },
+            })
+        });
+        self.on("domain_snapshot_delete", |state, args: DomainSnapshotDeleteArgs| {
+            state.delete_snapshot(&args.snap, args.flags)
+        });
+        self.on("domain_revert_to_snapshot", |state, args: DomainRevertToSnapshotArgs| {
+            state.revert_to_snapshot(&args.snap, args.flags)
+        });
+    }
+
+    fn register_networks(&self) {
+        self.on("network_define_xml", |state, args: NetworkDefineXmlArgs| {
+            Ok(NetworkDefineXmlRet { net: state.define_network(&args.xml)? })
+        });
+        self.on("network_create_xml", |state, args: NetworkCreateXmlArgs| {
+            Ok(NetworkCreateXmlRet { net: state.create_network(&args.xml)? })
+        });
+        self.on("network_create", |state, args: NetworkCreateArgs| {
+            let i = state.network(&args.net)?;
+            let net = &mut state.networks[i];
+            if net.active {
+                return Err(invalid("network is already active"));
+            }
+            net.active = true;
+            Ok(())
+        });
+        self.on("network_destroy", |state, args: NetworkDestroyArgs| {
+            let i = state.network(&args.net)?;
+            let net = &mut state.networks[i];
+            if !net.active {
+                return Err(invalid("network is not active"));
+            }
+            net.active = false;
+            if !net.persistent {
+                state.networks.remove(i);
+            }
+            Ok(())
+        });
+        self.on("network_undefine", |state, args: NetworkUndefineArgs| {
+            let i = state.network(&args.net)?;
+            let net = &mut state.networks[i];
+            if !net.persistent {
+                return Err(invalid("cannot undefine transient network"));
+            }
+            if net.active {
+                net.persistent = false;
+                net.autostart = false;
+            } else {
+                state.networks.remove(i);
+            }
+            Ok(())
+        });
+        self.on("network_lookup_by_name", |state, args: NetworkLookupByNameArgs| {
+            let Some(net) = state.networks.iter().find(|n| n.name == args.name) else {
+                return Err(no_network(format!("no network with matching name '{}'", args.name)));
+            };
+            Ok(NetworkLookupByNameRet { net: net.nonnull() })
+        });
+        self.on("network_lookup_by_uuid", |state, args: NetworkLookupByUuidArgs| {
+            let Some(net) = state.networks.iter().find(|n| n.uuid == args.uuid) else {
+                return Err(no_network(format!("no network with matching uuid '{}'", args.uuid)));
+            };
+            Ok(NetworkLookupByUuidRet { net: net.nonnull() })
+        });
+        self.on("network_get_xml_desc", |state, args: NetworkGetXmlDescArgs| {
+            let net = &state.networks[state.network(&args.net)?];
+            Ok(NetworkGetXmlDescRet { xml: net.to_xml() })
+        });
+        self.on("network_get_bridge_name", |state, args: NetworkGetBridgeNameArgs| {
+            let net = &state.networks[state.network(&args.net)?];
+            let Some(bridge) = &net.bridge else {
+                return Err(error(
+                    VIR_ERR_INTERNAL_ERROR,
+                    format!("internal error: network '{}' does not have a bridge name.", net.name),
+                ));
+            };
+            Ok(NetworkGetBridgeNameRet { name: bridge.clone() })
+        });
+        self.on("network_is_active", |state, args: NetworkIsActiveArgs| {
+            let net = &state.networks[state.network(&args.net)?];
+            Ok(NetworkIsActiveRet { active: net.active as i32 })
+        });
+        self.on("network_is_persistent", |state, args: NetworkIsPersistentArgs| {
+            let net = &state.networks[state.network(&args.net)?];
+            Ok(NetworkIsPersistentRet { persistent: net.persistent as i32 })
+        });
+        self.on("network_get_autostart", |state, args: NetworkGetAutostartArgs| {
+            let net = &state.networks[state.network(&args.net)?];
+            Ok(NetworkGetAutostartRet { autostart: net.autostart as i32 })
+        });
+        self.on("network_set_autostart", |state, args: NetworkSetAutostartArgs| {
+            let i = state.network(&args.net)?;
+            let net = &mut state.networks[i];
+            if !net.persistent {
+                return Err(invalid("cannot set autostart for transient network"));
+            }
+            net.autostart = args.autostart != 0;
+            Ok(())
+        });
+    }
+
+    fn register_storage(&self) {
+        self.on("storage_pool_define_xml", |state, args: StoragePoolDefineXmlArgs| {
+            Ok(StoragePoolDefineXmlRet { pool: state.define_pool(&args.xml)? })
+        });
+        self.on("storage_pool_create_xml", |state, args: StoragePoolCreateXmlArgs| {
+            Ok(StoragePoolCreateXmlRet { pool: state.create_pool(&args.xml)? })
+        });
+        self.on("storage_pool_create", |state, args: StoragePoolCreateArgs| {
+            let i = state.pool(&args.pool)?;
+            let pool = &mut state.pools[i];
+            if pool.active {
+                return Err(invalid(format!("storage pool '{}' is already active", pool.name)));
+            }
+            pool.active = true;
+            Ok(())
+        });
+        self.on("storage_pool_destroy", |state, args: StoragePoolDestroyArgs| {
+            let i = state.active_pool(&args.pool)?;
+            let pool = &mut state.pools[i];
+            pool.active = false;
+            if !pool.persistent {
+                state.pools.remove(i);
+            }
+            Ok(())
+        });
+        self.on("storage_pool_undefine", |state, args: StoragePoolUndefineArgs| {
+            let i = state.pool(&args.pool)?;
+            let pool = &state.pools[i];
+            if pool.active {
+                return Err(invalid(format!("storage pool '{}' is still active", pool.name)));
+            }
+            state.pools.remove(i);
+            Ok(())
+        });
+        self.on("storage_pool_lookup_by_name", |state, args: StoragePoolLookupByNameArgs| {
+            let Some(pool) = state.pools.iter().find(|p| p.name == args.name) else {
+                return Err(no_pool(format!("no storage pool with matching name '{}'", args.name)));
+            };
+            Ok(StoragePoolLookupByNameRet { pool: pool.nonnull() })
+        });
+        self.on("storage_pool_lookup_by_uuid", |state, args: StoragePoolLookupByUuidArgs| {
+            let Some(pool) = state.pools.iter().find(|p| p.uuid == args.uuid) else {
+                return Err(no_pool(format!("no storage pool with matching uuid '{}'", args.uuid)));
+            };
+            Ok(StoragePoolLookupByUuidRet { pool: pool.nonnull() })
+        });
+        self.on("storage_pool_lookup_by_volume", |state, args: StoragePoolLookupByVolumeArgs| {
+            let (i, _) = state.volume(&args.vol)?;
+            Ok(StoragePoolLookupByVolumeRet { pool: state.pools[i].nonnull() })
+        });
+        self.on("storage_pool_get_info", |state, args: StoragePoolGetInfoArgs| {
+            let pool = &state.pools[state.pool(&args.pool)?];
+            let allocation = pool.allocation();
+            Ok(StoragePoolGetInfoRet {
+                state: (if pool.active { VIR_STORAGE_POOL_RUNNING } else { VIR_STORAGE_POOL_INACTIVE }) as u8,
+                capacity: pool.capacity,
+                allocation,
+                available: pool.capacity.saturating_sub(allocation),
+            })
+        });
+        self.on("storage_pool_get_xml_desc", |state, args: StoragePoolGetXmlDescArgs| {
+            let pool = &state.pools[state.pool(&args.pool)?];
+            Ok(StoragePoolGetXmlDescRet { xml: pool.to_xml() })
+        });
+        self.on("storage_pool_is_active", |state, args: StoragePoolIsActiveArgs| {
+            let pool = &state.pools[state.pool(&args.pool)?];
+            Ok(StoragePoolIsActiveRet { active: pool.active as i32 })
+        });
+        self.on("storage_pool_is_persistent", |state, args: StoragePoolIsPersistentArgs| {
+            let pool = &state.pools[state.pool(&args.pool)?];
+            Ok(StoragePoolIsPersistentRet { persistent: pool.persistent as i32 })
+        });
+        self.on("storage_pool_num_of_volumes", |state, args: StoragePoolNumOfVolumesArgs| {
+            let pool = &state.pools[state.active_pool(&args.pool)?];
+            Ok(StoragePoolNumOfVolumesRet { num: pool.volumes.len() as i32 })
+        });
+        self.on("storage_pool_list_all_volumes", |state, args: StoragePoolListAllVolumesArgs| {
+            let pool = &state.pools[state.active_pool(&args.pool)?];
+            let ret = pool.volumes.len() as u32;
+            let vols = match args.need_results {
+                0 => Vec::new(),
+                _ => pool.volumes.iter().map(|v| pool.volume(v)).collect(),
+            };
+            Ok(StoragePoolListAllVolumesRet { vols, ret })
+        });
+
+        self.on("storage_vol_create_xml", |state, args: StorageVolCreateXmlArgs| {
+            Ok(StorageVolCreateXmlRet { vol: state.create_volume(&args.pool, &args.xml)? })
+        });
+        self.on("storage_vol_lookup_by_name", |state, args: StorageVolLookupByNameArgs| {
+            let pool = &state.pools[state.active_pool(&args.pool)?];
+            let Some(vol) = pool.volumes.iter().find(|v| v.name == args.name) else {
+                return Err(no_volume(format!("no storage vol with matching name '{}'", args.name)));
+            };
+            Ok(StorageVolLookupByNameRet { vol: pool.volume(vol) })
+        });
+        self.on("storage_vol_lookup_by_key", |state, args: StorageVolLookupByKeyArgs| {
+            let Some(vol) = state.volume_by_path(&args.key) else {
+                return Err(no_volume(format!("no storage vol with matching key {}", args.key)));
+            };
+            Ok(StorageVolLookupByKeyRet { vol })
+        });
+        self.on("storage_vol_lookup_by_path", |state, args: StorageVolLookupByPathArgs| {
+            let Some(vol) = state.volume_by_path(&args.path) else {
+                return Err(no_volume(format!("no storage vol with matching path '{}'", args.path)));
+            };
+            Ok(StorageVolLookupByPathRet { vol })
+        });
+        self.on("storage_vol_get_info", |state, args: StorageVolGetInfoArgs| {
+            let (i, v) = state.volume(&args.vol)?;
+            let vol = &state.pools[i].volumes[v];
+            Ok(StorageVolGetInfoRet {
+                r#type: VIR_STORAGE_VOL_FILE as i8,
+                capacity: vol.capacity,
+                allocation: vol.allocation,
+            })
+        });
+        self.on("storage_vol_get_xml_desc", |state, args: StorageVolGetXmlDescArgs| {
+            let (i, v) = state.volume(&args.vol)?;
+            let pool = &state.pools[i];
+            Ok(StorageVolGetXmlDescRet { xml: pool.volumes[v].to_xml(&pool.path) })
+        });
+        self.on("storage_vol_get_path", |state, args: StorageVolGetPathArgs| {
+            let (i, v) = state.volume(&args.vol)?;
+            let pool = &state.pools[i];
+            Ok(StorageVolGetPathRet { name: pool.volumes[v].path(&pool.path) })
+        });
+        self.on("storage_vol_delete", |state, args: StorageVolDeleteArgs| {
+            let (i, v) = state.volume(&args.vol)?;
+            state.pools[i].volumes.remove(v);
+            Ok(())
+        });
+    }
+}
+
+/// What the fake keeps of a domain's XML.
+#[derive(Clone)]
+struct DomainDef {
+    name: String,
+    uuid: Uuid,
+    description: Option<String>,
+    /// Maximum memory, in KiB.
+    memory: u64,
+    /// Current memory, in KiB.
+    current_memory: u64,
+    vcpus: u16,
+}
+
+impl DomainDef {
+    /// Parse a `<domain>` document; `uuid` gives the UUID of a domain
+    /// without one, by name.
+    fn parse(xml: &str, uuid: impl FnOnce(&str) -> Uuid) -> CallResult<Self> {
+        let doc = parse_xml(xml)?;
+        let root = root(&doc, "domain")?;
+        let name = name(root, "domain")?;
+        let memory = scaled(root, "memory", 1024)?.ok_or_else(|| xml_error("missing memory element"))? / 1024;
+        let current_memory = scaled(root, "currentMemory", 1024)?.map_or(memory, |current| current / 1024);
+        if current_memory > memory {
+            return Err(xml_error("current memory exceeds maximum memory"));
+        }
+        let vcpus = match text(root, "vcpu") {
+            Some(vcpus) => vcpus.parse().ok().filter(|&n| n > 0).ok_or_else(|| xml_error("invalid vcpu count"))?,
+            None => 1,
+        };
+        Ok(Self {
+            uuid: match element_uuid(root)? {
+                Some(uuid) => uuid,
+                None => uuid(&name),
+            },
+            description: text(root, "description").map(str::to_string),
+            name,
+            memory,
+            current_memory,
+            vcpus,
+        })
+    }
+
+    /// The domain's XML, with `id` if it is running.
+    fn to_xml(&self, id: Option<i32>) -> String {
+        let mut xml = match id {
+            Some(id) => format!("<domain type='test' id='{}'>\n", id),
+            None => "<domain type='test'>\n".to_string(),
+        };
+        xml += &format!("  <name>{}</name>\n", escape(&self.name));
+        xml += &format!("  <uuid>{}</uuid>\n", self.uuid);
+        if let Some(description) = &self.description {
+            xml += &format!("  <description>{}</description>\n", escape(description));
+        }
+        xml += &format!("  <memory unit='KiB'>{}</memory>\n", self.memory);
+        xml += &format!("  <currentMemory unit='KiB'>{}</currentMemory>\n", self.current_memory);
+        xml += &format!("  <vcpu placement='static'>{}</vcpu>\n", self.vcpus);
+        xml += "  <os>\n    <type arch='i686'>hvm</type>\n    <boot dev='hd'/>\n  </os>\n";
+        xml += "  <clock offset='utc'/>\n";
+        xml += "  <on_poweroff>destroy</on_poweroff>\n";
+        xml += "  <on_reboot>restart</on_reboot>\n";
+        xml += "  <on_crash>destroy</on_crash>\n";
+        xml += "</domain>\n";
+        xml
+    }
+}
+
+struct DomainObj {
+    def: DomainDef,
+    /// -1 while inactive.
+    id: i32,
+    /// `VIR_DOMAIN_*` state and the matching reason.
+    state: i32,
+    reason: i32,
+    persistent: bool,
+    autostart: bool,
+    started: Option<Instant>,
+    /// In creation order.
+    snapshots: Vec<Snapshot>,
+    current_snapshot: Option<String>,
+}
+
+impl DomainObj {
+    /// An inactive domain.
+    fn new(def: DomainDef, persistent: bool) -> Self {
+        Self {
+            def,
+            id: -1,
+            state: VIR_DOMAIN_SHUTOFF,
+            reason: VIR_DOMAIN_SHUTOFF_UNKNOWN,
+            persistent,
+            autostart: false,
+            started: None,
+            snapshots: Vec::new(),
+            current_snapshot: None,
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.id != -1
+    }
+
+    fn nonnull(&self) -> NonnullDomain {
+        NonnullDomain {
+            name: self.def.name.clone(),
+            uuid: self.def.uuid,
+            id: self.id,
+        }
+    }
+
+    fn snapshot(&self, snapshot: &Snapshot) -> NonnullDomainSnapshot {
+        NonnullDomainSnapshot {
+            name: snapshot.name.clone(),
+            dom: self.nonnull(),
+        }
+    }
+
+    /// Names of the snapshots descending from `name`.
+    fn descendants(&self, name: &str) -> Vec<String> {
+        let mut names = vec![name.to_string()];
+        let mut i = 0;
+        while i < names.len() {
+            let children = self.snapshots.iter().filter(|s| s.parent.as_ref() == Some(&names[i]));
+            names.extend(children.map(|s| s.name.clone()).collect::<Vec<_>>());
+            i += 1;
+        }
+        names.remove(0);
+        names
+    }
+
+    /// The snapshots `flags` select among all of them or, with `parent`,
+    /// among its children or descendants. Parents come before their
+    /// children, as with `VIR_DOMAIN_SNAPSHOT_LIST_TOPOLOGICAL`.
+    fn list_snapshots(&self, parent: Option<&str>, flags: DomainSnapshotListFlags) -> Vec<NonnullDomainSnapshot> {
+        use DomainSnapshotListFlags as F;
+        let descendants = parent.map(|name| self.descendants(name)).unwrap_or_default();
+        self.snapshots
+            .iter()
+            .filter(|s| match parent {
+                Some(_) if flags.contains(F::DESCENDANTS) => descendants.contains(&s.name),
+                Some(name) => s.parent.as_deref() == Some(name),
+                None => !flags.contains(F::ROOTS) || s.parent.is_none(),
+            })
+            .filter(|s| {
+                let leaf = !self.snapshots.iter().any(|c| c.parent.as_ref() == Some(&s.name));
+                let active = s.state != VIR_DOMAIN_SHUTOFF;
+                matches(flags, &[(F::LEAVES, leaf), (F::NO_LEAVES, !leaf)])
+                    && matches(flags, &[(F::METADATA, true), (F::NO_METADATA, false)])
+                    && matches(flags, &[(F::INACTIVE, !active), (F::ACTIVE, active), (F::DISK_ONLY, false)])
+                    && matches(flags, &[(F::INTERNAL, true), (F::EXTERNAL, false)])
+            })
+            .map(|s| self.snapshot(s))
+            .collect()
+    }
+}
+
+struct Snapshot {
+    name: String,
+    description: Option<String>,
+    parent: Option<String>,
+    /// State of the domain when the snapshot was taken.
+    state: i32,
+    creation_time: u64,
+    def: DomainDef,
+}
+
+impl Snapshot {
+    fn to_xml(&self) -> String {
+        let mut xml = "<domainsnapshot>\n".to_string();
+        xml += &format!("  <name>{}</name>\n", escape(&self.name));
+        if let Some(description) = &self.description {
+            xml += &format!("  <description>{}</description>\n", escape(description));
+        }
+        xml += &format!("  <state>{}</state>\n", state_name(self.state));
+        if let Some(parent) = &self.parent {
+            xml += &format!("  <parent>\n    <name>{}</name>\n  </parent>\n", escape(parent));
+        }
+        xml += &format!("  <creationTime>{}</creationTime>\n", self.creation_time);
+        for line in self.def.to_xml(None).lines() {
+            xml += &format!("  {}\n", line);
+        }
+        xml += "</domainsnapshot>\n";
+        xml
+    }
+}
+
+struct NetworkObj {
+    name: String,
+    uuid: Uuid,
+    bridge: Option<String>,
+    active: bool,
+    persistent: bool,
+    autostart: bool,
+}
+
+impl NetworkObj {
+    fn nonnull(&self) -> NonnullNetwork {
+        NonnullNetwork {
+            name: self.name.clone(),
+            uuid: self.uuid,
+        }
+    }
+
+    fn to_xml(&self) -> String {
+        let mut xml = "<network>\n".to_string();
+        xml += &format!("  <name>{}</name>\n", escape(&self.name));
+        xml += &format!("  <uuid>{}</uuid>\n", self.uuid);
+        if let Some(bridge) = &self.bridge {
+            xml += &format!("  <bridge name='{}'/>\n", escape(bridge));
+        }
+        xml += "</network>\n";
+        xml
+    }
+}
+
+struct PoolObj {
+    name: String,
+    uuid: Uuid,
+    /// The `type` attribute, e.g. `dir`.
+    pool_type: String,
+    path: String,
+    /// In bytes.
+    capacity: u64,
+    active: bool,
+    persistent: bool,
+    autostart: bool,
+    volumes: Vec<Volume>,
+}
+
+impl PoolObj {
+    fn nonnull(&self) -> NonnullStoragePool {
+        NonnullStoragePool {
+            name: self.name.clone(),
+            uuid: self.uuid,
+        }
+    }
+
+    fn volume(&self, volume: &Volume) -> NonnullStorageVol {
+        let path = volume.path(&self.path);
+        NonnullStorageVol {
+            pool: self.name.clone(),
+            name: volume.name.clone(),
+            key: path,
+        }
+    }
+
+    fn allocation(&self) -> u64 {
+        self.volumes.iter().map(|v| v.allocation).sum()
+    }
+
+    fn to_xml(&self) -> String {
+        let allocation = self.allocation();
+        let mut xml = format!("<pool type='{}'>\n", escape(&self.pool_type));
+        xml += &format!("  <name>{}</name>\n", escape(&self.name));
+        xml += &format!("  <uuid>{}</uuid>\n", self.uuid);
+        xml += &format!("  <capacity unit='bytes'>{}</capacity>\n", self.capacity);
+        xml += &format!("  <allocation unit='bytes'>{}</allocation>\n", allocation);
+        xml += &format!("  <available unit='bytes'>{}</available>\n", self.capacity.saturating_sub(allocation));
+        xml += &format!("  <target>\n    <path>{}</path>\n  </target>\n", escape(&self.path));
+        xml += "</pool>\n";
+        xml
+    }
+}
+
+struct Volume {
+    name: String,
+    /// In bytes.
+    capacity: u64,
+    allocation: u64,
+}
+
+impl Volume {
+    /// The volume's path, which is also its key.
+    fn path(&self, pool_path: &str) -> String {
+        format!("{}/{}", pool_path, self.name)
+    }
+
+    fn to_xml(&self, pool_path: &str) -> String {
+        let path = escape(&self.path(pool_path));
+        let mut xml = "<volume type='file'>\n".to_string();
+        xml += &format!("  <name>{}</name>\n", escape(&self.name));
+        xml += &format!("  <key>{}</key>\n", path);
+        xml += &format!("  <capacity unit='bytes'>{}</capacity>\n", self.capacity);
+        xml += &format!("  <allocation unit='bytes'>{}</allocation>\n", self.allocation);
+        xml += &format!("  <target>\n    <path>{}</path>\n  </target>\n", path);
+        xml += "</volume>\n";
+        xml
+    }
+}
+
+/// A callback registered with `connect_domain_event_callback_register_any`.
+struct Callback {
+    id: i32,
+    /// `VIR_DOMAIN_EVENT_ID_*`.
+    event_id: i32,
+    /// The domain it is limited to, if any.
+    dom: Option<Uuid>,
+}
+
+#[derive(Default)]
+struct State {
+    /// In creation order, as listed.
+    domains: Vec<DomainObj>,
+    networks: Vec<NetworkObj>,
+    pools: Vec<PoolObj>,
+    /// ID of the next domain started.
+    next_id: i32,
+    callbacks: Vec<Callback>,
+    next_callback_id: i32,
+    /// Events raised by the current call, emitted once it is answered.
+    events: Vec<DomainEventCallbackLifecycleMsg>,
+    /// UUIDs generated so far.
+    uuids: u64,
+}
+
+impl State {
+    /// A new random-looking, but reproducible, UUID.
+    fn new_uuid(&mut self, name: &str) -> Uuid {
+        self.uuids += 1;
+        let mut bytes = [0; 16];
+        for (i, half) in bytes.chunks_mut(8).enumerate() {
+            let mut hasher = DefaultHasher::new();
+            (name, self.uuids, i).hash(&mut hasher);
+            half.copy_from_slice(&hasher.finish().to_be_bytes());
+        }
+        // Version 4, RFC 4122 variant
+        bytes[6] = bytes[6] & 0x0f | 0x40;
+        bytes[8] = bytes[8] & 0x3f | 0x80;
+        FixedOpaque16::new(bytes)
+    }
+
+    /// Raise the lifecycle event `event` with `detail` for domain `i`.
+    fn event(&mut self, i: usize, event: i32, detail: i32) {
+        let dom = self.domains[i].nonnull();
+        let callbacks = self
+            .callbacks
+            .iter()
+            .filter(|c| c.event_id == VIR_DOMAIN_EVENT_ID_LIFECYCLE && c.dom.map_or(true, |uuid| uuid == dom.uuid));
+        for callback in callbacks {
+            self.events.push(DomainEventCallbackLifecycleMsg {
+                callback_id: callback.id,
+                msg: DomainEventLifecycleMsg {
+                    dom: dom.clone(),
+                    event,
+                    detail,
+                },
+            });
+        }
+    }
+
+    /// The index of the domain `dom`, looked up by UUID.
+    fn domain(&self, dom: &NonnullDomain) -> CallResult<usize> {
+        self.domains
+            .iter()
+            .position(|d| d.def.uuid == dom.uuid)
+            .ok_or_else(|| no_domain(format!("no domain with matching uuid '{}' ({})", dom.uuid, dom.name)))
+    }
+
+    /// The index of the domain `dom`, which must be running.
+    fn active_domain(&self, dom: &NonnullDomain) -> CallResult<usize> {
+        let i = self.domain(dom)?;
+        if !self.domains[i].is_active() {
+            return Err(invalid("domain is not running"));
+        }
+        Ok(i)
+    }
+
+    fn set_state(&mut self, i: usize, state: i32, reason: i32) {
+        let dom = &mut self.domains[i];
+        dom.state = state;
+        dom.reason = reason;
+    }
+
+    /// The index of the domain named like `def`, if any, checking that it
+    /// has the same UUID and that no other domain has it.
+    fn existing_domain(&self, def: &DomainDef) -> CallResult<Option<usize>> {
+        if let Some(other) = self.domains.iter().find(|d| d.def.uuid == def.uuid && d.def.name != def.name) {
+            return Err(error(
+                VIR_ERR_OPERATION_FAILED,
+                format!("operation failed: domain '{}' is already defined with uuid {}", other.def.name, def.uuid),
+            ));
+        }
+        let Some(i) = self.domains.iter().position(|d| d.def.name == def.name) else {
+            return Ok(None);
+        };
+        if self.domains[i].def.uuid != def.uuid {
+            return Err(error(
+                VIR_ERR_OPERATION_FAILED,
+                format!(
+                    "operation failed: domain '{}' already exists with uuid {}",
+                    def.name, self.domains[i].def.uuid
+                ),
+            ));
+        }
+        Ok(Some(i))
+    }
+
+    /// Parse the domain XML `xml`, giving a domain without UUID that of the
+    /// domain of the same name if any.
+    fn parse_domain(&mut self, xml: &str) -> CallResult<DomainDef> {
+        DomainDef::parse(xml, |name| match self.domains.iter().find(|d| d.def.name == name) {
+            Some(dom) => dom.def.uuid,
+            None => self.new_uuid(name),
+        })
+    }
+
+    fn define_domain(&mut self, xml: &str) -> CallResult<NonnullDomain> {
+        let def = self.parse_domain(xml)?;
+        match self.existing_domain(&def)? {
+            Some(i) => {
+                let dom = &mut self.domains[i];
+                dom.def = def;
+                dom.persistent = true;
+                self.event(i, VIR_DOMAIN_EVENT_DEFINED, VIR_DOMAIN_EVENT_DEFINED_UPDATED);
+                Ok(self.domains[i].nonnull())
+            }
+            None => {
+                self.domains.push(DomainObj::new(def, true));
+                let i = self.domains.len() - 1;
+                self.event(i, VIR_DOMAIN_EVENT_DEFINED, VIR_DOMAIN_EVENT_DEFINED_ADDED);
+                Ok(self.domains[i].nonnull())
+            }
+        }
+    }
+
+    fn create_domain(&mut self, xml: &str, flags: DomainCreateFlags) -> CallResult<NonnullDomain> {
+        let def = self.parse_domain(xml)?;
+        let i = match self.existing_domain(&def)? {
+            Some(i) if self.domains[i].is_active() => {
+                return Err(error(
+                    VIR_ERR_OPERATION_FAILED,
+                    format!("operation failed: domain '{}' is already active", def.name),
+                ));
+            }
+            Some(i) => {
+                self.domains[i].def = def;
+                i
+            }
+            None => {
+                self.domains.push(DomainObj::new(def, false));
+                self.domains.len() - 1
+            }
+        };
+        self.start(i, flags.contains(DomainCreateFlags::START_PAUSED));
+        Ok(self.domains[i].nonnull())
+    }
+
+    fn start_domain(&mut self, dom: &NonnullDomain, flags: DomainCreateFlags) -> CallResult<NonnullDomain> {
+        let i = self.domain(dom)?;
+        if self.domains[i].is_active() {
+            return Err(invalid("domain is already running"));
+        }
+        self.start(i, flags.contains(DomainCreateFlags::START_PAUSED));
+        Ok(self.domains[i].nonnull())
+    }
+
+    /// Boot the inactive domain `i`.
+    fn start(&mut self, i: usize, paused: bool) {
+        let dom = &mut self.domains[i];
+        dom.id = self.next_id;
+        dom.started = Some(Instant::now());
+        self.next_id += 1;
+        self.set_state(i, VIR_DOMAIN_RUNNING, VIR_DOMAIN_RUNNING_BOOTED);
+        self.event(i, VIR_DOMAIN_EVENT_STARTED, VIR_DOMAIN_EVENT_STARTED_BOOTED);
+        if paused {
+            self.set_state(i, VIR_DOMAIN_PAUSED, VIR_DOMAIN_PAUSED_USER);
+            self.event(i, VIR_DOMAIN_EVENT_SUSPENDED, VIR_DOMAIN_EVENT_SUSPENDED_PAUSED);
+        }
+    }
+
+    /// Stop the running domain `i`, which goes away if it is transient.
+    fn stop(&mut self, i: usize, reason: i32, detail: i32) {
+        let dom = &mut self.domains[i];
+        dom.id = -1;
+        dom.started = None;
+        self.set_state(i, VIR_DOMAIN_SHUTOFF, reason);
+        self.event(i, VIR_DOMAIN_EVENT_STOPPED, detail);
+        if !self.domains[i].persistent {
+            self.domains.remove(i);
+        }
+    }
+
+    fn destroy_domain(&mut self, dom: &NonnullDomain) -> CallResult<()> {
+        let i = self.active_domain(dom)?;
+        self.stop(i, VIR_DOMAIN_SHUTOFF_DESTROYED, VIR_DOMAIN_EVENT_STOPPED_DESTROYED);
+        Ok(())
+    }
+
+    fn shutdown_domain(&mut self, dom: &NonnullDomain) -> CallResult<()> {
+        let i = self.active_domain(dom)?;
+        if self.domains[i].state != VIR_DOMAIN_RUNNING {
+            return Err(invalid(format!("domain '{}' is not running", dom.name)));
+        }
+        self.stop(i, VIR_DOMAIN_SHUTOFF_SHUTDOWN, VIR_DOMAIN_EVENT_STOPPED_SHUTDOWN);
+        Ok(())
+    }
+
+    fn undefine_domain(&mut self, dom: &NonnullDomain, flags: DomainUndefineFlags) -> CallResult<()> {
+        let i = self.domain(dom)?;
+        let domain = &mut self.domains[i];
+        if !domain.persistent {
+            return Err(invalid("cannot undefine transient domain"));
+        }
+        if !domain.snapshots.is_empty() {
+            if !flags.contains(DomainUndefineFlags::SNAPSHOTS_METADATA) {
+                return Err(invalid(format!(
+                    "cannot undefine domain with {} snapshots",
+                    domain.snapshots.len()
+                )));
+            }
+            domain.snapshots.clear();
+            domain.current_snapshot = None;
+        }
+        domain.persistent = false;
+        domain.autostart = false;
+        self.event(i, VIR_DOMAIN_EVENT_UNDEFINED, VIR_DOMAIN_EVENT_UNDEFINED_REMOVED);
+        // A running domain stays, as a transient one
+        if !self.domains[i].is_active() {
+            self.domains.remove(i);
+        }
+        Ok(())
+    }
+
+    /// The indices of the domain and snapshot `snap`.
+    fn snapshot(&self, snap: &NonnullDomainSnapshot) -> CallResult<(usize, usize)> {
+        let i = self.domain(&snap.dom)?;
+        match self.domains[i].snapshots.iter().position(|s| s.name == snap.name) {
+            Some(s) => Ok((i, s)),
+            None => Err(no_snapshot(&snap.name)),
+        }
+    }
+
+    fn create_snapshot(
+        &mut self,
+        dom: &NonnullDomain,
+        xml: &str,
+        flags: DomainSnapshotCreateFlags,
+    ) -> CallResult<NonnullDomainSnapshot> {
+        let i = self.domain(dom)?;
+        let doc = parse_xml(xml)?;
+        let root = root(&doc, "domainsnapshot")?;
+        let creation_time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs());
+        let name = text(root, "name").map_or_else(|| creation_time.to_string(), str::to_string);
+
+        let domain = &mut self.domains[i];
+        if domain.snapshots.iter().any(|s| s.name == name) {
+            return Err(invalid(format!("domain snapshot '{}' already exists", name)));
+        }
+        domain.snapshots.push(Snapshot {
+            name: name.clone(),
+            description: text(root, "description").map(str::to_string),
+            parent: domain.current_snapshot.replace(name),
+            state: domain.state,
+            creation_time,
+            def: domain.def.clone(),
+        });
+        let snap = domain.snapshot(domain.snapshots.last().unwrap());
+        if flags.contains(DomainSnapshotCreateFlags::HALT) && domain.is_active() {
+            self.stop(i, VIR_DOMAIN_SHUTOFF_FROM_SNAPSHOT, VIR_DOMAIN_EVENT_STOPPED_FROM_SNAPSHOT);
+        }
+        Ok(snap)
+    }
+
+    fn delete_snapshot(&mut self, snap: &NonnullDomainSnapshot, flags: DomainSnapshotDeleteFlags) -> CallResult<()> {
+        let (i, s) = self.snapshot(snap)?;
+        let dom = &mut self.domains[i];
+        let parent = dom.snapshots[s].parent.clone();
+
+        let mut deleted = Vec::new();
+        if flags.intersects(DomainSnapshotDeleteFlags::CHILDREN | DomainSnapshotDeleteFlags::CHILDREN_ONLY) {
+            deleted = dom.descendants(&snap.name);
+        }
+        if !flags.contains(DomainSnapshotDeleteFlags::CHILDREN_ONLY) {
+            deleted.push(snap.name.clone());
+        }
+        dom.snapshots.retain(|s| !deleted.contains(&s.name));
+        // The children of a snapshot deleted alone move up to its parent
+        for child in &mut dom.snapshots {
+            if child.parent.as_ref() == Some(&snap.name) && deleted.contains(&snap.name) {
+                child.parent = parent.clone();
+            }
+        }
+        if dom.current_snapshot.as_ref().is_some_and(|current| deleted.contains(current)) {
+            dom.current_snapshot = match flags.contains(DomainSnapshotDeleteFlags::CHILDREN_ONLY) {
+                true => Some(snap.name.clone()),
+                false => parent,
+            };
+        }
+        Ok(())
+    }
+
+    fn revert_to_snapshot(&mut self, snap: &NonnullDomainSnapshot, flags: DomainSnapshotRevertFlags) -> CallResult<()> {
+        let (i, s) = self.snapshot(snap)?;
+        let dom = &mut self.domains[i];
+        let snapshot = &dom.snapshots[s];
+        let target = if flags.contains(DomainSnapshotRevertFlags::RUNNING) {
+            VIR_DOMAIN_RUNNING
+        } else if flags.contains(DomainSnapshotRevertFlags::PAUSED) {
+            VIR_DOMAIN_PAUSED
+        } else if [VIR_DOMAIN_RUNNING, VIR_DOMAIN_PAUSED].contains(&snapshot.state) {
+            snapshot.state
+        } else {
+            VIR_DOMAIN_SHUTOFF
+        };
+        dom.def = snapshot.def.clone();
+        dom.current_snapshot = Some(snap.name.clone());
+
+        if target == VIR_DOMAIN_SHUTOFF {
+            if self.domains[i].is_active() {
+                self.stop(i, VIR_DOMAIN_SHUTOFF_FROM_SNAPSHOT, VIR_DOMAIN_EVENT_STOPPED_FROM_SNAPSHOT);
+            }
+            return Ok(());
+        }
+        if !self.domains[i].is_active() {
+            let dom = &mut self.domains[i];
+            dom.id = self.next_id;
+            dom.started = Some(Instant::now());
+            self.next_id += 1;
+            self.set_state(i, VIR_DOMAIN_RUNNING, VIR_DOMAIN_RUNNING_FROM_SNAPSHOT);
+            self.event(i, VIR_DOMAIN_EVENT_STARTED, VIR_DOMAIN_EVENT_STARTED_FROM_SNAPSHOT);
+        }
+        let paused = self.domains[i].state == VIR_DOMAIN_PAUSED;
+        if target == VIR_DOMAIN_PAUSED && !paused {
+            self.set_state(i, VIR_DOMAIN_PAUSED, VIR_DOMAIN_PAUSED_FROM_SNAPSHOT);
+            self.event(i, VIR_DOMAIN_EVENT_SUSPENDED, VIR_DOMAIN_EVENT_SUSPENDED_FROM_SNAPSHOT);
+        } else if target == VIR_DOMAIN_RUNNING && paused {
+            self.set_state(i, VIR_DOMAIN_RUNNING, VIR_DOMAIN_RUNNING_FROM_SNAPSHOT);
+            self.event(i, VIR_DOMAIN_EVENT_RESUMED, VIR_DOMAIN_EVENT_RESUMED_FROM_SNAPSHOT);
+        }
+        Ok(())
+    }
+
+    /// The index of the network `net`, looked up by UUID.
+    fn network(&self, net: &NonnullNetwork) -> CallResult<usize> {
+        self.networks
+            .iter()
+            .position(|n| n.uuid == net.uuid)
+            .ok_or_else(|| no_network(format!("no network with matching uuid '{}' ({})", net.uuid, net.name)))
+    }
+
+    /// Parse the network XML `xml`; the index of the network of the same
+    /// name comes with it, if there is one.
+    fn parse_network(&mut self, xml: &str) -> CallResult<(NetworkObj, Option<usize>)> {
+        let doc = parse_xml(xml)?;
+        let root = root(&doc, "network")?;
+        let name = name(root, "network")?;
+        let existing = self.networks.iter().position(|n| n.name == name);
+        let uuid = match (element_uuid(root)?, existing) {
+            (Some(uuid), Some(i)) if uuid != self.networks[i].uuid => {
+                return Err(error(
+                    VIR_ERR_OPERATION_FAILED,
+                    format!("operation failed: network '{}' already exists with uuid {}", name, self.networks[i].uuid),
+                ));
+            }
+            (Some(uuid), _) => uuid,
+            (None, Some(i)) => self.networks[i].uuid,
+            (None, None) => self.new_uuid(&name),
+        };
+        let bridge = root
+            .children()
+            .find(|n| n.has_tag_name("bridge"))
+            .and_then(|n| n.attribute("name"))
+            .map(str::to_string);
+        let net = NetworkObj {
+            name,
+            uuid,
+            bridge,
+            active: false,
+            persistent: false,
+            autostart: false,
+        };
+        Ok((net, existing))
+    }
+
+    fn define_network(&mut self, xml: &str) -> CallResult<NonnullNetwork> {
+        let (net, existing) = self.parse_network(xml)?;
+        let net = match existing {
+            Some(i) => {
+                self.networks[i].bridge = net.bridge;
+                self.networks[i].persistent = true;
+                &self.networks[i]
+            }
+            None => {
+                self.networks.push(NetworkObj { persistent: true, ..net });
+                self.networks.last().unwrap()
+            }
+        };
+        Ok(net.nonnull())
+    }
+
+    fn create_network(&mut self, xml: &str) -> CallResult<NonnullNetwork> {
+        let (net, existing) = self.parse_network(xml)?;
+        let net = match existing {
+            Some(i) if self.networks[i].active => {
+                return Err(error(
+                    VIR_ERR_OPERATION_FAILED,
+                    format!("operation failed: network '{}' is already active", net.name),
+                ));
+            }
+            Some(i) => {
+                self.networks[i].bridge = net.bridge;
+                self.networks[i].active = true;
+                &self.networks[i]
+            }
+            None => {
+                self.networks.push(NetworkObj { active: true, ..net });
+                self.networks.last().unwrap()
+            }
+        };
+        Ok(net.nonnull())
+    }
+
+    /// The index of the storage pool `pool`, looked up by UUID.
+    fn pool(&self, pool: &NonnullStoragePool) -> CallResult<usize> {
+        self.pools
+            .iter()
+            .position(|p| p.uuid == pool.uuid)
+            .ok_or_else(|| no_pool(format!("no storage pool with matching uuid '{}' ({})", pool.uuid, pool.name)))
+    }
+
+    /// The index of the storage pool `pool`, which must be active.
+    fn active_pool(&self, pool: &NonnullStoragePool) -> CallResult<usize> {
+        let i = self.pool(pool)?;
+        if !self.pools[i].active {
+            return Err(invalid(format!("storage pool '{}' is not active", pool.name)));
+        }
+        Ok(i)
+    }
+
+    /// Parse the storage pool XML `xml`, rejecting the names and UUIDs
+    /// already used.
+    fn parse_pool(&mut self, xml: &str) -> CallResult<PoolObj> {
+        let doc = parse_xml(xml)?;
+        let root = root(&doc, "pool")?;
+        let name = name(root, "storage pool")?;
+        let uuid = element_uuid(root)?;
+        if let Some(other) = self.pools.iter().find(|p| p.name == name || Some(p.uuid) == uuid) {
+            return Err(error(
+                VIR_ERR_OPERATION_FAILED,
+                format!("operation failed: pool '{}' already exists with uuid {}", other.name, other.uuid),
+            ));
+        }
+        let path = root
+            .children()
+            .find(|n| n.has_tag_name("target"))
+            .and_then(|target| text(target, "path"))
+            .ok_or_else(|| xml_error("missing storage pool target path"))?;
+        Ok(PoolObj {
+            uuid: uuid.unwrap_or_else(|| self.new_uuid(&name)),
+            pool_type: root.attribute("type").unwrap_or("dir").to_string(),
+            path: path.trim_end_matches('/').to_string(),
+            capacity: scaled(root, "capacity", 1)?.unwrap_or(POOL_CAPACITY),
+            name,
+            active: false,
+            persistent: false,
+            autostart: false,
+            volumes: Vec::new(),
+        })
+    }
+
+    fn define_pool(&mut self, xml: &str) -> CallResult<NonnullStoragePool> {
+        let pool = self.parse_pool(xml)?;
+        self.pools.push(PoolObj { persistent: true, ..pool });
+        Ok(self.pools.last().unwrap().nonnull())
+    }
+
+    fn create_pool(&mut self, xml: &str) -> CallResult<NonnullStoragePool> {
+        let pool = self.parse_pool(xml)?;
+        self.pools.push(PoolObj { active: true, ..pool });
+        Ok(self.pools.last().unwrap().nonnull())
+    }
+
+    /// The indices of the pool and volume `vol`.
+    fn volume(&self, vol: &NonnullStorageVol) -> CallResult<(usize, usize)> {
+        let Some(i) = self.pools.iter().position(|p| p.name == vol.pool) else {
+            return Err(no_pool(format!("no storage pool with matching name '{}'", vol.pool)));
+        };
+        let pool = &self.pools[i];
+        if !pool.active {
+            return Err(invalid(format!("storage pool '{}' is not active", pool.name)));
+        }
+        match pool.volumes.iter().position(|v| v.name == vol.name) {
+            Some(v) => Ok((i, v)),
+            None => Err(no_volume(format!("no storage vol with matching name '{}'", vol.name))),
+        }
+    }
+
+    /// The volume at `path` in an active pool.
+    fn volume_by_path(&self, path: &str) -> Option<NonnullStorageVol> {
+        self.pools.iter().filter(|p| p.active).find_map(|pool| {
+            let vol = pool.volumes.iter().find(|v| v.path(&pool.path) == path)?;
+            Some(pool.volume(vol))
+        })
+    }
+
+    fn create_volume(&mut self, pool: &NonnullStoragePool, xml: &str) -> CallResult<NonnullStorageVol> {
+        let i = self.active_pool(pool)?;
+        let doc = parse_xml(xml)?;
+        let root = root(&doc, "volume")?;
+        let name = name(root, "volume")?;
+        let capacity = scaled(root, "capacity", 1)?.ok_or_else(|| xml_error("missing capacity element"))?;
+        let allocation = scaled(root, "allocation", 1)?.unwrap_or(capacity);
+
+        let pool = &mut self.pools[i];
+        if pool.volumes.iter().any(|v| v.name == name) {
+            return Err(error(
+                VIR_ERR_STORAGE_VOL_EXIST,
+                format!("storage volume '{}' exists already", name),
+            ));
+        }
+        if pool.allocation() + allocation > pool.capacity {
+            return Err(error(
+                VIR_ERR_INTERNAL_ERROR,
+                format!("internal error: Not enough free space in pool for volume '{}'", name),
+            ));
+        }
+        pool.volumes.push(Volume {
+            name,
+            capacity,
+            allocation,
+        });
+        Ok(pool.volume(pool.volumes.last().unwrap()))
+    }
+}
+
+/// Whether an object passes one group of `*_list_all_*` filter flags,
+/// given with whether the object has each: if any flag of the group is
+/// set, the object must have one of those set.
+fn matches<F: Flags + Copy>(flags: F, group: &[(F, bool)]) -> bool {
+    let mut set = group.iter().filter(|(flag, _)| flags.contains(*flag)).peekable();
+    set.peek().is_none() || set.any(|&(_, has)| has)
+}
+
+fn error(code: i32, message: impl Into<String>) -> ServiceError {
+    RemoteError::new(code, VIR_FROM_TEST, message).into()
+}
+
+fn invalid(message: impl std::fmt::Display) -> ServiceError {
+    error(VIR_ERR_OPERATION_INVALID, format!("Requested operation is not valid: {}", message))
+}
+
+fn no_domain(message: String) -> ServiceError {
+    error(VIR_ERR_NO_DOMAIN, format!("Domain not found: {}", message))
+}
+
+fn no_snapshot(name: &str) -> ServiceError {
+    error(
+        VIR_ERR_NO_DOMAIN_SNAPSHOT,
+        format!("Domain snapshot not found: no domain snapshot with matching name '{}'", name),
+    )
+}
+
+fn no_network(message: String) -> ServiceError {
+    error(VIR_ERR_NO_NETWORK, format!("Network not found: {}", message))
+}
+
+fn no_pool(message: String) -> ServiceError {
+    error(VIR_ERR_NO_STORAGE_POOL, format!("Storage pool not found: {}", message))
+}
+
+fn no_volume(message: String) -> ServiceError {
+    error(VIR_ERR_NO_STORAGE_VOL, format!("Storage volume not found: {}", message))
+}
+
+fn xml_error(message: impl std::fmt::Display) -> ServiceError {
+    error(VIR_ERR_XML_ERROR, format!("XML error: {}", message))
+}
+
+fn parse_xml(xml: &str) -> CallResult<Document<'_>> {
+    Document::parse(xml).map_err(xml_error)
+}
+
+/// The root element of `doc`, which must be `<name>`.
+fn root<'a, 'input>(doc: &'a Document<'input>, name: &str) -> CallResult<Node<'a, 'input>> {
+    let root = doc.root_element();
+    if !root.has_tag_name(name) {
+        return Err(xml_error(format!(
+            "unexpected root element <{}>, expecting <{}>",
+            root.tag_name().name(),
+            name
+        )));
+    }
+    Ok(root)
+}
+
+/// The trimmed text of the child element `name` of `node`.
+fn text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    let child = node.children().find(|n| n.has_tag_name(name))?;
+    Some(child.text().unwrap_or_default().trim())
+}
+
+/// The required `<name>` of an object of the given `kind`.
+fn name(node: Node, kind: &str) -> CallResult<String> {
+    match text(node, "name") {
+        Some(name) if !name.is_empty() => Ok(name.to_string()),
+        _ => Err(xml_error(format!("missing {} name", kind))),
+    }
+}
+
+fn element_uuid(node: Node) -> CallResult<Option<Uuid>> {
+    text(node, "uuid")
+        .map(|uuid| parse_uuid(uuid).ok_or_else(|| xml_error(format!("malformed uuid element '{}'", uuid))))
+        .transpose()
+}
+
+/// The size in the child element `name`, in bytes; `unit` is the size of
+/// its unit when it has no `unit` attribute.
+fn scaled(node: Node, name: &str, unit: u64) -> CallResult<Option<u64>> {
+    let Some(child) = node.children().find(|n| n.has_tag_name(name)) else {
+        return Ok(None);
+    };
+    let invalid = || xml_error(format!("invalid value for element <{}>", name));
+    let value: u64 = child.text().unwrap_or_default().trim().parse().map_err(|_| invalid())?;
+    let unit = match child.attribute("unit") {
+        None => unit,
+        Some("b" | "byte" | "bytes") => 1,
+        Some("KB") => 1000,
+        Some("k" | "K" | "KiB") => 1 << 10,
+        Some("MB") => 1000 * 1000,
+        Some("m" | "M" | "MiB") => 1 << 20,
+        Some("GB") => 1000 * 1000 * 1000,
+        Some("g" | "G" | "GiB") => 1 << 30,
+        Some("TB") => 1000 * 1000 * 1000 * 1000,
+        Some("t" | "T" | "TiB") => 1 << 40,
+        Some(unit) => return Err(xml_error(format!("unknown unit '{}' for element <{}>", unit, name))),
+    };
+    value.checked_mul(unit).map(Some).ok_or_else(invalid)
+}
+
+/// Parse a UUID, with or without dashes.
+fn parse_uuid(uuid: &str) -> Option<Uuid> {
+    let hex: Vec<u8> = uuid.bytes().filter(|&b| b != b'-').collect();
+    if hex.len() != 32 {
+        return None;
+    }
+    let mut bytes = [0; 16];
+    for (byte, pair) in bytes.iter_mut().zip(hex.chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(FixedOpaque16::new(bytes))
+}
+
+/// The name of a `VIR_DOMAIN_*` state in snapshot XML.
+fn state_name(state: i32) -> &'static str {
+    match state {
+        VIR_DOMAIN_RUNNING => "running",
+        VIR_DOMAIN_BLOCKED => "blocked",
+        VIR_DOMAIN_PAUSED => "paused",
+        VIR_DOMAIN_SHUTDOWN => "shutdown",
+        VIR_DOMAIN_SHUTOFF => "shutoff",
+        VIR_DOMAIN_CRASHED => "crashed",
+        VIR_DOMAIN_PMSUSPENDED => "pmsuspended",
+        _ => "nostate",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::Packet;
+    use crate::RpcError;
+
+    fn code(error: RpcError) -> i32 {
+        match error {
+            RpcError::Server(e) => e.code,
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    fn lifecycle(packet: Packet) -> (String, i32, i32) {
+        let event: DomainEventCallbackLifecycleMsg = libvirt_xdr::from_bytes(&packet.payload).unwrap();
+        (event.msg.dom.name, event.msg.event, event.msg.detail)
+    }
+
+    #[tokio::test]
+    async fn test_domain_lifecycle() {
+        let fake = FakeHypervisor::new();
+        let connection = fake.connect().await.unwrap();
+        let mut events = connection.events();
+        let client = Client::open(connection, URI).await.unwrap();
+        let api = client.api();
+        api.connect_domain_event_callback_register_any(VIR_DOMAIN_EVENT_ID_LIFECYCLE, None)
+            .await
+            .unwrap();
+
+        let test = api.domain_lookup_by_name("test").await.unwrap();
+        assert_eq!(test.id, 1);
+        let info = api.domain_get_info(&test).await.unwrap();
+        assert_eq!((info.state, info.memory, info.nr_virt_cpu), (VIR_DOMAIN_RUNNING as u8, 2097152, 2));
+
+        let xml = "<domain type='test'><name>vm1</name><memory unit='MiB'>512</memory><vcpu>4</vcpu></domain>";
+        let vm1 = api.domain_define_xml_flags(xml, DomainDefineFlags::empty()).await.unwrap();
+        assert_eq!(vm1.id, -1);
+        let vm1 = api.domain_create_with_flags(&vm1, DomainCreateFlags::empty()).await.unwrap();
+        assert_eq!(vm1.id, 2);
+        api.domain_suspend(&vm1).await.unwrap();
+        let state = api.domain_get_state(&vm1, 0).await.unwrap();
+        assert_eq!((state.state, state.reason), (VIR_DOMAIN_PAUSED, VIR_DOMAIN_PAUSED_USER));
+        api.domain_resume(&vm1).await.unwrap();
+        api.domain_shutdown(&vm1).await.unwrap();
+
+        let xml = api.domain_get_xml_desc(&vm1, DomainXmlFlags::empty()).await.unwrap();
+        assert!(xml.contains("<memory unit='KiB'>524288</memory>"));
+        assert!(xml.contains(&format!("<uuid>{}</uuid>", vm1.uuid)));
+
+        let ret = api.connect_list_all_domains(1, ConnectListAllDomainsFlags::INACTIVE).await.unwrap();
+        assert_eq!(ret.domains, [NonnullDomain { id: -1, ..vm1.clone() }]);
+        api.domain_undefine(&vm1).await.unwrap();
+        assert_eq!(api.connect_list_all_domains(0, ConnectListAllDomainsFlags::empty()).await.unwrap().ret, 1);
+
+        let mut received = Vec::new();
+        for _ in 0..6 {
+            received.push(lifecycle(events.recv().await.unwrap()));
+        }
+        let vm1 = |event, detail| ("vm1".to_string(), event, detail);
+        assert_eq!(received, [
+            vm1(VIR_DOMAIN_EVENT_DEFINED, VIR_DOMAIN_EVENT_DEFINED_ADDED),
+            vm1(VIR_DOMAIN_EVENT_STARTED, VIR_DOMAIN_EVENT_STARTED_BOOTED),
+            vm1(VIR_DOMAIN_EVENT_SUSPENDED, VIR_DOMAIN_EVENT_SUSPENDED_PAUSED),
+            vm1(VIR_DOMAIN_EVENT_RESUMED, VIR_DOMAIN_EVENT_RESUMED_UNPAUSED),
+            vm1(VIR_DOMAIN_EVENT_STOPPED, VIR_DOMAIN_EVENT_STOPPED_SHUTDOWN),
+            vm1(VIR_DOMAIN_EVENT_UNDEFINED, VIR_DOMAIN_EVENT_UNDEFINED_REMOVED),
+        ][..]);
+    }
+
+    #[tokio::test]
+    async fn test_domain_errors() {
+        let fake = FakeHypervisor::new();
+        let client = fake.client().await.unwrap();
+        let api = client.api();
+
+        assert_eq!(code(api.domain_lookup_by_name("missing").await.unwrap_err()), VIR_ERR_NO_DOMAIN);
+        let test = api.domain_lookup_by_name("test").await.unwrap();
+        let error = api.domain_create(&test).await.unwrap_err();
+        assert_eq!(code(error), VIR_ERR_OPERATION_INVALID);
+        assert_eq!(code(api.domain_resume(&test).await.unwrap_err()), VIR_ERR_OPERATION_INVALID);
+
+        let error = api.domain_define_xml("<domain><memory>1024</memory></domain>").await.unwrap_err();
+        assert_eq!(code(error), VIR_ERR_XML_ERROR);
+        let error = api.domain_define_xml("<network><name>x</name></network>").await.unwrap_err();
+        assert_eq!(code(error), VIR_ERR_XML_ERROR);
+
+        // Transient domains go away when stopped
+        let xml = "<domain><name>tmp</name><memory>1024</memory></domain>";
+        let tmp = api.domain_create_xml(xml, DomainCreateFlags::START_PAUSED).await.unwrap();
+        assert_eq!(api.domain_get_state(&tmp, 0).await.unwrap().state, VIR_DOMAIN_PAUSED);
+        assert_eq!(code(api.domain_undefine(&tmp).await.unwrap_err()), VIR_ERR_OPERATION_INVALID);
+        api.domain_destroy(&tmp).await.unwrap();
+        assert_eq!(code(api.domain_destroy(&tmp).await.unwrap_err()), VIR_ERR_NO_DOMAIN);
+    }
+
+    #[tokio::test]
+    async fn test_snapshots() {
+        let fake = FakeHypervisor::new();
+        let client = fake.client().await.unwrap();
+        let api = client.api();
+        let test = api.domain_lookup_by_name("test").await.unwrap();
+
+        let flags = DomainSnapshotCreateFlags::empty();
+        let s1 = api.domain_snapshot_create_xml(&test, "<domainsnapshot><name>s1</name></domainsnapshot>", flags).await.unwrap();
+        api.domain_destroy(&test).await.unwrap();
+        let s2 = api.domain_snapshot_create_xml(&test, "<domainsnapshot><name>s2</name></domainsnapshot>", flags).await.unwrap();
+        let xml = api.domain_snapshot_get_xml_desc(&s2, DomainSnapshotXmlFlags::empty()).await.unwrap();
+        assert!(xml.contains("<state>shutoff</state>"));
+        assert!(xml.contains("<parent>\n    <name>s1</name>\n  </parent>"));
+
+        // Undefining needs the snapshots gone first
+        let error = api.domain_undefine(&test).await.unwrap_err();
+        assert_eq!(code(error), VIR_ERR_OPERATION_INVALID);
+
+        api.domain_revert_to_snapshot(&s1, DomainSnapshotRevertFlags::empty()).await.unwrap();
+        let state = api.domain_get_state(&test, 0).await.unwrap();
+        assert_eq!((state.state, state.reason), (VIR_DOMAIN_RUNNING, VIR_DOMAIN_RUNNING_FROM_SNAPSHOT));
+        assert_eq!(api.domain_snapshot_current(&test, 0).await.unwrap().name, "s1");
+
+        api.domain_snapshot_delete(&s1, DomainSnapshotDeleteFlags::CHILDREN).await.unwrap();
+        assert_eq!(api.domain_list_all_snapshots(&test, 1, DomainSnapshotListFlags::empty()).await.unwrap().ret, 0);
+        let error = api.domain_snapshot_lookup_by_name(&test, "s2", 0).await.unwrap_err();
+        assert_eq!(code(error), VIR_ERR_NO_DOMAIN_SNAPSHOT);
+        assert!(!api.domain_has_current_snapshot(&test, 0).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_networks_and_storage() {
+        let fake = FakeHypervisor::new();
+        let client = fake.client().await.unwrap();
+        let api = client.api();
+
+        let default = api.network_lookup_by_name("default").await.unwrap();
+        assert_eq!(api.network_get_bridge_name(&default).await.unwrap(), "virbr0");
+        let xml = "<network><name>isolated</name><bridge name='virbr1'/></network>";
+        let isolated = api.network_create_xml(xml).await.unwrap();
+        let ret = api.connect_list_all_networks(1, ConnectListAllNetworksFlags::TRANSIENT).await.unwrap();
+        assert_eq!(ret.nets, std::slice::from_ref(&isolated));
+        api.network_destroy(&isolated).await.unwrap();
+        assert_eq!(code(api.network_destroy(&isolated).await.unwrap_err()), VIR_ERR_NO_NETWORK);
+
+        let pool = api.storage_pool_lookup_by_name("default-pool").await.unwrap();
+        let xml = "<volume><name>disk.img</name><capacity unit='GiB'>10</capacity><allocation>0</allocation></volume>";
+        let vol = api.storage_vol_create_xml(&pool, xml, StorageVolCreateFlags::empty()).await.unwrap();
+        assert_eq!(vol.key, "/default-pool/disk.img");
+        let error = api.storage_vol_create_xml(&pool, xml, StorageVolCreateFlags::empty()).await.unwrap_err();
+        assert_eq!(code(error), VIR_ERR_STORAGE_VOL_EXIST);
+        assert_eq!(api.storage_vol_lookup_by_path("/default-pool/disk.img").await.unwrap(), vol);
+        let info = api.storage_vol_get_info(&vol).await.unwrap();
+        assert_eq!((info.capacity, info.allocation), (10 << 30, 0));
+
+        let error = api.storage_pool_undefine(&pool).await.unwrap_err();
+        assert_eq!(code(error), VIR_ERR_OPERATION_INVALID);
+        api.storage_pool_destroy(&pool).await.unwrap();
+        let info = api.storage_pool_get_info(&pool).await.unwrap();
+        assert_eq!(info.state, VIR_STORAGE_POOL_INACTIVE as u8);
+        let error = api.storage_vol_get_info(&vol).await.unwrap_err();
+        assert_eq!(code(error), VIR_ERR_OPERATION_INVALID);
+    }
+}
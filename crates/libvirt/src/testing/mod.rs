@@ -0,0 +1,463 @@
+//! Testing without a libvirt daemon.
+//!
+//! [`MockDaemon`] answers connections made over a [`MemoryTransport`]
+//! the way libvirtd would: with the real framing and [`Packet`] format,
+//! replies or `remote_error`s, events and stream data. It handles
+//! `auth_list`, `connect_open` and `connect_close` itself; tests register
+//! responses for the other procedures they exercise, by procedure name
+//! and generated type. Calls to the rest fail with `VIR_ERR_NO_SUPPORT`.
+//!
+//! ```ignore
+//! use libvirt::testing::MockDaemon;
+//! use libvirt::*;
+//!
+//! let daemon = MockDaemon::new();
+//! daemon.respond("connect_get_hostname", ConnectGetHostnameRet { hostname: "mock".into() });
+//! daemon.on("domain_lookup_by_name", |args: DomainLookupByNameArgs| {
+//!     let uuid = FixedOpaque16::new([0; 16]);
+//!     Ok(DomainLookupByNameRet { dom: NonnullDomain { name: args.name, uuid, id: 1 } })
+//! });
+//!
+//! let client = daemon.client().await?;
+//! let dom = client.api().domain_lookup_by_name("vm1").await?;
+//! assert_eq!(daemon.calls_to::<DomainLookupByNameArgs>("domain_lookup_by_name").len(), 1);
+//! ```
+//!
+//! [`FakeHypervisor`] goes further and keeps state: it serves an
+//! in-memory model of `test:///default`, with domains, snapshots,
+//! networks, storage pools and volumes that calls define, start, stop and
+//! remove, and the lifecycle events that come with it.
+//!
+//! Only available with the `testing` feature.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use libvirt_xdr::{XdrDecode, XdrEncode};
+use tokio::sync::mpsc;
+
+use crate::error::{Error, Result};
+use crate::generated::{
+    procedure_info, AuthListRet, AuthType, ProcedureInfo, ServiceError, StreamDirection, PROGRAMS,
+};
+use crate::packet::{MessageType, Packet, Status};
+use crate::server::{Dispatcher, RemoteError, Service};
+use crate::transport::{MemoryTransport, Transport};
+use crate::{Client, Connection, StreamChunk};
+
+mod hypervisor;
+
+pub use hypervisor::FakeHypervisor;
+
+/// A procedure, as `(program, procedure number)`.
+type Key = (u32, u32);
+
+/// Turns a call's arguments into its reply.
+type Handler = Arc<dyn Fn(&[u8]) -> std::result::Result<Vec<u8>, ServiceError> + Send + Sync>;
+
+/// A scripted libvirt daemon, serving any number of in-memory connections.
+///
+/// Procedures are named as in [`ProgramInfo::procedure_by_name`](crate::generated::ProgramInfo::procedure_by_name),
+/// e.g. `domain_lookup_by_name` or `QEMU_PROC_DOMAIN_MONITOR_COMMAND`;
+/// unknown names panic. Clones share the same state.
+#[derive(Clone)]
+pub struct MockDaemon {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    handlers: Mutex<HashMap<Key, Handler>>,
+    /// Data sent on the streams of `@readstream` procedures.
+    downloads: Mutex<HashMap<Key, Vec<StreamChunk>>>,
+    /// Data received on the finished streams of `@writestream` procedures.
+    uploads: Mutex<HashMap<Key, Vec<Vec<StreamChunk>>>>,
+    /// Calls received, in order.
+    calls: Mutex<Vec<Packet>>,
+    /// Queues of the packets to send to each connected client.
+    clients: Mutex<Vec<mpsc::UnboundedSender<Packet>>>,
+    /// Errors that ended the connections of [`MockDaemon::connect`].
+    errors: Mutex<Vec<String>>,
+}
+
+impl Default for MockDaemon {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockDaemon {
+    /// Create a daemon accepting any connection with `AUTH_NONE`.
+    pub fn new() -> Self {
+        let daemon = Self {
+            inner: Arc::default(),
+        };
+        daemon.respond("auth_list", AuthListRet {
+            types: vec![AuthType::AuthNone],
+        });
+        daemon.respond("connect_open", ());
+        daemon.respond("connect_close", ());
+        daemon
+    }
+
+    /// Answer calls to `procedure` with `handler`, which gets the decoded
+    /// arguments (`()` if it takes none) and returns the value to reply
+    /// with, or the error.
+    pub fn on<A, R, F>(&self, procedure: &str, handler: F)
+    where
+        A: XdrDecode,
+        R: XdrEncode,
+        F: Fn(A) -> std::result::Result<R, ServiceError> + Send + Sync + 'static,
+    {
+        let (key, _) = lookup(procedure);
+        let handler = move |payload: &[u8]| {
+            let args = libvirt_xdr::from_bytes(payload).map_err(|e| ServiceError::Decode(e.to_string()))?;
+            let ret = handler(args)?;
+            libvirt_xdr::to_bytes(&ret).map_err(|e| ServiceError::Encode(e.to_string()))
+        };
+        self.inner.handlers.lock().unwrap().insert(key, Arc::new(handler));
+    }
+
+    /// Answer every call to `procedure` with `ret`.
+    pub fn respond<R: XdrEncode>(&self, procedure: &str, ret: R) {
+        let (key, _) = lookup(procedure);
+        let payload = libvirt_xdr::to_bytes(&ret).expect("failed to encode canned response");
+        let handler = move |_: &[u8]| Ok(payload.clone());
+        self.inner.handlers.lock().unwrap().insert(key, Arc::new(handler));
+    }
+
+    /// Fail every call to `procedure` with `error`.
+    pub fn fail(&self, procedure: &str, error: RemoteError) {
+        let (key, _) = lookup(procedure);
+        let handler = move |_: &[u8]| Err(error.clone().into());
+        self.inner.handlers.lock().unwrap().insert(key, Arc::new(handler));
+    }
+
+    /// Send `chunks` on the data stream of each successful call to
+    /// `procedure`, a `@readstream` one, then end the stream.
+    pub fn stream_data(&self, procedure: &str, chunks: impl IntoIterator<Item = Vec<u8>>) {
+        self.stream_chunks(procedure, chunks.into_iter().map(|chunk| StreamChunk::Data(Bytes::from(chunk))));
+    }
+
+    /// Like [`stream_data`](Self::stream_data), with holes between the
+    /// data, as sent on sparse streams.
+    pub fn stream_chunks(&self, procedure: &str, chunks: impl IntoIterator<Item = StreamChunk>) {
+        let (key, info) = lookup(procedure);
+        assert_eq!(info.stream, Some(StreamDirection::Read), "{} does not send a stream", info.name);
+        self.inner.downloads.lock().unwrap().insert(key, chunks.into_iter().collect());
+    }
+
+    /// Data received on the streams of the calls to `procedure`, a
+    /// `@writestream` one: one entry per stream the client finished.
+    /// Holes read as zeros.
+    pub fn uploads(&self, procedure: &str) -> Vec<Vec<u8>> {
+        let flatten = |chunks: Vec<StreamChunk>| {
+            let mut data = Vec::new();
+            for chunk in chunks {
+                match chunk {
+                    StreamChunk::Data(bytes) => data.extend_from_slice(&bytes),
+                    StreamChunk::Hole(length) => data.resize(data.len() + length as usize, 0),
+                }
+            }
+            data
+        };
+        self.upload_chunks(procedure).into_iter().map(flatten).collect()
+    }
+
+    /// Like [`uploads`](Self::uploads), keeping the data packets and holes
+    /// as the client sent them.
+    pub fn upload_chunks(&self, procedure: &str) -> Vec<Vec<StreamChunk>> {
+        let (key, _) = lookup(procedure);
+        self.inner.uploads.lock().unwrap().get(&key).cloned().unwrap_or_default()
+    }
+
+    /// Send the event `procedure` (e.g. `domain_event_callback_lifecycle`)
+    /// to every connected client.
+    pub fn emit<M: XdrEncode>(&self, procedure: &str, msg: &M) {
+        let ((program, procedure), _) = lookup(procedure);
+        let payload = libvirt_xdr::to_bytes(msg).expect("failed to encode event");
+        let mut packet = Packet::new_call_program(program, procedure, 0, Bytes::from(payload));
+        packet.msg_type = MessageType::Message;
+        let mut clients = self.inner.clients.lock().unwrap();
+        clients.retain(|client| client.send(packet.clone()).is_ok());
+    }
+
+    /// Calls received so far, in order.
+    pub fn calls(&self) -> Vec<Packet> {
+        self.inner.calls.lock().unwrap().clone()
+    }
+
+    /// Decoded arguments of the calls to `procedure` received so far.
+    pub fn calls_to<A: XdrDecode>(&self, procedure: &str) -> Vec<A> {
+        let ((program, procedure), _) = lookup(procedure);
+        let calls = self.inner.calls.lock().unwrap();
+        calls
+            .iter()
+            .filter(|call| (call.program, call.procedure) == (program, procedure))
+            .map(|call| libvirt_xdr::from_bytes(&call.payload).expect("failed to decode call arguments"))
+            .collect()
+    }
+
+    /// Errors that ended connections opened with
+    /// [`connect`](Self::connect), such as packets that do not decode; the
+    /// client only sees the connection close.
+    pub fn errors(&self) -> Vec<String> {
+        self.inner.errors.lock().unwrap().clone()
+    }
+
+    /// Open a connection to the daemon.
+    pub async fn connect(&self) -> Result<Connection> {
+        let (client, server) = MemoryTransport::pair();
+        let daemon = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = daemon.serve(server).await {
+                daemon.inner.errors.lock().unwrap().push(e.to_string());
+            }
+        });
+        Connection::from_transport(client).await
+    }
+
+    /// Open a connection to the daemon and authenticate and open
+    /// `mock:///` on it, as [`Client::connect`] does.
+    pub async fn client(&self) -> Result<Client> {
+        Client::open(self.connect().await?, "mock:///").await
+    }
+
+    /// Serve the client on the other end of `transport` until it
+    /// disconnects.
+    pub async fn serve<T: Transport>(&self, mut transport: T) -> Result<()> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        self.inner.clients.lock().unwrap().push(tx);
+
+        let dispatcher = Dispatcher::new(self.clone());
+        // Data received on the open `@writestream` streams, by serial
+        let mut uploads = HashMap::new();
+        loop {
+            tokio::select! {
+                Some(packet) = rx.recv() => transport.send(&packet.encode()).await?,
+                data = transport.recv() => {
+                    let data = match data {
+                        Ok(data) => data,
+                        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                        Err(e) => return Err(e),
+                    };
+                    for packet in self.handle(&dispatcher, Packet::decode(data)?, &mut uploads).await {
+                        transport.send(&packet.encode()).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The packets to send in response to `packet`.
+    async fn handle(
+        &self,
+        dispatcher: &Dispatcher<MockDaemon>,
+        packet: Packet,
+        uploads: &mut HashMap<i32, Vec<StreamChunk>>,
+    ) -> Vec<Packet> {
+        let key = (packet.program, packet.procedure);
+        match packet.msg_type {
+            MessageType::Call => {
+                self.inner.calls.lock().unwrap().push(packet.clone());
+                let Some(reply) = dispatcher.dispatch(&packet).await else {
+                    return Vec::new();
+                };
+                if reply.status != Status::Ok {
+                    return vec![reply];
+                }
+
+                let mut packets = vec![reply];
+                let (program, procedure, serial) = (packet.program, packet.procedure, packet.serial);
+                match procedure_info(program, procedure).and_then(|p| p.stream) {
+                    Some(StreamDirection::Read) => {
+                        let chunks = self.inner.downloads.lock().unwrap().get(&key).cloned();
+                        packets.extend(chunks.unwrap_or_default().into_iter().map(|chunk| match chunk {
+                            StreamChunk::Data(data) => Packet::new_stream(program, procedure, serial, Status::Continue, data),
+                            StreamChunk::Hole(length) => Packet::new_stream_hole(program, procedure, serial, length),
+                        }));
+                        packets.push(Packet::new_stream(program, procedure, serial, Status::Ok, Bytes::new()));
+                    }
+                    Some(StreamDirection::Write) => {
+                        uploads.insert(packet.serial, Vec::new());
+                    }
+                    None => {}
+                }
+                packets
+            }
+            MessageType::Stream | MessageType::StreamHole => {
+                let Some(chunks) = uploads.get_mut(&packet.serial) else {
+                    return Vec::new();
+                };
+                match packet.status {
+                    Status::Continue => {
+                        chunks.push(match packet.hole_length() {
+                            Some(length) => StreamChunk::Hole(length),
+                            None => StreamChunk::Data(packet.payload),
+                        });
+                        Vec::new()
+                    }
+                    // Finished: keep the data and confirm
+                    Status::Ok => {
+                        let chunks = uploads.remove(&packet.serial).unwrap_or_default();
+                        self.inner.uploads.lock().unwrap().entry(key).or_default().push(chunks);
+                        let (program, procedure) = key;
+                        vec![Packet::new_stream(program, procedure, packet.serial, Status::Ok, Bytes::new())]
+                    }
+                    // Aborted
+                    Status::Error => {
+                        uploads.remove(&packet.serial);
+                        Vec::new()
+                    }
+                }
+            }
+            MessageType::Reply | MessageType::Message => Vec::new(),
+        }
+    }
+}
+
+impl Service for MockDaemon {
+    async fn call(&self, program: u32, procedure: u32, payload: &[u8]) -> std::result::Result<Vec<u8>, ServiceError> {
+        let handler = self.inner.handlers.lock().unwrap().get(&(program, procedure)).cloned();
+        match handler {
+            Some(handler) => handler(payload),
+            None => Err(ServiceError::Unsupported { procedure }),
+        }
+    }
+}
+
+/// The program and number of the procedure named `name`, in any program.
+fn lookup(name: &str) -> (Key, &'static ProcedureInfo) {
+    PROGRAMS
+        .iter()
+        .find_map(|program| {
+            let procedure = program.procedure_by_name(name)?;
+            Some(((program.number, procedure.number), procedure))
+        })
+        .unwrap_or_else(|| panic!("unknown procedure `{}`", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::*;
+
+    fn domain(name: &str) -> NonnullDomain {
+        NonnullDomain {
+            name: name.to_string(),
+            uuid: FixedOpaque16::new([7; 16]),
+            id: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_calls() {
+        let daemon = MockDaemon::new();
+        daemon.respond("connect_get_hostname", ConnectGetHostnameRet {
+            hostname: "mock".to_string(),
+        });
+        daemon.on("domain_lookup_by_name", |args: DomainLookupByNameArgs| {
+            if args.name == "missing" {
+                return Err(RemoteError::new(VIR_ERR_NO_DOMAIN, VIR_FROM_TEST, "Domain not found").into());
+            }
+            Ok(DomainLookupByNameRet { dom: domain(&args.name) })
+        });
+
+        let client = daemon.client().await.unwrap();
+        assert_eq!(client.api().connect_get_hostname().await.unwrap(), "mock");
+        assert_eq!(client.api().domain_lookup_by_name("vm1").await.unwrap().name, "vm1");
+
+        let error = client.api().domain_lookup_by_name("missing").await.unwrap_err();
+        assert!(matches!(error, RpcError::Server(e) if e.code == VIR_ERR_NO_DOMAIN));
+        let error = client.api().connect_get_type().await.unwrap_err();
+        assert!(matches!(error, RpcError::Server(e) if e.code == VIR_ERR_NO_SUPPORT));
+
+        let names: Vec<_> = daemon
+            .calls_to::<DomainLookupByNameArgs>("domain_lookup_by_name")
+            .into_iter()
+            .map(|args| args.name)
+            .collect();
+        assert_eq!(names, ["vm1", "missing"]);
+        assert_eq!(daemon.calls().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_packet() {
+        // A packet the client cannot decode fails the calls waiting
+        let (client, mut server) = MemoryTransport::pair();
+        let connection = Connection::from_transport(client).await.unwrap();
+        let call = tokio::spawn(async move { connection.call(1, Bytes::new()).await });
+        server.recv().await.unwrap();
+        server.send(&[0; 8]).await.unwrap();
+        let error = call.await.unwrap().unwrap_err();
+        assert!(matches!(error, crate::Error::Connection(m) if m == "packet error: packet too short"));
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        let daemon = MockDaemon::new();
+        let connection = daemon.connect().await.unwrap();
+        let mut events = connection.events();
+
+        let msg = DomainEventCallbackLifecycleMsg {
+            callback_id: 3,
+            msg: DomainEventLifecycleMsg {
+                dom: domain("vm1"),
+                event: 5,
+                detail: 1,
+            },
+        };
+        // Wait for the daemon to see the connection
+        let client = GeneratedClient::new(connection);
+        client.auth_list().await.unwrap();
+        daemon.emit("domain_event_callback_lifecycle", &msg);
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.msg_type, MessageType::Message);
+        assert_eq!(event.procedure, Procedure::ProcDomainEventCallbackLifecycle as u32);
+        assert_eq!(libvirt_xdr::from_bytes::<DomainEventCallbackLifecycleMsg>(&event.payload).unwrap(), msg);
+    }
+
+    #[tokio::test]
+    async fn test_streams() {
+        let daemon = MockDaemon::new();
+        daemon.respond("domain_screenshot", DomainScreenshotRet {
+            mime: Some("image/png".to_string()),
+        });
+        daemon.stream_data("domain_screenshot", [b"PNG".to_vec(), b"DATA".to_vec()]);
+        daemon.respond("storage_vol_upload", ());
+        let client = daemon.client().await.unwrap();
+        let rpc = client.rpc();
+
+        let args = DomainScreenshotArgs {
+            dom: domain("vm1"),
+            screen: 0,
+            flags: 0,
+        };
+        let (ret, stream) = rpc.domain_screenshot(args).await.unwrap();
+        assert_eq!(ret.mime.as_deref(), Some("image/png"));
+        let mut data = Vec::new();
+        while let Some(chunk) = rpc.inner().stream_recv(&stream).await.unwrap() {
+            data.extend(chunk);
+        }
+        assert_eq!(data, b"PNGDATA");
+        rpc.inner().stream_finish(&stream).await.unwrap();
+
+        let args = StorageVolUploadArgs {
+            vol: NonnullStorageVol {
+                pool: "default".to_string(),
+                name: "disk.img".to_string(),
+                key: "/var/lib/libvirt/images/disk.img".to_string(),
+            },
+            offset: 0,
+            length: 0,
+            flags: StorageVolUploadFlags::empty(),
+        };
+        let stream = rpc.storage_vol_upload(args).await.unwrap();
+        rpc.inner().stream_send(&stream, b"abc".to_vec()).await.unwrap();
+        rpc.inner().stream_send(&stream, b"def".to_vec()).await.unwrap();
+        rpc.inner().stream_finish(&stream).await.unwrap();
+        assert_eq!(daemon.uploads("storage_vol_upload"), [b"abcdef".to_vec()]);
+    }
+}
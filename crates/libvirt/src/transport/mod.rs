@@ -4,9 +4,19 @@
 //! - Unix socket (default for local connections)
 //! - TCP (for remote connections)
 //! - TLS (for secure remote connections)
+//! - In-memory pairs (for tests, with the `testing` feature)
+//! - Recording of the session on another transport, and its replay
 
+#[cfg(any(test, feature = "testing"))]
+mod memory;
+mod recording;
+mod tcp;
 mod unix;
 
+#[cfg(any(test, feature = "testing"))]
+pub use memory::MemoryTransport;
+pub use recording::{Direction, RecordedPacket, Recording, RecordingTransport, ReplayTransport};
+pub use tcp::TcpTransport;
 pub use unix::UnixTransport;
 
 use async_trait::async_trait;
@@ -23,6 +33,8 @@ pub trait Transport: Send + Sync {
     /// Receive a complete packet from the remote.
     ///
     /// This reads the length prefix and then reads the complete packet.
+    /// It must be cancel safe: the connection drops it to send a request
+    /// and calls it again afterwards.
     async fn recv(&mut self) -> Result<Bytes>;
 
     /// Close the transport.
@@ -33,32 +45,38 @@ pub trait Transport: Send + Sync {
 ///
 /// The libvirt protocol uses a 4-byte big-endian length prefix.
 /// The length value includes the 4 bytes of the length field itself.
+///
+/// Bytes read so far are kept in `buf`, so a read cancelled midway
+/// resumes where it stopped on the next call.
 async fn read_framed<R: tokio::io::AsyncRead + Unpin>(
     reader: &mut R,
     buf: &mut BytesMut,
 ) -> Result<Bytes> {
+    use bytes::Buf;
     use tokio::io::AsyncReadExt;
 
-    // Read length prefix (4 bytes)
-    let mut len_buf = [0u8; 4];
-    reader.read_exact(&mut len_buf).await?;
-    let total_len = u32::from_be_bytes(len_buf) as usize;
-
-    if total_len > crate::packet::MAX_PACKET_SIZE {
-        return Err(crate::error::Error::PacketTooLarge(total_len));
-    }
-
-    // The length includes the 4-byte length field, so body is len - 4
-    let body_len = total_len.saturating_sub(4);
-    if body_len == 0 {
-        return Ok(Bytes::new());
+    loop {
+        if buf.len() >= 4 {
+            // Length prefix (4 bytes)
+            let total_len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+            if total_len > crate::packet::MAX_PACKET_SIZE {
+                return Err(crate::error::Error::PacketTooLarge(total_len));
+            }
+
+            // The length includes the 4-byte length field, so body is len - 4
+            let total_len = total_len.max(4);
+            if buf.len() >= total_len {
+                let mut frame = buf.split_to(total_len);
+                frame.advance(4);
+                return Ok(frame.freeze());
+            }
+            buf.reserve(total_len - buf.len());
+        }
+
+        if reader.read_buf(buf).await? == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
     }
-
-    // Read the packet body (header + payload)
-    buf.resize(body_len, 0);
-    reader.read_exact(buf).await?;
-
-    Ok(Bytes::copy_from_slice(buf))
 }
 
 /// Write a framed message.
@@ -0,0 +1,545 @@
+//! Recording and replaying RPC sessions.
+//!
+//! A [`RecordingTransport`] wraps another transport and logs every packet
+//! sent and received on it, with the time since the session started. A
+//! [`ReplayTransport`] plays such a [`Recording`] back to a
+//! [`Connection`](crate::Connection): it checks that the client sends the
+//! recorded packets, in order, and answers with the recorded replies,
+//! events and stream data. A session captured on a production host can so
+//! be reproduced offline against another version of the client.
+//!
+//! ```ignore
+//! use libvirt::transport::{RecordingTransport, ReplayTransport, UnixTransport};
+//! use libvirt::Connection;
+//!
+//! // Capture
+//! let transport = UnixTransport::connect("/var/run/libvirt/libvirt-sock").await?;
+//! let conn = Connection::from_transport(RecordingTransport::create(transport, "session.rec")?).await?;
+//!
+//! // Reproduce
+//! let conn = Connection::from_transport(ReplayTransport::open("session.rec")?).await?;
+//! ```
+//!
+//! # File format
+//!
+//! All integers are big-endian. The file starts with the magic
+//! `LVRPCREC`, the format version (`u32`, 1) and the wall-clock start of
+//! the session (`u64` microseconds since the Unix epoch). Each packet
+//! follows as its direction (`u8`: 0 sent, 1 received), the time since
+//! the start (`u64` microseconds), its length (`u32`) and its bytes
+//! without the length prefix, as [`Packet::decode`] takes them.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::sync::oneshot;
+
+use super::Transport;
+use crate::error::{Error, Result};
+use crate::generated::procedure_info;
+use crate::packet::{MessageType, Packet};
+
+/// Identifies recording files.
+const MAGIC: &[u8; 8] = b"LVRPCREC";
+
+/// Version of the recording format.
+const VERSION: u32 = 1;
+
+/// Which way a recorded packet went, from the client's side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Sent by the client.
+    Sent = 0,
+    /// Received from the daemon.
+    Received = 1,
+}
+
+/// A packet of a recorded session.
+#[derive(Debug, Clone)]
+pub struct RecordedPacket {
+    /// Which way the packet went.
+    pub direction: Direction,
+    /// Time since the session started.
+    pub elapsed: Duration,
+    /// The packet, without its length prefix.
+    pub data: Bytes,
+}
+
+impl RecordedPacket {
+    /// Decode the packet.
+    pub fn packet(&self) -> Result<Packet> {
+        Ok(Packet::decode(self.data.clone())?)
+    }
+}
+
+/// A recorded session.
+#[derive(Debug, Clone)]
+pub struct Recording {
+    /// When the session started.
+    pub started: SystemTime,
+    /// The packets, in the order they were sent or received.
+    pub packets: Vec<RecordedPacket>,
+}
+
+impl Recording {
+    /// Read the recording file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+
+    /// Read a recording.
+    ///
+    /// A packet cut short at the end, as left by a process killed while
+    /// recording it, is ignored.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut header = [0; 20];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(Error::Protocol("not an RPC session recording".to_string()));
+        }
+        let version = u32::from_be_bytes(header[8..12].try_into().unwrap());
+        if version != VERSION {
+            return Err(Error::Protocol(format!("unsupported recording version {}", version)));
+        }
+        let started = UNIX_EPOCH + Duration::from_micros(u64::from_be_bytes(header[12..].try_into().unwrap()));
+
+        let mut packets = Vec::new();
+        loop {
+            let mut header = [0; 13];
+            match read_full(&mut reader, &mut header)? {
+                0 => break,
+                n if n < header.len() => break,
+                _ => {}
+            }
+            let direction = match header[0] {
+                0 => Direction::Sent,
+                1 => Direction::Received,
+                d => return Err(Error::Protocol(format!("invalid packet direction {} in recording", d))),
+            };
+            let elapsed = Duration::from_micros(u64::from_be_bytes(header[1..9].try_into().unwrap()));
+            let len = u32::from_be_bytes(header[9..].try_into().unwrap()) as usize;
+            if len > crate::packet::MAX_PACKET_SIZE {
+                return Err(Error::PacketTooLarge(len));
+            }
+            let mut data = vec![0; len];
+            if read_full(&mut reader, &mut data)? < len {
+                break;
+            }
+            packets.push(RecordedPacket {
+                direction,
+                elapsed,
+                data: Bytes::from(data),
+            });
+        }
+        Ok(Self { started, packets })
+    }
+
+    /// Write the recording, in the format [`read_from`](Self::read_from)
+    /// reads.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(&header(self.started))?;
+        for packet in &self.packets {
+            writer.write_all(&record(packet.direction, packet.elapsed, &packet.data))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Read into `buf` until it is full or the reader ends, returning the
+/// number of bytes read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(read)
+}
+
+/// The file header of a session started at `started`.
+fn header(started: SystemTime) -> BytesMut {
+    let started = started.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut buf = BytesMut::with_capacity(20);
+    buf.put_slice(MAGIC);
+    buf.put_u32(VERSION);
+    buf.put_u64(started.as_micros() as u64);
+    buf
+}
+
+/// The record of a packet.
+fn record(direction: Direction, elapsed: Duration, data: &[u8]) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(13 + data.len());
+    buf.put_u8(direction as u8);
+    buf.put_u64(elapsed.as_micros() as u64);
+    buf.put_u32(data.len() as u32);
+    buf.put_slice(data);
+    buf
+}
+
+/// A transport logging the packets it carries to a recording.
+///
+/// The records are written on a thread of their own, so that sending and
+/// receiving never wait on the disk; the writer is flushed whenever the
+/// thread has caught up with the packets, so the recording survives the
+/// process crashing. Write errors are reported by the next send or
+/// receive.
+pub struct RecordingTransport<T, W = BufWriter<File>> {
+    inner: T,
+    jobs: mpsc::Sender<Job>,
+    writer: Option<JoinHandle<std::io::Result<W>>>,
+    started: Instant,
+}
+
+/// Work for the thread writing a recording.
+enum Job {
+    /// Write the record of a packet.
+    Record(BytesMut),
+    /// Flush the writer and report the result.
+    Flush(oneshot::Sender<std::io::Result<()>>),
+}
+
+impl<T: Transport> RecordingTransport<T, BufWriter<File>> {
+    /// Record the session on `inner` to a new file at `path`.
+    pub fn create(inner: T, path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(inner, BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<T: Transport, W: Write + Send + 'static> RecordingTransport<T, W> {
+    /// Record the session on `inner` to `writer`.
+    pub fn new(inner: T, mut writer: W) -> Result<Self> {
+        writer.write_all(&header(SystemTime::now()))?;
+        writer.flush()?;
+        let (jobs, queue) = mpsc::channel();
+        let writer = std::thread::Builder::new()
+            .name("libvirt-recording".to_string())
+            .spawn(move || write_records(writer, queue))?;
+        Ok(Self {
+            inner,
+            jobs,
+            writer: Some(writer),
+            started: Instant::now(),
+        })
+    }
+
+    /// Get the wrapped transport and the writer back, once the packets
+    /// recorded so far are written.
+    pub fn into_inner(mut self) -> Result<(T, W)> {
+        drop(self.jobs);
+        match self.writer.take().map(JoinHandle::join) {
+            Some(Ok(writer)) => Ok((self.inner, writer?)),
+            Some(Err(_)) => Err(writer_panicked()),
+            None => Err(already_failed()),
+        }
+    }
+
+    fn record(&mut self, direction: Direction, data: &[u8]) -> Result<()> {
+        let job = Job::Record(record(direction, self.started.elapsed(), data));
+        if self.jobs.send(job).is_err() {
+            return Err(self.writer_error());
+        }
+        Ok(())
+    }
+
+    /// The error that stopped the writer thread.
+    fn writer_error(&mut self) -> Error {
+        // The thread has returned, since it dropped the queue
+        match self.writer.take().map(JoinHandle::join) {
+            Some(Ok(Err(e))) => e.into(),
+            Some(Err(_)) => writer_panicked(),
+            Some(Ok(Ok(_))) | None => already_failed(),
+        }
+    }
+}
+
+fn writer_panicked() -> Error {
+    Error::Io(std::io::Error::other("the recording writer panicked"))
+}
+
+fn already_failed() -> Error {
+    Error::Io(std::io::Error::other("the recording already failed"))
+}
+
+/// Write the records queued on `queue` to `writer` until the transport is
+/// dropped, flushing whenever the queue is empty.
+fn write_records<W: Write>(mut writer: W, queue: mpsc::Receiver<Job>) -> std::io::Result<W> {
+    while let Ok(job) = queue.recv() {
+        let mut next = Some(job);
+        while let Some(job) = next.take().or_else(|| queue.try_recv().ok()) {
+            match job {
+                Job::Record(record) => writer.write_all(&record)?,
+                Job::Flush(done) => {
+                    let _ = done.send(writer.flush());
+                }
+            }
+        }
+        writer.flush()?;
+    }
+    Ok(writer)
+}
+
+#[async_trait]
+impl<T: Transport, W: Write + Send + 'static> Transport for RecordingTransport<T, W> {
+    async fn send(&mut self, data: &[u8]) -> Result<()> {
+        self.inner.send(data).await?;
+        // Recorded without the length prefix, like received packets
+        self.record(Direction::Sent, data.get(4..).unwrap_or_default())
+    }
+
+    async fn recv(&mut self) -> Result<Bytes> {
+        let data = self.inner.recv().await?;
+        // Queued before returning, so that the packet cannot be lost to
+        // cancellation
+        self.record(Direction::Received, &data)?;
+        Ok(data)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.inner.close().await?;
+        let (done, flushed) = oneshot::channel();
+        if self.jobs.send(Job::Flush(done)).is_err() {
+            return Err(self.writer_error());
+        }
+        match flushed.await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(self.writer_error()),
+        }
+    }
+}
+
+/// A transport playing a recorded session back to the client.
+///
+/// The client must send the packets the recording has, in order: the
+/// same calls and stream data, with the same payloads. Only serials may
+/// differ. The recorded replies and stream packets get the serials of
+/// the client's calls they answer. Each received packet is delivered once
+/// the client has sent the packets recorded before it; the end of the
+/// recording closes the connection. Timings are not reproduced.
+///
+/// Sending anything else fails with [`Error::Protocol`], describing the
+/// difference.
+pub struct ReplayTransport {
+    packets: VecDeque<RecordedPacket>,
+    /// Serials of the client's calls, by recorded serial.
+    serials: HashMap<i32, i32>,
+}
+
+impl ReplayTransport {
+    /// Replay `recording`.
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            packets: recording.packets.into(),
+            serials: HashMap::new(),
+        }
+    }
+
+    /// Replay the recording file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(Recording::open(path)?))
+    }
+
+    /// Number of packets not replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.packets.len()
+    }
+}
+
+#[async_trait]
+impl Transport for ReplayTransport {
+    async fn send(&mut self, data: &[u8]) -> Result<()> {
+        let sent = Packet::decode(Bytes::copy_from_slice(data.get(4..).unwrap_or_default()))?;
+        // Received packets recorded first wait for the client to read them
+        let Some(i) = self.packets.iter().position(|p| p.direction == Direction::Sent) else {
+            return Err(mismatch(format!("{} sent after the end of the recording", describe(&sent))));
+        };
+        let expected = self.packets.remove(i).unwrap().packet()?;
+
+        let header = |p: &Packet| (p.program, p.version, p.procedure, p.msg_type, p.status);
+        if header(&sent) != header(&expected) {
+            return Err(mismatch(format!(
+                "{} sent where the recording has {}",
+                describe(&sent),
+                describe(&expected)
+            )));
+        }
+        if sent.payload != expected.payload {
+            return Err(mismatch(format!("{} sent with a different payload", describe(&sent))));
+        }
+        match sent.msg_type {
+            MessageType::Call => {
+                self.serials.insert(expected.serial, sent.serial);
+            }
+            _ if self.serials.get(&expected.serial) != Some(&sent.serial) => {
+                return Err(mismatch(format!(
+                    "{} sent for another call than the recorded one",
+                    describe(&sent)
+                )));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<Bytes> {
+        match self.packets.front() {
+            Some(packet) if packet.direction == Direction::Received => {}
+            Some(_) => {
+                // Wait for the client to send the next recorded packet,
+                // which cancels this
+                return std::future::pending().await;
+            }
+            None => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+        }
+
+        let mut packet = self.packets.pop_front().unwrap().packet()?;
+        if matches!(packet.msg_type, MessageType::Reply | MessageType::Stream | MessageType::StreamHole) {
+            if let Some(&serial) = self.serials.get(&packet.serial) {
+                packet.serial = serial;
+            }
+        }
+        let mut data = packet.encode();
+        Ok(data.split_off(4).freeze())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn mismatch(message: String) -> Error {
+    Error::Protocol(format!("replay mismatch: {}", message))
+}
+
+/// E.g. `call to REMOTE_PROC_CONNECT_OPEN (serial 2)`.
+fn describe(packet: &Packet) -> String {
+    let kind = match packet.msg_type {
+        MessageType::Call => "call to",
+        MessageType::Reply => "reply to",
+        MessageType::Message => "event",
+        MessageType::Stream => "stream data of",
+        MessageType::StreamHole => "stream hole of",
+    };
+    match procedure_info(packet.program, packet.procedure) {
+        Some(procedure) => format!("{} {} (serial {})", kind, procedure.name, packet.serial),
+        None => format!(
+            "{} procedure {} of program {:#x} (serial {})",
+            kind, packet.procedure, packet.program, packet.serial
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::{ConnectGetHostnameRet, Procedure};
+    use crate::packet::Status;
+    use crate::testing::MockDaemon;
+    use crate::transport::MemoryTransport;
+    use crate::{Client, Connection};
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let path = std::env::temp_dir().join(format!("libvirt-pure-recording-{}.rec", std::process::id()));
+        let daemon = MockDaemon::new();
+        daemon.respond("connect_get_hostname", ConnectGetHostnameRet {
+            hostname: "recorded".to_string(),
+        });
+        let (client, server) = MemoryTransport::pair();
+        let serve = daemon.clone();
+        tokio::spawn(async move { serve.serve(server).await });
+
+        let transport = RecordingTransport::create(client, &path).unwrap();
+        let client = Client::open(Connection::from_transport(transport).await.unwrap(), "mock:///")
+            .await
+            .unwrap();
+        assert_eq!(client.api().connect_get_hostname().await.unwrap(), "recorded");
+        drop(client);
+
+        // auth_list, connect_open and connect_get_hostname, with replies,
+        // once the writer thread has caught up
+        let mut recording = Recording::open(&path).unwrap();
+        for _ in 0..100 {
+            if recording.packets.len() == 6 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            recording = Recording::open(&path).unwrap();
+        }
+        let directions: Vec<_> = recording.packets.iter().map(|p| p.direction).collect();
+        assert_eq!(directions, [Direction::Sent, Direction::Received].repeat(3));
+        let hostname = recording.packets[4].packet().unwrap();
+        assert_eq!(hostname.procedure, Procedure::ProcConnectGetHostname as u32);
+
+        let replay = ReplayTransport::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let client = Client::open(Connection::from_transport(replay).await.unwrap(), "mock:///")
+            .await
+            .unwrap();
+        assert_eq!(client.api().connect_get_hostname().await.unwrap(), "recorded");
+        // The recording ends there
+        assert!(client.api().connect_get_hostname().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_recording_writer() {
+        let (client, mut server) = MemoryTransport::pair();
+        let mut transport = RecordingTransport::new(client, Vec::new()).unwrap();
+        let call = Packet::new_call(Procedure::ProcConnectGetHostname as u32, 1, Bytes::new());
+        transport.send(&call.encode()).await.unwrap();
+        server.send(&call.encode()).await.unwrap();
+        assert_eq!(transport.recv().await.unwrap(), server.recv().await.unwrap());
+        transport.close().await.unwrap();
+
+        let (_, written) = transport.into_inner().unwrap();
+        let recording = Recording::read_from(&written[..]).unwrap();
+        let directions: Vec<_> = recording.packets.iter().map(|p| p.direction).collect();
+        assert_eq!(directions, [Direction::Sent, Direction::Received]);
+        assert!(recording.packets.iter().all(|p| p.data == call.encode()[4..]));
+    }
+
+    #[tokio::test]
+    async fn test_replay_remaps_serials() {
+        let call = |serial| Packet::new_call(Procedure::ProcConnectGetHostname as u32, serial, Bytes::new());
+        let recorded = |direction, packet: Packet| RecordedPacket {
+            direction,
+            elapsed: Duration::ZERO,
+            data: packet.encode().split_off(4).freeze(),
+        };
+        let reply = Packet::new_reply(&call(40), Status::Ok, Bytes::from_static(b"data"));
+        let mut replay = ReplayTransport::new(Recording {
+            started: SystemTime::now(),
+            packets: vec![recorded(Direction::Sent, call(40)), recorded(Direction::Received, reply)],
+        });
+
+        // Nothing to receive before the call is sent
+        let recv = tokio::time::timeout(Duration::from_millis(10), replay.recv()).await;
+        assert!(recv.is_err());
+        replay.send(&call(3).encode()).await.unwrap();
+        let reply = Packet::decode(replay.recv().await.unwrap()).unwrap();
+        assert_eq!((reply.serial, &reply.payload[..]), (3, &b"data"[..]));
+        assert_eq!(replay.remaining(), 0);
+
+        let error = replay.send(&call(4).encode()).await.unwrap_err();
+        assert!(error.to_string().contains("after the end of the recording"));
+
+        let mut replay = ReplayTransport::new(Recording {
+            started: SystemTime::now(),
+            packets: vec![recorded(Direction::Sent, call(1))],
+        });
+        let other = Packet::new_call(Procedure::ProcConnectGetType as u32, 1, Bytes::new());
+        let error = replay.send(&other.encode()).await.unwrap_err();
+        assert!(error.to_string().contains("REMOTE_PROC_CONNECT_GET_TYPE"), "{}", error);
+    }
+}
@@ -0,0 +1,49 @@
+//! TCP transport implementation.
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use super::{read_framed, write_framed, Transport};
+use crate::error::Result;
+
+/// Plain TCP transport, as used by `qemu+tcp://` URIs.
+pub struct TcpTransport {
+    stream: TcpStream,
+    read_buf: BytesMut,
+}
+
+impl TcpTransport {
+    /// Connect to `addr`, e.g. `"host:16509"`.
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(Self::from_stream(stream))
+    }
+
+    /// Wrap a connected socket, e.g. one accepted by a server.
+    pub fn from_stream(stream: TcpStream) -> Self {
+        // Calls are small and latency bound
+        let _ = stream.set_nodelay(true);
+        Self {
+            stream,
+            read_buf: BytesMut::with_capacity(4096),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn send(&mut self, data: &[u8]) -> Result<()> {
+        write_framed(&mut self.stream, data).await
+    }
+
+    async fn recv(&mut self) -> Result<Bytes> {
+        read_framed(&mut self.stream, &mut self.read_buf).await
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+        self.stream.shutdown().await?;
+        Ok(())
+    }
+}
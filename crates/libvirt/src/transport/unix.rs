@@ -17,10 +17,15 @@ impl UnixTransport {
     /// Connect to a Unix socket.
     pub async fn connect(path: &str) -> Result<Self> {
         let stream = UnixStream::connect(path).await?;
-        Ok(Self {
+        Ok(Self::from_stream(stream))
+    }
+
+    /// Wrap a connected socket, e.g. one accepted by a server.
+    pub fn from_stream(stream: UnixStream) -> Self {
+        Self {
             stream,
             read_buf: BytesMut::with_capacity(4096),
-        })
+        }
     }
 }
 
@@ -0,0 +1,9 @@
+//! Helpers for the XML documents sent to libvirt.
+
+/// Escape `text` for use as element text or an attribute value.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&apos;")
+}
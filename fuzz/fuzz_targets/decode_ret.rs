@@ -0,0 +1,18 @@
+//! Fuzz `from_bytes` for every generated `*_ret` and `*_msg` type.
+//!
+//! The first two bytes (big-endian) select the type from
+//! `generated::fuzzing::DECODERS`; the rest is the XDR payload.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use libvirt_pure::generated::fuzzing::DECODERS;
+
+fuzz_target!(|data: &[u8]| {
+    let [hi, lo, payload @ ..] = data else {
+        return;
+    };
+    let index = u16::from_be_bytes([*hi, *lo]) as usize % DECODERS.len();
+    let (_name, roundtrip) = DECODERS[index];
+    roundtrip(payload);
+});
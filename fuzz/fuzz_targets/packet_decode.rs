@@ -0,0 +1,18 @@
+//! Fuzz `Packet::decode` with arbitrary frames (length prefix stripped).
+
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use libvirt_pure::packet::Packet;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(packet) = Packet::decode(Bytes::copy_from_slice(data)) else {
+        return;
+    };
+
+    // `encode` writes the length prefix, which `decode` does not expect.
+    let encoded = packet.encode().freeze();
+    let again = Packet::decode(encoded.slice(4..)).expect("re-encoded packet failed to decode");
+    assert_eq!(again.encode().freeze(), encoded);
+});